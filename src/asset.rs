@@ -0,0 +1,467 @@
+//! Grouping of related camera files into assets.
+//!
+//! Cameras save one picture as several files: a RAW file next to a JPEG or HEIF, sidecar
+//! files such as XMP, THM and AAE, and for Live Photos a short video. Files belong to the
+//! same asset when they are in the same folder and share a DCF file number, or for names
+//! that do not follow DCF, a base name.
+
+use crate::camera::{self, CameraDevice, CameraFile};
+use crate::dcf::DcfFileName;
+use crate::download::{self, CollisionPolicy, DownloadOptions};
+use crate::error::{Error, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Extensions of files that describe another file with the same base name.
+const SIDECAR_EXTENSIONS: &[&str] = &["xmp", "thm", "aae"];
+/// Extensions of videos, which pair with an image as a Live Photo.
+const VIDEO_EXTENSIONS: &[&str] = &["mov", "mp4", "avi", "mts", "m4v"];
+
+/// The part a file plays in an asset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// A rendered image, such as a JPEG or HEIF file.
+    Image,
+    Raw,
+    /// A video, on its own or as the motion part of a Live Photo.
+    Video,
+    /// A file describing the others, such as an XMP, THM or AAE file.
+    Sidecar,
+}
+
+impl Role {
+    /// The role of a file, from its extension and RAW flag.
+    pub fn of(file: &CameraFile) -> Role {
+        let extension = extension(&file.name);
+        if file.is_raw {
+            Role::Raw
+        } else if SIDECAR_EXTENSIONS.contains(&extension.as_str()) {
+            Role::Sidecar
+        } else if VIDEO_EXTENSIONS.contains(&extension.as_str()) {
+            Role::Video
+        } else {
+            Role::Image
+        }
+    }
+}
+
+/// Which member becomes the primary file of an asset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrimaryPreference {
+    /// The rendered image, then the RAW file, then the video.
+    Image,
+    /// The RAW file, then the rendered image, then the video.
+    Raw,
+}
+
+/// A file of an asset.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+    pub file: CameraFile,
+    pub role: Role,
+}
+
+/// Files that together make up one picture or video.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Asset {
+    /// The folder of the files on the camera.
+    pub folder: Option<String>,
+    /// The base name shared by the files, such as `IMG_0001`.
+    pub base_name: String,
+    /// The files of the asset, sorted by name.
+    pub members: Vec<Member>,
+    primary: usize,
+}
+
+impl Asset {
+    /// The file that represents the asset.
+    pub fn primary(&self) -> &CameraFile {
+        &self.members[self.primary].file
+    }
+
+    /// Make the member named `name` the primary file.
+    pub fn set_primary(&mut self, name: &str) -> Result<()> {
+        let index = self
+            .members
+            .iter()
+            .position(|member| member.file.name == name && member.role != Role::Sidecar)
+            .ok_or_else(|| {
+                Error::InvalidValue(format!(
+                    "{} is not a primary candidate of {}",
+                    name, self.base_name
+                ))
+            })?;
+        self.primary = index;
+        Ok(())
+    }
+
+    /// All files of the asset.
+    pub fn files(&self) -> impl Iterator<Item = &CameraFile> {
+        self.members.iter().map(|member| &member.file)
+    }
+
+    /// The files playing `role`.
+    pub fn files_with_role(&self, role: Role) -> impl Iterator<Item = &CameraFile> {
+        self.members
+            .iter()
+            .filter(move |member| member.role == role)
+            .map(|member| &member.file)
+    }
+
+    /// Whether the asset has both a RAW file and a rendered image.
+    pub fn is_raw_plus_image(&self) -> bool {
+        self.has_role(Role::Raw) && self.has_role(Role::Image)
+    }
+
+    /// Whether the asset pairs a still image with a video, like a Live Photo.
+    pub fn is_live_photo(&self) -> bool {
+        self.has_role(Role::Video) && (self.has_role(Role::Image) || self.has_role(Role::Raw))
+    }
+
+    /// Total size of the files.
+    pub fn file_size(&self) -> u64 {
+        self.files().map(|file| file.file_size).sum()
+    }
+
+    /// Whether any file of the asset is locked.
+    pub fn is_locked(&self) -> bool {
+        self.files().any(|file| file.is_locked)
+    }
+
+    /// Download every file of the asset into `folder` under its own name.
+    ///
+    /// Files already in `folder` are left alone: a file with the name of a member fails the
+    /// download with an `AlreadyExists` I/O error. If any file fails, the files already
+    /// written for the asset are removed again.
+    pub fn download(&self, camera: &dyn CameraDevice, folder: &Path) -> Result<Vec<PathBuf>> {
+        fs::create_dir_all(folder)?;
+        let mut written: Vec<PathBuf> = Vec::new();
+        for file in self.files() {
+            let options = DownloadOptions::new(folder).collision_policy(CollisionPolicy::Error);
+            match download::download(camera, file, &options) {
+                Ok(result) => written.push(result.path),
+                Err(error) => {
+                    for path in &written {
+                        let _ = fs::remove_file(path);
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        Ok(written)
+    }
+
+    /// Delete every file of the asset from the camera, or none if any file is locked.
+    pub fn delete(&self, camera: &dyn CameraDevice) -> Result<()> {
+        if let Some(locked) = self.files().find(|file| file.is_locked) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is locked", locked.path()),
+            )));
+        }
+        // Sidecars attached to a member are deleted by the camera together with it.
+        let attached = self
+            .files()
+            .flat_map(|file| file.sidecar_files.iter().map(CameraFile::path))
+            .collect::<HashSet<_>>();
+        let files = self
+            .files()
+            .filter(|file| !attached.contains(&file.path()))
+            .cloned()
+            .collect::<Vec<_>>();
+        camera.delete_files(&files)
+    }
+
+    fn has_role(&self, role: Role) -> bool {
+        self.members.iter().any(|member| member.role == role)
+    }
+
+    fn choose_primary(&mut self, preference: PrimaryPreference) {
+        let order: &[Role] = match preference {
+            PrimaryPreference::Image => &[Role::Image, Role::Raw, Role::Video],
+            PrimaryPreference::Raw => &[Role::Raw, Role::Image, Role::Video],
+        };
+        self.primary = order
+            .iter()
+            .find_map(|&role| self.members.iter().position(|member| member.role == role))
+            .unwrap_or(0);
+    }
+}
+
+/// Files grouped into assets.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Grouping {
+    /// Assets ordered by folder and base name.
+    pub assets: Vec<Asset>,
+    /// Sidecar files without any other file of the same base name.
+    pub orphan_sidecars: Vec<CameraFile>,
+}
+
+impl Grouping {
+    /// Group all files on `camera`, including sidecars `media_files` leaves out.
+    pub fn of_device(camera: &dyn CameraDevice, preference: PrimaryPreference) -> Result<Grouping> {
+        let mut files = Vec::new();
+        camera::collect_files(&camera.contents()?, &mut files);
+        Ok(Grouping::new(&files, preference))
+    }
+
+    /// Group `files` and the sidecar files attached to them into assets.
+    ///
+    /// Orphan sidecars are only found among the files passed in.
+    pub fn new(files: &[CameraFile], preference: PrimaryPreference) -> Grouping {
+        let mut seen = HashSet::new();
+        let mut groups: BTreeMap<(Option<String>, String), Vec<CameraFile>> = BTreeMap::new();
+        let all = files
+            .iter()
+            .flat_map(|file| std::iter::once(file).chain(file.sidecar_files.iter()));
+        for file in all {
+            if seen.insert(file.path()) {
+                groups
+                    .entry((file.parent_folder.clone(), group_key(&file.name)))
+                    .or_default()
+                    .push(file.clone());
+            }
+        }
+        let mut grouping = Grouping::default();
+        for ((folder, _), mut files) in groups {
+            files.sort_by(|a, b| a.name.cmp(&b.name));
+            let members = files
+                .into_iter()
+                .map(|file| Member {
+                    role: Role::of(&file),
+                    file,
+                })
+                .collect::<Vec<_>>();
+            if members.iter().all(|member| member.role == Role::Sidecar) {
+                grouping
+                    .orphan_sidecars
+                    .extend(members.into_iter().map(|member| member.file));
+                continue;
+            }
+            let mut asset = Asset {
+                folder,
+                base_name: String::new(),
+                members,
+                primary: 0,
+            };
+            asset.choose_primary(preference);
+            asset.base_name = base_name(&asset.primary().name).to_string();
+            grouping.assets.push(asset);
+        }
+        grouping
+    }
+
+    /// All files of the assets.
+    pub fn files(&self) -> Vec<CameraFile> {
+        self.assets
+            .iter()
+            .flat_map(|asset| asset.files().cloned())
+            .collect()
+    }
+}
+
+/// Whether a file name has the extension of a sidecar file.
+pub fn is_sidecar(name: &str) -> bool {
+    SIDECAR_EXTENSIONS.contains(&extension(name).as_str())
+}
+
+/// The key files of one asset share: the DCF file number, or else the base name.
+fn group_key(name: &str) -> String {
+    match DcfFileName::parse(name) {
+        Some(dcf) => format!("dcf:{:04}", dcf.number),
+        None => format!("name:{}", base_name(name).to_ascii_uppercase()),
+    }
+}
+
+fn base_name(name: &str) -> &str {
+    match name.rsplit_once('.') {
+        Some((base, _)) if !base.is_empty() => base,
+        _ => name,
+    }
+}
+
+fn extension(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((base, extension)) if !base.is_empty() => extension.to_ascii_lowercase(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockCamera;
+    use crate::testing::ScratchDir;
+
+    fn file(folder: &str, name: &str) -> CameraFile {
+        CameraFile {
+            name: name.to_string(),
+            parent_folder: Some(folder.to_string()),
+            file_size: 10,
+            is_raw: name.ends_with(".CR2"),
+            ..CameraFile::default()
+        }
+    }
+
+    fn names(asset: &Asset) -> Vec<&str> {
+        asset.files().map(|file| file.name.as_str()).collect()
+    }
+
+    #[test]
+    fn groups_files_by_folder_and_file_number() {
+        let mut jpeg = file("DCIM/100CANON", "IMG_0001.JPG");
+        jpeg.sidecar_files = vec![file("DCIM/100CANON", "IMG_0001.XMP")];
+        let files = [
+            jpeg,
+            file("DCIM/100CANON", "IMG_0001.CR2"),
+            file("DCIM/100CANON", "IMG_0002.JPG"),
+            file("DCIM/100CANON", "IMG_0002.MOV"),
+            // The same number in another folder is another picture.
+            file("DCIM/101CANON", "IMG_0001.JPG"),
+            file("DCIM/101CANON", "IMG_0003.THM"),
+            file("Export", "holiday.jpeg"),
+            file("Export", "Holiday.aae"),
+        ];
+        let grouping = Grouping::new(&files, PrimaryPreference::Image);
+        let assets: Vec<_> = grouping.assets.iter().map(names).collect();
+        assert_eq!(
+            assets,
+            vec![
+                vec!["IMG_0001.CR2", "IMG_0001.JPG", "IMG_0001.XMP"],
+                vec!["IMG_0002.JPG", "IMG_0002.MOV"],
+                vec!["IMG_0001.JPG"],
+                vec!["Holiday.aae", "holiday.jpeg"],
+            ]
+        );
+        let orphans: Vec<_> = grouping
+            .orphan_sidecars
+            .iter()
+            .map(|file| file.name.as_str())
+            .collect();
+        assert_eq!(orphans, ["IMG_0003.THM"]);
+
+        let first = &grouping.assets[0];
+        assert_eq!(first.folder.as_deref(), Some("DCIM/100CANON"));
+        assert_eq!(first.base_name, "IMG_0001");
+        assert!(first.is_raw_plus_image());
+        assert!(!first.is_live_photo());
+        assert_eq!(first.file_size(), 30);
+        let sidecars: Vec<_> = first.files_with_role(Role::Sidecar).collect();
+        assert_eq!(sidecars.len(), 1);
+        assert!(grouping.assets[1].is_live_photo());
+        assert_eq!(grouping.files().len(), 8);
+    }
+
+    #[test]
+    fn the_primary_file_follows_the_preference() {
+        let files = [
+            file("DCIM/100CANON", "IMG_0001.CR2"),
+            file("DCIM/100CANON", "IMG_0001.JPG"),
+            file("DCIM/100CANON", "IMG_0001.XMP"),
+        ];
+        let image = Grouping::new(&files, PrimaryPreference::Image);
+        assert_eq!(image.assets[0].primary().name, "IMG_0001.JPG");
+        let mut raw = Grouping::new(&files, PrimaryPreference::Raw);
+        assert_eq!(raw.assets[0].primary().name, "IMG_0001.CR2");
+
+        let asset = &mut raw.assets[0];
+        asset.set_primary("IMG_0001.JPG").unwrap();
+        assert_eq!(asset.primary().name, "IMG_0001.JPG");
+        assert!(matches!(
+            asset.set_primary("IMG_0001.XMP"),
+            Err(Error::InvalidValue(_))
+        ));
+        assert!(matches!(
+            asset.set_primary("IMG_0002.JPG"),
+            Err(Error::InvalidValue(_))
+        ));
+        assert_eq!(asset.primary().name, "IMG_0001.JPG");
+
+        let video = Grouping::new(
+            &[file("DCIM/100CANON", "MVI_0004.MP4")],
+            PrimaryPreference::Raw,
+        );
+        assert_eq!(video.assets[0].primary().name, "MVI_0004.MP4");
+    }
+
+    fn mock_asset(camera: &MockCamera) -> Asset {
+        let files = [
+            camera.add_file("IMG_0001.JPG", b"jpeg"),
+            camera.add_file("IMG_0001.XMP", b"xmp"),
+        ];
+        Grouping::new(&files, PrimaryPreference::Image)
+            .assets
+            .remove(0)
+    }
+
+    #[test]
+    fn download_saves_every_member() {
+        let dir = ScratchDir::new("asset-download");
+        let camera = MockCamera::new("Mock");
+        let asset = mock_asset(&camera);
+        let paths = asset.download(&camera, dir.path()).unwrap();
+        assert_eq!(
+            paths,
+            [
+                dir.path().join("IMG_0001.JPG"),
+                dir.path().join("IMG_0001.XMP")
+            ]
+        );
+        assert_eq!(fs::read(&paths[0]).unwrap(), b"jpeg");
+        assert_eq!(fs::read(&paths[1]).unwrap(), b"xmp");
+    }
+
+    #[test]
+    fn a_failed_download_keeps_existing_files_and_removes_new_ones() {
+        let dir = ScratchDir::new("asset-rollback");
+        let camera = MockCamera::new("Mock");
+        let asset = mock_asset(&camera);
+        fs::write(dir.path().join("IMG_0001.XMP"), b"mine").unwrap();
+        match asset.download(&camera, dir.path()) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::AlreadyExists),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(dir.files(), ["IMG_0001.XMP"]);
+        assert_eq!(fs::read(dir.path().join("IMG_0001.XMP")).unwrap(), b"mine");
+
+        // A member missing from the camera fails after the others were written.
+        fs::remove_file(dir.path().join("IMG_0001.XMP")).unwrap();
+        camera
+            .delete_files(&[asset.members[1].file.clone()])
+            .unwrap();
+        assert!(asset.download(&camera, dir.path()).is_err());
+        assert!(dir.files().is_empty());
+    }
+
+    #[test]
+    fn delete_removes_every_member_unless_one_is_locked() {
+        let camera = MockCamera::new("Mock");
+        let mut asset = mock_asset(&camera);
+        asset.members[0].file.is_locked = true;
+        match asset.delete(&camera) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::PermissionDenied),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(camera.file_names(), ["IMG_0001.JPG", "IMG_0001.XMP"]);
+
+        asset.members[0].file.is_locked = false;
+        asset.delete(&camera).unwrap();
+        assert!(camera.file_names().is_empty());
+    }
+
+    #[test]
+    fn delete_leaves_attached_sidecars_to_the_camera() {
+        let camera = MockCamera::new("Mock");
+        let mut jpeg = camera.add_file("IMG_0001.JPG", b"jpeg");
+        jpeg.sidecar_files = vec![camera.add_file("IMG_0001.XMP", b"xmp")];
+        let asset = Grouping::new(&[jpeg], PrimaryPreference::Image)
+            .assets
+            .remove(0);
+        assert_eq!(names(&asset), ["IMG_0001.JPG", "IMG_0001.XMP"]);
+        asset.delete(&camera).unwrap();
+        // The mock does not delete sidecars with their file, as cameras do.
+        assert_eq!(camera.file_names(), ["IMG_0001.XMP"]);
+    }
+}
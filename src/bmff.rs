@@ -0,0 +1,551 @@
+//! Boxes of the ISO base media file format, the structure of HEIF images, CR3 RAW files and
+//! MP4 and QuickTime movies.
+//!
+//! Boxes are read through `Read` + `Seek`, so that only the headers and the few small boxes
+//! that matter are fetched, whether from a file or from a camera with `CameraFileReader`.
+
+use crate::error::{Error, Result};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+
+/// Largest box body read into memory, guarding against corrupt sizes.
+const MAX_BODY_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Brands of the `ftyp` box of HEIF and AVIF images.
+pub const HEIF_BRANDS: &[&[u8; 4]] = &[
+    b"mif1", b"msf1", b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"avif",
+];
+
+/// The header of a box.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoxHeader {
+    /// The four-character type, such as `moov`.
+    pub kind: [u8; 4],
+    /// Offset of the box in the file.
+    pub offset: u64,
+    /// Size of the header, including a 64-bit size.
+    pub header_size: u64,
+    /// Size of the box, header included.
+    pub size: u64,
+}
+
+impl BoxHeader {
+    /// Whether the box is of type `kind`.
+    pub fn is(&self, kind: &[u8; 4]) -> bool {
+        &self.kind == kind
+    }
+
+    /// Offsets of the body of the box in the file.
+    pub fn body(&self) -> Range<u64> {
+        self.offset + self.header_size..self.end()
+    }
+
+    pub fn end(&self) -> u64 {
+        self.offset + self.size
+    }
+}
+
+/// Read the header of the box at `offset`, which must end by `end`. Returns `None` at `end`.
+pub fn read_header<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    end: u64,
+) -> Result<Option<BoxHeader>> {
+    if offset + 8 > end {
+        return Ok(None);
+    }
+    let head = read_at(reader, offset, 16.min(end - offset))?;
+    if head.len() < 8 {
+        return Ok(None);
+    }
+    let mut kind = [0; 4];
+    kind.copy_from_slice(&head[4..8]);
+    let (size, header_size) = match u32_at(&head, 0) {
+        Some(0) => (end - offset, 8),
+        Some(1) => match u64_at(&head, 8) {
+            Some(size) => (size, 16),
+            None => return Err(Error::malformed("truncated 64-bit box size")),
+        },
+        Some(size) => (u64::from(size), 8),
+        None => return Ok(None),
+    };
+    if size < header_size || offset.checked_add(size).is_none_or(|box_end| box_end > end) {
+        return Err(Error::malformed(format!(
+            "box '{}' at offset {} overruns its parent",
+            String::from_utf8_lossy(&kind),
+            offset
+        )));
+    }
+    Ok(Some(BoxHeader {
+        kind,
+        offset,
+        header_size,
+        size,
+    }))
+}
+
+/// The boxes within `range`, such as the body of a parent box or the whole file.
+pub fn children<R: Read + Seek>(reader: &mut R, range: Range<u64>) -> Result<Vec<BoxHeader>> {
+    let mut boxes = Vec::new();
+    let mut offset = range.start;
+    while let Some(header) = read_header(reader, offset, range.end)? {
+        offset = header.end();
+        boxes.push(header);
+    }
+    Ok(boxes)
+}
+
+/// The first box of type `kind` within `range`.
+pub fn find<R: Read + Seek>(
+    reader: &mut R,
+    range: Range<u64>,
+    kind: &[u8; 4],
+) -> Result<Option<BoxHeader>> {
+    let mut offset = range.start;
+    while let Some(header) = read_header(reader, offset, range.end)? {
+        if header.is(kind) {
+            return Ok(Some(header));
+        }
+        offset = header.end();
+    }
+    Ok(None)
+}
+
+/// The box at the end of `path`, a list of box types from `range` down.
+pub fn find_path<R: Read + Seek>(
+    reader: &mut R,
+    range: Range<u64>,
+    path: &[&[u8; 4]],
+) -> Result<Option<BoxHeader>> {
+    let mut found = None;
+    let mut range = range;
+    for kind in path {
+        match find(reader, range, kind)? {
+            Some(header) => {
+                range = header.body();
+                found = Some(header);
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(found)
+}
+
+/// The body of a box.
+pub fn read_body<R: Read + Seek>(reader: &mut R, header: &BoxHeader) -> Result<Vec<u8>> {
+    let body = header.body();
+    if body.end - body.start > MAX_BODY_SIZE {
+        return Err(Error::malformed(format!(
+            "box '{}' is too large to read",
+            String::from_utf8_lossy(&header.kind)
+        )));
+    }
+    let data = read_at(reader, body.start, body.end - body.start)?;
+    if (data.len() as u64) < body.end - body.start {
+        return Err(Error::malformed("truncated box"));
+    }
+    Ok(data)
+}
+
+/// The major and compatible brands of the `ftyp` box heading a file, if it has one.
+pub fn brands<R: Read + Seek>(reader: &mut R) -> Result<Option<Vec<[u8; 4]>>> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    let header = match read_header(reader, 0, end) {
+        Ok(Some(header)) if header.is(b"ftyp") && header.size <= 4096 => header,
+        _ => return Ok(None),
+    };
+    let body = read_body(reader, &header)?;
+    // The minor version between the major and compatible brands is not a brand.
+    let brands = body
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(index, _)| *index != 1)
+        .map(|(_, brand)| [brand[0], brand[1], brand[2], brand[3]])
+        .collect();
+    Ok(Some(brands))
+}
+
+/// An item of a HEIF file, such as an image, its thumbnail or its EXIF metadata.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeifItem {
+    pub id: u32,
+    /// The item type, such as `hvc1`, `grid`, `Exif` or `mime`.
+    pub kind: [u8; 4],
+    /// Offsets of the data of the item in the file, in order.
+    pub extents: Vec<Range<u64>>,
+    /// Indexes of the item's properties in `HeifMeta::properties`.
+    pub properties: Vec<usize>,
+}
+
+impl HeifItem {
+    /// Total size of the data of the item.
+    pub fn size(&self) -> u64 {
+        self.extents
+            .iter()
+            .map(|extent| extent.end - extent.start)
+            .sum()
+    }
+}
+
+/// A reference between HEIF items, such as from a thumbnail (`thmb`) or metadata (`cdsc`)
+/// item to the image it describes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeifReference {
+    pub kind: [u8; 4],
+    pub from: u32,
+    pub to: Vec<u32>,
+}
+
+/// The `meta` box of a HEIF file: its items, their properties and references.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeifMeta {
+    /// The item of the main image.
+    pub primary: Option<u32>,
+    pub items: Vec<HeifItem>,
+    /// The property boxes, such as `ispe` with the image size, in their order in `ipco`.
+    pub properties: Vec<BoxHeader>,
+    pub references: Vec<HeifReference>,
+}
+
+impl HeifMeta {
+    /// Read the `meta` box at the top level of a HEIF file.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<HeifMeta> {
+        let end = reader.seek(SeekFrom::End(0))?;
+        let meta = find(reader, 0..end, b"meta")?
+            .ok_or_else(|| Error::malformed("the HEIF file has no meta box"))?;
+        // `meta` is a full box: a version and flags precede the children.
+        let children_range = meta.body().start + 4..meta.end();
+        let boxes = children(reader, children_range)?;
+        let mut heif = HeifMeta::default();
+        let mut idat = None;
+        let mut locations = Vec::new();
+        let mut associations = Vec::new();
+        for header in &boxes {
+            match &header.kind {
+                b"pitm" => {
+                    let body = read_body(reader, header)?;
+                    let mut cursor = FullBox::new(&body);
+                    heif.primary = cursor.id();
+                }
+                b"iinf" => heif.items = read_item_infos(reader, header)?,
+                b"iloc" => locations = read_locations(&read_body(reader, header)?)?,
+                b"idat" => idat = Some(header.body().start),
+                b"iref" => heif.references = read_references(&read_body(reader, header)?),
+                b"iprp" => {
+                    for child in children(reader, header.body())? {
+                        if child.is(b"ipco") {
+                            heif.properties = children(reader, child.body())?;
+                        } else if child.is(b"ipma") {
+                            associations.extend(read_associations(&read_body(reader, &child)?));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (id, construction, extents) in locations {
+            let base = match (construction, idat) {
+                (0, _) => 0,
+                (1, Some(idat)) => idat,
+                _ => continue,
+            };
+            if let Some(item) = heif.items.iter_mut().find(|item| item.id == id) {
+                item.extents = extents
+                    .into_iter()
+                    .map(|extent| base + extent.start..base + extent.end)
+                    .collect();
+            }
+        }
+        for (id, properties) in associations {
+            if let Some(item) = heif.items.iter_mut().find(|item| item.id == id) {
+                item.properties = properties;
+            }
+        }
+        Ok(heif)
+    }
+
+    pub fn item(&self, id: u32) -> Option<&HeifItem> {
+        self.items.iter().find(|item| item.id == id)
+    }
+
+    /// The items referencing `to` with a reference of type `kind`, such as the thumbnails
+    /// (`thmb`) of an image.
+    pub fn referencing(&self, kind: &[u8; 4], to: u32) -> Vec<&HeifItem> {
+        self.references
+            .iter()
+            .filter(|reference| &reference.kind == kind && reference.to.contains(&to))
+            .filter_map(|reference| self.item(reference.from))
+            .collect()
+    }
+
+    /// The first property of type `kind` of `item`.
+    pub fn property(&self, item: &HeifItem, kind: &[u8; 4]) -> Option<&BoxHeader> {
+        item.properties
+            .iter()
+            .filter_map(|&index| self.properties.get(index))
+            .find(|property| property.is(kind))
+    }
+
+    /// Width and height of an image item, from its `ispe` property.
+    pub fn dimensions<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        item: &HeifItem,
+    ) -> Result<Option<(u32, u32)>> {
+        let ispe = match self.property(item, b"ispe") {
+            Some(ispe) => ispe,
+            None => return Ok(None),
+        };
+        let body = read_body(reader, ispe)?;
+        Ok(u32_at(&body, 4).zip(u32_at(&body, 8)))
+    }
+
+    /// The data of an item, its extents joined.
+    pub fn read_item<R: Read + Seek>(&self, reader: &mut R, item: &HeifItem) -> Result<Vec<u8>> {
+        if item.size() > MAX_BODY_SIZE {
+            return Err(Error::malformed("the HEIF item is too large to read"));
+        }
+        let mut data = Vec::with_capacity(item.size() as usize);
+        for extent in &item.extents {
+            let part = read_at(reader, extent.start, extent.end - extent.start)?;
+            if (part.len() as u64) < extent.end - extent.start {
+                return Err(Error::malformed("truncated HEIF item"));
+            }
+            data.extend(part);
+        }
+        Ok(data)
+    }
+}
+
+fn read_item_infos<R: Read + Seek>(reader: &mut R, iinf: &BoxHeader) -> Result<Vec<HeifItem>> {
+    let head = read_at(reader, iinf.body().start, 8)?;
+    let version = head.first().copied().unwrap_or(0);
+    let entries_start = iinf.body().start + if version == 0 { 6 } else { 8 };
+    let mut items = Vec::new();
+    for infe in children(reader, entries_start..iinf.end())? {
+        if !infe.is(b"infe") {
+            continue;
+        }
+        let body = read_body(reader, &infe)?;
+        let mut cursor = FullBox::new(&body);
+        // Versions 0 and 1 carry no item type, and so no images.
+        let id = match cursor.version {
+            2 => cursor.u16().map(u32::from),
+            3 => cursor.u32(),
+            _ => continue,
+        };
+        let kind = cursor.skip(2).and_then(|cursor| cursor.bytes(4));
+        if let (Some(id), Some(kind)) = (id, kind) {
+            items.push(HeifItem {
+                id,
+                kind: [kind[0], kind[1], kind[2], kind[3]],
+                extents: Vec::new(),
+                properties: Vec::new(),
+            });
+        }
+    }
+    Ok(items)
+}
+
+/// An item, the construction method of its location and its extents.
+type ItemLocation = (u32, u8, Vec<Range<u64>>);
+
+/// The item locations of an `iloc` body.
+fn read_locations(body: &[u8]) -> Result<Vec<ItemLocation>> {
+    let truncated = || Error::malformed("truncated iloc box");
+    let mut cursor = FullBox::new(body);
+    let version = cursor.version;
+    let sizes = cursor.u16().ok_or_else(truncated)?;
+    let offset_size = (sizes >> 12) as usize;
+    let length_size = (sizes >> 8 & 0xF) as usize;
+    let base_offset_size = (sizes >> 4 & 0xF) as usize;
+    let index_size = if version >= 1 {
+        (sizes & 0xF) as usize
+    } else {
+        0
+    };
+    let count = if version < 2 {
+        cursor.u16().map(u32::from)
+    } else {
+        cursor.u32()
+    }
+    .ok_or_else(truncated)?;
+    let mut locations = Vec::new();
+    for _ in 0..count {
+        let id = cursor.id_of_version(version < 2).ok_or_else(truncated)?;
+        let construction = if version >= 1 {
+            (cursor.u16().ok_or_else(truncated)? & 0xF) as u8
+        } else {
+            0
+        };
+        cursor.skip(2).ok_or_else(truncated)?;
+        let base = cursor.sized(base_offset_size).ok_or_else(truncated)?;
+        let extent_count = cursor.u16().ok_or_else(truncated)?;
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            cursor.sized(index_size).ok_or_else(truncated)?;
+            let offset = cursor.sized(offset_size).ok_or_else(truncated)?;
+            let length = cursor.sized(length_size).ok_or_else(truncated)?;
+            let start = base + offset;
+            extents.push(start..start + length);
+        }
+        locations.push((id, construction, extents));
+    }
+    Ok(locations)
+}
+
+fn read_references(body: &[u8]) -> Vec<HeifReference> {
+    let version = body.first().copied().unwrap_or(0);
+    let mut references = Vec::new();
+    let mut offset = 4;
+    while let Some(size) = u32_at(body, offset) {
+        let size = size as usize;
+        let end = match offset.checked_add(size) {
+            Some(end) if size >= 8 && end <= body.len() => end,
+            _ => break,
+        };
+        let mut kind = [0; 4];
+        kind.copy_from_slice(&body[offset + 4..offset + 8]);
+        let mut cursor = FullBox {
+            data: &body[offset + 8..end],
+            position: 0,
+            version,
+            flags: 0,
+        };
+        if let Some(from) = cursor.id_of_version(version == 0) {
+            let count = cursor.u16().unwrap_or(0);
+            let to = (0..count)
+                .map_while(|_| cursor.id_of_version(version == 0))
+                .collect();
+            references.push(HeifReference { kind, from, to });
+        }
+        offset = end;
+    }
+    references
+}
+
+/// The property indexes, from 0, associated with each item by an `ipma` body.
+fn read_associations(body: &[u8]) -> Vec<(u32, Vec<usize>)> {
+    let mut cursor = FullBox::new(body);
+    let large_indexes = cursor.flags & 1 != 0;
+    let version = cursor.version;
+    let count = cursor.u32().unwrap_or(0);
+    let mut associations = Vec::new();
+    for _ in 0..count {
+        let id = match cursor.id_of_version(version < 1) {
+            Some(id) => id,
+            None => break,
+        };
+        let property_count = cursor.u8().unwrap_or(0);
+        let mut properties = Vec::new();
+        for _ in 0..property_count {
+            // The top bit marks essential properties; indexes start at 1, and 0 means none.
+            let index = if large_indexes {
+                cursor.u16().map(|index| usize::from(index & 0x7FFF))
+            } else {
+                cursor.u8().map(|index| usize::from(index & 0x7F))
+            };
+            match index {
+                Some(0) => {}
+                Some(index) => properties.push(index - 1),
+                None => break,
+            }
+        }
+        associations.push((id, properties));
+    }
+    associations
+}
+
+/// A cursor over the body of a full box, after its version and flags.
+struct FullBox<'a> {
+    data: &'a [u8],
+    position: usize,
+    version: u8,
+    flags: u32,
+}
+
+impl<'a> FullBox<'a> {
+    fn new(body: &'a [u8]) -> FullBox<'a> {
+        FullBox {
+            data: body,
+            position: 4.min(body.len()),
+            version: body.first().copied().unwrap_or(0),
+            flags: u32_at(body, 0).unwrap_or(0) & 0xFF_FFFF,
+        }
+    }
+
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.checked_add(count)?)?;
+        self.position += count;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, count: usize) -> Option<&mut FullBox<'a>> {
+        self.bytes(count)?;
+        Some(self)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// An item ID, 16 bits wide in version 0 boxes and 32 bits in later ones.
+    fn id(&mut self) -> Option<u32> {
+        self.id_of_version(self.version == 0)
+    }
+
+    fn id_of_version(&mut self, short: bool) -> Option<u32> {
+        if short {
+            self.u16().map(u32::from)
+        } else {
+            self.u32()
+        }
+    }
+
+    /// An unsigned number of 0, 4 or 8 bytes, as sized in `iloc` boxes.
+    fn sized(&mut self, size: usize) -> Option<u64> {
+        match size {
+            0 => Some(0),
+            4 => self.u32().map(u64::from),
+            8 => self
+                .bytes(8)
+                .map(|b| u64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])),
+            _ => None,
+        }
+    }
+}
+
+/// Up to `length` bytes at `offset`, fewer at the end of the data.
+pub(crate) fn read_at<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    length: u64,
+) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::new();
+    reader.by_ref().take(length).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+pub(crate) fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub(crate) fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    let b = data.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_be_bytes([
+        b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+    ]))
+}
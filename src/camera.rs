@@ -0,0 +1,262 @@
+//! Cross-platform camera model.
+//!
+//! `CameraDevice` offers the operations of `ICCameraDevice` on backends implemented in Rust,
+//! such as PTP/IP cameras, so that the same code can browse, download, delete and capture
+//! regardless of how the camera is connected.
+
+use crate::constants::ICEXIFOrientationType;
+use crate::download::{DownloadOptions, DownloadResult};
+use crate::error::{Error, Result};
+use crate::ptp::{DevicePropCode, Property, PropertyValue};
+use crate::uti;
+use bitflags::bitflags;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+bitflags! {
+    /// Camera capabilities, mirroring the `ICCameraDeviceCan*` capability constants.
+    pub struct CameraCapabilities: u32 {
+        /// The camera can capture a picture when `take_picture` is called.
+        const ICCameraDeviceCanTakePicture = 0x0000_0001;
+        /// The camera reports pictures taken with its own shutter release.
+        const ICCameraDeviceCanTakePictureUsingShutterReleaseOnCamera = 0x0000_0002;
+        /// The camera can delete a file at a time.
+        const ICCameraDeviceCanDeleteOneFile = 0x0000_0004;
+        /// The camera can delete all files in a single operation.
+        const ICCameraDeviceCanDeleteAllFiles = 0x0000_0008;
+        /// The camera can synchronize its date and time with that of the host computer.
+        const ICCameraDeviceCanSyncClock = 0x0000_0010;
+        /// The host can upload files to the camera.
+        const ICCameraDeviceCanReceiveFile = 0x0000_0020;
+        /// The camera can accept PTP commands.
+        const ICCameraDeviceCanAcceptPTPCommands = 0x0000_0040;
+    }
+}
+
+/// A folder on a camera, the counterpart of `ICCameraFolder`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CameraFolder {
+    /// Name of this folder.
+    pub name: String,
+    /// Path of the parent folder relative to the storage root, or `None` for a storage.
+    pub parent_folder: Option<String>,
+    /// Indicates the protection state of this folder.
+    pub is_locked: bool,
+    /// Creation date of this folder.
+    pub creation_date: Option<SystemTime>,
+    /// Modification date of this folder.
+    pub modification_date: Option<SystemTime>,
+    /// PTP object handle value if the folder is on a camera that uses PTP protocol.
+    pub ptp_object_handle: Option<u32>,
+    /// The file system path of the folder on a mass-storage device.
+    pub file_system_path: Option<PathBuf>,
+    /// A list of items contained by this folder.
+    pub contents: Vec<CameraItem>,
+}
+
+impl CameraFolder {
+    /// Path of this folder relative to the storage root.
+    pub fn path(&self) -> String {
+        join_path(self.parent_folder.as_ref(), &self.name)
+    }
+}
+
+/// A file on a camera, the counterpart of `ICCameraFile`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CameraFile {
+    /// Name of this file.
+    pub name: String,
+    /// Path of the parent folder relative to the storage root.
+    pub parent_folder: Option<String>,
+    /// Item UTI. This is an Uniform Type Identifier string.
+    pub uti: String,
+    /// Size of file in bytes, or 0 if the camera did not report it, as PTP cameras do for
+    /// files of 4 GiB and more.
+    pub file_size: u64,
+    /// Creation date of this file.
+    pub creation_date: Option<SystemTime>,
+    /// Modification date of this file.
+    pub modification_date: Option<SystemTime>,
+    /// Indicates if the file is a raw image file.
+    pub is_raw: bool,
+    /// Indicates the protection state of this file.
+    pub is_locked: bool,
+    /// Desired orientation of image to use when it is downloaded.
+    pub orientation: Option<ICEXIFOrientationType>,
+    /// Duration of audio/video file in seconds.
+    pub duration: Option<f64>,
+    /// PTP object handle value if the file is on a camera that uses PTP protocol.
+    pub ptp_object_handle: Option<u32>,
+    /// The file system path of the file on a mass-storage device.
+    pub file_system_path: Option<PathBuf>,
+    /// Set if the file was captured after the device's content was fully enumerated.
+    pub was_added_after_content_catalog_completed: bool,
+    /// Sidecar files associated with this file, such as an XMP file with the same base name.
+    pub sidecar_files: Vec<CameraFile>,
+}
+
+impl CameraFile {
+    /// Whether the UTI of this file is `uti` or conforms to it, such as `public.image`.
+    pub fn conforms_to(&self, uti: &str) -> bool {
+        uti::conforms_to(&self.uti, uti)
+    }
+
+    /// Path of this file relative to the storage root.
+    pub fn path(&self) -> String {
+        join_path(self.parent_folder.as_ref(), &self.name)
+    }
+}
+
+/// An item on a camera, the counterpart of `ICCameraItem`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CameraItem {
+    Folder(CameraFolder),
+    File(CameraFile),
+}
+
+impl CameraItem {
+    /// Name of this item.
+    pub fn name(&self) -> &str {
+        match self {
+            CameraItem::Folder(folder) => &folder.name,
+            CameraItem::File(file) => &file.name,
+        }
+    }
+}
+
+/// Notifications sent by a camera, mirroring the `ICCameraDeviceDelegate` callbacks.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CameraEvent {
+    /// Items were added to the camera, for example by a capture.
+    ItemsAdded(Vec<CameraFile>),
+    /// Items were removed from the camera.
+    ItemsRemoved(Vec<CameraFile>),
+    /// The value of a device property changed.
+    PropertyChanged(u16),
+    /// A capture started with `take_picture` has completed.
+    CaptureComplete,
+    /// The camera was disconnected.
+    DeviceRemoved,
+}
+
+/// Operations of `ICCameraDevice` available on every backend.
+///
+/// Optional operations return `Error::Unsupported` unless the backend reports the
+/// matching capability.
+pub trait CameraDevice: Send + Sync {
+    /// Name of the device.
+    fn name(&self) -> String;
+    /// The capabilities of the device.
+    fn capabilities(&self) -> CameraCapabilities;
+    /// The serial number of the device, if it provides one.
+    fn serial_number_string(&self) -> Option<String> {
+        None
+    }
+    /// A string that identifies this device across connections.
+    fn persistent_id_string(&self) -> String {
+        self.serial_number_string().unwrap_or_else(|| self.name())
+    }
+    /// The battery charge level from 0 to 100, if the device reports one.
+    fn battery_level(&self) -> Option<u8> {
+        None
+    }
+    /// The time offset, in seconds, between the camera's clock and the computer's clock.
+    /// This value is positive if the camera's clock is ahead of the computer's clock.
+    fn time_offset(&self) -> Option<f64> {
+        None
+    }
+    /// Free space in bytes on the storages of the device, if it reports it.
+    fn free_space(&self) -> Option<u64> {
+        None
+    }
+    /// Contents of the camera. Each item in this list corresponds to a storage on the camera.
+    fn contents(&self) -> Result<Vec<CameraItem>>;
+    /// All image, movie and audio files on the camera, without regard to the folder hierarchy.
+    fn media_files(&self) -> Result<Vec<CameraFile>> {
+        let mut files = Vec::new();
+        collect_files(&self.contents()?, &mut files);
+        Ok(files)
+    }
+    /// The media files whose UTI conforms to `uti`, such as `public.camera-raw-image`, the
+    /// counterpart of `filesOfType`.
+    fn files_of_type(&self, uti: &str) -> Result<Vec<CameraFile>> {
+        let mut files = self.media_files()?;
+        files.retain(|file| file.conforms_to(uti));
+        Ok(files)
+    }
+    /// Download a file from the camera into `writer`, returning the number of bytes written.
+    fn download_file(&self, file: &CameraFile, writer: &mut dyn Write) -> Result<u64>;
+    /// Download a file into a directory as described by `options`.
+    fn download(&self, file: &CameraFile, options: &DownloadOptions) -> Result<DownloadResult> {
+        crate::download::download(self, file, options)
+    }
+    /// Read up to `length` bytes of a file starting at `offset`.
+    fn read_data_from_file(&self, file: &CameraFile, offset: u64, length: u64) -> Result<Vec<u8>>;
+    /// Delete files from the camera.
+    fn delete_files(&self, _files: &[CameraFile]) -> Result<()> {
+        Err(Error::Unsupported("delete".into()))
+    }
+    /// Upload a file at `path` to the camera into `folder`, a path relative to the storage root.
+    fn upload_file(&self, _path: &Path, _folder: Option<&str>) -> Result<CameraFile> {
+        Err(Error::Unsupported("upload".into()))
+    }
+    /// Capture a new image using the camera.
+    fn take_picture(&self) -> Result<()> {
+        Err(Error::Unsupported("take picture".into()))
+    }
+    /// Enable tethered capture on the camera.
+    fn enable_tethering(&self) -> Result<()> {
+        Err(Error::Unsupported("tethering".into()))
+    }
+    /// Disable tethered capture on the camera.
+    fn disable_tethering(&self) -> Result<()> {
+        Err(Error::Unsupported("tethering".into()))
+    }
+    /// Synchronize camera's clock with the computer's clock.
+    fn sync_clock(&self) -> Result<()> {
+        Err(Error::Unsupported("clock synchronization".into()))
+    }
+    /// A device property, such as the aperture or exposure compensation, and the values it
+    /// may take.
+    fn property(&self, code: DevicePropCode) -> Result<Property> {
+        Err(Error::Unsupported(format!("property {}", code)))
+    }
+    /// Set a device property. The camera reports the new value with a `PropertyChanged`
+    /// event.
+    fn set_property_value(&self, code: DevicePropCode, _value: &PropertyValue) -> Result<()> {
+        Err(Error::Unsupported(format!("property {}", code)))
+    }
+    /// Wait up to `timeout` for the next notification from the camera.
+    fn next_event(&self, _timeout: Duration) -> Result<Option<CameraEvent>> {
+        Ok(None)
+    }
+}
+
+/// Append all files in `items` and their sub-folders to `files`.
+pub fn collect_files(items: &[CameraItem], files: &mut Vec<CameraFile>) {
+    for item in items {
+        match item {
+            CameraItem::Folder(folder) => collect_files(&folder.contents, files),
+            CameraItem::File(file) => files.push(file.clone()),
+        }
+    }
+}
+
+fn join_path(parent: Option<&String>, name: &str) -> String {
+    match parent {
+        Some(parent) if !parent.is_empty() => format!("{}/{}", parent, name),
+        _ => name.to_string(),
+    }
+}
+
+/// Best-effort UTI and RAW flag for a file name, based on its extension.
+pub(crate) fn file_type(name: &str) -> (String, bool) {
+    match uti::for_file_name(name) {
+        Some(ty) => (
+            ty.identifier.to_string(),
+            ty.conforms_to(uti::CAMERA_RAW_IMAGE),
+        ),
+        None => (uti::DATA.to_string(), false),
+    }
+}
@@ -0,0 +1,303 @@
+//! Calendar date and time without a time zone, as recorded by camera clocks.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A calendar date and time with one-second resolution and no time zone.
+///
+/// Conversions to and from `SystemTime` treat the value as UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Build a date and time, returning `None` if any field is out of range.
+    pub fn new(
+        year: i32,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Option<DateTime> {
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        Some(DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    /// The date and time `secs` seconds after 1970-01-01 00:00:00.
+    pub fn from_unix(secs: i64) -> DateTime {
+        let days = secs.div_euclid(86_400);
+        let rem = secs.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem % 3600 / 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    /// Seconds since 1970-01-01 00:00:00.
+    pub fn to_unix(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * 86_400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second)
+    }
+
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => DateTime::from_unix(after.as_secs() as i64),
+            Err(before) => {
+                let before = before.duration();
+                let mut secs = -(before.as_secs() as i64);
+                if before.subsec_nanos() > 0 {
+                    secs -= 1;
+                }
+                DateTime::from_unix(secs)
+            }
+        }
+    }
+
+    pub fn to_system_time(&self) -> SystemTime {
+        let secs = self.to_unix();
+        if secs >= 0 {
+            UNIX_EPOCH + Duration::from_secs(secs as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+        }
+    }
+
+    /// This date and time shifted by a number of seconds.
+    pub fn add_seconds(&self, secs: i64) -> DateTime {
+        DateTime::from_unix(self.to_unix() + secs)
+    }
+
+    /// Day of the week, 0 for Sunday through 6 for Saturday.
+    pub fn weekday(&self) -> u8 {
+        (days_from_civil(self.year, self.month, self.day) + 4).rem_euclid(7) as u8
+    }
+
+    /// Parse the PTP form `YYYYMMDDThhmmss[.s][Z|±hhmm]`.
+    ///
+    /// A UTC offset, if present, is applied so that the result is in UTC.
+    pub fn parse_ptp(value: &str) -> Option<DateTime> {
+        let bytes = value.as_bytes();
+        if bytes.len() < 15 || bytes[8] != b'T' {
+            return None;
+        }
+        let date = DateTime::new(
+            digits(&bytes[0..4])? as i32,
+            digits(&bytes[4..6])? as u8,
+            digits(&bytes[6..8])? as u8,
+            digits(&bytes[9..11])? as u8,
+            digits(&bytes[11..13])? as u8,
+            digits(&bytes[13..15])? as u8,
+        )?;
+        let mut rest = &bytes[15..];
+        if rest.first() == Some(&b'.') {
+            rest = &rest[2.min(rest.len())..];
+        }
+        match rest {
+            [] | [b'Z'] => Some(date),
+            [sign @ b'+', ..] | [sign @ b'-', ..] if rest.len() == 5 => {
+                let offset = digits(&rest[1..3])? as i64 * 3600 + digits(&rest[3..5])? as i64 * 60;
+                Some(date.add_seconds(if *sign == b'+' { -offset } else { offset }))
+            }
+            _ => None,
+        }
+    }
+
+    /// Parse the ISO 8601 form `YYYY-MM-DDThh:mm:ss[.s][Z|±hh:mm]` used by GPX and XMP,
+    /// returning the date as written and its UTC offset, if present. Fractions of a second
+    /// are dropped.
+    pub fn parse_iso8601(value: &str) -> Option<(DateTime, Option<UtcOffset>)> {
+        let bytes = value.trim().as_bytes();
+        if bytes.len() < 19
+            || bytes[4] != b'-'
+            || bytes[7] != b'-'
+            || !matches!(bytes[10], b'T' | b't' | b' ')
+            || bytes[13] != b':'
+            || bytes[16] != b':'
+        {
+            return None;
+        }
+        let date = DateTime::new(
+            digits(&bytes[0..4])? as i32,
+            digits(&bytes[5..7])? as u8,
+            digits(&bytes[8..10])? as u8,
+            digits(&bytes[11..13])? as u8,
+            digits(&bytes[14..16])? as u8,
+            digits(&bytes[17..19])? as u8,
+        )?;
+        let mut rest = &bytes[19..];
+        if rest.first() == Some(&b'.') {
+            let fraction = rest[1..].iter().take_while(|b| b.is_ascii_digit()).count();
+            rest = &rest[1 + fraction..];
+        }
+        match rest {
+            [] => Some((date, None)),
+            _ => Some((date, Some(UtcOffset::parse(std::str::from_utf8(rest).ok()?)?))),
+        }
+    }
+
+    /// Format as the PTP form `YYYYMMDDThhmmss`.
+    pub fn to_ptp_string(&self) -> String {
+        format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// Formats as `YYYY-MM-DD hh:mm:ss`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// The difference between a local time and UTC, as recorded in EXIF `OffsetTime` fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UtcOffset {
+    /// Seconds east of UTC.
+    pub seconds: i32,
+}
+
+impl UtcOffset {
+    pub const UTC: UtcOffset = UtcOffset { seconds: 0 };
+
+    /// An offset of `hours` and `minutes` from UTC, within ±18 hours. The sign of `hours`, or
+    /// of `minutes` when `hours` is zero, gives the direction: negative is west of UTC.
+    pub fn new(hours: i32, minutes: i32) -> Option<UtcOffset> {
+        let sign = if hours < 0 || (hours == 0 && minutes < 0) {
+            -1
+        } else {
+            1
+        };
+        let seconds = sign * (hours.abs() * 3600 + minutes.abs() * 60);
+        if seconds.abs() > 18 * 3600 {
+            return None;
+        }
+        Some(UtcOffset { seconds })
+    }
+
+    /// Parse `+02:00`, `-0530`, `+09`, `Z` or `UTC`.
+    pub fn parse(value: &str) -> Option<UtcOffset> {
+        let value = value.trim();
+        if value == "Z" || value.eq_ignore_ascii_case("UTC") {
+            return Some(UtcOffset::UTC);
+        }
+        let bytes = value.as_bytes();
+        let sign = match bytes.first()? {
+            b'+' => 1,
+            b'-' => -1,
+            _ => return None,
+        };
+        let (hours, minutes) = match &bytes[1..] {
+            [h1, h2] => (&[*h1, *h2][..], &b"00"[..]),
+            [h1, h2, m1, m2] | [h1, h2, b':', m1, m2] => (&[*h1, *h2][..], &[*m1, *m2][..]),
+            _ => return None,
+        };
+        let hours = digits(hours)? as i32;
+        let minutes = digits(minutes)? as i32;
+        if minutes > 59 {
+            return None;
+        }
+        UtcOffset::new(sign * hours, sign * minutes)
+    }
+
+    /// The local date and time at `utc`.
+    pub fn to_local(self, utc: DateTime) -> DateTime {
+        utc.add_seconds(i64::from(self.seconds))
+    }
+
+    /// The UTC date and time of the local `local`.
+    pub fn to_utc(self, local: DateTime) -> DateTime {
+        local.add_seconds(-i64::from(self.seconds))
+    }
+}
+
+impl fmt::Display for UtcOffset {
+    /// Formats as `+02:00`, the form of EXIF `OffsetTime` fields.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.seconds < 0 { '-' } else { '+' };
+        let minutes = self.seconds.abs() / 60;
+        write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+    }
+}
+
+pub(crate) fn digits(bytes: &[u8]) -> Option<u32> {
+    if bytes.is_empty() {
+        return None;
+    }
+    bytes.iter().try_fold(0u32, |acc, &b| {
+        if b.is_ascii_digit() {
+            Some(acc * 10 + u32::from(b - b'0'))
+        } else {
+            None
+        }
+    })
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+pub(crate) fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+// Howard Hinnant's days-from-civil algorithm.
+fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    let y = i64::from(year) - if month <= 2 { 1 } else { 0 };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = i64::from(month);
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year as i32, month, day)
+}
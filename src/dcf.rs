@@ -0,0 +1,549 @@
+//! Folder and file naming of the Design rule for Camera File system (DCF).
+//!
+//! DCF folders live in the `DCIM` folder and are named with a folder number from 100 to 999
+//! followed by five free characters, such as `100CANON`. Files are named with four free
+//! characters followed by a file number from 0001 to 9999, such as `IMG_0001.JPG`. Files
+//! sharing a folder and a file number form a single DCF object, such as a JPEG and its
+//! RAW or THM counterpart.
+
+use crate::camera::{CameraFile, CameraFolder, CameraItem};
+use crate::error::{Error, Result};
+use crate::uti;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Lowest DCF folder number.
+pub const FIRST_FOLDER_NUMBER: u16 = 100;
+/// Highest DCF folder number.
+pub const LAST_FOLDER_NUMBER: u16 = 999;
+/// Lowest DCF file number.
+pub const FIRST_FILE_NUMBER: u16 = 1;
+/// Highest DCF file number.
+pub const LAST_FILE_NUMBER: u16 = 9999;
+
+/// Free characters of folders created for uploads when the card has no DCF folder yet.
+const DEFAULT_FREE_CHARS: &str = "IMAGE";
+/// Free characters of uploaded files whose original name is not a DCF name.
+const DEFAULT_PREFIX: &str = "IMG_";
+/// Extension of uploaded files without a usable extension of their own.
+const DEFAULT_EXTENSION: &str = "DAT";
+
+/// A DCF folder name, such as `100CANON`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DcfFolderName {
+    /// Folder number, from 100 to 999.
+    pub number: u16,
+    /// The five free characters following the number.
+    pub free_chars: String,
+}
+
+impl DcfFolderName {
+    /// A folder name from its parts, checking them against the DCF rules.
+    pub fn new(number: u16, free_chars: &str) -> Result<DcfFolderName> {
+        let name = format!("{:03}{}", number, free_chars);
+        DcfFolderName::parse(&name)
+            .ok_or_else(|| Error::InvalidValue(format!("{} is not a DCF folder name", name)))
+    }
+
+    /// Parse a folder name, returning `None` if it is not DCF compliant.
+    pub fn parse(name: &str) -> Option<DcfFolderName> {
+        let bytes = name.as_bytes();
+        if bytes.len() != 8 || !bytes[..3].iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        if !bytes[3..].iter().all(|&b| is_free_char(b)) {
+            return None;
+        }
+        let number = name[..3].parse().ok()?;
+        if !(FIRST_FOLDER_NUMBER..=LAST_FOLDER_NUMBER).contains(&number) {
+            return None;
+        }
+        Some(DcfFolderName {
+            number,
+            free_chars: name[3..].to_ascii_uppercase(),
+        })
+    }
+}
+
+impl fmt::Display for DcfFolderName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:03}{}", self.number, self.free_chars)
+    }
+}
+
+/// A DCF file name, such as `IMG_0001.JPG`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DcfFileName {
+    /// The four free characters preceding the number.
+    pub prefix: String,
+    /// File number, from 1 to 9999.
+    pub number: u16,
+    /// Extension, without the dot.
+    pub extension: String,
+}
+
+impl DcfFileName {
+    /// A file name from its parts, checking them against the DCF rules.
+    pub fn new(prefix: &str, number: u16, extension: &str) -> Result<DcfFileName> {
+        let name = format!("{}{:04}.{}", prefix, number, extension);
+        DcfFileName::parse(&name)
+            .ok_or_else(|| Error::InvalidValue(format!("{} is not a DCF file name", name)))
+    }
+
+    /// Parse a file name, returning `None` if it is not DCF compliant.
+    pub fn parse(name: &str) -> Option<DcfFileName> {
+        let (base, extension) = name.split_once('.')?;
+        let bytes = base.as_bytes();
+        if bytes.len() != 8 || !bytes[..4].iter().all(|&b| is_free_char(b)) {
+            return None;
+        }
+        if !bytes[4..].iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        if !is_extension(extension) {
+            return None;
+        }
+        let number = base[4..].parse().ok()?;
+        if !(FIRST_FILE_NUMBER..=LAST_FILE_NUMBER).contains(&number) {
+            return None;
+        }
+        Some(DcfFileName {
+            prefix: base[..4].to_ascii_uppercase(),
+            number,
+            extension: extension.to_ascii_uppercase(),
+        })
+    }
+
+    /// The name without its extension, such as `IMG_0001`.
+    pub fn base_name(&self) -> String {
+        format!("{}{:04}", self.prefix, self.number)
+    }
+
+    /// Whether the name marks an image in the Adobe RGB option color space, whose names
+    /// start with an underscore.
+    pub fn is_adobe_rgb(&self) -> bool {
+        self.prefix.starts_with('_')
+    }
+}
+
+impl fmt::Display for DcfFileName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.base_name(), self.extension)
+    }
+}
+
+/// Files sharing a DCF folder and a file number.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DcfObject {
+    pub folder: DcfFolderName,
+    pub number: u16,
+    /// The files of the object, sorted by name.
+    pub files: Vec<CameraFile>,
+}
+
+impl DcfObject {
+    /// The DCF base name of the object, such as `IMG_0001`, taken from its first file.
+    pub fn base_name(&self) -> String {
+        self.files
+            .iter()
+            .find_map(|file| DcfFileName::parse(&file.name))
+            .map(|name| name.base_name())
+            .unwrap_or_default()
+    }
+}
+
+/// Where to upload a file so that its name does not collide with the camera's own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UploadTarget {
+    /// Path of the folder relative to the storage root, as passed to `upload_file`.
+    pub folder: String,
+    /// Whether the folder has to be created first.
+    pub create_folder: bool,
+    /// Name to give the uploaded file.
+    pub name: DcfFileName,
+}
+
+/// Whether the item is a DCF folder inside a `DCIM` folder.
+pub fn is_dcf_folder(folder: &CameraFolder) -> bool {
+    dcf_folder_name(folder).is_some()
+}
+
+/// Whether the file has a DCF name and is inside a DCF folder.
+pub fn is_dcf_file(file: &CameraFile) -> bool {
+    let parent = match &file.parent_folder {
+        Some(parent) => parent,
+        None => return false,
+    };
+    let mut components = parent.rsplit('/');
+    let folder = components.next().and_then(DcfFolderName::parse);
+    let dcim = components.next().is_some_and(is_dcim);
+    folder.is_some() && dcim && DcfFileName::parse(&file.name).is_some()
+}
+
+/// Group DCF files into DCF objects, returning the objects and the files that are not DCF.
+///
+/// Objects are ordered by folder number and file number.
+pub fn group_objects(files: &[CameraFile]) -> (Vec<DcfObject>, Vec<CameraFile>) {
+    let mut objects: BTreeMap<(u16, String, u16), DcfObject> = BTreeMap::new();
+    let mut foreign = Vec::new();
+    for file in files {
+        if !is_dcf_file(file) {
+            foreign.push(file.clone());
+            continue;
+        }
+        let parent = file.parent_folder.as_deref().unwrap_or_default();
+        let folder = parent.rsplit('/').next().and_then(DcfFolderName::parse);
+        let name = DcfFileName::parse(&file.name);
+        if let (Some(folder), Some(name)) = (folder, name) {
+            objects
+                .entry((folder.number, parent.to_string(), name.number))
+                .or_insert_with(|| DcfObject {
+                    folder,
+                    number: name.number,
+                    files: Vec::new(),
+                })
+                .files
+                .push(file.clone());
+        }
+    }
+    let objects = objects
+        .into_values()
+        .map(|mut object| {
+            object.files.sort_by(|a, b| a.name.cmp(&b.name));
+            object
+        })
+        .collect();
+    (objects, foreign)
+}
+
+/// The DCF folders of a device's contents, by path relative to the storage root.
+pub fn folders(contents: &[CameraItem]) -> Vec<(String, DcfFolderName)> {
+    let mut found = Vec::new();
+    visit_folders(contents, &mut |folder| {
+        if let Some(name) = dcf_folder_name(folder) {
+            found.push((folder.path(), name));
+        }
+    });
+    found.sort_by(|a, b| a.1.cmp(&b.1));
+    found
+}
+
+/// The number a new DCF folder should get, or `None` if all numbers are used.
+pub fn next_folder_number(contents: &[CameraItem]) -> Option<u16> {
+    match folders(contents).iter().map(|(_, name)| name.number).max() {
+        Some(LAST_FOLDER_NUMBER) => None,
+        Some(number) => Some(number + 1),
+        None => Some(FIRST_FOLDER_NUMBER),
+    }
+}
+
+/// The number a new file in the DCF folder at `folder_path` should get, or `None` if the
+/// folder is full.
+pub fn next_file_number(contents: &[CameraItem], folder_path: &str) -> Option<u16> {
+    let mut highest = 0;
+    visit_folders(contents, &mut |folder| {
+        if folder.path() == folder_path {
+            for item in &folder.contents {
+                if let Some(name) = DcfFileName::parse(item.name()) {
+                    highest = highest.max(name.number);
+                }
+            }
+        }
+    });
+    if highest >= LAST_FILE_NUMBER {
+        None
+    } else {
+        Some(highest + 1)
+    }
+}
+
+/// Choose a folder and a DCF name for a file named `original_name` uploaded to a device.
+///
+/// The file goes to the highest-numbered DCF folder with the next free file number,
+/// or to a new folder when that one is full. DCF names keep their free characters;
+/// other names get `IMG_` and the preferred extension of their type, such as `JPG` for
+/// `.jpeg`, or `DAT` when their own extension is missing or too long for DCF.
+pub fn upload_target(contents: &[CameraItem], original_name: &str) -> Result<UploadTarget> {
+    let (prefix, extension) = match DcfFileName::parse(original_name) {
+        Some(name) => (name.prefix, name.extension),
+        None => (DEFAULT_PREFIX.to_string(), upload_extension(original_name)),
+    };
+    let all_folders = folders(contents);
+    if let Some((path, _)) = all_folders.last() {
+        if let Some(number) = next_file_number(contents, path) {
+            return Ok(UploadTarget {
+                folder: path.clone(),
+                create_folder: false,
+                name: DcfFileName::new(&prefix, number, &extension)?,
+            });
+        }
+    }
+    let number = next_folder_number(contents)
+        .ok_or_else(|| Error::InvalidValue("no DCF folder number is free".into()))?;
+    let free_chars = all_folders
+        .last()
+        .map_or(DEFAULT_FREE_CHARS, |(_, name)| name.free_chars.as_str());
+    let folder = DcfFolderName::new(number, free_chars)?;
+    let dcim = dcim_path(contents)
+        .ok_or_else(|| Error::InvalidValue("the device has no DCIM folder".into()))?;
+    Ok(UploadTarget {
+        folder: format!("{}/{}", dcim, folder),
+        create_folder: true,
+        name: DcfFileName::new(&prefix, FIRST_FILE_NUMBER, &extension)?,
+    })
+}
+
+/// The DCF extension of a file named `original_name`, which is not a DCF name.
+fn upload_extension(original_name: &str) -> String {
+    let extension = original_name
+        .rsplit_once('.')
+        .map_or("", |(_, extension)| extension);
+    let preferred = uti::for_extension(extension)
+        .and_then(|ty| ty.extensions.iter().copied().find(|e| is_extension(e)));
+    match preferred {
+        Some(preferred) => preferred.to_ascii_uppercase(),
+        None if is_extension(extension) => extension.to_ascii_uppercase(),
+        None => DEFAULT_EXTENSION.to_string(),
+    }
+}
+
+/// DCF extensions have three characters; four are accepted for later formats like HEIC.
+fn is_extension(extension: &str) -> bool {
+    (3..=4).contains(&extension.len()) && extension.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+/// Folder names and file prefixes allow upper-case letters, digits and underscores.
+/// Lower case is accepted for file systems that do not preserve case.
+fn is_free_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+fn is_dcim(name: &str) -> bool {
+    name.eq_ignore_ascii_case("DCIM")
+}
+
+fn dcf_folder_name(folder: &CameraFolder) -> Option<DcfFolderName> {
+    let parent = folder.parent_folder.as_deref()?;
+    if !is_dcim(parent.rsplit('/').next()?) {
+        return None;
+    }
+    DcfFolderName::parse(&folder.name)
+}
+
+fn dcim_path(contents: &[CameraItem]) -> Option<String> {
+    let mut found = None;
+    visit_folders(contents, &mut |folder| {
+        if found.is_none() && is_dcim(&folder.name) {
+            found = Some(folder.path());
+        }
+    });
+    found
+}
+
+fn visit_folders(items: &[CameraItem], visit: &mut dyn FnMut(&CameraFolder)) {
+    for item in items {
+        if let CameraItem::Folder(folder) = item {
+            visit(folder);
+            visit_folders(&folder.contents, visit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(name: &str, parent: &str, contents: Vec<CameraItem>) -> CameraItem {
+        CameraItem::Folder(CameraFolder {
+            name: name.to_string(),
+            parent_folder: Some(parent.to_string()).filter(|parent| !parent.is_empty()),
+            contents,
+            ..CameraFolder::default()
+        })
+    }
+
+    fn file(name: &str, parent: &str) -> CameraFile {
+        CameraFile {
+            name: name.to_string(),
+            parent_folder: Some(parent.to_string()),
+            ..CameraFile::default()
+        }
+    }
+
+    /// A card with the DCF folders `folders`, each holding files numbered `numbers`.
+    fn card(folders: &[&str], numbers: &[u16]) -> Vec<CameraItem> {
+        let dcf_folders = folders
+            .iter()
+            .map(|name| {
+                let parent = format!("CARD/DCIM/{}", name);
+                let files = numbers
+                    .iter()
+                    .map(|number| {
+                        CameraItem::File(file(&format!("IMG_{:04}.JPG", number), &parent))
+                    })
+                    .collect();
+                folder(name, "CARD/DCIM", files)
+            })
+            .collect();
+        vec![folder(
+            "CARD",
+            "",
+            vec![folder("DCIM", "CARD", dcf_folders)],
+        )]
+    }
+
+    #[test]
+    fn folder_names_have_a_number_and_five_free_characters() {
+        let name = DcfFolderName::parse("100canon").unwrap();
+        assert_eq!((name.number, name.free_chars.as_str()), (100, "CANON"));
+        assert_eq!(name.to_string(), "100CANON");
+        assert_eq!(DcfFolderName::parse("999_TEST").unwrap().number, 999);
+        for invalid in [
+            "099CANON",
+            "100CANO",
+            "100CANON1",
+            "1A0CANON",
+            "100CA-ON",
+            "100CAN N",
+        ] {
+            assert_eq!(DcfFolderName::parse(invalid), None, "{}", invalid);
+        }
+        assert!(DcfFolderName::new(101, "NIKON").is_ok());
+        assert!(matches!(
+            DcfFolderName::new(1000, "NIKON"),
+            Err(Error::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn file_names_have_four_free_characters_a_number_and_an_extension() {
+        let name = DcfFileName::parse("img_0042.jpg").unwrap();
+        assert_eq!(name.prefix, "IMG_");
+        assert_eq!(name.number, 42);
+        assert_eq!(name.extension, "JPG");
+        assert_eq!(name.base_name(), "IMG_0042");
+        assert_eq!(name.to_string(), "IMG_0042.JPG");
+        assert!(!name.is_adobe_rgb());
+        assert!(DcfFileName::parse("_DSC9999.NEF").unwrap().is_adobe_rgb());
+        assert_eq!(
+            DcfFileName::parse("DSCF0001.HEIC").unwrap().extension,
+            "HEIC"
+        );
+        for invalid in [
+            "IMG_0000.JPG",
+            "IMG_001.JPG",
+            "IMG_00001.JPG",
+            "IMG-0001.JPG",
+            "IMG_0001",
+            "IMG_0001.JP",
+            "IMG_0001.JPEG2",
+            "IMG_0001.J-G",
+            "IMG_000A.JPG",
+        ] {
+            assert_eq!(DcfFileName::parse(invalid), None, "{}", invalid);
+        }
+        assert!(matches!(
+            DcfFileName::new("IM G", 1, "JPG"),
+            Err(Error::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn files_are_grouped_into_objects() {
+        let files = [
+            file("IMG_0002.JPG", "CARD/DCIM/100CANON"),
+            file("IMG_0001.JPG", "CARD/DCIM/100CANON"),
+            file("IMG_0001.CR2", "CARD/DCIM/100CANON"),
+            file("IMG_0001.JPG", "CARD/DCIM/101CANON"),
+            file("IMG_0001.JPG", "CARD/PRIVATE/100CANON"),
+            file("notes.txt", "CARD/DCIM/100CANON"),
+        ];
+        assert!(is_dcf_file(&files[0]));
+        assert!(!is_dcf_file(&files[4]));
+        let (objects, foreign) = group_objects(&files);
+        let summary: Vec<_> = objects
+            .iter()
+            .map(|object| {
+                let names: Vec<_> = object.files.iter().map(|f| f.name.as_str()).collect();
+                (object.folder.number, object.number, names)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (100, 1, vec!["IMG_0001.CR2", "IMG_0001.JPG"]),
+                (100, 2, vec!["IMG_0002.JPG"]),
+                (101, 1, vec!["IMG_0001.JPG"]),
+            ]
+        );
+        assert_eq!(objects[0].base_name(), "IMG_0001");
+        let foreign: Vec<_> = foreign.iter().map(|f| f.path()).collect();
+        assert_eq!(
+            foreign,
+            [
+                "CARD/PRIVATE/100CANON/IMG_0001.JPG",
+                "CARD/DCIM/100CANON/notes.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn next_numbers_follow_the_highest_in_use() {
+        let contents = card(&["101CANON", "100CANON"], &[7, 3]);
+        let paths: Vec<_> = folders(&contents)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths, ["CARD/DCIM/100CANON", "CARD/DCIM/101CANON"]);
+        assert_eq!(next_folder_number(&contents), Some(102));
+        assert_eq!(next_file_number(&contents, "CARD/DCIM/101CANON"), Some(8));
+        assert_eq!(next_file_number(&contents, "CARD/DCIM/102CANON"), Some(1));
+
+        assert_eq!(next_folder_number(&card(&[], &[])), Some(100));
+        assert_eq!(next_folder_number(&card(&["999CANON"], &[])), None);
+        let full = card(&["100CANON"], &[9999]);
+        assert_eq!(next_file_number(&full, "CARD/DCIM/100CANON"), None);
+    }
+
+    #[test]
+    fn uploads_go_to_the_last_folder_or_a_new_one() {
+        let contents = card(&["100CANON", "101CANON"], &[1, 2]);
+        let target = upload_target(&contents, "_MG_0100.CR2").unwrap();
+        assert_eq!(target.folder, "CARD/DCIM/101CANON");
+        assert!(!target.create_folder);
+        assert_eq!(target.name.to_string(), "_MG_0003.CR2");
+
+        let full = card(&["100CANON"], &[9999]);
+        let target = upload_target(&full, "holiday.jpeg").unwrap();
+        assert_eq!(target.folder, "CARD/DCIM/101CANON");
+        assert!(target.create_folder);
+        assert_eq!(target.name.to_string(), "IMG_0001.JPG");
+
+        let empty = card(&[], &[]);
+        let target = upload_target(&empty, "scan.tiff").unwrap();
+        assert_eq!(target.folder, "CARD/DCIM/100IMAGE");
+        assert_eq!(target.name.to_string(), "IMG_0001.TIF");
+
+        assert!(upload_target(&card(&["999CANON"], &[9999]), "a.jpg").is_err());
+        assert!(upload_target(&[], "a.jpg").is_err());
+    }
+
+    #[test]
+    fn foreign_names_get_a_dcf_extension() {
+        let contents = card(&["100CANON"], &[]);
+        let name = |original| upload_target(&contents, original).unwrap().name.to_string();
+        assert_eq!(name("clip.mov"), "IMG_0001.MOV");
+        assert_eq!(name("photo.HEIC"), "IMG_0001.HEIC");
+        assert_eq!(name("photo.hif"), "IMG_0001.HEIC");
+        assert_eq!(name("raw.ABCD"), "IMG_0001.ABCD");
+        for original in [
+            "README",
+            "archive.tar.gz",
+            "notes.markdown",
+            "odd.j-g",
+            ".hidden",
+        ] {
+            assert_eq!(name(original), "IMG_0001.DAT", "{}", original);
+        }
+    }
+}
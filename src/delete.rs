@@ -0,0 +1,743 @@
+//! Deleting files from a camera only once they are safely copied.
+//!
+//! A `DeletePlanner` first plans a deletion, looking for a copy of every file in the import
+//! ledger or among local files and comparing sizes and, optionally, content hashes. The
+//! plan can be shown as a dry run before `DeletePlanner::run` deletes the files with a copy,
+//! in batches the camera supports, and reports the outcome of every file.
+
+use crate::camera::{self, CameraCapabilities, CameraDevice, CameraFile};
+use crate::constants::ICReturnCode;
+use crate::error::{Error, Result};
+use crate::import::Library;
+use crate::ledger::{ImportLedger, LedgerEntry};
+use crate::sha256::{self, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Default number of files deleted with one request on cameras that delete several at once.
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// Where the copy of a file to delete was found.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Evidence {
+    /// The import ledger records the file as imported to this path.
+    Ledger(PathBuf),
+    /// This local file has the name and size, or the content, of the file.
+    LocalFile(PathBuf),
+}
+
+impl Evidence {
+    /// The path of the copy.
+    pub fn path(&self) -> &Path {
+        match self {
+            Evidence::Ledger(path) | Evidence::LocalFile(path) => path,
+        }
+    }
+}
+
+/// Why a file is kept on the camera.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeepReason {
+    /// The file is protected on the camera.
+    Locked,
+    /// No copy of the file was found.
+    NotCopied,
+    /// The ledger records a copy at this path, but it no longer exists.
+    CopyMissing(PathBuf),
+    /// The copy at this path has a different size.
+    SizeMismatch(PathBuf),
+    /// The copy at this path has different content.
+    HashMismatch(PathBuf),
+    /// A sidecar file deleted together with this file is kept, for the reason given.
+    Sidecar(String, Box<KeepReason>),
+    /// Checking the copy failed with this error.
+    CheckFailed(String),
+}
+
+impl fmt::Display for KeepReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeepReason::Locked => write!(f, "locked"),
+            KeepReason::NotCopied => write!(f, "no copy found"),
+            KeepReason::CopyMissing(path) => write!(f, "copy {} is missing", path.display()),
+            KeepReason::SizeMismatch(path) => {
+                write!(f, "copy {} has a different size", path.display())
+            }
+            KeepReason::HashMismatch(path) => {
+                write!(f, "copy {} has different content", path.display())
+            }
+            KeepReason::Sidecar(name, reason) => write!(f, "sidecar {}: {}", name, reason),
+            KeepReason::CheckFailed(error) => write!(f, "checking the copy failed: {}", error),
+        }
+    }
+}
+
+/// What the deletion does with a file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    Delete(Evidence),
+    Keep(KeepReason),
+}
+
+/// A file of a delete plan.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedDeletion {
+    pub file: CameraFile,
+    pub decision: Decision,
+}
+
+/// The files of a deletion and what will be done with each. Its `Display` output lists the
+/// decisions as a dry run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeletePlan {
+    pub files: Vec<PlannedDeletion>,
+}
+
+impl DeletePlan {
+    /// The files that will be deleted.
+    pub fn to_delete(&self) -> impl Iterator<Item = &CameraFile> {
+        self.files
+            .iter()
+            .filter(|planned| matches!(planned.decision, Decision::Delete(_)))
+            .map(|planned| &planned.file)
+    }
+
+    /// The number of files that will be kept.
+    pub fn kept_count(&self) -> usize {
+        self.files.len() - self.to_delete().count()
+    }
+}
+
+impl fmt::Display for DeletePlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for planned in &self.files {
+            match &planned.decision {
+                Decision::Delete(evidence) => writeln!(
+                    f,
+                    "delete {} (copy at {})",
+                    planned.file.path(),
+                    evidence.path().display()
+                )?,
+                Decision::Keep(reason) => writeln!(f, "keep {}: {}", planned.file.path(), reason)?,
+            }
+        }
+        write!(
+            f,
+            "{} to delete, {} to keep",
+            self.files.len() - self.kept_count(),
+            self.kept_count()
+        )
+    }
+}
+
+/// What happened to a file of a delete plan.
+#[derive(Debug)]
+pub enum DeleteOutcome {
+    Deleted,
+    Kept(KeepReason),
+    /// The camera failed to delete the file.
+    Failed(Error),
+    /// The deletion was canceled before the file was deleted.
+    Canceled,
+}
+
+impl DeleteOutcome {
+    /// The matching `ICReturnCode`.
+    pub fn return_code(&self) -> ICReturnCode {
+        match self {
+            DeleteOutcome::Deleted | DeleteOutcome::Kept(_) => ICReturnCode::ICReturnSuccess,
+            DeleteOutcome::Failed(_) => ICReturnCode::ICReturnDeleteFilesFailed,
+            DeleteOutcome::Canceled => ICReturnCode::ICReturnDeleteFilesCanceled,
+        }
+    }
+}
+
+/// The outcome of a file of a delete plan.
+#[derive(Debug)]
+pub struct DeleteResult {
+    pub file: CameraFile,
+    pub outcome: DeleteOutcome,
+}
+
+/// The outcome of a deletion, in the order of the plan.
+#[derive(Debug, Default)]
+pub struct DeleteReport {
+    pub results: Vec<DeleteResult>,
+}
+
+impl DeleteReport {
+    pub fn deleted(&self) -> impl Iterator<Item = &CameraFile> {
+        self.results
+            .iter()
+            .filter(|result| matches!(result.outcome, DeleteOutcome::Deleted))
+            .map(|result| &result.file)
+    }
+
+    /// Whether every file to delete was deleted.
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(|result| {
+            matches!(
+                result.outcome,
+                DeleteOutcome::Deleted | DeleteOutcome::Kept(_)
+            )
+        })
+    }
+}
+
+impl fmt::Display for DeleteReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut counts = [0; 4];
+        for result in &self.results {
+            counts[match result.outcome {
+                DeleteOutcome::Deleted => 0,
+                DeleteOutcome::Kept(_) => 1,
+                DeleteOutcome::Failed(_) => 2,
+                DeleteOutcome::Canceled => 3,
+            }] += 1;
+        }
+        write!(
+            f,
+            "deleted {} files, kept {}, failed {}, canceled {}",
+            counts[0], counts[1], counts[2], counts[3]
+        )
+    }
+}
+
+/// Plans and runs deletions of files that were copied off a camera.
+pub struct DeletePlanner<'a> {
+    camera: &'a dyn CameraDevice,
+    device_id: String,
+    ledger: Option<&'a ImportLedger>,
+    library: Option<PathBuf>,
+    compare_hashes: bool,
+    batch_size: usize,
+}
+
+impl<'a> DeletePlanner<'a> {
+    /// A planner that finds no copies until given a ledger or a library folder.
+    pub fn new(camera: &'a dyn CameraDevice) -> DeletePlanner<'a> {
+        DeletePlanner {
+            camera,
+            device_id: camera.persistent_id_string(),
+            ledger: None,
+            library: None,
+            compare_hashes: false,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Accept copies the import ledger records.
+    pub fn with_ledger(mut self, ledger: &'a ImportLedger) -> DeletePlanner<'a> {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    /// Accept copies among the files in `folder` and its sub-folders.
+    pub fn with_library<P: AsRef<Path>>(mut self, folder: P) -> DeletePlanner<'a> {
+        self.library = Some(folder.as_ref().to_path_buf());
+        self
+    }
+
+    /// Compare the content of copies, not just their size.
+    ///
+    /// Copies recorded in the ledger are compared with the recorded hash. Other copies are
+    /// compared with the file on the camera, which is read in full.
+    pub fn compare_hashes(mut self, compare: bool) -> DeletePlanner<'a> {
+        self.compare_hashes = compare;
+        self
+    }
+
+    /// Delete up to `files` files with one request, on cameras that delete several at once.
+    pub fn batch_size(mut self, files: usize) -> DeletePlanner<'a> {
+        self.batch_size = files.max(1);
+        self
+    }
+
+    /// Plan the deletion of `files`. A file is only deleted if the sidecar files the camera
+    /// deletes with it have a copy as well.
+    pub fn plan(&self, files: &[CameraFile]) -> Result<DeletePlan> {
+        let library = match &self.library {
+            Some(folder) => Some(Library::scan(folder)?),
+            None => None,
+        };
+        let mut hashes = HashMap::new();
+        let mut plan = DeletePlan::default();
+        for file in files {
+            let mut decision = self.decide(file, library.as_ref(), &mut hashes);
+            if let Decision::Delete(_) = decision {
+                for sidecar in &file.sidecar_files {
+                    if let Decision::Keep(reason) =
+                        self.decide(sidecar, library.as_ref(), &mut hashes)
+                    {
+                        decision = Decision::Keep(KeepReason::Sidecar(
+                            sidecar.name.clone(),
+                            Box::new(reason),
+                        ));
+                        break;
+                    }
+                }
+            }
+            plan.files.push(PlannedDeletion {
+                file: file.clone(),
+                decision,
+            });
+        }
+        Ok(plan)
+    }
+
+    /// Plan the deletion of all media files of the camera.
+    pub fn plan_all(&self) -> Result<DeletePlan> {
+        self.plan(&self.camera.media_files()?)
+    }
+
+    /// Delete the files of `plan` that have a copy, stopping when `cancel` is set.
+    ///
+    /// Cameras that delete several files at once get batches of files. If a batch fails on
+    /// a camera that also deletes single files, its files are deleted one by one to find
+    /// out which of them failed.
+    pub fn run(&self, plan: &DeletePlan, cancel: Option<&AtomicBool>) -> DeleteReport {
+        let capabilities = self.camera.capabilities();
+        let one = capabilities.contains(CameraCapabilities::ICCameraDeviceCanDeleteOneFile);
+        let all = capabilities.contains(CameraCapabilities::ICCameraDeviceCanDeleteAllFiles);
+        let batch_size = if all { self.batch_size } else { 1 };
+        let mut outcomes = plan
+            .files
+            .iter()
+            .map(|planned| match &planned.decision {
+                Decision::Keep(reason) => Some(DeleteOutcome::Kept(reason.clone())),
+                Decision::Delete(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let pending = (0..plan.files.len())
+            .filter(|&index| outcomes[index].is_none())
+            .collect::<Vec<_>>();
+        for batch in pending.chunks(batch_size) {
+            if cancel.is_some_and(|cancel| cancel.load(Ordering::SeqCst)) {
+                for &index in batch {
+                    outcomes[index] = Some(DeleteOutcome::Canceled);
+                }
+                continue;
+            }
+            let files = batch
+                .iter()
+                .map(|&index| plan.files[index].file.clone())
+                .collect::<Vec<_>>();
+            if !one && !all {
+                for &index in batch {
+                    let error = Error::Unsupported("delete".into());
+                    outcomes[index] = Some(DeleteOutcome::Failed(error));
+                }
+                continue;
+            }
+            let error = match self.camera.delete_files(&files) {
+                Ok(()) => {
+                    for &index in batch {
+                        outcomes[index] = Some(DeleteOutcome::Deleted);
+                    }
+                    continue;
+                }
+                Err(error) => error,
+            };
+            if batch.len() == 1 || !one {
+                for &index in batch {
+                    outcomes[index] = Some(DeleteOutcome::Failed(error.duplicate()));
+                }
+                continue;
+            }
+            for (&index, file) in batch.iter().zip(&files) {
+                outcomes[index] =
+                    Some(match self.camera.delete_files(std::slice::from_ref(file)) {
+                        Ok(()) => DeleteOutcome::Deleted,
+                        // The failed batch may have deleted the file before it stopped, but
+                        // only a file the camera no longer lists counts as deleted.
+                        Err(Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+                            match self.is_on_camera(file) {
+                                Ok(false) => DeleteOutcome::Deleted,
+                                Ok(true) => DeleteOutcome::Failed(Error::Io(err)),
+                                Err(error) => DeleteOutcome::Failed(error),
+                            }
+                        }
+                        Err(error) => DeleteOutcome::Failed(error),
+                    });
+            }
+        }
+        DeleteReport {
+            results: plan
+                .files
+                .iter()
+                .zip(outcomes)
+                .map(|(planned, outcome)| DeleteResult {
+                    file: planned.file.clone(),
+                    outcome: outcome.unwrap_or(DeleteOutcome::Canceled),
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether the camera still lists `file`.
+    fn is_on_camera(&self, file: &CameraFile) -> Result<bool> {
+        let mut files = Vec::new();
+        camera::collect_files(&self.camera.contents()?, &mut files);
+        let path = file.path();
+        Ok(files.iter().any(|listed| listed.path() == path))
+    }
+
+    fn decide(
+        &self,
+        file: &CameraFile,
+        library: Option<&Library>,
+        hashes: &mut HashMap<PathBuf, [u8; 32]>,
+    ) -> Decision {
+        if file.is_locked {
+            return Decision::Keep(KeepReason::Locked);
+        }
+        let mut reason = KeepReason::NotCopied;
+        if let Some((entry, destination)) = self
+            .ledger
+            .and_then(|ledger| ledger.entry(&self.device_id, file))
+            .and_then(|entry| entry.destination.as_ref().map(|path| (entry, path)))
+        {
+            match self.check_recorded(file, entry, destination, hashes) {
+                Ok(None) => return Decision::Delete(Evidence::Ledger(destination.clone())),
+                Ok(Some(mismatch)) => reason = mismatch,
+                Err(error) => reason = KeepReason::CheckFailed(error.to_string()),
+            }
+        }
+        if let Some(library) = library {
+            match self.find_local_copy(file, library, hashes) {
+                Ok(Some(path)) => return Decision::Delete(Evidence::LocalFile(path)),
+                Ok(None) => {}
+                Err(error) => reason = KeepReason::CheckFailed(error.to_string()),
+            }
+        }
+        Decision::Keep(reason)
+    }
+
+    /// Why the copy at `path` recorded in the ledger does not match `file`, if it does not.
+    ///
+    /// A copy changed by post-processing, such as geotagging, is compared with the size and
+    /// hash the ledger recorded for it afterwards.
+    fn check_recorded(
+        &self,
+        file: &CameraFile,
+        entry: &LedgerEntry,
+        path: &Path,
+        hashes: &mut HashMap<PathBuf, [u8; 32]>,
+    ) -> Result<Option<KeepReason>> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Some(KeepReason::CopyMissing(path.to_path_buf())))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if metadata.len() != entry.saved_size.unwrap_or(file.file_size) {
+            return Ok(Some(KeepReason::SizeMismatch(path.to_path_buf())));
+        }
+        if self.compare_hashes {
+            let expected = match entry.saved_sha256.or(entry.sha256) {
+                Some(digest) => digest,
+                None => self.camera_hash(file)?,
+            };
+            if local_hash(path, hashes)? != expected {
+                return Ok(Some(KeepReason::HashMismatch(path.to_path_buf())));
+            }
+        }
+        Ok(None)
+    }
+
+    /// A local file with the size and name of `file`, or with its size and content when
+    /// comparing hashes.
+    fn find_local_copy(
+        &self,
+        file: &CameraFile,
+        library: &Library,
+        hashes: &mut HashMap<PathBuf, [u8; 32]>,
+    ) -> Result<Option<PathBuf>> {
+        let candidates = library.with_size(file.file_size);
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        if !self.compare_hashes {
+            return Ok(candidates
+                .iter()
+                .map(|(path, _)| path)
+                .find(|path| {
+                    path.file_name()
+                        .is_some_and(|name| name.to_string_lossy().eq_ignore_ascii_case(&file.name))
+                })
+                .cloned());
+        }
+        let digest = self.camera_hash(file)?;
+        for (path, _) in candidates {
+            if local_hash(path, hashes)? == digest {
+                return Ok(Some(path.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn camera_hash(&self, file: &CameraFile) -> Result<[u8; 32]> {
+        let mut sha = Sha256::new();
+        self.camera.download_file(file, &mut sha)?;
+        Ok(sha.finish())
+    }
+}
+
+fn local_hash(path: &Path, cache: &mut HashMap<PathBuf, [u8; 32]>) -> Result<[u8; 32]> {
+    if let Some(digest) = cache.get(path) {
+        return Ok(*digest);
+    }
+    let digest = sha256::digest_reader(&mut File::open(path)?)?;
+    cache.insert(path.to_path_buf(), digest);
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraItem;
+    use crate::mock::MockCamera;
+    use crate::testing::ScratchDir;
+    use std::io::Write;
+
+    /// A camera deleting several files at once, whose batches stop after their first file.
+    /// Single deletes of files it does not have, or of the `stuck` files it keeps listing,
+    /// fail with `NotFound`.
+    struct BatchCamera {
+        camera: MockCamera,
+        stuck: Vec<String>,
+    }
+
+    impl CameraDevice for BatchCamera {
+        fn name(&self) -> String {
+            self.camera.name()
+        }
+
+        fn capabilities(&self) -> CameraCapabilities {
+            CameraCapabilities::ICCameraDeviceCanDeleteOneFile
+                | CameraCapabilities::ICCameraDeviceCanDeleteAllFiles
+        }
+
+        fn contents(&self) -> Result<Vec<CameraItem>> {
+            self.camera.contents()
+        }
+
+        fn download_file(&self, file: &CameraFile, writer: &mut dyn Write) -> Result<u64> {
+            self.camera.download_file(file, writer)
+        }
+
+        fn read_data_from_file(
+            &self,
+            file: &CameraFile,
+            offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>> {
+            self.camera.read_data_from_file(file, offset, length)
+        }
+
+        fn delete_files(&self, files: &[CameraFile]) -> Result<()> {
+            if files.len() > 1 {
+                self.camera.delete_files(&files[..1])?;
+                return Err(Error::Io(io::Error::other("the batch stopped")));
+            }
+            let name = &files[0].name;
+            if self.stuck.contains(name) || !self.camera.file_names().contains(name) {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} not found", name),
+                )));
+            }
+            self.camera.delete_files(files)
+        }
+    }
+
+    /// A library folder holding copies of `names`, with the content the mock camera gives
+    /// files it adds with `add_file(name, name.as_bytes())`.
+    fn library(names: &[&str]) -> ScratchDir {
+        let library = ScratchDir::new("library");
+        for name in names {
+            fs::write(library.path().join(name), name.as_bytes()).unwrap();
+        }
+        library
+    }
+
+    fn add_files(camera: &MockCamera, names: &[&str]) -> Vec<CameraFile> {
+        names
+            .iter()
+            .map(|name| camera.add_file(name, name.as_bytes()))
+            .collect()
+    }
+
+    fn outcomes(report: &DeleteReport) -> Vec<(&str, String)> {
+        report
+            .results
+            .iter()
+            .map(|result| {
+                let outcome = match &result.outcome {
+                    DeleteOutcome::Deleted => "deleted".to_string(),
+                    DeleteOutcome::Kept(reason) => format!("kept: {}", reason),
+                    DeleteOutcome::Failed(_) => "failed".to_string(),
+                    DeleteOutcome::Canceled => "canceled".to_string(),
+                };
+                (result.file.name.as_str(), outcome)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dry_run_lists_every_decision() {
+        let camera = MockCamera::new("Mock");
+        let mut files = add_files(&camera, &["IMG_0001.JPG", "IMG_0002.JPG", "IMG_0003.JPG"]);
+        files[1].is_locked = true;
+        let library = library(&["IMG_0001.JPG", "IMG_0002.JPG"]);
+        let plan = DeletePlanner::new(&camera)
+            .with_library(library.path())
+            .plan(&files)
+            .unwrap();
+        assert_eq!(
+            plan.to_string(),
+            format!(
+                "delete MOCK/DCIM/100MOCK_/IMG_0001.JPG (copy at {})\n\
+                 keep MOCK/DCIM/100MOCK_/IMG_0002.JPG: locked\n\
+                 keep MOCK/DCIM/100MOCK_/IMG_0003.JPG: no copy found\n\
+                 1 to delete, 2 to keep",
+                library.path().join("IMG_0001.JPG").display()
+            )
+        );
+        assert_eq!(plan.kept_count(), 2);
+    }
+
+    #[test]
+    fn copies_must_match_in_size_and_with_hashes_in_content() {
+        let camera = MockCamera::new("Mock");
+        let files = add_files(&camera, &["IMG_0001.JPG", "IMG_0002.JPG"]);
+        let library = library(&["IMG_0001.JPG"]);
+        fs::write(library.path().join("IMG_0002.JPG"), b"IMG_0002.JP_").unwrap();
+
+        let plan = DeletePlanner::new(&camera)
+            .with_library(library.path())
+            .plan(&files)
+            .unwrap();
+        assert_eq!(plan.to_delete().count(), 2);
+
+        let plan = DeletePlanner::new(&camera)
+            .with_library(library.path())
+            .compare_hashes(true)
+            .plan(&files)
+            .unwrap();
+        let to_delete: Vec<_> = plan.to_delete().map(|file| file.name.as_str()).collect();
+        assert_eq!(to_delete, ["IMG_0001.JPG"]);
+        assert_eq!(
+            plan.files[1].decision,
+            Decision::Keep(KeepReason::NotCopied)
+        );
+    }
+
+    #[test]
+    fn files_with_an_uncopied_sidecar_are_kept() {
+        let camera = MockCamera::new("Mock");
+        let files = add_files(&camera, &["IMG_0001.JPG", "IMG_0002.JPG"]);
+        let sidecars = add_files(&camera, &["IMG_0001.XMP", "IMG_0002.XMP"]);
+        let files: Vec<_> = files
+            .into_iter()
+            .zip(sidecars)
+            .map(|(file, sidecar)| CameraFile {
+                sidecar_files: vec![sidecar],
+                ..file
+            })
+            .collect();
+        let library = library(&["IMG_0001.JPG", "IMG_0001.XMP", "IMG_0002.JPG"]);
+        let planner = DeletePlanner::new(&camera).with_library(library.path());
+        let plan = planner.plan(&files).unwrap();
+        assert_eq!(
+            plan.files[1].decision,
+            Decision::Keep(KeepReason::Sidecar(
+                "IMG_0002.XMP".to_string(),
+                Box::new(KeepReason::NotCopied)
+            ))
+        );
+
+        let report = planner.run(&plan, None);
+        assert_eq!(
+            outcomes(&report),
+            [
+                ("IMG_0001.JPG", "deleted".to_string()),
+                (
+                    "IMG_0002.JPG",
+                    "kept: sidecar IMG_0002.XMP: no copy found".to_string()
+                ),
+            ]
+        );
+        assert!(report.is_success());
+        assert_eq!(
+            camera.file_names(),
+            ["IMG_0001.XMP", "IMG_0002.JPG", "IMG_0002.XMP"]
+        );
+    }
+
+    #[test]
+    fn run_reports_the_outcome_of_every_file() {
+        let camera = MockCamera::new("Mock");
+        let mut files = add_files(&camera, &["IMG_0001.JPG", "IMG_0002.JPG", "IMG_0003.JPG"]);
+        files[0].is_locked = true;
+        let library = library(&["IMG_0001.JPG", "IMG_0002.JPG", "IMG_0003.JPG"]);
+        let planner = DeletePlanner::new(&camera).with_library(library.path());
+        let plan = planner.plan(&files).unwrap();
+
+        let cancel = AtomicBool::new(true);
+        let report = planner.run(&plan, Some(&cancel));
+        assert_eq!(
+            outcomes(&report),
+            [
+                ("IMG_0001.JPG", "kept: locked".to_string()),
+                ("IMG_0002.JPG", "canceled".to_string()),
+                ("IMG_0003.JPG", "canceled".to_string()),
+            ]
+        );
+        assert!(!report.is_success());
+        assert_eq!(camera.file_names().len(), 3);
+
+        let report = planner.run(&plan, None);
+        assert_eq!(
+            report.to_string(),
+            "deleted 2 files, kept 1, failed 0, canceled 0"
+        );
+        assert_eq!(camera.file_names(), ["IMG_0001.JPG"]);
+    }
+
+    #[test]
+    fn files_of_a_failed_batch_are_deleted_one_by_one() {
+        let camera = BatchCamera {
+            camera: MockCamera::new("Mock"),
+            stuck: vec!["IMG_0003.JPG".to_string()],
+        };
+        let files = add_files(
+            &camera.camera,
+            &["IMG_0001.JPG", "IMG_0002.JPG", "IMG_0003.JPG"],
+        );
+        let library = library(&["IMG_0001.JPG", "IMG_0002.JPG", "IMG_0003.JPG"]);
+        let planner = DeletePlanner::new(&camera).with_library(library.path());
+        let plan = planner.plan(&files).unwrap();
+        let report = planner.run(&plan, None);
+        // The batch deleted the first file before it stopped. The camera reports the third
+        // as not found, but still lists it.
+        assert_eq!(
+            outcomes(&report),
+            [
+                ("IMG_0001.JPG", "deleted".to_string()),
+                ("IMG_0002.JPG", "deleted".to_string()),
+                ("IMG_0003.JPG", "failed".to_string()),
+            ]
+        );
+        assert_eq!(
+            report.results[2].outcome.return_code(),
+            ICReturnCode::ICReturnDeleteFilesFailed
+        );
+        assert_eq!(camera.camera.file_names(), ["IMG_0003.JPG"]);
+    }
+}
@@ -0,0 +1,903 @@
+//! Downloads to a directory with the options of `requestDownloadFile`.
+//!
+//! `DownloadOptions` covers the `ICDownloadsDirectoryURL`, `ICSaveAsFilename`, `ICOverwrite`,
+//! `ICDeleteAfterSuccessfulDownload` and `ICDownloadSidecarFiles` keys, and `DownloadResult`
+//! the `ICSavedFilename` and `ICSavedAncillaryFiles` keys reported back. Files are written to
+//! a temporary file in the target directory first and only take their final name once
+//! complete, so an interrupted download never leaves a partial file under that name.
+
+use crate::camera::{CameraDevice, CameraFile};
+use crate::error::{Error, Result};
+use crate::sha256::{self, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::SystemTime;
+
+/// Names reserved by Windows, which cannot be used as file base names there.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+/// Number of bytes requested at a time when resuming a download with ranged reads.
+const RESUME_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+/// Number of hexadecimal digits of the content hash used by `CollisionPolicy::RenameWithHash`.
+const HASH_SUFFIX_LEN: usize = 8;
+
+/// Distinguishes temporary files of concurrent downloads within a process.
+static PART_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// What to do when a file with the target name already exists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Replace the existing file.
+    Overwrite,
+    /// Keep the existing file and do not download.
+    Skip,
+    /// Save as `name-1.ext`, `name-2.ext`, ... using the first free name.
+    RenameWithCounter,
+    /// Save as `name-<hash>.ext` with the start of the content's SHA-256 digest. A file
+    /// already saved under that name has the same content and is kept.
+    RenameWithHash,
+    /// Fail with an `AlreadyExists` I/O error.
+    Error,
+}
+
+/// How a download is checked before it takes its final name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verification {
+    /// Accept the data received, even if its size differs from `CameraFile::file_size`.
+    None,
+    /// Compare the number of bytes received with `CameraFile::file_size`.
+    Size,
+    /// Check the size, then read the file from the camera once more and the saved data back
+    /// from disk, and compare their SHA-256 digests with that of the data received.
+    Checksum,
+}
+
+/// Options of a download, the counterpart of the `requestDownloadFile` options dictionary.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DownloadOptions {
+    directory: PathBuf,
+    save_as: Option<String>,
+    collision_policy: CollisionPolicy,
+    delete_after_download: bool,
+    download_sidecar_files: bool,
+    preserve_dates: bool,
+    resume: bool,
+    retries: u32,
+    verification: Verification,
+}
+
+impl DownloadOptions {
+    /// Download into `directory` under the file's own name, renaming with a counter on
+    /// collision, resuming interrupted downloads, retrying twice and checking the size.
+    pub fn new<P: AsRef<Path>>(directory: P) -> DownloadOptions {
+        DownloadOptions {
+            directory: directory.as_ref().to_path_buf(),
+            save_as: None,
+            collision_policy: CollisionPolicy::RenameWithCounter,
+            delete_after_download: false,
+            download_sidecar_files: false,
+            preserve_dates: true,
+            resume: true,
+            retries: 2,
+            verification: Verification::Size,
+        }
+    }
+
+    /// Save the file under `name` instead of its own name, like `ICSaveAsFilename`.
+    pub fn save_as(mut self, name: &str) -> DownloadOptions {
+        self.save_as = Some(name.to_string());
+        self
+    }
+
+    /// What to do when the target name is taken.
+    pub fn collision_policy(mut self, policy: CollisionPolicy) -> DownloadOptions {
+        self.collision_policy = policy;
+        self
+    }
+
+    /// Replace existing files, like `ICOverwrite`.
+    pub fn overwrite(self, overwrite: bool) -> DownloadOptions {
+        self.collision_policy(if overwrite {
+            CollisionPolicy::Overwrite
+        } else {
+            CollisionPolicy::RenameWithCounter
+        })
+    }
+
+    /// Delete the file from the camera once it is saved, like
+    /// `ICDeleteAfterSuccessfulDownload`.
+    pub fn delete_after_download(mut self, delete: bool) -> DownloadOptions {
+        self.delete_after_download = delete;
+        self
+    }
+
+    /// Download the file's sidecar files next to it, like `ICDownloadSidecarFiles`.
+    pub fn download_sidecar_files(mut self, download: bool) -> DownloadOptions {
+        self.download_sidecar_files = download;
+        self
+    }
+
+    /// Give saved files the modification date of the file on the camera. On by default.
+    pub fn preserve_dates(mut self, preserve: bool) -> DownloadOptions {
+        self.preserve_dates = preserve;
+        self
+    }
+
+    /// Keep the data of a failed download and continue after it with ranged reads the next
+    /// time the file is downloaded to the same name. On by default.
+    pub fn resume(mut self, resume: bool) -> DownloadOptions {
+        self.resume = resume;
+        self
+    }
+
+    /// Try a download that failed with an I/O or device error up to `retries` more times,
+    /// continuing after the data received.
+    pub fn retries(mut self, retries: u32) -> DownloadOptions {
+        self.retries = retries;
+        self
+    }
+
+    /// How the download is checked. The file is only deleted from the camera after it
+    /// passed the check.
+    pub fn verification(mut self, verification: Verification) -> DownloadOptions {
+        self.verification = verification;
+        self
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn get_collision_policy(&self) -> CollisionPolicy {
+        self.collision_policy
+    }
+}
+
+/// The outcome of a download, the counterpart of the options dictionary passed to
+/// `didDownloadFile:error:options:contextInfo:`.
+#[derive(Debug)]
+pub struct DownloadResult {
+    /// Where the file is, whether it was saved now or kept from before.
+    pub path: PathBuf,
+    /// Name of the saved file, like `ICSavedFilename`.
+    pub saved_filename: String,
+    /// Sidecar files saved with the file, like `ICSavedAncillaryFiles`.
+    pub ancillary_files: Vec<PathBuf>,
+    /// Bytes written for the file itself.
+    pub bytes_written: u64,
+    /// Bytes kept from an earlier, interrupted download of the file.
+    pub resumed_from: u64,
+    /// SHA-256 digest of the content, if the file was downloaded.
+    pub sha256: Option<[u8; 32]>,
+    /// Whether an existing file was kept instead of downloading.
+    pub skipped: bool,
+    /// Whether the file was deleted from the camera.
+    pub deleted: bool,
+    /// Why deleting the file from the camera after the download failed.
+    pub delete_error: Option<Error>,
+}
+
+/// Download `file` as described by `options`.
+pub fn download<C: CameraDevice + ?Sized>(
+    camera: &C,
+    file: &CameraFile,
+    options: &DownloadOptions,
+) -> Result<DownloadResult> {
+    let name = sanitize_file_name(options.save_as.as_deref().unwrap_or(&file.name));
+    let saved = save(camera, file, &name, options.collision_policy, options)?;
+    let mut result = DownloadResult {
+        saved_filename: file_name(&saved.path),
+        path: saved.path,
+        ancillary_files: Vec::new(),
+        bytes_written: saved.size,
+        resumed_from: saved.resumed_from,
+        sha256: saved.sha256,
+        skipped: saved.sha256.is_none(),
+        deleted: false,
+        delete_error: None,
+    };
+    if options.download_sidecar_files {
+        // Sidecars take the saved name of their file and must describe it, so they replace
+        // files of that name, unless the existing file was kept.
+        let base_name = split_extension(&result.saved_filename).0.to_string();
+        let policy = if result.skipped {
+            CollisionPolicy::Skip
+        } else {
+            CollisionPolicy::Overwrite
+        };
+        for sidecar in &file.sidecar_files {
+            let extension = split_extension(&sidecar.name).1;
+            let name = sanitize_file_name(&join_extension(&base_name, extension));
+            let saved = save(camera, sidecar, &name, policy, options)?;
+            result.ancillary_files.push(saved.path);
+        }
+    }
+    if options.delete_after_download && !result.skipped {
+        match camera.delete_files(std::slice::from_ref(file)) {
+            Ok(()) => result.deleted = true,
+            Err(error) => result.delete_error = Some(error),
+        }
+    }
+    Ok(result)
+}
+
+/// A file saved by `save`.
+struct Saved {
+    path: PathBuf,
+    size: u64,
+    /// `None` if an existing file was kept.
+    sha256: Option<[u8; 32]>,
+    resumed_from: u64,
+}
+
+fn save<C: CameraDevice + ?Sized>(
+    camera: &C,
+    file: &CameraFile,
+    name: &str,
+    policy: CollisionPolicy,
+    options: &DownloadOptions,
+) -> Result<Saved> {
+    let directory = &options.directory;
+    let target = directory.join(name);
+    if target.exists() {
+        match policy {
+            CollisionPolicy::Skip => {
+                return Ok(Saved {
+                    path: target,
+                    size: 0,
+                    sha256: None,
+                    resumed_from: 0,
+                })
+            }
+            CollisionPolicy::Error => return Err(already_exists(&target)),
+            _ => {}
+        }
+    }
+    fs::create_dir_all(directory)?;
+    let mut part = if options.resume {
+        PartFile::resume(directory, name, file)?
+    } else {
+        PartFile::create(directory, name)?
+    };
+    let mut attempt = 0;
+    loop {
+        match transfer(camera, file, &mut part, options.verification) {
+            Ok(()) => break,
+            Err(error) if attempt < options.retries && is_retryable(&error) => attempt += 1,
+            Err(error) => {
+                if options.resume && part.size() > 0 {
+                    part.keep();
+                } else {
+                    part.discard();
+                }
+                return Err(error);
+            }
+        }
+    }
+    if options.verification == Verification::Checksum {
+        if let Err(error) = verify_checksum(camera, file, &part) {
+            part.discard();
+            return Err(error);
+        }
+    }
+    let size = part.size();
+    let sha256 = part.digest();
+    let resumed_from = part.resumed_from;
+    let date = if options.preserve_dates {
+        file.modification_date.or(file.creation_date)
+    } else {
+        None
+    };
+    Ok(match part.persist(directory, name, policy, date)? {
+        Persisted::Saved(path) => Saved {
+            path,
+            size,
+            sha256: Some(sha256),
+            resumed_from,
+        },
+        Persisted::Kept(path) => Saved {
+            path,
+            size: 0,
+            sha256: None,
+            resumed_from: 0,
+        },
+    })
+}
+
+/// Receive the rest of `file` into `part`, continuing with ranged reads after the data it
+/// already holds, and unless `verification` is `None`, check the size against
+/// `CameraFile::file_size`.
+fn transfer<C: CameraDevice + ?Sized>(
+    camera: &C,
+    file: &CameraFile,
+    part: &mut PartFile,
+    verification: Verification,
+) -> Result<()> {
+    if part.size() > 0 && part.size() < file.file_size {
+        match resume_transfer(camera, file, part) {
+            Err(Error::Unsupported(_)) => part.restart()?,
+            result => result?,
+        }
+    } else if part.size() > 0 && part.size() != file.file_size {
+        part.restart()?;
+    }
+    if part.size() == 0 {
+        camera.download_file(file, part)?;
+    }
+    // A file size of zero means that the camera did not report one.
+    if verification != Verification::None && file.file_size > 0 && part.size() != file.file_size {
+        let received = part.size();
+        if received > file.file_size {
+            part.restart()?;
+        }
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "received {} of {} bytes of {}",
+                received,
+                file.file_size,
+                file.path()
+            ),
+        )));
+    }
+    Ok(())
+}
+
+fn resume_transfer<C: CameraDevice + ?Sized>(
+    camera: &C,
+    file: &CameraFile,
+    part: &mut PartFile,
+) -> Result<()> {
+    while part.size() < file.file_size {
+        let length = (file.file_size - part.size()).min(RESUME_CHUNK_SIZE);
+        let data = camera.read_data_from_file(file, part.size(), length)?;
+        if data.is_empty() {
+            break;
+        }
+        part.write_all(&data)?;
+    }
+    Ok(())
+}
+
+/// Read `file` from the camera once more, and `part` back from disk, and compare both
+/// with the digest of the data received.
+fn verify_checksum<C: CameraDevice + ?Sized>(
+    camera: &C,
+    file: &CameraFile,
+    part: &PartFile,
+) -> Result<()> {
+    let received = part.digest();
+    let mut sha = Sha256::new();
+    camera.download_file(file, &mut sha)?;
+    let on_camera = sha.finish();
+    let on_disk = sha256::digest_reader(&mut File::open(&part.path)?)?;
+    if on_camera != received || on_disk != received {
+        return Err(Error::malformed(format!(
+            "checksum of {} does not match the file on the camera",
+            file.path()
+        )));
+    }
+    Ok(())
+}
+
+/// Whether a failed transfer may succeed when tried again.
+fn is_retryable(error: &Error) -> bool {
+    matches!(error, Error::Io(_) | Error::Response(_))
+}
+
+/// Where `PartFile::persist` left the content.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Persisted {
+    /// The content was saved at this path.
+    Saved(PathBuf),
+    /// The content was dropped in favor of the existing file at this path.
+    Kept(PathBuf),
+}
+
+/// A temporary file receiving a download, hashing the data written to it.
+pub(crate) struct PartFile {
+    path: PathBuf,
+    file: File,
+    sha: Sha256,
+    size: u64,
+    /// Bytes kept from an earlier download, until the data is dropped with `restart`.
+    resumed_from: u64,
+}
+
+impl PartFile {
+    /// Create a hidden temporary file for `name` in `directory`.
+    pub(crate) fn create(directory: &Path, name: &str) -> Result<PartFile> {
+        let counter = PART_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = directory.join(format!(".{}.{}-{}.part", name, std::process::id(), counter));
+        let file = File::options().write(true).create_new(true).open(&path)?;
+        Ok(PartFile {
+            path,
+            file,
+            sha: Sha256::new(),
+            size: 0,
+            resumed_from: 0,
+        })
+    }
+
+    /// Open the temporary file of an earlier download of `file` to `name` in `directory`,
+    /// or create it, to continue after the data it holds.
+    ///
+    /// The name of the temporary file identifies the camera file by size and date, so that
+    /// data of another file saved under the same name is not mistaken for its start.
+    pub(crate) fn resume(directory: &Path, name: &str, file: &CameraFile) -> Result<PartFile> {
+        let date = file
+            .modification_date
+            .or(file.creation_date)
+            .and_then(|date| date.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |date| date.as_secs());
+        let path = directory.join(format!(".{}.{}-{}.part", name, file.file_size, date));
+        let mut part = PartFile {
+            file: File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?,
+            path,
+            sha: Sha256::new(),
+            size: 0,
+            resumed_from: 0,
+        };
+        io::copy(&mut (&part.file), &mut part.sha)?;
+        part.size = part.file.seek(SeekFrom::End(0))?;
+        part.resumed_from = part.size;
+        Ok(part)
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Drop the data written so far.
+    pub(crate) fn restart(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.sha = Sha256::new();
+        self.size = 0;
+        self.resumed_from = 0;
+        Ok(())
+    }
+
+    /// Leave the temporary file for a later download to resume.
+    pub(crate) fn keep(self) {
+        let _ = self.file.sync_all();
+    }
+
+    /// SHA-256 digest of the data written so far.
+    pub(crate) fn digest(&self) -> [u8; 32] {
+        self.sha.clone().finish()
+    }
+
+    /// Remove the temporary file.
+    pub(crate) fn discard(self) {
+        let _ = fs::remove_file(&self.path);
+    }
+
+    /// Give the content the name `name` in `directory`, resolving a collision with `policy`,
+    /// and set its modification date to `date`.
+    pub(crate) fn persist(
+        self,
+        directory: &Path,
+        name: &str,
+        policy: CollisionPolicy,
+        date: Option<SystemTime>,
+    ) -> Result<Persisted> {
+        let result = self.persist_inner(directory, name, policy, date);
+        if !matches!(result, Ok(Persisted::Saved(_))) {
+            let _ = fs::remove_file(&self.path);
+        }
+        result
+    }
+
+    fn persist_inner(
+        &self,
+        directory: &Path,
+        name: &str,
+        policy: CollisionPolicy,
+        date: Option<SystemTime>,
+    ) -> Result<Persisted> {
+        self.file.sync_all()?;
+        if let Some(date) = date {
+            self.file.set_modified(date)?;
+        }
+        let target = directory.join(name);
+        let (base_name, extension) = split_extension(name);
+        match policy {
+            CollisionPolicy::Overwrite => {
+                fs::rename(&self.path, &target)?;
+                Ok(Persisted::Saved(target))
+            }
+            CollisionPolicy::Skip => match self.link(&target)? {
+                true => Ok(Persisted::Saved(target)),
+                false => Ok(Persisted::Kept(target)),
+            },
+            CollisionPolicy::Error => match self.link(&target)? {
+                true => Ok(Persisted::Saved(target)),
+                false => Err(already_exists(&target)),
+            },
+            CollisionPolicy::RenameWithCounter => {
+                let mut counter = 0;
+                loop {
+                    let candidate = match counter {
+                        0 => target.clone(),
+                        n => directory
+                            .join(join_extension(&format!("{}-{}", base_name, n), extension)),
+                    };
+                    if self.link(&candidate)? {
+                        return Ok(Persisted::Saved(candidate));
+                    }
+                    counter += 1;
+                }
+            }
+            CollisionPolicy::RenameWithHash => {
+                if self.link(&target)? {
+                    return Ok(Persisted::Saved(target));
+                }
+                let hex = sha256::to_hex(&self.digest());
+                let hashed = directory.join(join_extension(
+                    &format!("{}-{}", base_name, &hex[..HASH_SUFFIX_LEN]),
+                    extension,
+                ));
+                match self.link(&hashed)? {
+                    true => Ok(Persisted::Saved(hashed)),
+                    false => Ok(Persisted::Kept(hashed)),
+                }
+            }
+        }
+    }
+
+    /// Move the temporary file to `target` unless that exists, returning whether it moved.
+    ///
+    /// A hard link makes the check and the move atomic; on file systems without hard links
+    /// the check and the rename are separate steps.
+    fn link(&self, target: &Path) -> Result<bool> {
+        match fs::hard_link(&self.path, target) {
+            Ok(()) => {
+                fs::remove_file(&self.path)?;
+                Ok(true)
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+            Err(_) if target.exists() => Ok(false),
+            Err(_) => {
+                fs::rename(&self.path, target)?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl Write for PartFile {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(data)?;
+        self.sha.update(&data[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// A file name a device supplied, made safe to create in a target directory.
+///
+/// Only the last path component is kept, characters that are not allowed in file names
+/// are replaced with `_`, leading dots are removed so that the name can neither refer to
+/// a parent directory nor be hidden, and names reserved on Windows get a `_` prefix.
+pub fn sanitize_file_name(name: &str) -> String {
+    let last = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let mut sanitized = last
+        .chars()
+        .map(|c| match c {
+            ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    sanitized = sanitized
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' '])
+        .to_string();
+    if sanitized.is_empty() {
+        return "untitled".to_string();
+    }
+    let stem = split_extension(&sanitized).0.to_ascii_uppercase();
+    if RESERVED_NAMES.contains(&stem.as_str()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+/// Split a file name into its base name and extension.
+pub(crate) fn split_extension(name: &str) -> (&str, &str) {
+    match name.rsplit_once('.') {
+        Some((base, extension)) if !base.is_empty() => (base, extension),
+        _ => (name, ""),
+    }
+}
+
+fn join_extension(base_name: &str, extension: &str) -> String {
+    if extension.is_empty() {
+        base_name.to_string()
+    } else {
+        format!("{}.{}", base_name, extension)
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn already_exists(path: &Path) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} already exists", path.display()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockCamera;
+    use crate::testing::ScratchDir;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn part(directory: &ScratchDir, data: &[u8]) -> PartFile {
+        let mut part = PartFile::create(directory.path(), "IMG_0001.JPG").unwrap();
+        part.write_all(data).unwrap();
+        part
+    }
+
+    fn persist(directory: &ScratchDir, data: &[u8], policy: CollisionPolicy) -> Result<Persisted> {
+        part(directory, data).persist(directory.path(), "IMG_0001.JPG", policy, None)
+    }
+
+    /// A directory holding `IMG_0001.JPG` with the content `old`.
+    fn taken() -> ScratchDir {
+        let directory = ScratchDir::new("collision");
+        fs::write(directory.path().join("IMG_0001.JPG"), b"old").unwrap();
+        directory
+    }
+
+    fn read(directory: &ScratchDir, name: &str) -> Vec<u8> {
+        fs::read(directory.path().join(name)).unwrap()
+    }
+
+    #[test]
+    fn every_policy_saves_under_a_free_name() {
+        for &policy in &[
+            CollisionPolicy::Overwrite,
+            CollisionPolicy::Skip,
+            CollisionPolicy::RenameWithCounter,
+            CollisionPolicy::RenameWithHash,
+            CollisionPolicy::Error,
+        ] {
+            let directory = ScratchDir::new("free");
+            let persisted = persist(&directory, b"new", policy).unwrap();
+            let target = directory.path().join("IMG_0001.JPG");
+            assert_eq!(persisted, Persisted::Saved(target), "{:?}", policy);
+            assert_eq!(directory.files(), ["IMG_0001.JPG"], "{:?}", policy);
+            assert_eq!(read(&directory, "IMG_0001.JPG"), b"new");
+        }
+    }
+
+    #[test]
+    fn overwrite_replaces_the_existing_file() {
+        let directory = taken();
+        let persisted = persist(&directory, b"new", CollisionPolicy::Overwrite).unwrap();
+        let target = directory.path().join("IMG_0001.JPG");
+        assert_eq!(persisted, Persisted::Saved(target));
+        assert_eq!(directory.files(), ["IMG_0001.JPG"]);
+        assert_eq!(read(&directory, "IMG_0001.JPG"), b"new");
+    }
+
+    #[test]
+    fn skip_keeps_the_existing_file() {
+        let directory = taken();
+        let persisted = persist(&directory, b"new", CollisionPolicy::Skip).unwrap();
+        let target = directory.path().join("IMG_0001.JPG");
+        assert_eq!(persisted, Persisted::Kept(target));
+        assert_eq!(directory.files(), ["IMG_0001.JPG"]);
+        assert_eq!(read(&directory, "IMG_0001.JPG"), b"old");
+    }
+
+    #[test]
+    fn error_fails_and_removes_the_temporary_file() {
+        let directory = taken();
+        match persist(&directory, b"new", CollisionPolicy::Error) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::AlreadyExists),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(directory.files(), ["IMG_0001.JPG"]);
+        assert_eq!(read(&directory, "IMG_0001.JPG"), b"old");
+    }
+
+    #[test]
+    fn rename_with_counter_takes_the_first_free_name() {
+        let directory = taken();
+        fs::write(directory.path().join("IMG_0001-1.JPG"), b"old").unwrap();
+        let persisted = persist(&directory, b"new", CollisionPolicy::RenameWithCounter).unwrap();
+        let target = directory.path().join("IMG_0001-2.JPG");
+        assert_eq!(persisted, Persisted::Saved(target));
+        assert_eq!(
+            directory.files(),
+            ["IMG_0001-1.JPG", "IMG_0001-2.JPG", "IMG_0001.JPG"]
+        );
+        assert_eq!(read(&directory, "IMG_0001-2.JPG"), b"new");
+    }
+
+    #[test]
+    fn rename_with_hash_keeps_a_file_of_the_same_content() {
+        let directory = taken();
+        let hex = sha256::to_hex(&sha256::digest(b"new"));
+        let hashed = directory
+            .path()
+            .join(format!("IMG_0001-{}.JPG", &hex[..HASH_SUFFIX_LEN]));
+        let persisted = persist(&directory, b"new", CollisionPolicy::RenameWithHash).unwrap();
+        assert_eq!(persisted, Persisted::Saved(hashed.clone()));
+        assert_eq!(fs::read(&hashed).unwrap(), b"new");
+
+        let persisted = persist(&directory, b"new", CollisionPolicy::RenameWithHash).unwrap();
+        assert_eq!(persisted, Persisted::Kept(hashed));
+        assert_eq!(directory.files().len(), 2);
+        assert_eq!(read(&directory, "IMG_0001.JPG"), b"old");
+    }
+
+    /// A camera holding `IMG_0001.JPG` with the content `0123456789`, and that file.
+    fn camera() -> (MockCamera, CameraFile) {
+        let camera = MockCamera::new("Mock");
+        let date = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let file = CameraFile {
+            modification_date: Some(date),
+            creation_date: Some(date),
+            ..camera.add_file("IMG_0001.JPG", b"0123456789")
+        };
+        (camera, file)
+    }
+
+    /// Leave `data` as the temporary file of an interrupted download of `file`.
+    fn interrupted(directory: &ScratchDir, file: &CameraFile, data: &[u8]) {
+        let name = format!(".{}.{}-1600000000.part", file.name, file.file_size);
+        fs::write(directory.path().join(name), data).unwrap();
+    }
+
+    #[test]
+    fn download_resumes_after_the_data_of_an_interrupted_download() {
+        let (camera, file) = camera();
+        let directory = ScratchDir::new("resume");
+        // Data the camera does not have shows that the start was not downloaded again.
+        interrupted(&directory, &file, b"abcde");
+        let result = download(&camera, &file, &DownloadOptions::new(directory.path())).unwrap();
+        assert_eq!(result.resumed_from, 5);
+        assert_eq!(result.bytes_written, 10);
+        assert_eq!(result.sha256, Some(sha256::digest(b"abcde56789")));
+        assert_eq!(directory.files(), ["IMG_0001.JPG"]);
+        assert_eq!(read(&directory, "IMG_0001.JPG"), b"abcde56789");
+    }
+
+    #[test]
+    fn download_restarts_when_the_interrupted_data_is_larger_than_the_file() {
+        let (camera, file) = camera();
+        let directory = ScratchDir::new("restart");
+        interrupted(&directory, &file, b"abcdefghijklmnop");
+        let result = download(&camera, &file, &DownloadOptions::new(directory.path())).unwrap();
+        assert_eq!(result.bytes_written, 10);
+        assert_eq!(directory.files(), ["IMG_0001.JPG"]);
+        assert_eq!(read(&directory, "IMG_0001.JPG"), b"0123456789");
+    }
+
+    #[test]
+    fn checksum_mismatch_keeps_the_file_on_the_camera() {
+        let (camera, file) = camera();
+        let options = |directory: &ScratchDir, verification| {
+            DownloadOptions::new(directory.path())
+                .verification(verification)
+                .delete_after_download(true)
+        };
+
+        let directory = ScratchDir::new("checksum-mismatch");
+        interrupted(&directory, &file, b"abcde");
+        let checksum = options(&directory, Verification::Checksum);
+        match download(&camera, &file, &checksum) {
+            Err(Error::Malformed(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(camera.file_names(), ["IMG_0001.JPG"]);
+        assert!(directory.files().is_empty());
+
+        // Checking only the size lets the same data through and deletes the file.
+        interrupted(&directory, &file, b"abcde");
+        let result = download(&camera, &file, &options(&directory, Verification::Size)).unwrap();
+        assert!(result.deleted);
+        assert!(camera.file_names().is_empty());
+    }
+
+    #[test]
+    fn checksum_match_deletes_the_file_from_the_camera() {
+        let (camera, file) = camera();
+        let directory = ScratchDir::new("checksum-match");
+        let options = DownloadOptions::new(directory.path())
+            .verification(Verification::Checksum)
+            .delete_after_download(true);
+        let result = download(&camera, &file, &options).unwrap();
+        assert!(result.deleted);
+        assert!(result.delete_error.is_none());
+        assert!(camera.file_names().is_empty());
+        assert_eq!(read(&directory, "IMG_0001.JPG"), b"0123456789");
+    }
+
+    #[test]
+    fn only_verification_none_accepts_a_size_other_than_reported() {
+        let (camera, file) = camera();
+        let file = CameraFile {
+            file_size: 20,
+            ..file
+        };
+
+        let directory = ScratchDir::new("size");
+        let options = DownloadOptions::new(directory.path()).resume(false);
+        match download(&camera, &file, &options) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(directory.files().is_empty());
+
+        let options = options.verification(Verification::None);
+        let result = download(&camera, &file, &options).unwrap();
+        assert_eq!(result.bytes_written, 10);
+        assert_eq!(read(&directory, "IMG_0001.JPG"), b"0123456789");
+    }
+
+    #[test]
+    fn files_of_unknown_size_are_downloaded_whole() {
+        let (camera, file) = camera();
+        let file = CameraFile {
+            file_size: 0,
+            ..file
+        };
+        for &verification in &[Verification::Size, Verification::Checksum] {
+            let directory = ScratchDir::new("unknown-size");
+            // Data of an interrupted download cannot be resumed without the size.
+            interrupted(&directory, &file, b"abcde");
+            let options = DownloadOptions::new(directory.path()).verification(verification);
+            let result = download(&camera, &file, &options).unwrap();
+            assert_eq!(result.resumed_from, 0, "{:?}", verification);
+            assert_eq!(result.bytes_written, 10, "{:?}", verification);
+            assert_eq!(directory.files(), ["IMG_0001.JPG"]);
+            assert_eq!(read(&directory, "IMG_0001.JPG"), b"0123456789");
+        }
+    }
+
+    #[test]
+    fn sanitize_file_name_keeps_names_inside_the_directory() {
+        assert_eq!(sanitize_file_name("IMG_0001.JPG"), "IMG_0001.JPG");
+        assert_eq!(sanitize_file_name("../x"), "x");
+        assert_eq!(sanitize_file_name("..\\..\\x.jpg"), "x.jpg");
+        assert_eq!(sanitize_file_name("/etc/passwd"), "passwd");
+        assert_eq!(sanitize_file_name(".hidden"), "hidden");
+        assert_eq!(sanitize_file_name("..."), "untitled");
+        assert_eq!(sanitize_file_name(""), "untitled");
+        assert_eq!(sanitize_file_name("dir/"), "untitled");
+    }
+
+    #[test]
+    fn sanitize_file_name_replaces_reserved_names_and_characters() {
+        assert_eq!(sanitize_file_name("CON.jpg"), "_CON.jpg");
+        assert_eq!(sanitize_file_name("lpt1"), "_lpt1");
+        assert_eq!(sanitize_file_name("CONSOLE.jpg"), "CONSOLE.jpg");
+        assert_eq!(sanitize_file_name("a\u{1}b\nc.jpg"), "a_b_c.jpg");
+        assert_eq!(sanitize_file_name("a:b*c?.jpg"), "a_b_c_.jpg");
+        assert_eq!(sanitize_file_name("name. . "), "name");
+    }
+}
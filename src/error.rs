@@ -0,0 +1,79 @@
+use crate::ptp::ResponseCode;
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors returned by the pure-Rust parts of this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O error from the underlying stream or filesystem.
+    Io(io::Error),
+    /// Data received from a device or read from a file is not well formed.
+    Malformed(String),
+    /// A PTP operation completed with a response code other than `OK`.
+    Response(ResponseCode),
+    /// The operation is not supported by the device or backend.
+    Unsupported(String),
+    /// A value passed by the caller is not accepted by the device or is out of range.
+    InvalidValue(String),
+}
+
+/// Result type used by the pure-Rust parts of this crate.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn malformed<S: Into<String>>(message: S) -> Error {
+        Error::Malformed(message.into())
+    }
+
+    /// A copy of the error, for each of several items that failed together.
+    pub(crate) fn duplicate(&self) -> Error {
+        match self {
+            Error::Io(err) => Error::Io(io::Error::new(err.kind(), err.to_string())),
+            Error::Malformed(message) => Error::Malformed(message.clone()),
+            Error::Response(code) => Error::Response(*code),
+            Error::Unsupported(what) => Error::Unsupported(what.clone()),
+            Error::InvalidValue(message) => Error::InvalidValue(message.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Malformed(message) => write!(f, "malformed data: {}", message),
+            Error::Response(code) => write!(f, "device responded with {}", code),
+            Error::Unsupported(what) => write!(f, "{} is not supported by this device", what),
+            Error::InvalidValue(message) => write!(f, "invalid value: {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::Io(err) => return err,
+            Error::Malformed(_) => io::ErrorKind::InvalidData,
+            Error::Unsupported(_) => io::ErrorKind::Unsupported,
+            Error::InvalidValue(_) => io::ErrorKind::InvalidInput,
+            Error::Response(_) => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
@@ -0,0 +1,1118 @@
+//! EXIF metadata read from the TIFF structure embedded in JPEG files or heading TIFF-based files.
+
+use crate::datetime::{self, DateTime};
+use crate::error::{Error, Result};
+use crate::jpeg;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Number of bytes read from the start of a file to find its EXIF metadata.
+pub(crate) const EXIF_HEAD_SIZE: u64 = 1024 * 1024;
+
+/// Tags of the primary image directory.
+pub mod tag {
+    pub const IMAGE_WIDTH: u16 = 0x0100;
+    pub const IMAGE_LENGTH: u16 = 0x0101;
+    pub const IMAGE_DESCRIPTION: u16 = 0x010E;
+    pub const MAKE: u16 = 0x010F;
+    pub const MODEL: u16 = 0x0110;
+    pub const ORIENTATION: u16 = 0x0112;
+    pub const SOFTWARE: u16 = 0x0131;
+    pub const DATE_TIME: u16 = 0x0132;
+    pub const ARTIST: u16 = 0x013B;
+    pub const JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+    pub const JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+    pub const COPYRIGHT: u16 = 0x8298;
+    pub const EXIF_IFD_POINTER: u16 = 0x8769;
+    pub const GPS_INFO_IFD_POINTER: u16 = 0x8825;
+    pub const EXPOSURE_TIME: u16 = 0x829A;
+    pub const F_NUMBER: u16 = 0x829D;
+    pub const EXPOSURE_PROGRAM: u16 = 0x8822;
+    pub const ISO_SPEED_RATINGS: u16 = 0x8827;
+    pub const DATE_TIME_ORIGINAL: u16 = 0x9003;
+    pub const DATE_TIME_DIGITIZED: u16 = 0x9004;
+    pub const OFFSET_TIME: u16 = 0x9010;
+    pub const OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+    pub const OFFSET_TIME_DIGITIZED: u16 = 0x9012;
+    pub const EXPOSURE_BIAS_VALUE: u16 = 0x9204;
+    pub const FLASH: u16 = 0x9209;
+    pub const FOCAL_LENGTH: u16 = 0x920A;
+    pub const MAKER_NOTE: u16 = 0x927C;
+    pub const SUB_SEC_TIME_ORIGINAL: u16 = 0x9291;
+    pub const PIXEL_X_DIMENSION: u16 = 0xA002;
+    pub const PIXEL_Y_DIMENSION: u16 = 0xA003;
+    pub const INTEROPERABILITY_IFD_POINTER: u16 = 0xA005;
+    pub const FOCAL_LENGTH_IN_35MM_FILM: u16 = 0xA405;
+    pub const IMAGE_UNIQUE_ID: u16 = 0xA420;
+    pub const CAMERA_OWNER_NAME: u16 = 0xA430;
+    pub const BODY_SERIAL_NUMBER: u16 = 0xA431;
+    pub const LENS_SPECIFICATION: u16 = 0xA432;
+    pub const LENS_MAKE: u16 = 0xA433;
+    pub const LENS_MODEL: u16 = 0xA434;
+    pub const LENS_SERIAL_NUMBER: u16 = 0xA435;
+}
+
+/// Tags of the GPS directory.
+pub mod gps_tag {
+    pub const GPS_VERSION_ID: u16 = 0x0000;
+    pub const GPS_LATITUDE_REF: u16 = 0x0001;
+    pub const GPS_LATITUDE: u16 = 0x0002;
+    pub const GPS_LONGITUDE_REF: u16 = 0x0003;
+    pub const GPS_LONGITUDE: u16 = 0x0004;
+    pub const GPS_ALTITUDE_REF: u16 = 0x0005;
+    pub const GPS_ALTITUDE: u16 = 0x0006;
+    pub const GPS_TIME_STAMP: u16 = 0x0007;
+    pub const GPS_MAP_DATUM: u16 = 0x0012;
+    pub const GPS_DATE_STAMP: u16 = 0x001D;
+}
+
+const TAG_NAMES: &[(u16, &str)] = &[
+    (tag::IMAGE_WIDTH, "ImageWidth"),
+    (tag::IMAGE_LENGTH, "ImageLength"),
+    (tag::IMAGE_DESCRIPTION, "ImageDescription"),
+    (tag::MAKE, "Make"),
+    (tag::MODEL, "Model"),
+    (tag::ORIENTATION, "Orientation"),
+    (tag::SOFTWARE, "Software"),
+    (tag::DATE_TIME, "DateTime"),
+    (tag::ARTIST, "Artist"),
+    (tag::JPEG_INTERCHANGE_FORMAT, "JPEGInterchangeFormat"),
+    (
+        tag::JPEG_INTERCHANGE_FORMAT_LENGTH,
+        "JPEGInterchangeFormatLength",
+    ),
+    (tag::COPYRIGHT, "Copyright"),
+    (tag::EXIF_IFD_POINTER, "ExifIFDPointer"),
+    (tag::GPS_INFO_IFD_POINTER, "GPSInfoIFDPointer"),
+    (tag::EXPOSURE_TIME, "ExposureTime"),
+    (tag::F_NUMBER, "FNumber"),
+    (tag::EXPOSURE_PROGRAM, "ExposureProgram"),
+    (tag::ISO_SPEED_RATINGS, "ISOSpeedRatings"),
+    (tag::DATE_TIME_ORIGINAL, "DateTimeOriginal"),
+    (tag::DATE_TIME_DIGITIZED, "DateTimeDigitized"),
+    (tag::OFFSET_TIME, "OffsetTime"),
+    (tag::OFFSET_TIME_ORIGINAL, "OffsetTimeOriginal"),
+    (tag::OFFSET_TIME_DIGITIZED, "OffsetTimeDigitized"),
+    (tag::EXPOSURE_BIAS_VALUE, "ExposureBiasValue"),
+    (tag::FLASH, "Flash"),
+    (tag::FOCAL_LENGTH, "FocalLength"),
+    (tag::MAKER_NOTE, "MakerNote"),
+    (tag::SUB_SEC_TIME_ORIGINAL, "SubSecTimeOriginal"),
+    (tag::PIXEL_X_DIMENSION, "PixelXDimension"),
+    (tag::PIXEL_Y_DIMENSION, "PixelYDimension"),
+    (
+        tag::INTEROPERABILITY_IFD_POINTER,
+        "InteroperabilityIFDPointer",
+    ),
+    (tag::FOCAL_LENGTH_IN_35MM_FILM, "FocalLengthIn35mmFilm"),
+    (tag::IMAGE_UNIQUE_ID, "ImageUniqueID"),
+    (tag::CAMERA_OWNER_NAME, "CameraOwnerName"),
+    (tag::BODY_SERIAL_NUMBER, "BodySerialNumber"),
+    (tag::LENS_SPECIFICATION, "LensSpecification"),
+    (tag::LENS_MAKE, "LensMake"),
+    (tag::LENS_MODEL, "LensModel"),
+    (tag::LENS_SERIAL_NUMBER, "LensSerialNumber"),
+];
+
+const GPS_TAG_NAMES: &[(u16, &str)] = &[
+    (gps_tag::GPS_VERSION_ID, "GPSVersionID"),
+    (gps_tag::GPS_LATITUDE_REF, "GPSLatitudeRef"),
+    (gps_tag::GPS_LATITUDE, "GPSLatitude"),
+    (gps_tag::GPS_LONGITUDE_REF, "GPSLongitudeRef"),
+    (gps_tag::GPS_LONGITUDE, "GPSLongitude"),
+    (gps_tag::GPS_ALTITUDE_REF, "GPSAltitudeRef"),
+    (gps_tag::GPS_ALTITUDE, "GPSAltitude"),
+    (gps_tag::GPS_TIME_STAMP, "GPSTimeStamp"),
+    (gps_tag::GPS_MAP_DATUM, "GPSMapDatum"),
+    (gps_tag::GPS_DATE_STAMP, "GPSDateStamp"),
+];
+
+/// Maximum number of directories followed, guarding against offset loops.
+const MAX_DIRECTORIES: usize = 16;
+
+/// The image file directory a field was read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Ifd {
+    /// IFD0, describing the main image.
+    Primary,
+    /// IFD1, describing the thumbnail.
+    Thumbnail,
+    Exif,
+    Gps,
+    Interoperability,
+}
+
+/// The value of a field, by TIFF field type.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+}
+
+impl Value {
+    /// The text of an ASCII value, without trailing NULs and spaces.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Ascii(text) => Some(text.trim_end_matches(['\0', ' '])),
+            _ => None,
+        }
+    }
+
+    /// The `index`th component of a numeric value, as a float.
+    pub fn as_f64(&self, index: usize) -> Option<f64> {
+        let ratio = |n: f64, d: f64| if d == 0.0 { None } else { Some(n / d) };
+        match self {
+            Value::Byte(v) => v.get(index).map(|&n| f64::from(n)),
+            Value::Short(v) => v.get(index).map(|&n| f64::from(n)),
+            Value::Long(v) => v.get(index).map(|&n| f64::from(n)),
+            Value::SByte(v) => v.get(index).map(|&n| f64::from(n)),
+            Value::SShort(v) => v.get(index).map(|&n| f64::from(n)),
+            Value::SLong(v) => v.get(index).map(|&n| f64::from(n)),
+            Value::Float(v) => v.get(index).map(|&n| f64::from(n)),
+            Value::Double(v) => v.get(index).copied(),
+            Value::Rational(v) => v
+                .get(index)
+                .and_then(|&(n, d)| ratio(f64::from(n), f64::from(d))),
+            Value::SRational(v) => v
+                .get(index)
+                .and_then(|&(n, d)| ratio(f64::from(n), f64::from(d))),
+            Value::Ascii(_) | Value::Undefined(_) => None,
+        }
+    }
+
+    /// The `index`th component of an integer value.
+    pub fn as_u32(&self, index: usize) -> Option<u32> {
+        match self {
+            Value::Byte(v) => v.get(index).map(|&n| u32::from(n)),
+            Value::Short(v) => v.get(index).map(|&n| u32::from(n)),
+            Value::Long(v) => v.get(index).copied(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    /// Text as is, numbers separated by spaces, and rationals as decimals.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn join<T: fmt::Display>(f: &mut fmt::Formatter, values: &[T]) -> fmt::Result {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{}", value)?;
+            }
+            Ok(())
+        }
+        match self {
+            Value::Ascii(_) => f.write_str(self.as_str().unwrap_or_default()),
+            Value::Byte(v) | Value::Undefined(v) => join(f, v),
+            Value::Short(v) => join(f, v),
+            Value::Long(v) => join(f, v),
+            Value::SByte(v) => join(f, v),
+            Value::SShort(v) => join(f, v),
+            Value::SLong(v) => join(f, v),
+            Value::Float(v) => join(f, v),
+            Value::Double(v) => join(f, v),
+            Value::Rational(v) => join(
+                f,
+                &v.iter()
+                    .map(|&(n, d)| format_ratio(f64::from(n), f64::from(d)))
+                    .collect::<Vec<_>>(),
+            ),
+            Value::SRational(v) => join(
+                f,
+                &v.iter()
+                    .map(|&(n, d)| format_ratio(f64::from(n), f64::from(d)))
+                    .collect::<Vec<_>>(),
+            ),
+        }
+    }
+}
+
+fn format_ratio(n: f64, d: f64) -> String {
+    if d == 0.0 {
+        return "0".into();
+    }
+    let value = n / d;
+    if value.fract() == 0.0 {
+        format!("{}", value)
+    } else {
+        format!("{:.1}", value)
+    }
+}
+
+/// A field of an image file directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub ifd: Ifd,
+    pub tag: u16,
+    pub value: Value,
+    /// Offset of the value in the data passed to `read`.
+    pub offset: usize,
+}
+
+impl Field {
+    /// The name of the field's tag, such as `DateTimeOriginal`.
+    pub fn name(&self) -> Option<&'static str> {
+        tag_name(self.ifd, self.tag)
+    }
+}
+
+/// EXIF metadata of an image.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Exif {
+    /// Whether the TIFF structure is big-endian (`MM`).
+    pub big_endian: bool,
+    /// Offset of the TIFF header in the data passed to `read`.
+    pub tiff_offset: usize,
+    pub fields: Vec<Field>,
+}
+
+impl Exif {
+    /// The value of `tag` in `ifd`.
+    pub fn get_in(&self, ifd: Ifd, tag: u16) -> Option<&Value> {
+        self.fields
+            .iter()
+            .find(|field| field.ifd == ifd && field.tag == tag)
+            .map(|field| &field.value)
+    }
+
+    /// The value of `tag` in the primary image or EXIF directory.
+    pub fn get(&self, tag: u16) -> Option<&Value> {
+        self.get_in(Ifd::Primary, tag)
+            .or_else(|| self.get_in(Ifd::Exif, tag))
+    }
+
+    /// The value of the field named `name`, such as `LensModel` or `GPSLatitude`.
+    pub fn get_by_name(&self, name: &str) -> Option<&Value> {
+        [Ifd::Primary, Ifd::Exif, Ifd::Gps, Ifd::Interoperability]
+            .iter()
+            .flat_map(|&ifd| self.fields.iter().filter(move |field| field.ifd == ifd))
+            .find(|field| field.name().is_some_and(|n| n.eq_ignore_ascii_case(name)))
+            .map(|field| &field.value)
+    }
+
+    /// The text of an ASCII field in the primary image or EXIF directory.
+    pub fn string(&self, tag: u16) -> Option<String> {
+        self.get(tag)
+            .and_then(Value::as_str)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+    }
+
+    /// When the picture was taken, from `DateTimeOriginal`, `DateTimeDigitized` or `DateTime`.
+    pub fn date_time_original(&self) -> Option<DateTime> {
+        [
+            tag::DATE_TIME_ORIGINAL,
+            tag::DATE_TIME_DIGITIZED,
+            tag::DATE_TIME,
+        ]
+        .iter()
+        .find_map(|&tag| {
+            self.get(tag)
+                .and_then(Value::as_str)
+                .and_then(parse_date_time)
+        })
+    }
+}
+
+/// The name of a tag, such as `Make`.
+pub fn tag_name(ifd: Ifd, tag: u16) -> Option<&'static str> {
+    let names = if ifd == Ifd::Gps {
+        GPS_TAG_NAMES
+    } else {
+        TAG_NAMES
+    };
+    names.iter().find(|(t, _)| *t == tag).map(|(_, name)| *name)
+}
+
+/// Parse the EXIF date form `YYYY:MM:DD hh:mm:ss`.
+pub fn parse_date_time(value: &str) -> Option<DateTime> {
+    let bytes = value.as_bytes();
+    if bytes.len() < 19 || bytes[4] != b':' || bytes[7] != b':' || bytes[13] != b':' {
+        return None;
+    }
+    DateTime::new(
+        datetime::digits(&bytes[0..4])? as i32,
+        datetime::digits(&bytes[5..7])? as u8,
+        datetime::digits(&bytes[8..10])? as u8,
+        datetime::digits(&bytes[11..13])? as u8,
+        datetime::digits(&bytes[14..16])? as u8,
+        datetime::digits(&bytes[17..19])? as u8,
+    )
+}
+
+/// Format a date in the EXIF form `YYYY:MM:DD hh:mm:ss`.
+pub fn format_date_time(date: &DateTime) -> String {
+    format!(
+        "{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+        date.year, date.month, date.day, date.hour, date.minute, date.second
+    )
+}
+
+/// Read EXIF metadata from the start of a JPEG file or a TIFF-based file such as most RAW
+/// formats.
+///
+/// `data` may be truncated; fields whose values lie past its end are left out.
+pub fn read(data: &[u8]) -> Result<Exif> {
+    if data.starts_with(&[0xFF, jpeg::SOI]) {
+        let offset = find_jpeg_exif(data)?
+            .ok_or_else(|| Error::malformed("the JPEG data has no EXIF segment"))?;
+        read_tiff(data, offset)
+    } else {
+        read_tiff(data, 0)
+    }
+}
+
+/// Read the TIFF structure starting at `offset` in `data`.
+pub fn read_tiff(data: &[u8], offset: usize) -> Result<Exif> {
+    let tiff = data
+        .get(offset..)
+        .filter(|tiff| tiff.len() >= 8)
+        .ok_or_else(|| Error::malformed("truncated TIFF header"))?;
+    let big_endian = match &tiff[..2] {
+        b"II" => false,
+        b"MM" => true,
+        _ => return Err(Error::malformed("missing TIFF byte order mark")),
+    };
+    let reader = TiffReader { tiff, big_endian };
+    let mut exif = Exif {
+        big_endian,
+        tiff_offset: offset,
+        fields: Vec::new(),
+    };
+    let mut queue = vec![(Ifd::Primary, reader.u32(4).unwrap_or(0) as usize)];
+    let mut visited = Vec::new();
+    while let Some((ifd, ifd_offset)) = queue.pop() {
+        if ifd_offset == 0 || visited.contains(&ifd_offset) || visited.len() >= MAX_DIRECTORIES {
+            continue;
+        }
+        visited.push(ifd_offset);
+        let next = reader.read_directory(ifd, ifd_offset, offset, &mut exif.fields);
+        for field in exif.fields.iter().filter(|field| field.ifd == ifd) {
+            let pointer = field.value.as_u32(0).map(|pointer| pointer as usize);
+            match (ifd, field.tag, pointer) {
+                (Ifd::Primary, tag::EXIF_IFD_POINTER, Some(pointer)) => {
+                    queue.push((Ifd::Exif, pointer))
+                }
+                (Ifd::Primary, tag::GPS_INFO_IFD_POINTER, Some(pointer)) => {
+                    queue.push((Ifd::Gps, pointer))
+                }
+                (Ifd::Exif, tag::INTEROPERABILITY_IFD_POINTER, Some(pointer)) => {
+                    queue.push((Ifd::Interoperability, pointer))
+                }
+                _ => {}
+            }
+        }
+        if ifd == Ifd::Primary {
+            if let Some(next) = next {
+                queue.push((Ifd::Thumbnail, next));
+            }
+        }
+    }
+    Ok(exif)
+}
+
+/// Offset of the TIFF header in the EXIF APP1 segment of a JPEG file.
+///
+/// Segments are walked one at a time so that data truncated after the EXIF segment is
+/// still accepted.
+fn find_jpeg_exif(data: &[u8]) -> Result<Option<usize>> {
+    let mut offset = 2;
+    while offset + 4 <= data.len() {
+        if data[offset] != 0xFF {
+            return Err(Error::malformed(format!(
+                "expected a JPEG marker at offset {}",
+                offset
+            )));
+        }
+        let marker = data[offset + 1];
+        if marker == 0xFF {
+            offset += 1;
+            continue;
+        }
+        if marker == jpeg::SOS || marker == jpeg::EOI {
+            break;
+        }
+        let length = usize::from(u16::from_be_bytes([data[offset + 2], data[offset + 3]]));
+        let payload = offset + 4;
+        if marker == jpeg::APP1 && data[payload..].starts_with(b"Exif\0\0") {
+            return Ok(Some(payload + 6));
+        }
+        offset += 2 + length;
+    }
+    Ok(None)
+}
+
+struct TiffReader<'a> {
+    tiff: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> TiffReader<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Option<&'a [u8]> {
+        self.tiff.get(offset..offset.checked_add(len)?)
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let b = self.bytes(offset, 2)?;
+        Some(if self.big_endian {
+            u16::from_be_bytes([b[0], b[1]])
+        } else {
+            u16::from_le_bytes([b[0], b[1]])
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let b = self.bytes(offset, 4)?;
+        Some(if self.big_endian {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        })
+    }
+
+    /// Append the fields of the directory at `offset` and return the offset of the next one.
+    fn read_directory(
+        &self,
+        ifd: Ifd,
+        offset: usize,
+        base: usize,
+        fields: &mut Vec<Field>,
+    ) -> Option<usize> {
+        let count = usize::from(self.u16(offset)?);
+        for i in 0..count {
+            let entry = offset + 2 + i * 12;
+            let (tag, field_type, components) =
+                match (self.u16(entry), self.u16(entry + 2), self.u32(entry + 4)) {
+                    (Some(tag), Some(field_type), Some(components)) => {
+                        (tag, field_type, components as usize)
+                    }
+                    _ => return None,
+                };
+            let size = match type_size(field_type) {
+                Some(size) => size,
+                None => continue,
+            };
+            let len = match size.checked_mul(components) {
+                Some(len) => len,
+                None => continue,
+            };
+            let value_offset = if len <= 4 {
+                entry + 8
+            } else {
+                match self.u32(entry + 8) {
+                    Some(value_offset) => value_offset as usize,
+                    None => continue,
+                }
+            };
+            if let Some(value) = self.value(field_type, value_offset, components) {
+                fields.push(Field {
+                    ifd,
+                    tag,
+                    value,
+                    offset: base + value_offset,
+                });
+            }
+        }
+        match self.u32(offset + 2 + count * 12)? {
+            0 => None,
+            next => Some(next as usize),
+        }
+    }
+
+    fn value(&self, field_type: u16, offset: usize, count: usize) -> Option<Value> {
+        let size = type_size(field_type)?;
+        let bytes = self.bytes(offset, size.checked_mul(count)?)?;
+        let u16s = || (0..count).map(|i| self.u16(offset + i * 2).unwrap_or(0));
+        let u32s = || (0..count).map(|i| self.u32(offset + i * 4).unwrap_or(0));
+        let u32_pairs = || {
+            (0..count).map(|i| {
+                (
+                    self.u32(offset + i * 8).unwrap_or(0),
+                    self.u32(offset + i * 8 + 4).unwrap_or(0),
+                )
+            })
+        };
+        Some(match field_type {
+            1 => Value::Byte(bytes.to_vec()),
+            2 => Value::Ascii(
+                String::from_utf8_lossy(bytes.split(|&b| b == 0).next().unwrap_or_default())
+                    .into_owned(),
+            ),
+            3 => Value::Short(u16s().collect()),
+            4 => Value::Long(u32s().collect()),
+            5 => Value::Rational(u32_pairs().collect()),
+            6 => Value::SByte(bytes.iter().map(|&b| b as i8).collect()),
+            7 => Value::Undefined(bytes.to_vec()),
+            8 => Value::SShort(u16s().map(|n| n as i16).collect()),
+            9 => Value::SLong(u32s().map(|n| n as i32).collect()),
+            10 => Value::SRational(u32_pairs().map(|(n, d)| (n as i32, d as i32)).collect()),
+            11 => Value::Float(u32s().map(f32::from_bits).collect()),
+            12 => Value::Double(
+                (0..count)
+                    .map(|i| {
+                        let high = u64::from(self.u32(offset + i * 8).unwrap_or(0));
+                        let low = u64::from(self.u32(offset + i * 8 + 4).unwrap_or(0));
+                        let bits = if self.big_endian {
+                            high << 32 | low
+                        } else {
+                            low << 32 | high
+                        };
+                        f64::from_bits(bits)
+                    })
+                    .collect(),
+            ),
+            _ => return None,
+        })
+    }
+}
+
+/// Size in bytes of one component of a TIFF field type.
+fn type_size(field_type: u16) -> Option<usize> {
+    match field_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+/// Changes to the EXIF metadata of a JPEG file or a TIFF-based file, such as most RAW
+/// formats.
+///
+/// Existing data is never moved, so that offsets elsewhere in the file, such as those of
+/// maker notes and RAW image data, stay valid. A value that keeps its type and size is
+/// overwritten in place. A directory that gains fields or larger values is written again
+/// after the end of the TIFF data, and the pointer to it is updated. In JPEG files the EXIF
+/// segment grows accordingly, and it must stay below 64 KiB.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExifEditor {
+    changes: Vec<(Ifd, u16, Value)>,
+}
+
+impl ExifEditor {
+    pub fn new() -> ExifEditor {
+        ExifEditor::default()
+    }
+
+    /// Set `tag` in `ifd` to `value`, adding the field, and the directory, if needed.
+    /// Fields of the thumbnail directory cannot be set.
+    pub fn set(mut self, ifd: Ifd, tag: u16, value: Value) -> ExifEditor {
+        self.changes.retain(|(i, t, _)| !(*i == ifd && *t == tag));
+        self.changes.push((ifd, tag, value));
+        self
+    }
+
+    /// Whether no change has been set.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The content of a file with the changes applied. A JPEG file without EXIF metadata
+    /// gains an EXIF segment.
+    pub fn apply(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let (mut head, tail) = self.apply_head(data, data.len() as u64)?;
+        head.extend_from_slice(&tail);
+        Ok(head)
+    }
+
+    /// Apply the changes to the file at `path`, replacing it atomically and keeping its
+    /// modification date. Only the first MiB is read in memory, where the EXIF metadata must lie;
+    /// the rest of the file is copied as is. Nothing is written without changes.
+    pub fn apply_to_file(&self, path: &Path) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let mut head = Vec::new();
+        (&mut file).take(EXIF_HEAD_SIZE).read_to_end(&mut head)?;
+        let (edited, appended) = self.apply_head(&head, metadata.len())?;
+        let modified = metadata.modified().ok();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let temporary = path.with_file_name(format!(".{}.{}.exif", name, std::process::id()));
+        let written = File::create(&temporary).and_then(|mut out| {
+            out.write_all(&edited)?;
+            io::copy(&mut file, &mut out)?;
+            out.write_all(&appended)?;
+            if let Some(modified) = modified {
+                out.set_modified(modified)?;
+            }
+            out.sync_all()
+        });
+        if let Err(err) = written.and_then(|()| fs::rename(&temporary, path)) {
+            let _ = fs::remove_file(&temporary);
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    /// The changes applied to `head`, the start of a file of `length` bytes: the new start
+    /// of the file, and the bytes to append after its end.
+    fn apply_head(&self, head: &[u8], length: u64) -> Result<(Vec<u8>, Vec<u8>)> {
+        if !head.starts_with(&[0xFF, jpeg::SOI]) {
+            let mut tiff = head.to_vec();
+            let length = usize::try_from(length)
+                .map_err(|_| Error::InvalidValue("the TIFF data exceeds 4 GiB".into()))?;
+            let appended = self.edit_tiff(&mut tiff, length)?;
+            return Ok((tiff, appended));
+        }
+        let complete = head.len() as u64 == length;
+        let (segment, mut tiff) = match find_jpeg_exif(head)? {
+            Some(offset) => {
+                // The TIFF header follows the marker, the length and the `Exif\0\0` header.
+                let start = offset - 10;
+                let length = usize::from(u16::from_be_bytes([head[start + 2], head[start + 3]]));
+                if !complete && start + 2 + length > head.len() {
+                    return Err(Error::Unsupported(
+                        "editing EXIF metadata past the start of a file".into(),
+                    ));
+                }
+                let end = (start + 2 + length).min(head.len());
+                if end < offset {
+                    return Err(Error::malformed("EXIF segment shorter than its header"));
+                }
+                (start..end, head[offset..end].to_vec())
+            }
+            None => {
+                // After a JFIF segment, which must come first, or else right after SOI.
+                let mut start = 2;
+                if head.get(2..4) == Some(&[0xFF, 0xE0][..]) {
+                    if let Some(length) = head.get(4..6) {
+                        start = (4 + usize::from(u16::from_be_bytes([length[0], length[1]])))
+                            .min(head.len());
+                    }
+                }
+                let tiff = b"MM\0\x2A\0\0\0\x08\0\0\0\0\0\0".to_vec();
+                (start..start, tiff)
+            }
+        };
+        let end = tiff.len();
+        let appended = self.edit_tiff(&mut tiff, end)?;
+        tiff.extend_from_slice(&appended);
+        let length = 2 + 6 + tiff.len();
+        if length > usize::from(u16::MAX) {
+            return Err(Error::InvalidValue(
+                "the EXIF metadata does not fit in a JPEG segment".into(),
+            ));
+        }
+        let mut out = Vec::with_capacity(head.len() + tiff.len());
+        out.extend_from_slice(&head[..segment.start]);
+        out.extend_from_slice(&[0xFF, jpeg::APP1]);
+        out.extend_from_slice(&(length as u16).to_be_bytes());
+        out.extend_from_slice(b"Exif\0\0");
+        out.extend_from_slice(&tiff);
+        out.extend_from_slice(&head[segment.end..]);
+        Ok((out, Vec::new()))
+    }
+
+    /// Apply the changes to `tiff`, the start of TIFF data of `length` bytes, returning the
+    /// directories and values to append after its end.
+    fn edit_tiff(&self, tiff: &mut [u8], length: usize) -> Result<Vec<u8>> {
+        let big_endian = match tiff.get(..2) {
+            Some(b"II") => false,
+            Some(b"MM") => true,
+            _ => return Err(Error::malformed("missing TIFF byte order mark")),
+        };
+        if tiff.len() < 8 {
+            return Err(Error::malformed("truncated TIFF header"));
+        }
+        let editor = TiffEditor { big_endian };
+        let mut appended = Vec::new();
+        let mut changes = self.changes.clone();
+        // Children first, since writing a directory anew changes the pointer in its parent.
+        for &ifd in &[Ifd::Interoperability, Ifd::Gps, Ifd::Exif, Ifd::Primary] {
+            let (pending, rest): (Vec<_>, Vec<_>) =
+                changes.into_iter().partition(|(i, _, _)| *i == ifd);
+            changes = rest;
+            if pending.is_empty() {
+                continue;
+            }
+            let pending = pending
+                .into_iter()
+                .map(|(_, tag, value)| (tag, editor.encode(&value)))
+                .collect::<Vec<_>>();
+            let location = editor.directory_offset(tiff, ifd);
+            let directory = match location {
+                Some(offset) => Some(
+                    editor
+                        .read_directory(tiff, offset)
+                        .ok_or_else(|| Error::malformed("truncated image file directory"))?,
+                ),
+                None => None,
+            };
+            if let (Some(offset), Some(directory)) = (location, &directory) {
+                if editor.patch_in_place(tiff, offset, directory, &pending) {
+                    continue;
+                }
+            }
+            let (entries, next) = directory.unwrap_or_default();
+            let offset = editor.write_directory(&mut appended, length, entries, next, pending)?;
+            match ifd {
+                Ifd::Primary => editor.put_u32(tiff, 4, offset),
+                Ifd::Exif => changes.push((
+                    Ifd::Primary,
+                    tag::EXIF_IFD_POINTER,
+                    Value::Long(vec![offset]),
+                )),
+                Ifd::Gps => changes.push((
+                    Ifd::Primary,
+                    tag::GPS_INFO_IFD_POINTER,
+                    Value::Long(vec![offset]),
+                )),
+                Ifd::Interoperability => changes.push((
+                    Ifd::Exif,
+                    tag::INTEROPERABILITY_IFD_POINTER,
+                    Value::Long(vec![offset]),
+                )),
+                Ifd::Thumbnail => unreachable!("thumbnail fields are rejected"),
+            }
+        }
+        if let Some((ifd, tag, _)) = changes.first() {
+            return Err(Error::Unsupported(format!(
+                "setting field 0x{:04X} of the {:?} directory",
+                tag, ifd
+            )));
+        }
+        Ok(appended)
+    }
+}
+
+/// A raw directory entry: tag, field type, component count and the value or its offset.
+type Entry = (u16, u16, u32, [u8; 4]);
+
+/// Field type, component count and bytes of an encoded value.
+type Encoded = (u16, u32, Vec<u8>);
+
+struct TiffEditor {
+    big_endian: bool,
+}
+
+impl TiffEditor {
+    fn reader<'a>(&self, tiff: &'a [u8]) -> TiffReader<'a> {
+        TiffReader {
+            tiff,
+            big_endian: self.big_endian,
+        }
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    fn put_u32(&self, tiff: &mut [u8], offset: usize, value: u32) {
+        tiff[offset..offset + 4].copy_from_slice(&self.u32_bytes(value));
+    }
+
+    /// Offset of the directory `ifd`, if the file has it.
+    fn directory_offset(&self, tiff: &[u8], ifd: Ifd) -> Option<usize> {
+        let reader = self.reader(tiff);
+        let pointer = |parent: usize, wanted: u16| {
+            let (entries, _) = self.read_directory(tiff, parent)?;
+            let (_, _, _, value) = entries.into_iter().find(|entry| entry.0 == wanted)?;
+            Some(self.reader(&value).u32(0)? as usize).filter(|&offset| offset != 0)
+        };
+        let primary = reader.u32(4).map(|offset| offset as usize)?;
+        match ifd {
+            Ifd::Primary => Some(primary),
+            Ifd::Exif => pointer(primary, tag::EXIF_IFD_POINTER),
+            Ifd::Gps => pointer(primary, tag::GPS_INFO_IFD_POINTER),
+            Ifd::Interoperability => pointer(
+                pointer(primary, tag::EXIF_IFD_POINTER)?,
+                tag::INTEROPERABILITY_IFD_POINTER,
+            ),
+            Ifd::Thumbnail => None,
+        }
+    }
+
+    /// The entries and the next directory pointer of the directory at `offset`.
+    fn read_directory(&self, tiff: &[u8], offset: usize) -> Option<(Vec<Entry>, u32)> {
+        let reader = self.reader(tiff);
+        let count = usize::from(reader.u16(offset)?);
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let entry = offset + 2 + i * 12;
+            let value = reader.bytes(entry + 8, 4)?;
+            entries.push((
+                reader.u16(entry)?,
+                reader.u16(entry + 2)?,
+                reader.u32(entry + 4)?,
+                [value[0], value[1], value[2], value[3]],
+            ));
+        }
+        Some((entries, reader.u32(offset + 2 + count * 12)?))
+    }
+
+    /// Overwrite the values of `pending` in the directory at `offset` if every one of them
+    /// has a field of the same type and size. Returns whether it did.
+    fn patch_in_place(
+        &self,
+        tiff: &mut [u8],
+        offset: usize,
+        directory: &(Vec<Entry>, u32),
+        pending: &[(u16, Encoded)],
+    ) -> bool {
+        let mut targets = Vec::new();
+        for (tag, (field_type, count, bytes)) in pending {
+            let found = directory
+                .0
+                .iter()
+                .position(|entry| entry.0 == *tag && entry.1 == *field_type && entry.2 == *count);
+            let index = match found {
+                Some(index) => index,
+                None => return false,
+            };
+            let value_offset = if bytes.len() <= 4 {
+                offset + 2 + index * 12 + 8
+            } else {
+                match self.reader(&directory.0[index].3).u32(0) {
+                    Some(value_offset) => value_offset as usize,
+                    None => return false,
+                }
+            };
+            if value_offset + bytes.len() > tiff.len() {
+                return false;
+            }
+            targets.push((value_offset, bytes));
+        }
+        for (value_offset, bytes) in targets {
+            tiff[value_offset..value_offset + bytes.len()].copy_from_slice(bytes);
+        }
+        true
+    }
+
+    /// Append a directory with `entries`, where `pending` replaces fields of the same tag,
+    /// to `appended`, the bytes following TIFF data of `length` bytes, and return its offset.
+    fn write_directory(
+        &self,
+        appended: &mut Vec<u8>,
+        length: usize,
+        mut entries: Vec<Entry>,
+        next: u32,
+        pending: Vec<(u16, Encoded)>,
+    ) -> Result<u32> {
+        for (tag, (field_type, count, bytes)) in pending {
+            let mut value = [0; 4];
+            if bytes.len() <= 4 {
+                value[..bytes.len()].copy_from_slice(&bytes);
+            } else {
+                align(appended, length);
+                value = self.u32_bytes(tiff_offset(appended, length)?);
+                appended.extend_from_slice(&bytes);
+            }
+            entries.retain(|entry| entry.0 != tag);
+            entries.push((tag, field_type, count, value));
+        }
+        entries.sort_by_key(|entry| entry.0);
+        align(appended, length);
+        let offset = tiff_offset(appended, length)?;
+        appended.extend_from_slice(&self.u16_bytes(entries.len() as u16));
+        for (tag, field_type, count, value) in entries {
+            appended.extend_from_slice(&self.u16_bytes(tag));
+            appended.extend_from_slice(&self.u16_bytes(field_type));
+            appended.extend_from_slice(&self.u32_bytes(count));
+            appended.extend_from_slice(&value);
+        }
+        appended.extend_from_slice(&self.u32_bytes(next));
+        Ok(offset)
+    }
+
+    fn encode(&self, value: &Value) -> Encoded {
+        let mut bytes = Vec::new();
+        let (field_type, count) = match value {
+            Value::Byte(v) => {
+                bytes.extend_from_slice(v);
+                (1, v.len())
+            }
+            Value::Ascii(text) => {
+                bytes.extend_from_slice(text.as_bytes());
+                bytes.push(0);
+                (2, bytes.len())
+            }
+            Value::Short(v) => {
+                v.iter()
+                    .for_each(|&n| bytes.extend_from_slice(&self.u16_bytes(n)));
+                (3, v.len())
+            }
+            Value::Long(v) => {
+                v.iter()
+                    .for_each(|&n| bytes.extend_from_slice(&self.u32_bytes(n)));
+                (4, v.len())
+            }
+            Value::Rational(v) => {
+                for &(n, d) in v {
+                    bytes.extend_from_slice(&self.u32_bytes(n));
+                    bytes.extend_from_slice(&self.u32_bytes(d));
+                }
+                (5, v.len())
+            }
+            Value::SByte(v) => {
+                bytes.extend(v.iter().map(|&n| n as u8));
+                (6, v.len())
+            }
+            Value::Undefined(v) => {
+                bytes.extend_from_slice(v);
+                (7, v.len())
+            }
+            Value::SShort(v) => {
+                v.iter()
+                    .for_each(|&n| bytes.extend_from_slice(&self.u16_bytes(n as u16)));
+                (8, v.len())
+            }
+            Value::SLong(v) => {
+                v.iter()
+                    .for_each(|&n| bytes.extend_from_slice(&self.u32_bytes(n as u32)));
+                (9, v.len())
+            }
+            Value::SRational(v) => {
+                for &(n, d) in v {
+                    bytes.extend_from_slice(&self.u32_bytes(n as u32));
+                    bytes.extend_from_slice(&self.u32_bytes(d as u32));
+                }
+                (10, v.len())
+            }
+            Value::Float(v) => {
+                v.iter()
+                    .for_each(|&n| bytes.extend_from_slice(&self.u32_bytes(n.to_bits())));
+                (11, v.len())
+            }
+            Value::Double(v) => {
+                for &n in v {
+                    let bits = n.to_bits();
+                    if self.big_endian {
+                        bytes.extend_from_slice(&bits.to_be_bytes());
+                    } else {
+                        bytes.extend_from_slice(&bits.to_le_bytes());
+                    }
+                }
+                (12, v.len())
+            }
+        };
+        (field_type, count as u32, bytes)
+    }
+}
+
+/// Pad `tiff` to an even length, as TIFF requires of value and directory offsets.
+/// Pad `appended`, the bytes following TIFF data of `length` bytes, to a word boundary.
+fn align(appended: &mut Vec<u8>, length: usize) {
+    if (length + appended.len()) % 2 == 1 {
+        appended.push(0);
+    }
+}
+
+/// The offset of the end of `appended`, the bytes following TIFF data of `length` bytes.
+fn tiff_offset(appended: &[u8], length: usize) -> Result<u32> {
+    u32::try_from(length + appended.len())
+        .map_err(|_| Error::InvalidValue("the TIFF data exceeds 4 GiB".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ascii, exif_segment, jpeg, long, tiff_header, write_ifd, ScratchDir};
+
+    #[test]
+    fn apply_rejects_exif_segment_shorter_than_its_header() {
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x00];
+        data.extend_from_slice(b"Exif\0\0II*\0\x08\0\0\0\0\0\0\0\0\0");
+        let editor = ExifEditor::new().set(Ifd::Primary, tag::ORIENTATION, Value::Short(vec![6]));
+        assert!(matches!(editor.apply(&data), Err(Error::Malformed(_))));
+    }
+
+    #[test]
+    fn apply_to_file_reads_the_start_of_large_files_only() {
+        let mut tiff = tiff_header(8);
+        write_ifd(
+            &mut tiff,
+            8,
+            vec![
+                ascii(tag::MAKE, "Canon"),
+                long(tag::EXIF_IFD_POINTER, &[0x40]),
+            ],
+            0,
+        );
+        write_ifd(
+            &mut tiff,
+            0x40,
+            vec![ascii(tag::DATE_TIME_ORIGINAL, "2021:05:04 03:02:01")],
+            0,
+        );
+        let editor = ExifEditor::new()
+            .set(
+                Ifd::Exif,
+                tag::DATE_TIME_ORIGINAL,
+                Value::Ascii("2021:05:04 04:02:01".into()),
+            )
+            .set(
+                Ifd::Primary,
+                tag::DATE_TIME,
+                Value::Ascii("2021:05:04 04:02:01".into()),
+            );
+        let padding = (0..EXIF_HEAD_SIZE as usize + 1001).map(|i| (i % 251) as u8);
+        let dir = ScratchDir::new("exif-large");
+        for (name, mut data) in [
+            ("a.tif", tiff.clone()),
+            ("b.jpg", jpeg(8, 8, &exif_segment(&tiff))),
+        ] {
+            data.extend(padding.clone());
+            let path = dir.path().join(name);
+            fs::write(&path, &data).unwrap();
+            editor.apply_to_file(&path).unwrap();
+            let edited = fs::read(&path).unwrap();
+            assert_eq!(edited, editor.apply(&data).unwrap());
+            let exif = read(&edited).unwrap();
+            assert_eq!(
+                exif.get_in(Ifd::Primary, tag::DATE_TIME)
+                    .and_then(Value::as_str),
+                Some("2021:05:04 04:02:01")
+            );
+            assert_eq!(
+                exif.get_in(Ifd::Exif, tag::DATE_TIME_ORIGINAL)
+                    .and_then(Value::as_str),
+                Some("2021:05:04 04:02:01")
+            );
+        }
+        assert_eq!(dir.files(), ["a.tif", "b.jpg"]);
+    }
+
+    #[test]
+    fn apply_to_file_leaves_files_alone_without_changes() {
+        let dir = ScratchDir::new("exif-unchanged");
+        let path = dir.path().join("movie.mov");
+        fs::write(&path, b"not an image").unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        ExifEditor::new().apply_to_file(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"not an image");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+    }
+}
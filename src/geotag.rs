@@ -0,0 +1,771 @@
+//! Geotagging of downloaded files from the track of a GPS logger.
+//!
+//! A `Track` holds the points of one or more GPX files. A `Geotagger` finds the position at
+//! the capture time of each file, converted to UTC after correcting the camera's clock, by
+//! interpolating between the track points around it or taking the nearest one within a
+//! tolerance. The position is written as GPS EXIF fields into JPEG, TIFF and DNG files, and
+//! into an XMP sidecar for other formats, such as proprietary RAW files and movies, which
+//! are better left unmodified.
+
+use crate::datetime::DateTime;
+use crate::download::split_extension;
+use crate::error::{Error, Result};
+use crate::exif::{self, gps_tag, tag, ExifEditor, Ifd, Value, EXIF_HEAD_SIZE};
+use crate::timeshift::ClockCorrection;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Extensions of the files GPS fields are written into, rather than into a sidecar.
+const EMBEDDED_EXTENSIONS: &[&str] = &["jpg", "jpeg", "tif", "tiff", "dng"];
+
+/// XML namespace of the EXIF properties of XMP, GPS properties included.
+const XMP_EXIF_NAMESPACE: &str = "http://ns.adobe.com/exif/1.0/";
+
+/// The GPS properties written into XMP sidecars.
+const XMP_GPS_PROPERTIES: &[&str] = &[
+    "GPSVersionID",
+    "GPSLatitude",
+    "GPSLongitude",
+    "GPSAltitudeRef",
+    "GPSAltitude",
+    "GPSTimeStamp",
+    "GPSMapDatum",
+];
+
+/// An XMP packet without properties, for new sidecars.
+const EMPTY_XMP: &str = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+  <rdf:Description rdf:about=\"\"/>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>
+";
+
+/// A point of a GPS track.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackPoint {
+    /// When the point was recorded, in UTC.
+    pub time: DateTime,
+    /// Degrees north of the equator, negative south of it.
+    pub latitude: f64,
+    /// Degrees east of the prime meridian, negative west of it.
+    pub longitude: f64,
+    /// Meters above sea level.
+    pub elevation: Option<f64>,
+}
+
+/// Track points sorted by time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Track {
+    points: Vec<TrackPoint>,
+}
+
+impl Track {
+    /// A track of `points` in any order.
+    pub fn from_points(points: Vec<TrackPoint>) -> Track {
+        let mut track = Track::default();
+        track.extend(points);
+        track
+    }
+
+    /// Read the track points of a GPX file. Points without a time are left out.
+    pub fn parse_gpx(gpx: &str) -> Result<Track> {
+        if !gpx.contains("<gpx") {
+            return Err(Error::malformed("not a GPX document"));
+        }
+        let mut points = Vec::new();
+        let mut rest = gpx;
+        while let Some(start) = rest.find("<trkpt") {
+            rest = &rest[start + "<trkpt".len()..];
+            let tag_end = rest
+                .find('>')
+                .ok_or_else(|| Error::malformed("unterminated trkpt element"))?;
+            let attributes = &rest[..tag_end];
+            let body = if attributes.ends_with('/') {
+                ""
+            } else {
+                let end = rest
+                    .find("</trkpt>")
+                    .ok_or_else(|| Error::malformed("unterminated trkpt element"))?;
+                &rest[tag_end + 1..end]
+            };
+            let coordinate = |name| {
+                attribute(attributes, name)
+                    .and_then(|value| value.trim().parse::<f64>().ok())
+                    .filter(|value| value.is_finite())
+            };
+            let (latitude, longitude) = match (coordinate("lat"), coordinate("lon")) {
+                (Some(latitude), Some(longitude))
+                    if latitude.abs() <= 90.0 && longitude.abs() <= 180.0 =>
+                {
+                    (latitude, longitude)
+                }
+                _ => return Err(Error::malformed("trkpt without valid lat and lon")),
+            };
+            let time = element(body, "time")
+                .and_then(DateTime::parse_iso8601)
+                .map(|(time, offset)| offset.map_or(time, |offset| offset.to_utc(time)));
+            if let Some(time) = time {
+                points.push(TrackPoint {
+                    time,
+                    latitude,
+                    longitude,
+                    elevation: element(body, "ele").and_then(|ele| ele.trim().parse().ok()),
+                });
+            }
+        }
+        Ok(Track::from_points(points))
+    }
+
+    /// Read the track points of the GPX file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Track> {
+        Track::parse_gpx(&fs::read_to_string(path)?)
+    }
+
+    /// Add the points of another track, such as the GPX file of another day.
+    pub fn merge(&mut self, other: Track) {
+        self.extend(other.points);
+    }
+
+    pub fn points(&self) -> &[TrackPoint] {
+        &self.points
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// The position at `time`, in UTC.
+    ///
+    /// With `interpolate`, a time between two points at most `tolerance` away from it gets
+    /// the position in between them. Otherwise, the nearest point within `tolerance` is
+    /// used.
+    pub fn locate(&self, time: DateTime, tolerance: Duration, interpolate: bool) -> Option<Fix> {
+        let target = time.to_unix();
+        let tolerance = tolerance.as_secs() as i64;
+        let after = self
+            .points
+            .partition_point(|point| point.time.to_unix() < target);
+        let before = after.checked_sub(1).map(|index| &self.points[index]);
+        let after = self.points.get(after);
+        let distance = |point: &TrackPoint| (point.time.to_unix() - target).abs();
+        let fix = |point: &TrackPoint, interpolated| Fix {
+            latitude: point.latitude,
+            longitude: point.longitude,
+            elevation: point.elevation,
+            time,
+            interpolated,
+        };
+        if let (true, Some(before), Some(after)) = (interpolate, before, after) {
+            let (start, end) = (before.time.to_unix(), after.time.to_unix());
+            if distance(before) <= tolerance && distance(after) <= tolerance && end > start {
+                let t = (target - start) as f64 / (end - start) as f64;
+                let mut longitude_delta = after.longitude - before.longitude;
+                // Take the short way across the antimeridian.
+                if longitude_delta > 180.0 {
+                    longitude_delta -= 360.0;
+                } else if longitude_delta < -180.0 {
+                    longitude_delta += 360.0;
+                }
+                let mut longitude = before.longitude + longitude_delta * t;
+                if longitude > 180.0 {
+                    longitude -= 360.0;
+                } else if longitude < -180.0 {
+                    longitude += 360.0;
+                }
+                return Some(Fix {
+                    latitude: before.latitude + (after.latitude - before.latitude) * t,
+                    longitude,
+                    elevation: match (before.elevation, after.elevation) {
+                        (Some(a), Some(b)) => Some(a + (b - a) * t),
+                        (elevation, None) | (None, elevation) => elevation,
+                    },
+                    time,
+                    interpolated: target != start,
+                });
+            }
+        }
+        [before, after]
+            .iter()
+            .flatten()
+            .filter(|point| distance(point) <= tolerance)
+            .min_by_key(|point| distance(point))
+            .map(|point| fix(point, false))
+    }
+
+    fn extend(&mut self, points: Vec<TrackPoint>) {
+        self.points.extend(points);
+        self.points.sort_by_key(|point| point.time);
+        self.points.dedup_by_key(|point| point.time);
+    }
+}
+
+/// A position found for a capture time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fix {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub elevation: Option<f64>,
+    /// The capture time, in UTC.
+    pub time: DateTime,
+    /// Whether the position lies between two track points rather than on one.
+    pub interpolated: bool,
+}
+
+/// Where the position of a file was written.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// Into the EXIF metadata of the file itself.
+    Embedded,
+    /// Into this XMP sidecar.
+    Sidecar(PathBuf),
+}
+
+/// Why a file was not geotagged.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NoFixReason {
+    /// The file has no capture date and no modification date.
+    NoCaptureTime,
+    /// The capture date has no time zone, and none was given.
+    UnknownTimeZone,
+    /// No track point is within the tolerance of the capture time, which is in UTC.
+    OutOfTrack(DateTime),
+    /// The file, or its sidecar, has a position already.
+    AlreadyTagged,
+}
+
+impl fmt::Display for NoFixReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NoFixReason::NoCaptureTime => f.write_str("no capture time"),
+            NoFixReason::UnknownTimeZone => f.write_str("the time zone of the capture is unknown"),
+            NoFixReason::OutOfTrack(time) => write!(f, "no track point near {} UTC", time),
+            NoFixReason::AlreadyTagged => f.write_str("already has a position"),
+        }
+    }
+}
+
+/// What geotagging did with a file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geotag {
+    Tagged { fix: Fix, target: Target },
+    Untagged(NoFixReason),
+}
+
+/// Settings and track for geotagging files.
+#[derive(Clone, Debug)]
+pub struct Geotagger {
+    pub track: Track,
+    /// Largest difference between a capture time and a track point used for it.
+    pub tolerance: Duration,
+    /// Whether positions between two track points are interpolated; otherwise the nearest
+    /// point is used.
+    pub interpolate: bool,
+    /// Correction of the camera's clock, applied to capture dates before matching. Its
+    /// time zone is the one of the capture dates; without one, the `OffsetTimeOriginal`
+    /// EXIF field must give it.
+    pub correction: ClockCorrection,
+    /// Whether GPS fields are written into JPEG, TIFF and DNG files. When false, every
+    /// file gets an XMP sidecar.
+    pub embed: bool,
+    /// Whether files with a position are tagged again.
+    pub overwrite: bool,
+}
+
+impl Geotagger {
+    /// Interpolate positions within 5 minutes of the capture time and embed them where
+    /// possible.
+    pub fn new(track: Track) -> Geotagger {
+        Geotagger {
+            track,
+            tolerance: Duration::from_secs(5 * 60),
+            interpolate: true,
+            correction: ClockCorrection::default(),
+            embed: true,
+            overwrite: false,
+        }
+    }
+
+    /// Find the position of the file at `path` and write it.
+    pub fn tag(&self, path: &Path) -> Result<Geotag> {
+        self.tag_corrected(path, &self.correction)
+    }
+
+    /// Tag every file of `paths`. Sidecars are left out, as they are written for the
+    /// files they belong to.
+    pub fn tag_all<I, P>(&self, paths: I) -> GeotagLog
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.tag_all_corrected(paths, &self.correction)
+    }
+
+    pub(crate) fn tag_all_corrected<I, P>(
+        &self,
+        paths: I,
+        correction: &ClockCorrection,
+    ) -> GeotagLog
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let mut log = GeotagLog::default();
+        for path in paths {
+            let path = path.as_ref();
+            if is_sidecar(path) {
+                continue;
+            }
+            match self.tag_corrected(path, correction) {
+                Ok(Geotag::Tagged { fix, target }) => log.tagged.push(TaggedFile {
+                    path: path.to_path_buf(),
+                    fix,
+                    target,
+                }),
+                Ok(Geotag::Untagged(reason)) => log.untagged.push(UntaggedFile {
+                    path: path.to_path_buf(),
+                    reason,
+                }),
+                Err(error) => log.failed.push(FailedGeotag {
+                    path: path.to_path_buf(),
+                    error,
+                }),
+            }
+        }
+        log
+    }
+
+    fn tag_corrected(&self, path: &Path, correction: &ClockCorrection) -> Result<Geotag> {
+        let mut head = Vec::new();
+        File::open(path)?
+            .take(EXIF_HEAD_SIZE)
+            .read_to_end(&mut head)?;
+        let exif = exif::read(&head).ok();
+        let embed = self.embed && is_embeddable(path) && exif.is_some();
+        let sidecar = sidecar_path(path);
+        if !self.overwrite {
+            let tagged = if embed {
+                exif.as_ref()
+                    .is_some_and(|exif| exif.get_in(Ifd::Gps, gps_tag::GPS_LATITUDE).is_some())
+            } else {
+                fs::read_to_string(&sidecar).is_ok_and(|xmp| xmp.contains("exif:GPSLatitude"))
+            };
+            if tagged {
+                return Ok(Geotag::Untagged(NoFixReason::AlreadyTagged));
+            }
+        }
+
+        let time = match exif.as_ref().and_then(|exif| exif.date_time_original()) {
+            Some(date) => {
+                let recorded_zone = exif
+                    .as_ref()
+                    .and_then(|exif| exif.get(tag::OFFSET_TIME_ORIGINAL))
+                    .and_then(Value::as_str)
+                    .and_then(crate::datetime::UtcOffset::parse);
+                match correction.target_zone().or(recorded_zone) {
+                    Some(zone) => zone.to_utc(correction.correct(date)),
+                    None => return Ok(Geotag::Untagged(NoFixReason::UnknownTimeZone)),
+                }
+            }
+            // The modification date is a point in time already; only the clock is off.
+            None => match fs::metadata(path)?.modified() {
+                Ok(modified) => {
+                    DateTime::from_system_time(modified).add_seconds(-correction.camera_offset)
+                }
+                Err(_) => return Ok(Geotag::Untagged(NoFixReason::NoCaptureTime)),
+            },
+        };
+        let fix = match self.track.locate(time, self.tolerance, self.interpolate) {
+            Some(fix) => fix,
+            None => return Ok(Geotag::Untagged(NoFixReason::OutOfTrack(time))),
+        };
+
+        if embed {
+            gps_editor(&fix).apply_to_file(path)?;
+            return Ok(Geotag::Tagged {
+                fix,
+                target: Target::Embedded,
+            });
+        }
+        write_sidecar_properties(&sidecar, XMP_GPS_PROPERTIES, &xmp_gps_properties(&fix))?;
+        Ok(Geotag::Tagged {
+            fix,
+            target: Target::Sidecar(sidecar),
+        })
+    }
+}
+
+/// A file geotagging wrote a position for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaggedFile {
+    pub path: PathBuf,
+    pub fix: Fix,
+    pub target: Target,
+}
+
+/// A file geotagging found no position for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UntaggedFile {
+    pub path: PathBuf,
+    pub reason: NoFixReason,
+}
+
+/// A file geotagging failed to read or write.
+#[derive(Debug)]
+pub struct FailedGeotag {
+    pub path: PathBuf,
+    pub error: Error,
+}
+
+/// The files of a geotagging run.
+#[derive(Debug, Default)]
+pub struct GeotagLog {
+    pub tagged: Vec<TaggedFile>,
+    pub untagged: Vec<UntaggedFile>,
+    pub failed: Vec<FailedGeotag>,
+}
+
+impl GeotagLog {
+    /// Whether no file failed. Files without a position do not count as failures.
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for GeotagLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sidecars = self
+            .tagged
+            .iter()
+            .filter(|file| matches!(file.target, Target::Sidecar(_)))
+            .count();
+        write!(
+            f,
+            "tagged {} files ({} with sidecars), no position for {}, failed {}",
+            self.tagged.len(),
+            sidecars,
+            self.untagged.len(),
+            self.failed.len()
+        )
+    }
+}
+
+/// The GPS EXIF fields for `fix`.
+fn gps_editor(fix: &Fix) -> ExifEditor {
+    let latitude_ref = if fix.latitude < 0.0 { "S" } else { "N" };
+    let longitude_ref = if fix.longitude < 0.0 { "W" } else { "E" };
+    let time = fix.time;
+    let mut editor = ExifEditor::new()
+        .set(
+            Ifd::Gps,
+            gps_tag::GPS_VERSION_ID,
+            Value::Byte(vec![2, 3, 0, 0]),
+        )
+        .set(
+            Ifd::Gps,
+            gps_tag::GPS_LATITUDE_REF,
+            Value::Ascii(latitude_ref.into()),
+        )
+        .set(
+            Ifd::Gps,
+            gps_tag::GPS_LATITUDE,
+            Value::Rational(degrees_minutes_seconds(fix.latitude)),
+        )
+        .set(
+            Ifd::Gps,
+            gps_tag::GPS_LONGITUDE_REF,
+            Value::Ascii(longitude_ref.into()),
+        )
+        .set(
+            Ifd::Gps,
+            gps_tag::GPS_LONGITUDE,
+            Value::Rational(degrees_minutes_seconds(fix.longitude)),
+        )
+        .set(
+            Ifd::Gps,
+            gps_tag::GPS_TIME_STAMP,
+            Value::Rational(vec![
+                (u32::from(time.hour), 1),
+                (u32::from(time.minute), 1),
+                (u32::from(time.second), 1),
+            ]),
+        )
+        .set(
+            Ifd::Gps,
+            gps_tag::GPS_DATE_STAMP,
+            Value::Ascii(format!(
+                "{:04}:{:02}:{:02}",
+                time.year, time.month, time.day
+            )),
+        )
+        .set(
+            Ifd::Gps,
+            gps_tag::GPS_MAP_DATUM,
+            Value::Ascii("WGS-84".into()),
+        );
+    if let Some(elevation) = fix.elevation {
+        editor = editor
+            .set(
+                Ifd::Gps,
+                gps_tag::GPS_ALTITUDE_REF,
+                Value::Byte(vec![u8::from(elevation < 0.0)]),
+            )
+            .set(
+                Ifd::Gps,
+                gps_tag::GPS_ALTITUDE,
+                Value::Rational(vec![((elevation.abs() * 100.0).round() as u32, 100)]),
+            );
+    }
+    editor
+}
+
+/// Degrees, minutes and seconds of an angle, as EXIF rationals with the seconds in
+/// thousandths.
+fn degrees_minutes_seconds(angle: f64) -> Vec<(u32, u32)> {
+    let millis = (angle.abs() * 3_600_000.0).round() as u64;
+    vec![
+        ((millis / 3_600_000) as u32, 1),
+        ((millis % 3_600_000 / 60_000) as u32, 1),
+        ((millis % 60_000) as u32, 1000),
+    ]
+}
+
+/// The XMP GPS properties for `fix`, in the forms of the XMP specification.
+fn xmp_gps_properties(fix: &Fix) -> Vec<(&'static str, String)> {
+    let coordinate = |angle: f64, positive: char, negative: char| {
+        let minutes = (angle.abs() * 60.0 * 1_000_000.0).round() / 1_000_000.0;
+        format!(
+            "{},{:.6}{}",
+            (minutes / 60.0).floor(),
+            minutes % 60.0,
+            if angle < 0.0 { negative } else { positive }
+        )
+    };
+    let time = fix.time;
+    let mut properties = vec![
+        ("GPSVersionID", "2.3.0.0".to_string()),
+        ("GPSLatitude", coordinate(fix.latitude, 'N', 'S')),
+        ("GPSLongitude", coordinate(fix.longitude, 'E', 'W')),
+        (
+            "GPSTimeStamp",
+            format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                time.year, time.month, time.day, time.hour, time.minute, time.second
+            ),
+        ),
+        ("GPSMapDatum", "WGS-84".to_string()),
+    ];
+    if let Some(elevation) = fix.elevation {
+        properties.push((
+            "GPSAltitudeRef",
+            if elevation < 0.0 { "1" } else { "0" }.to_string(),
+        ));
+        properties.push((
+            "GPSAltitude",
+            format!("{}/100", (elevation.abs() * 100.0).round() as u64),
+        ));
+    }
+    properties
+}
+
+/// Set EXIF properties in the XMP sidecar at `sidecar`, creating it if needed, after
+/// removing the properties named in `replaced`.
+pub(crate) fn write_sidecar_properties(
+    sidecar: &Path,
+    replaced: &[&str],
+    properties: &[(&str, String)],
+) -> Result<()> {
+    let xmp = match fs::read_to_string(sidecar) {
+        Ok(xmp) => xmp,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => EMPTY_XMP.to_string(),
+        Err(err) => return Err(err.into()),
+    };
+    let xmp = set_xmp_properties(&xmp, replaced, properties)?;
+    write_atomically(sidecar, xmp.as_bytes())
+}
+
+/// Set EXIF properties of an XMP packet as attributes of its first `rdf:Description`,
+/// removing the properties named in `replaced` first.
+fn set_xmp_properties(
+    xmp: &str,
+    replaced: &[&str],
+    properties: &[(&str, String)],
+) -> Result<String> {
+    let mut xmp = xmp.to_string();
+    for name in replaced {
+        remove_xmp_property(&mut xmp, name);
+    }
+    let start = xmp
+        .find("<rdf:Description")
+        .ok_or_else(|| Error::malformed("the XMP packet has no rdf:Description"))?;
+    let end = start
+        + xmp[start..]
+            .find('>')
+            .ok_or_else(|| Error::malformed("unterminated rdf:Description"))?;
+    let insert_at = if xmp[..end].ends_with('/') {
+        end - 1
+    } else {
+        end
+    };
+    let mut attributes = String::new();
+    if !xmp[start..end].contains("xmlns:exif=") {
+        attributes.push_str(&format!("\n    xmlns:exif=\"{}\"", XMP_EXIF_NAMESPACE));
+    }
+    for (name, value) in properties {
+        attributes.push_str(&format!("\n    exif:{}=\"{}\"", name, xml_escape(value)));
+    }
+    xmp.insert_str(insert_at, &attributes);
+    Ok(xmp)
+}
+
+/// Remove every `exif:{name}` attribute and element.
+fn remove_xmp_property(xmp: &mut String, name: &str) {
+    let attribute = format!("exif:{}=", name);
+    while let Some(start) = xmp.find(&attribute) {
+        let value_start = start + attribute.len();
+        let quote = match xmp[value_start..].chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => break,
+        };
+        let end = match xmp[value_start + 1..].find(quote) {
+            Some(end) => value_start + 1 + end + 1,
+            None => break,
+        };
+        let start = xmp[..start].trim_end().len();
+        xmp.replace_range(start..end, "");
+    }
+    let open = format!("<exif:{}", name);
+    let close = format!("</exif:{}>", name);
+    let mut from = 0;
+    while let Some(found) = xmp[from..].find(&open) {
+        let start = from + found;
+        // `<exif:GPSAltitude` also starts `<exif:GPSAltitudeRef`.
+        let next = xmp[start + open.len()..].chars().next();
+        if !matches!(next, Some('>' | '/' | ' ' | '\t' | '\r' | '\n')) {
+            from = start + open.len();
+            continue;
+        }
+        let tag_end = match xmp[start..].find('>') {
+            Some(end) => start + end + 1,
+            None => return,
+        };
+        let end = if xmp[..tag_end].ends_with("/>") {
+            tag_end
+        } else {
+            match xmp[tag_end..].find(&close) {
+                Some(end) => tag_end + end + close.len(),
+                None => return,
+            }
+        };
+        let start = xmp[..start].trim_end_matches([' ', '\t']).len();
+        let end = end + xmp[end..].len() - xmp[end..].trim_start_matches([' ', '\t']).len();
+        let end = if xmp[end..].starts_with('\n') {
+            end + 1
+        } else {
+            end
+        };
+        xmp.replace_range(start..end, "");
+        from = start;
+    }
+}
+
+/// The value of the attribute `name` in the attributes of a start tag.
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attributes;
+    loop {
+        let found = rest.find(name)?;
+        let preceded = rest[..found]
+            .chars()
+            .next_back()
+            .is_none_or(char::is_whitespace);
+        let after = rest[found + name.len()..].trim_start();
+        rest = &rest[found + name.len()..];
+        if !preceded {
+            continue;
+        }
+        if let Some(after) = after.strip_prefix('=') {
+            let after = after.trim_start();
+            let quote = after.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let value = &after[1..];
+            return value.find(quote).map(|end| &value[..end]);
+        }
+    }
+}
+
+/// The text of the first element `name` in `body`.
+fn element<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = body.find(&open)? + open.len();
+    let end = body[start..].find(&close)?;
+    Some(&body[start..start + end])
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+}
+
+pub(crate) fn is_embeddable(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    EMBEDDED_EXTENSIONS.contains(&extension.as_str())
+}
+
+fn is_sidecar(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("xmp"))
+}
+
+/// The XMP sidecar of the file at `path`: an existing `.xmp` file with the same base name
+/// in any case, or else one with a lowercase extension.
+pub(crate) fn sidecar_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let base = split_extension(&name).0;
+    let folder = path.parent().unwrap_or_else(|| Path::new("."));
+    let existing = fs::read_dir(folder).ok().and_then(|entries| {
+        entries
+            .flatten()
+            .map(|entry| entry.path())
+            .find(|candidate| {
+                let candidate_name = candidate
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let (candidate_base, extension) = split_extension(&candidate_name);
+                candidate_base == base && extension.eq_ignore_ascii_case("xmp")
+            })
+    });
+    existing.unwrap_or_else(|| folder.join(format!("{}.xmp", base)))
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let temporary = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let written = File::create(&temporary).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(err) = written.and_then(|()| fs::rename(&temporary, path)) {
+        let _ = fs::remove_file(&temporary);
+        return Err(err.into());
+    }
+    Ok(())
+}
@@ -0,0 +1,1386 @@
+//! Bulk import of new files from a camera into a local folder.
+//!
+//! An `Importer` first plans an import, choosing a destination for every file from the
+//! folder and name templates and recognizing files that are already in the destination,
+//! then runs the plan with one or more parallel transfers and reports the outcome.
+
+use crate::asset::{Asset, Role};
+use crate::camera::{CameraDevice, CameraFile};
+use crate::datetime::DateTime;
+use crate::download::{split_extension, CollisionPolicy, PartFile, Persisted};
+use crate::error::{Error, Result};
+use crate::exif::{self, Exif};
+use crate::geotag::{GeotagLog, Geotagger, Target};
+use crate::ledger::{ImportLedger, LedgerEntry};
+use crate::sha256;
+use crate::timeshift::{ClockCorrection, CorrectionLog};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Number of bytes read from the start of a file to find its EXIF metadata.
+const EXIF_HEAD_SIZE: u64 = 256 * 1024;
+/// Largest difference between modification dates still considered the same date.
+/// FAT file systems store modification times with a two-second resolution.
+const DATE_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// A file or folder name pattern with fields in braces, such as `{date}_{seq:4}`.
+///
+/// Fields describing the capture date come from the EXIF `DateTimeOriginal` when the file
+/// has one, and from the file dates otherwise:
+///
+/// - `{year}`, `{month}`, `{day}`, `{hour}`, `{minute}`, `{second}`
+/// - `{date}` as `YYYY-MM-DD` and `{time}` as `hhmmss`
+///
+/// Other fields are `{camera}` for the device name, `{name}` for the original name without
+/// its extension, `{ext}` for the original extension, `{folder}` for the name of the
+/// folder holding the file on the camera, `{seq}` or `{seq:N}` for a sequence number padded
+/// to N digits, `{make}`, `{model}` and `{lens}`, and `{exif:Tag}` for any EXIF field by
+/// name, such as `{exif:ISOSpeedRatings}`. `{{` and `}}` stand for literal braces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Field {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    Date,
+    Time,
+    Camera,
+    Name,
+    Extension,
+    Folder,
+    Sequence(usize),
+    Make,
+    Model,
+    Lens,
+    Exif(String),
+}
+
+impl Template {
+    /// Parse a template, failing on unknown fields and unbalanced braces.
+    pub fn parse(pattern: &str) -> Result<Template> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => {
+                                return Err(Error::InvalidValue(format!(
+                                    "unterminated field in template {}",
+                                    pattern
+                                )))
+                            }
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(parse_field(&name)?));
+                }
+                '}' => {
+                    return Err(Error::InvalidValue(format!(
+                        "unmatched }} in template {}",
+                        pattern
+                    )))
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Whether the template uses fields that need the file's EXIF metadata.
+    pub(crate) fn needs_exif(&self) -> bool {
+        self.parts.iter().any(|part| match part {
+            Part::Field(field) => !matches!(
+                field,
+                Field::Camera | Field::Name | Field::Extension | Field::Folder | Field::Sequence(_)
+            ),
+            Part::Literal(_) => false,
+        })
+    }
+
+    /// Render the template for `file` on the camera named `camera`.
+    pub(crate) fn render_file(
+        &self,
+        file: &CameraFile,
+        camera: &str,
+        date: DateTime,
+        exif: Option<&Exif>,
+        sequence: u32,
+    ) -> String {
+        self.render(&Context {
+            file,
+            camera,
+            date,
+            exif,
+            sequence,
+        })
+    }
+
+    fn render(&self, context: &Context) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => rendered.push_str(text),
+                Part::Field(field) => rendered.push_str(&sanitize_name(&context.field(field))),
+            }
+        }
+        rendered
+    }
+}
+
+fn parse_field(name: &str) -> Result<Field> {
+    let (name, argument) = match name.split_once(':') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (name, None),
+    };
+    Ok(match (name, argument) {
+        ("year", None) => Field::Year,
+        ("month", None) => Field::Month,
+        ("day", None) => Field::Day,
+        ("hour", None) => Field::Hour,
+        ("minute", None) => Field::Minute,
+        ("second", None) => Field::Second,
+        ("date", None) => Field::Date,
+        ("time", None) => Field::Time,
+        ("camera", None) => Field::Camera,
+        ("name", None) => Field::Name,
+        ("ext", None) => Field::Extension,
+        ("folder", None) => Field::Folder,
+        ("seq", None) => Field::Sequence(0),
+        ("seq", Some(width)) => Field::Sequence(width.parse().map_err(|_| {
+            Error::InvalidValue(format!("{} is not a sequence number width", width))
+        })?),
+        ("make", None) => Field::Make,
+        ("model", None) => Field::Model,
+        ("lens", None) => Field::Lens,
+        ("exif", Some(tag)) if !tag.is_empty() => Field::Exif(tag.to_string()),
+        _ => {
+            return Err(Error::InvalidValue(format!(
+                "unknown template field {{{}}}",
+                match argument {
+                    Some(argument) => format!("{}:{}", name, argument),
+                    None => name.to_string(),
+                }
+            )))
+        }
+    })
+}
+
+/// The values template fields are rendered from.
+struct Context<'a> {
+    file: &'a CameraFile,
+    camera: &'a str,
+    date: DateTime,
+    exif: Option<&'a Exif>,
+    sequence: u32,
+}
+
+impl<'a> Context<'a> {
+    fn field(&self, field: &Field) -> String {
+        let date = &self.date;
+        let exif_string = |tag| {
+            self.exif
+                .and_then(|exif| exif.string(tag))
+                .unwrap_or_else(|| "unknown".into())
+        };
+        match field {
+            Field::Year => format!("{:04}", date.year),
+            Field::Month => format!("{:02}", date.month),
+            Field::Day => format!("{:02}", date.day),
+            Field::Hour => format!("{:02}", date.hour),
+            Field::Minute => format!("{:02}", date.minute),
+            Field::Second => format!("{:02}", date.second),
+            Field::Date => format!("{:04}-{:02}-{:02}", date.year, date.month, date.day),
+            Field::Time => format!("{:02}{:02}{:02}", date.hour, date.minute, date.second),
+            Field::Camera => self.camera.to_string(),
+            Field::Name => split_extension(&self.file.name).0.to_string(),
+            Field::Extension => split_extension(&self.file.name).1.to_string(),
+            Field::Folder => self
+                .file
+                .parent_folder
+                .as_deref()
+                .and_then(|parent| parent.rsplit('/').next())
+                .unwrap_or_default()
+                .to_string(),
+            Field::Sequence(width) => format!("{:0width$}", self.sequence, width = *width),
+            Field::Make => exif_string(exif::tag::MAKE),
+            Field::Model => exif_string(exif::tag::MODEL),
+            Field::Lens => exif_string(exif::tag::LENS_MODEL),
+            Field::Exif(name) => self
+                .exif
+                .and_then(|exif| exif.get_by_name(name))
+                .map(|value| value.to_string())
+                .filter(|value| !value.is_empty())
+                .unwrap_or_else(|| "unknown".into()),
+        }
+    }
+}
+
+/// How files already in the destination are recognized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicateCheck {
+    /// Import every file.
+    None,
+    /// Skip files for which the destination holds a file of the same size and modification
+    /// date, without downloading them.
+    SizeAndDate,
+    /// Skip files of the same size and date like `SizeAndDate`, and download files that
+    /// only match in size to compare their content hash.
+    Content,
+}
+
+/// Settings of an import.
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    /// Folder files are imported into.
+    pub destination: PathBuf,
+    /// Layout of sub-folders of the destination, such as `{year}/{date}`. An empty
+    /// template imports all files into the destination itself.
+    pub folder_template: Template,
+    /// Name of imported files, without the extension, which is kept from the original.
+    pub name_template: Template,
+    pub duplicates: DuplicateCheck,
+    /// Number of files transferred at the same time.
+    pub parallel_transfers: usize,
+    /// First value of `{seq}`.
+    pub sequence_start: u32,
+    /// Whether sidecar files are imported next to the files they belong to.
+    pub include_sidecars: bool,
+    /// What to do when a file of the destination already has the planned name.
+    pub collisions: CollisionPolicy,
+    /// Correction of the camera's clock, applied to the dates the templates use and to the
+    /// imported files. The files then no longer match the camera's content byte for byte.
+    pub clock_correction: Option<ClockCorrection>,
+    /// Geotagging of the imported files, after their dates are corrected.
+    pub geotagging: Option<Geotagger>,
+}
+
+impl ImportOptions {
+    /// Import into `destination` with folders per day, original names, content duplicate
+    /// checks and two parallel transfers.
+    pub fn new<P: AsRef<Path>>(destination: P) -> ImportOptions {
+        ImportOptions {
+            destination: destination.as_ref().to_path_buf(),
+            folder_template: Template::parse("{year}/{date}").expect("valid template"),
+            name_template: Template::parse("{name}").expect("valid template"),
+            duplicates: DuplicateCheck::Content,
+            parallel_transfers: 2,
+            sequence_start: 1,
+            include_sidecars: true,
+            collisions: CollisionPolicy::RenameWithCounter,
+            clock_correction: None,
+            geotagging: None,
+        }
+    }
+}
+
+/// What the import does with a file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlannedAction {
+    /// Download the file.
+    Download,
+    /// Download the file and compare its content with destination files of the same size.
+    Verify(Vec<PathBuf>),
+    Skip(SkipReason),
+}
+
+/// Why a file was not imported.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SkipReason {
+    /// The destination already holds the file at this path.
+    Duplicate(PathBuf),
+    /// The file is a sidecar already imported with another file.
+    SharedSidecar,
+    /// The import ledger records the file, or its content, as imported before.
+    AlreadyImported,
+    /// The file was left out on request, such as with `ImportPlan::skip_asset`.
+    Excluded,
+    /// A different file has the planned name and the collision policy keeps it.
+    NameTaken(PathBuf),
+}
+
+/// A file of an import plan.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedFile {
+    pub file: CameraFile,
+    /// Where the file will be saved.
+    pub destination: PathBuf,
+    /// When the picture was taken, as used by the templates.
+    pub capture_date: DateTime,
+    pub action: PlannedAction,
+}
+
+/// The files of an import and what will be done with each.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportPlan {
+    pub files: Vec<PlannedFile>,
+}
+
+impl ImportPlan {
+    /// Number of files the plan will download.
+    pub fn download_count(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|file| !matches!(file.action, PlannedAction::Skip(_)))
+            .count()
+    }
+
+    /// Total size of the files the plan will download.
+    pub fn download_size(&self) -> u64 {
+        self.files
+            .iter()
+            .filter(|file| !matches!(file.action, PlannedAction::Skip(_)))
+            .map(|file| file.file.file_size)
+            .sum()
+    }
+
+    /// Leave every file of `asset` out of the import.
+    pub fn skip_asset(&mut self, asset: &Asset) {
+        let paths = asset.files().map(CameraFile::path).collect::<HashSet<_>>();
+        for planned in &mut self.files {
+            if paths.contains(&planned.file.path()) {
+                planned.action = PlannedAction::Skip(SkipReason::Excluded);
+            }
+        }
+    }
+}
+
+/// A file saved by an import.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedFile {
+    pub file: CameraFile,
+    pub path: PathBuf,
+    pub size: u64,
+    /// SHA-256 digest of the content.
+    pub sha256: [u8; 32],
+}
+
+/// A file an import left out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkippedFile {
+    pub file: CameraFile,
+    pub reason: SkipReason,
+}
+
+/// A file an import failed to save.
+#[derive(Debug)]
+pub struct FailedFile {
+    pub file: CameraFile,
+    pub error: Error,
+}
+
+/// The outcome of an import.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: Vec<ImportedFile>,
+    pub skipped: Vec<SkippedFile>,
+    pub failed: Vec<FailedFile>,
+    /// Bytes transferred from the camera, including files found to be duplicates.
+    pub bytes_transferred: u64,
+    pub elapsed: Duration,
+    /// The dates changed by the clock correction of the options.
+    pub corrections: CorrectionLog,
+    /// The positions written by the geotagging of the options.
+    pub geotags: GeotagLog,
+}
+
+impl ImportReport {
+    /// Whether every file was imported or skipped.
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "imported {} files ({} bytes), skipped {}, failed {} in {:.1}s",
+            self.imported.len(),
+            self.imported.iter().map(|file| file.size).sum::<u64>(),
+            self.skipped.len(),
+            self.failed.len(),
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// Plans and runs imports from a camera.
+pub struct Importer<'a> {
+    camera: &'a dyn CameraDevice,
+    device_id: String,
+    options: ImportOptions,
+    ledger: Option<Mutex<&'a mut ImportLedger>>,
+}
+
+impl<'a> Importer<'a> {
+    pub fn new(camera: &'a dyn CameraDevice, options: ImportOptions) -> Importer<'a> {
+        Importer {
+            camera,
+            device_id: camera.persistent_id_string(),
+            options,
+            ledger: None,
+        }
+    }
+
+    /// Skip files recorded in `ledger` and record the files this importer imports.
+    ///
+    /// Files found to be in the destination already are recorded too, so that later
+    /// imports skip them without looking at the destination.
+    pub fn with_ledger(mut self, ledger: &'a mut ImportLedger) -> Importer<'a> {
+        self.ledger = Some(Mutex::new(ledger));
+        self
+    }
+
+    pub fn options(&self) -> &ImportOptions {
+        &self.options
+    }
+
+    /// Plan the import of all media files of the camera.
+    pub fn plan(&self) -> Result<ImportPlan> {
+        self.plan_files(self.camera.media_files()?)
+    }
+
+    /// Plan the import of every file of `assets`.
+    ///
+    /// The templates are rendered for the primary file of each asset and the other files
+    /// take the same name with their own extension.
+    pub fn plan_assets(&self, assets: &[Asset]) -> Result<ImportPlan> {
+        let groups = assets
+            .iter()
+            .map(|asset| {
+                let primary = asset.primary().path();
+                let mut files = vec![asset.primary().clone()];
+                files.extend(
+                    asset
+                        .members
+                        .iter()
+                        .filter(|member| member.file.path() != primary)
+                        .filter(|member| {
+                            self.options.include_sidecars || member.role != Role::Sidecar
+                        })
+                        .map(|member| member.file.clone()),
+                );
+                // Sidecars are members of the asset themselves.
+                for file in &mut files {
+                    file.sidecar_files.clear();
+                }
+                files
+            })
+            .collect();
+        self.plan_groups(groups)
+    }
+
+    /// Plan the import of `files`.
+    pub fn plan_files(&self, files: Vec<CameraFile>) -> Result<ImportPlan> {
+        self.plan_groups(files.into_iter().map(|file| vec![file]).collect())
+    }
+
+    /// Plan groups of files saved under one base name, rendered for the first file.
+    fn plan_groups(&self, groups: Vec<Vec<CameraFile>>) -> Result<ImportPlan> {
+        let options = &self.options;
+        let needs_exif = options.folder_template.needs_exif() || options.name_template.needs_exif();
+        let camera_name = self.camera.name();
+        let mut dated = groups
+            .into_iter()
+            .filter(|group| !group.is_empty())
+            .map(|group| {
+                let exif = if needs_exif {
+                    read_exif(self.camera, &group[0])
+                } else {
+                    None
+                };
+                let mut date = capture_date(&group[0], exif.as_ref());
+                if let Some(correction) = &options.clock_correction {
+                    date = correction.correct(date);
+                }
+                (group, exif, date)
+            })
+            .collect::<Vec<_>>();
+        dated.sort_by(|a, b| a.2.cmp(&b.2).then_with(|| a.0[0].name.cmp(&b.0[0].name)));
+
+        let library = match options.duplicates {
+            DuplicateCheck::None => Library::default(),
+            _ => Library::scan(&options.destination)?,
+        };
+        let mut planned_paths = HashSet::new();
+        let mut planned_sources = HashSet::new();
+        // Files sharing a folder and base name on the camera, such as RAW+JPEG pairs,
+        // share a sequence number so that they keep sharing a base name.
+        let mut sequences: HashMap<(Option<String>, String), u32> = HashMap::new();
+        let mut next_sequence = options.sequence_start;
+        let mut plan = ImportPlan::default();
+        for (group, exif, date) in dated {
+            let first = &group[0];
+            let key = (
+                first.parent_folder.clone(),
+                split_extension(&first.name).0.to_ascii_uppercase(),
+            );
+            let sequence = *sequences.entry(key).or_insert_with(|| {
+                next_sequence += 1;
+                next_sequence - 1
+            });
+            let context = Context {
+                file: first,
+                camera: &camera_name,
+                date,
+                exif: exif.as_ref(),
+                sequence,
+            };
+            let folder = join_within(
+                &options.destination,
+                &options.folder_template.render(&context),
+            )?;
+            let base_name = options.name_template.render(&context);
+            let mut members = Vec::new();
+            for file in group {
+                let sidecars = if options.include_sidecars {
+                    file.sidecar_files.clone()
+                } else {
+                    Vec::new()
+                };
+                members.push((file, false));
+                members.extend(sidecars.into_iter().map(|sidecar| (sidecar, true)));
+            }
+            for (file, attached) in members {
+                if !planned_sources.insert(file.path()) {
+                    if attached {
+                        plan.files.push(PlannedFile {
+                            destination: PathBuf::new(),
+                            capture_date: date,
+                            action: PlannedAction::Skip(SkipReason::SharedSidecar),
+                            file,
+                        });
+                    }
+                    continue;
+                }
+                let extension = split_extension(&file.name).1;
+                let mut action = if self.is_recorded(&file) {
+                    PlannedAction::Skip(SkipReason::AlreadyImported)
+                } else {
+                    library.action(&file, options.duplicates)
+                };
+                let destination = if let PlannedAction::Skip(SkipReason::Duplicate(path)) = &action
+                {
+                    path.clone()
+                } else {
+                    // Only renaming with a counter avoids names taken on disk up front; the
+                    // other policies resolve collisions when the file is saved.
+                    let avoid_existing = options.collisions == CollisionPolicy::RenameWithCounter;
+                    let destination = free_path(
+                        &folder,
+                        &base_name,
+                        extension,
+                        &planned_paths,
+                        avoid_existing,
+                    );
+                    if destination.parent() != Some(folder.as_path()) {
+                        return Err(Error::InvalidValue(format!(
+                            "file name {} is not a single path component",
+                            base_name
+                        )));
+                    }
+                    planned_paths.insert(destination.clone());
+                    if options.collisions == CollisionPolicy::Skip
+                        && action == PlannedAction::Download
+                        && destination.exists()
+                    {
+                        action = PlannedAction::Skip(SkipReason::NameTaken(destination.clone()));
+                    }
+                    destination
+                };
+                plan.files.push(PlannedFile {
+                    file,
+                    destination,
+                    capture_date: date,
+                    action,
+                });
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Run a plan, transferring up to `parallel_transfers` files at a time.
+    pub fn run(&self, plan: ImportPlan) -> ImportReport {
+        let started = Instant::now();
+        let next = AtomicUsize::new(0);
+        let imported_hashes = Mutex::new(HashMap::new());
+        let library_hashes = Mutex::new(HashMap::new());
+        let outcomes = Mutex::new(Vec::new());
+        let workers = self
+            .options
+            .parallel_transfers
+            .max(1)
+            .min(plan.files.len().max(1));
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let planned = match plan.files.get(index) {
+                        Some(planned) => planned,
+                        None => break,
+                    };
+                    let outcome = self.transfer(planned, &imported_hashes, &library_hashes);
+                    lock(&outcomes).push((index, outcome));
+                });
+            }
+        });
+        let mut outcomes = outcomes
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        outcomes.sort_by_key(|(index, _)| *index);
+        let mut report = ImportReport::default();
+        for (index, outcome) in outcomes {
+            let file = plan.files[index].file.clone();
+            match outcome {
+                Outcome::Imported { path, size, sha256 } => {
+                    report.bytes_transferred += size;
+                    report.imported.push(ImportedFile {
+                        file,
+                        path,
+                        size,
+                        sha256,
+                    });
+                }
+                Outcome::Skipped(reason, transferred) => {
+                    report.bytes_transferred += transferred;
+                    report.skipped.push(SkippedFile { file, reason });
+                }
+                Outcome::Failed(error) => report.failed.push(FailedFile { file, error }),
+            }
+        }
+        if let Some(correction) = &self.options.clock_correction {
+            report.corrections = correction.apply_all(report.imported.iter().map(|f| &f.path));
+        }
+        if let Some(geotagger) = &self.options.geotagging {
+            // Corrected dates are only expressed in the time zone of the correction.
+            let correction = match &self.options.clock_correction {
+                Some(correction) => ClockCorrection {
+                    camera_offset: 0,
+                    camera_zone: correction
+                        .target_zone()
+                        .or_else(|| geotagger.correction.target_zone()),
+                    zone: None,
+                },
+                None => geotagger.correction,
+            };
+            report.geotags =
+                geotagger.tag_all_corrected(report.imported.iter().map(|f| &f.path), &correction);
+        }
+        self.record_post_processing(&mut report);
+        report.elapsed = started.elapsed();
+        report
+    }
+
+    /// Plan and run an import of all media files of the camera.
+    pub fn import(&self) -> Result<ImportReport> {
+        let plan = self.plan()?;
+        Ok(self.run(plan))
+    }
+
+    fn transfer(
+        &self,
+        planned: &PlannedFile,
+        imported_hashes: &Mutex<HashMap<[u8; 32], PathBuf>>,
+        library_hashes: &Mutex<HashMap<PathBuf, [u8; 32]>>,
+    ) -> Outcome {
+        let candidates = match &planned.action {
+            PlannedAction::Skip(reason) => {
+                let outcome = Outcome::Skipped(reason.clone(), 0);
+                return self.record(planned, outcome);
+            }
+            PlannedAction::Download => &[][..],
+            PlannedAction::Verify(candidates) => &candidates[..],
+        };
+        match self.download(planned, candidates, imported_hashes, library_hashes) {
+            Ok(outcome) => self.record(planned, outcome),
+            Err(error) => Outcome::Failed(error),
+        }
+    }
+
+    fn is_recorded(&self, file: &CameraFile) -> bool {
+        self.ledger
+            .as_ref()
+            .is_some_and(|ledger| lock(ledger).is_imported(&self.device_id, file))
+    }
+
+    /// Why a file with the content digest `digest` is skipped, if the ledger records it.
+    fn recorded_hash(&self, digest: &[u8; 32]) -> Option<SkipReason> {
+        let ledger = lock(self.ledger.as_ref()?);
+        let entry = ledger.find_by_hash(digest)?;
+        Some(match &entry.destination {
+            Some(path) => SkipReason::Duplicate(path.clone()),
+            None => SkipReason::AlreadyImported,
+        })
+    }
+
+    /// Record an imported or duplicate file in the ledger, failing the file if that fails.
+    fn record(&self, planned: &PlannedFile, outcome: Outcome) -> Outcome {
+        let ledger = match &self.ledger {
+            Some(ledger) => ledger,
+            None => return outcome,
+        };
+        let mut entry = LedgerEntry::new(&self.device_id, &planned.file);
+        match &outcome {
+            Outcome::Imported { path, sha256, .. } => {
+                entry.destination = Some(path.clone());
+                entry.sha256 = Some(*sha256);
+            }
+            Outcome::Skipped(SkipReason::Duplicate(path), _) => {
+                entry.destination = Some(path.clone());
+            }
+            _ => return outcome,
+        }
+        match lock(ledger).record(entry) {
+            Ok(()) => outcome,
+            Err(error) => Outcome::Failed(error),
+        }
+    }
+
+    /// Record the size and hash of the copies post-processing changed, so that they still
+    /// count as copies of the camera files. A file whose entry cannot be updated fails.
+    fn record_post_processing(&self, report: &mut ImportReport) {
+        let ledger = match &self.ledger {
+            Some(ledger) => ledger,
+            None => return,
+        };
+        let mut edited = report
+            .corrections
+            .changes
+            .iter()
+            .map(|change| &change.path)
+            .collect::<HashSet<_>>();
+        edited.extend(
+            report
+                .geotags
+                .tagged
+                .iter()
+                .filter(|tagged| tagged.target == Target::Embedded)
+                .map(|tagged| &tagged.path),
+        );
+        let mut entries = Vec::new();
+        let mut failed = Vec::new();
+        for (index, imported) in report.imported.iter().enumerate() {
+            if !edited.contains(&imported.path) {
+                continue;
+            }
+            let saved = File::open(&imported.path).and_then(|mut file| {
+                let size = file.metadata()?.len();
+                Ok((size, sha256::digest_reader(&mut file)?))
+            });
+            match saved {
+                Ok((_, digest)) if digest == imported.sha256 => {}
+                Ok((size, digest)) => {
+                    let mut entry = LedgerEntry::new(&self.device_id, &imported.file);
+                    entry.destination = Some(imported.path.clone());
+                    entry.sha256 = Some(imported.sha256);
+                    entry.saved_size = Some(size);
+                    entry.saved_sha256 = Some(digest);
+                    entries.push((index, entry));
+                }
+                Err(err) => failed.push((index, err.into())),
+            }
+        }
+        let indices = entries.iter().map(|(index, _)| *index).collect::<Vec<_>>();
+        if let Err(error) = lock(ledger).record_all(entries.into_iter().map(|(_, e)| e).collect()) {
+            failed.extend(indices.into_iter().map(|index| (index, error.duplicate())));
+        }
+        failed.sort_by_key(|(index, _)| *index);
+        for (index, error) in failed.into_iter().rev() {
+            let imported = report.imported.remove(index);
+            report.failed.push(FailedFile {
+                file: imported.file,
+                error,
+            });
+        }
+    }
+
+    fn download(
+        &self,
+        planned: &PlannedFile,
+        candidates: &[PathBuf],
+        imported_hashes: &Mutex<HashMap<[u8; 32], PathBuf>>,
+        library_hashes: &Mutex<HashMap<PathBuf, [u8; 32]>>,
+    ) -> Result<Outcome> {
+        let folder = planned
+            .destination
+            .parent()
+            .unwrap_or_else(|| Path::new("."));
+        let name = planned
+            .destination
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if self.options.collisions == CollisionPolicy::Error && planned.destination.exists() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", planned.destination.display()),
+            )));
+        }
+        fs::create_dir_all(folder)?;
+        let mut part = PartFile::create(folder, &name)?;
+        if let Err(error) = self.camera.download_file(&planned.file, &mut part) {
+            part.discard();
+            return Err(error);
+        }
+        let size = part.size();
+        let digest = part.digest();
+        let duplicate =
+            match self.duplicate_of(&digest, candidates, imported_hashes, library_hashes) {
+                Ok(duplicate) => duplicate,
+                Err(error) => {
+                    part.discard();
+                    return Err(error);
+                }
+            };
+        if let Some(reason) = duplicate {
+            part.discard();
+            return Ok(Outcome::Skipped(reason, size));
+        }
+        let date = planned
+            .file
+            .modification_date
+            .or(planned.file.creation_date);
+        let path = match part.persist(folder, &name, self.options.collisions, date)? {
+            Persisted::Saved(path) => path,
+            // Names derived from the content hash are only kept for the same content.
+            Persisted::Kept(path) if self.options.collisions == CollisionPolicy::RenameWithHash => {
+                return Ok(Outcome::Skipped(SkipReason::Duplicate(path), size))
+            }
+            Persisted::Kept(path) => {
+                return Ok(Outcome::Skipped(SkipReason::NameTaken(path), size))
+            }
+        };
+        lock(imported_hashes).insert(digest, path.clone());
+        Ok(Outcome::Imported {
+            path,
+            size,
+            sha256: digest,
+        })
+    }
+
+    /// Why a downloaded file with the content digest `digest` is skipped, if it is a
+    /// duplicate of a destination file, a recorded import or a file imported in this run.
+    fn duplicate_of(
+        &self,
+        digest: &[u8; 32],
+        candidates: &[PathBuf],
+        imported_hashes: &Mutex<HashMap<[u8; 32], PathBuf>>,
+        library_hashes: &Mutex<HashMap<PathBuf, [u8; 32]>>,
+    ) -> Result<Option<SkipReason>> {
+        for candidate in candidates {
+            if library_hash(candidate, library_hashes)? == *digest {
+                return Ok(Some(SkipReason::Duplicate(candidate.clone())));
+            }
+        }
+        if let Some(reason) = self.recorded_hash(digest) {
+            return Ok(Some(reason));
+        }
+        if self.options.duplicates != DuplicateCheck::None {
+            if let Some(path) = lock(imported_hashes).get(digest) {
+                return Ok(Some(SkipReason::Duplicate(path.clone())));
+            }
+        }
+        Ok(None)
+    }
+}
+
+enum Outcome {
+    Imported {
+        path: PathBuf,
+        size: u64,
+        sha256: [u8; 32],
+    },
+    /// A skipped file and the number of bytes transferred to decide to skip it.
+    Skipped(SkipReason, u64),
+    Failed(Error),
+}
+
+/// Files already in the destination, by size.
+#[derive(Default)]
+pub(crate) struct Library {
+    by_size: HashMap<u64, Vec<(PathBuf, Option<SystemTime>)>>,
+}
+
+impl Library {
+    pub(crate) fn scan(destination: &Path) -> Result<Library> {
+        let mut library = Library::default();
+        if destination.is_dir() {
+            library.scan_dir(destination)?;
+        }
+        Ok(library)
+    }
+
+    fn scan_dir(&mut self, dir: &Path) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                self.scan_dir(&entry.path())?;
+            } else if metadata.is_file() {
+                self.by_size
+                    .entry(metadata.len())
+                    .or_default()
+                    .push((entry.path(), metadata.modified().ok()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Paths and modification dates of the files of `size` bytes.
+    pub(crate) fn with_size(&self, size: u64) -> &[(PathBuf, Option<SystemTime>)] {
+        self.by_size.get(&size).map_or(&[], Vec::as_slice)
+    }
+
+    fn action(&self, file: &CameraFile, check: DuplicateCheck) -> PlannedAction {
+        let same_size = match self.by_size.get(&file.file_size) {
+            Some(same_size) if check != DuplicateCheck::None => same_size,
+            _ => return PlannedAction::Download,
+        };
+        let date = file.modification_date.or(file.creation_date);
+        let same_date = same_size
+            .iter()
+            .find(|(_, modified)| match (date, modified) {
+                (Some(date), Some(modified)) => {
+                    let difference = date
+                        .duration_since(*modified)
+                        .or_else(|_| modified.duration_since(date))
+                        .unwrap_or_default();
+                    difference <= DATE_TOLERANCE
+                }
+                _ => false,
+            });
+        match (same_date, check) {
+            (Some((path, _)), _) => PlannedAction::Skip(SkipReason::Duplicate(path.clone())),
+            (None, DuplicateCheck::Content) => {
+                PlannedAction::Verify(same_size.iter().map(|(path, _)| path.clone()).collect())
+            }
+            (None, _) => PlannedAction::Download,
+        }
+    }
+}
+
+fn library_hash(path: &Path, cache: &Mutex<HashMap<PathBuf, [u8; 32]>>) -> Result<[u8; 32]> {
+    if let Some(digest) = lock(cache).get(path) {
+        return Ok(*digest);
+    }
+    let digest = sha256::digest_reader(&mut File::open(path)?)?;
+    lock(cache).insert(path.to_path_buf(), digest);
+    Ok(digest)
+}
+
+/// The EXIF metadata at the start of a file on the camera.
+pub(crate) fn read_exif(camera: &dyn CameraDevice, file: &CameraFile) -> Option<Exif> {
+    let head = camera
+        .read_data_from_file(file, 0, EXIF_HEAD_SIZE.min(file.file_size))
+        .ok()?;
+    exif::read(&head).ok()
+}
+
+/// When the picture was taken, from EXIF or else from the file dates.
+///
+/// File dates are converted as UTC, while EXIF dates are the camera's local time.
+pub(crate) fn capture_date(file: &CameraFile, exif: Option<&Exif>) -> DateTime {
+    exif.and_then(Exif::date_time_original).unwrap_or_else(|| {
+        DateTime::from_system_time(
+            file.creation_date
+                .or(file.modification_date)
+                .unwrap_or_else(SystemTime::now),
+        )
+    })
+}
+
+/// A path in `folder` for `base_name` and `extension` that is not planned and, with
+/// `avoid_existing`, not taken on disk, adding `-1`, `-2`, ... to the base name as needed.
+fn free_path(
+    folder: &Path,
+    base_name: &str,
+    extension: &str,
+    planned: &HashSet<PathBuf>,
+    avoid_existing: bool,
+) -> PathBuf {
+    let mut counter = 0;
+    loop {
+        let name = match (counter, extension.is_empty()) {
+            (0, true) => base_name.to_string(),
+            (0, false) => format!("{}.{}", base_name, extension),
+            (n, true) => format!("{}-{}", base_name, n),
+            (n, false) => format!("{}-{}.{}", base_name, n, extension),
+        };
+        let path = folder.join(name);
+        let taken = planned.contains(&path) || (avoid_existing && path.exists());
+        if !taken {
+            return path;
+        }
+        counter += 1;
+    }
+}
+
+/// Replace characters that cannot appear in a file name, and leading dots, so that a value
+/// such as `..` from the camera cannot name a parent folder.
+pub(crate) fn sanitize_name(name: &str) -> String {
+    let sanitized = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let trimmed = sanitized.trim_start_matches('.');
+    if trimmed.is_empty() && !sanitized.is_empty() {
+        "_".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// `relative` joined to `root`, or an error if it would leave `root`, as a template with a
+/// literal `..` or an absolute path would.
+fn join_within(root: &Path, relative: &str) -> Result<PathBuf> {
+    let escapes = Path::new(relative)
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+    let path = root.join(relative);
+    if escapes || !path.starts_with(root) {
+        return Err(Error::InvalidValue(format!(
+            "{} is outside the import destination {}",
+            relative,
+            root.display()
+        )));
+    }
+    Ok(path)
+}
+
+fn lock<V>(mutex: &Mutex<V>) -> MutexGuard<'_, V> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datetime::UtcOffset;
+    use crate::delete::{Decision, DeletePlanner, Evidence};
+    use crate::geotag::{Track, TrackPoint};
+    use crate::mock::MockCamera;
+    use crate::testing::{ascii, exif_segment, jpeg, long, tiff_header, write_ifd, ScratchDir};
+    use std::time::UNIX_EPOCH;
+
+    /// 2020-09-13 12:26:40 UTC.
+    fn date() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+    }
+
+    fn add_file(camera: &MockCamera, name: &str, data: &[u8], date: SystemTime) -> CameraFile {
+        CameraFile {
+            creation_date: Some(date),
+            modification_date: Some(date),
+            ..camera.add_file(name, data)
+        }
+    }
+
+    fn options(destination: &ScratchDir, folder: &str, name: &str) -> ImportOptions {
+        ImportOptions {
+            folder_template: Template::parse(folder).unwrap(),
+            name_template: Template::parse(name).unwrap(),
+            ..ImportOptions::new(destination.path())
+        }
+    }
+
+    #[test]
+    fn templates_render_the_destination_of_each_file() {
+        let camera = MockCamera::new("Mock");
+        let later = date() + Duration::from_secs(60);
+        let files = vec![
+            add_file(&camera, "IMG_0002.JPG", b"second", later),
+            add_file(&camera, "IMG_0001.JPG", b"first", date()),
+        ];
+        let destination = ScratchDir::new("templates");
+        let options = options(&destination, "{year}/{date}", "{camera}_{seq:4}_{name}");
+        let importer = Importer::new(&camera, options);
+        let plan = importer.plan_files(files).unwrap();
+        let planned: Vec<_> = plan
+            .files
+            .iter()
+            .map(|planned| (planned.file.name.as_str(), planned.destination.clone()))
+            .collect();
+        let folder = destination.path().join("2020/2020-09-13");
+        assert_eq!(
+            planned,
+            [
+                ("IMG_0001.JPG", folder.join("Mock_0001_IMG_0001.JPG")),
+                ("IMG_0002.JPG", folder.join("Mock_0002_IMG_0002.JPG")),
+            ]
+        );
+        assert_eq!(plan.download_count(), 2);
+        assert_eq!(plan.download_size(), 11);
+
+        let report = importer.run(plan);
+        assert!(report.is_success());
+        assert_eq!(report.imported.len(), 2);
+        assert_eq!(report.bytes_transferred, 11);
+        assert_eq!(
+            destination.files(),
+            [
+                "2020/2020-09-13/Mock_0001_IMG_0001.JPG",
+                "2020/2020-09-13/Mock_0002_IMG_0002.JPG"
+            ]
+        );
+        assert_eq!(
+            fs::read(folder.join("Mock_0001_IMG_0001.JPG")).unwrap(),
+            b"first"
+        );
+    }
+
+    #[test]
+    fn raw_and_jpeg_files_share_a_sequence_number() {
+        let camera = MockCamera::new("Mock").with_raw_captures();
+        camera.take_picture().unwrap();
+        camera.take_picture().unwrap();
+        let destination = ScratchDir::new("raw-jpeg");
+        let importer = Importer::new(&camera, options(&destination, "", "{seq:3}"));
+        let report = importer.import().unwrap();
+        assert!(report.is_success());
+        assert_eq!(
+            destination.files(),
+            ["001.CR2", "001.JPG", "002.CR2", "002.JPG"]
+        );
+        assert_eq!(
+            fs::read(destination.path().join("002.CR2")).unwrap(),
+            b"Mock IMG_0002.CR2"
+        );
+    }
+
+    #[test]
+    fn files_of_the_same_size_and_date_are_skipped_without_downloading() {
+        let destination = ScratchDir::new("size-and-date");
+        let existing = destination.path().join("IMG_0001.JPG");
+        fs::write(&existing, b"12345").unwrap();
+        let modified = fs::metadata(&existing).unwrap().modified().unwrap();
+
+        let camera = MockCamera::new("Mock");
+        let files = vec![
+            add_file(&camera, "IMG_0001.JPG", b"abcde", modified),
+            add_file(&camera, "IMG_0002.JPG", b"fghij", date()),
+        ];
+        let options = ImportOptions {
+            duplicates: DuplicateCheck::SizeAndDate,
+            ..options(&destination, "", "{name}")
+        };
+        let importer = Importer::new(&camera, options);
+        let plan = importer.plan_files(files).unwrap();
+        let actions: Vec<_> = plan
+            .files
+            .iter()
+            .map(|planned| (planned.file.name.as_str(), &planned.action))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                ("IMG_0002.JPG", &PlannedAction::Download),
+                (
+                    "IMG_0001.JPG",
+                    &PlannedAction::Skip(SkipReason::Duplicate(existing.clone()))
+                ),
+            ]
+        );
+
+        let report = importer.run(plan);
+        assert_eq!(report.imported.len(), 1);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.bytes_transferred, 5);
+        assert_eq!(fs::read(&existing).unwrap(), b"12345");
+    }
+
+    #[test]
+    fn files_of_the_same_size_are_compared_by_content() {
+        let destination = ScratchDir::new("content");
+        let existing = destination.path().join("old.JPG");
+        fs::write(&existing, b"same content").unwrap();
+
+        let camera = MockCamera::new("Mock");
+        let files = vec![
+            add_file(&camera, "IMG_0001.JPG", b"same content", date()),
+            add_file(&camera, "IMG_0002.JPG", b"new  content", date()),
+        ];
+        let importer = Importer::new(&camera, options(&destination, "", "{name}"));
+        let plan = importer.plan_files(files).unwrap();
+        for planned in &plan.files {
+            assert_eq!(
+                planned.action,
+                PlannedAction::Verify(vec![existing.clone()])
+            );
+        }
+
+        let report = importer.run(plan);
+        assert!(report.is_success());
+        let skipped: Vec<_> = report
+            .skipped
+            .iter()
+            .map(|skipped| (skipped.file.name.as_str(), &skipped.reason))
+            .collect();
+        assert_eq!(
+            skipped,
+            [("IMG_0001.JPG", &SkipReason::Duplicate(existing.clone()))]
+        );
+        assert_eq!(report.imported.len(), 1);
+        assert_eq!(report.imported[0].file.name, "IMG_0002.JPG");
+        assert_eq!(report.bytes_transferred, 24);
+        assert_eq!(destination.files(), ["IMG_0002.JPG", "old.JPG"]);
+    }
+
+    #[test]
+    fn a_parent_folder_named_dot_dot_stays_inside_the_destination() {
+        let camera = MockCamera::new("Mock");
+        let file = CameraFile {
+            parent_folder: Some("MOCK/DCIM/..".to_string()),
+            ..add_file(&camera, "IMG_0001.JPG", b"data", date())
+        };
+        let destination = ScratchDir::new("dot-dot");
+        let importer = Importer::new(&camera, options(&destination, "{folder}", "{folder}"));
+        let plan = importer.plan_files(vec![file.clone()]).unwrap();
+        assert_eq!(
+            plan.files[0].destination,
+            destination.path().join("_").join("_.JPG")
+        );
+
+        let importer = Importer::new(&camera, options(&destination, "../{folder}", "{name}"));
+        match importer.plan_files(vec![file]) {
+            Err(Error::InvalidValue(_)) => {}
+            other => panic!("unexpected plan {:?}", other),
+        }
+    }
+
+    /// Import a JPEG file taken at `date()` with the post-processing `configure` sets up, which
+    /// must change the copy, and check that the delete planner still accepts the copy.
+    fn check_edited_copy_is_deletable(name: &str, configure: fn(ImportOptions) -> ImportOptions) {
+        let mut tiff = tiff_header(8);
+        write_ifd(
+            &mut tiff,
+            8,
+            vec![long(exif::tag::EXIF_IFD_POINTER, &[0x40])],
+            0,
+        );
+        write_ifd(
+            &mut tiff,
+            0x40,
+            vec![
+                ascii(exif::tag::DATE_TIME_ORIGINAL, "2020:09:13 12:26:40"),
+                ascii(exif::tag::OFFSET_TIME_ORIGINAL, "+00:00"),
+            ],
+            0,
+        );
+        let data = jpeg(8, 8, &exif_segment(&tiff));
+        let camera = MockCamera::new("Mock");
+        let file = add_file(&camera, "IMG_0001.JPG", &data, date());
+        let destination = ScratchDir::new(name);
+        let ledger_path = destination.path().join("ledger");
+        let mut ledger = ImportLedger::open(&ledger_path).unwrap();
+        let options = configure(options(&destination, "", "{name}"));
+        let importer = Importer::new(&camera, options).with_ledger(&mut ledger);
+        let report = importer.run(importer.plan_files(vec![file.clone()]).unwrap());
+        assert!(report.is_success());
+        let copy = destination.path().join("IMG_0001.JPG");
+        let saved = fs::read(&copy).unwrap();
+        assert_ne!(saved, data);
+
+        // The ledger keeps the size and hash of the edited copy across sessions.
+        drop(ledger);
+        let ledger = ImportLedger::open(&ledger_path).unwrap();
+        let entry = ledger.entry("Mock", &file).unwrap();
+        assert_eq!(entry.sha256, Some(sha256::digest(&data)));
+        assert_eq!(entry.saved_size, Some(saved.len() as u64));
+        assert_eq!(entry.saved_sha256, Some(sha256::digest(&saved)));
+        for compare in [false, true] {
+            let plan = DeletePlanner::new(&camera)
+                .with_ledger(&ledger)
+                .compare_hashes(compare)
+                .plan(std::slice::from_ref(&file))
+                .unwrap();
+            assert_eq!(
+                plan.files[0].decision,
+                Decision::Delete(Evidence::Ledger(copy.clone()))
+            );
+        }
+    }
+
+    #[test]
+    fn geotagged_copies_can_still_be_deleted_from_the_camera() {
+        check_edited_copy_is_deletable("geotag-delete", |options| {
+            let track = Track::from_points(vec![TrackPoint {
+                time: DateTime::from_system_time(date()),
+                latitude: 48.85,
+                longitude: 2.35,
+                elevation: None,
+            }]);
+            ImportOptions {
+                geotagging: Some(Geotagger::new(track)),
+                ..options
+            }
+        });
+    }
+
+    #[test]
+    fn copies_with_corrected_dates_can_still_be_deleted_from_the_camera() {
+        check_edited_copy_is_deletable("timeshift-delete", |options| ImportOptions {
+            // Rewrites the capture date and its time zone in place.
+            clock_correction: Some(ClockCorrection {
+                camera_offset: 60,
+                camera_zone: Some(UtcOffset::UTC),
+                zone: UtcOffset::new(2, 0),
+            }),
+            ..options
+        });
+    }
+
+    #[test]
+    fn sanitize_name_removes_separators_and_leading_dots() {
+        assert_eq!(sanitize_name("a/b\\c:d"), "a_b_c_d");
+        assert_eq!(sanitize_name(".."), "_");
+        assert_eq!(sanitize_name(".hidden"), "hidden");
+        assert_eq!(sanitize_name(""), "");
+    }
+}
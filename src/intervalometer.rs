@@ -0,0 +1,834 @@
+//! Time-lapse capture timed by the computer.
+//!
+//! An `Intervalometer` takes pictures on a `Schedule` through a `TetherSession` and
+//! downloads them in the background. A frame that comes due while the previous download
+//! is still running is skipped or waits for it, and the run stops when the battery or the
+//! free space on the camera runs low. The progress is kept in a `TimelapseState`, so that
+//! a run that stopped because the camera was disconnected can resume on the reconnected
+//! camera. Time is read from a `Clock`, which `MockClock` replaces in tests.
+
+use crate::camera::{CameraDevice, CameraFile};
+use crate::error::{Error, Result};
+use crate::tether::{Capture, TetherOptions, TetherSession, Trigger};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds per day.
+const DAY: f64 = 86400.0;
+/// Julian day of the Unix epoch.
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+/// Julian day of J2000.0, the epoch of the solar position formulas.
+const J2000: f64 = 2451545.0;
+/// Altitude of the sun's center at sunrise and sunset, allowing for refraction and the
+/// sun's radius.
+const SUNRISE_ALTITUDE: f64 = -0.833;
+/// Tilt of the earth's axis in degrees.
+const OBLIQUITY: f64 = 23.4397;
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+    /// Wait until `time`, returning at once if it has passed.
+    fn sleep_until(&self, time: SystemTime);
+}
+
+/// The computer's clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep_until(&self, time: SystemTime) {
+        if let Ok(duration) = time.duration_since(SystemTime::now()) {
+            thread::sleep(duration);
+        }
+    }
+}
+
+/// A clock that only moves when told to, and jumps ahead instead of sleeping.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<SystemTime>,
+}
+
+impl MockClock {
+    pub fn new(now: SystemTime) -> MockClock {
+        MockClock {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *lock(&self.now) += duration;
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *lock(&self.now) = now;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *lock(&self.now)
+    }
+
+    fn sleep_until(&self, time: SystemTime) {
+        let mut now = lock(&self.now);
+        if time > *now {
+            *now = time;
+        }
+    }
+}
+
+/// An interval that changes linearly from `from` to `to` between `ramp_start` and
+/// `ramp_end`, such as longer intervals at night ramping to shorter ones after sunrise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ramp {
+    /// Time of the first frame.
+    pub start: SystemTime,
+    /// No frames are taken after this time.
+    pub end: SystemTime,
+    pub ramp_start: SystemTime,
+    pub ramp_end: SystemTime,
+    /// Interval before `ramp_start`.
+    pub from: Duration,
+    /// Interval after `ramp_end`.
+    pub to: Duration,
+}
+
+impl Ramp {
+    /// The interval after a frame taken at `time`.
+    pub fn interval_at(&self, time: SystemTime) -> Duration {
+        let span = match self.ramp_end.duration_since(self.ramp_start) {
+            Ok(span) if span > Duration::from_secs(0) => span,
+            _ if time < self.ramp_start => return self.from,
+            _ => return self.to,
+        };
+        let progress = match time.duration_since(self.ramp_start) {
+            Ok(elapsed) => (elapsed.as_secs_f64() / span.as_secs_f64()).min(1.0),
+            Err(_) => 0.0,
+        };
+        let from = self.from.as_secs_f64();
+        Duration::from_secs_f64(from + (self.to.as_secs_f64() - from) * progress)
+    }
+}
+
+/// When the frames of a time-lapse are taken.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schedule {
+    /// Frames at `start`, `start + interval`, ... up to `frames` frames, or without end.
+    Interval {
+        start: SystemTime,
+        interval: Duration,
+        frames: Option<u32>,
+    },
+    /// Frames with an interval that ramps between two values.
+    Ramp(Ramp),
+    /// Frames at the given times, in order.
+    Times(Vec<SystemTime>),
+}
+
+impl Schedule {
+    /// Frames at the given times, sorted.
+    pub fn times(mut times: Vec<SystemTime>) -> Schedule {
+        times.sort();
+        times.dedup();
+        Schedule::Times(times)
+    }
+
+    /// Frames from `start` to `end` with `night` intervals ramping to `day` intervals over
+    /// `ramp`, centered on the first sunrise after `start` at the location.
+    ///
+    /// Fails if the sun does not rise on that day at the location.
+    pub fn sunrise_ramp(
+        latitude: f64,
+        longitude: f64,
+        start: SystemTime,
+        end: SystemTime,
+        night: Duration,
+        day: Duration,
+        ramp: Duration,
+    ) -> Result<Schedule> {
+        let no_sunrise = || {
+            Error::InvalidValue(format!(
+                "the sun does not rise at {}, {} on that day",
+                latitude, longitude
+            ))
+        };
+        let mut rise = sunrise(start, latitude, longitude).ok_or_else(no_sunrise)?;
+        if rise < start {
+            let next_day = start + Duration::from_secs(DAY as u64);
+            rise = sunrise(next_day, latitude, longitude).ok_or_else(no_sunrise)?;
+        }
+        Ok(Schedule::Ramp(Ramp {
+            start,
+            end,
+            ramp_start: rise - ramp / 2,
+            ramp_end: rise + ramp / 2,
+            from: night,
+            to: day,
+        }))
+    }
+
+    /// Time of frame `index`, given the time of the frame before it, or `None` after the
+    /// last frame.
+    pub fn frame_time(&self, index: u32, previous: Option<SystemTime>) -> Option<SystemTime> {
+        match self {
+            Schedule::Interval {
+                start,
+                interval,
+                frames,
+            } => {
+                if frames.is_some_and(|frames| index >= frames) {
+                    return None;
+                }
+                Some(*start + *interval * index)
+            }
+            Schedule::Ramp(ramp) => {
+                let time = match previous {
+                    Some(previous) if index > 0 => previous + ramp.interval_at(previous),
+                    _ => ramp.start,
+                };
+                Some(time).filter(|time| *time <= ramp.end)
+            }
+            Schedule::Times(times) => times.get(index as usize).copied(),
+        }
+    }
+}
+
+/// Time of sunrise on the UTC day of `day` at a location in degrees, with east longitudes
+/// positive, or `None` if the sun does not rise or set that day.
+pub fn sunrise(day: SystemTime, latitude: f64, longitude: f64) -> Option<SystemTime> {
+    sun_event(day, latitude, longitude, -1.0)
+}
+
+/// Time of sunset on the UTC day of `day` at a location, like `sunrise`.
+pub fn sunset(day: SystemTime, latitude: f64, longitude: f64) -> Option<SystemTime> {
+    sun_event(day, latitude, longitude, 1.0)
+}
+
+/// The sunrise equation, solved for the hour angle on the side of noon given by `sign`.
+fn sun_event(day: SystemTime, latitude: f64, longitude: f64, sign: f64) -> Option<SystemTime> {
+    let days = day.duration_since(UNIX_EPOCH).ok()?.as_secs_f64() / DAY;
+    let noon = days.floor() + UNIX_EPOCH_JULIAN_DAY + 0.5;
+    let mean_solar_noon = noon - J2000 + 0.0008 - longitude / 360.0;
+    let anomaly = (357.5291 + 0.985_600_28 * mean_solar_noon).rem_euclid(360.0);
+    let m = anomaly.to_radians();
+    let center = 1.9148 * m.sin() + 0.02 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+    let ecliptic_longitude = (anomaly + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit =
+        J2000 + mean_solar_noon + 0.0053 * m.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+    let sin_declination = ecliptic_longitude.sin() * OBLIQUITY.to_radians().sin();
+    let cos_declination = (1.0 - sin_declination * sin_declination).sqrt();
+    let phi = latitude.to_radians();
+    let cos_hour_angle = (SUNRISE_ALTITUDE.to_radians().sin() - phi.sin() * sin_declination)
+        / (phi.cos() * cos_declination);
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    let julian_day = transit + sign * hour_angle / 360.0;
+    let secs = (julian_day - UNIX_EPOCH_JULIAN_DAY) * DAY;
+    Some(UNIX_EPOCH + Duration::from_secs_f64(secs.max(0.0)))
+}
+
+/// What to do when a frame comes due while a download is still running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusyPolicy {
+    /// Leave out the frame.
+    Skip,
+    /// Take the frame once the downloads have finished.
+    Queue,
+}
+
+/// Settings of a time-lapse run.
+#[derive(Clone, Debug)]
+pub struct IntervalometerOptions {
+    pub busy: BusyPolicy,
+    /// Stop when the battery level drops below this percentage.
+    pub min_battery_level: Option<u8>,
+    /// Stop when the free space on the camera drops below this number of bytes.
+    pub min_free_space: Option<u64>,
+    /// Frames due longer ago than this, such as during a disconnect, are missed instead of
+    /// taken late.
+    pub late_tolerance: Duration,
+}
+
+impl Default for IntervalometerOptions {
+    /// Skip frames while downloading, stop below 5% battery or 100 MB of free space, and
+    /// miss frames more than 2 seconds late.
+    fn default() -> IntervalometerOptions {
+        IntervalometerOptions {
+            busy: BusyPolicy::Skip,
+            min_battery_level: Some(5),
+            min_free_space: Some(100_000_000),
+            late_tolerance: Duration::from_secs(2),
+        }
+    }
+}
+
+/// The progress of a time-lapse, kept across runs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TimelapseState {
+    /// Index of the next frame of the schedule.
+    pub next_frame: u32,
+    /// Scheduled time of the previous frame.
+    pub previous_frame_time: Option<SystemTime>,
+    /// Number of frames taken.
+    pub taken: u32,
+    /// Number of frames skipped while downloading.
+    pub skipped: u32,
+    /// Number of frames missed, such as while disconnected.
+    pub missed: u32,
+    /// Frames taken whose files could not be downloaded because the camera was
+    /// disconnected. They are downloaded when the run resumes.
+    pub pending_downloads: Vec<PendingDownload>,
+}
+
+/// The files of a frame waiting to be downloaded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingDownload {
+    pub index: u32,
+    /// Value of `{seq}` for the frame.
+    pub sequence: u32,
+    pub files: Vec<CameraFile>,
+}
+
+/// Something that happened during a time-lapse run.
+#[derive(Debug)]
+pub enum FrameEvent {
+    /// Frame `index` was taken at `at`.
+    Taken {
+        index: u32,
+        scheduled: SystemTime,
+        at: SystemTime,
+    },
+    /// Frame `index` was left out because a download was running.
+    Skipped { index: u32, scheduled: SystemTime },
+    /// Frame `index` was due too long ago.
+    Missed { index: u32, scheduled: SystemTime },
+    /// Taking frame `index` failed.
+    Failed { index: u32, error: Error },
+    /// The files of frame `index` were downloaded.
+    Downloaded { index: u32, capture: Capture },
+    /// Downloading the files of frame `index` failed.
+    DownloadFailed { index: u32, error: Error },
+}
+
+/// Why a time-lapse run ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StopReason {
+    /// The schedule has no more frames.
+    Finished,
+    /// The stop flag was set.
+    Stopped,
+    LowBattery(u8),
+    LowFreeSpace(u64),
+    /// The camera was disconnected. The run can resume with the same state once it is
+    /// connected again.
+    Disconnected,
+}
+
+/// Takes the frames of a time-lapse.
+pub struct Intervalometer<'a> {
+    camera: &'a dyn CameraDevice,
+    clock: &'a dyn Clock,
+    schedule: Schedule,
+    tether: TetherOptions,
+    options: IntervalometerOptions,
+    stop: Option<&'a AtomicBool>,
+}
+
+impl<'a> Intervalometer<'a> {
+    pub fn new(
+        camera: &'a dyn CameraDevice,
+        clock: &'a dyn Clock,
+        schedule: Schedule,
+        tether: TetherOptions,
+        options: IntervalometerOptions,
+    ) -> Intervalometer<'a> {
+        Intervalometer {
+            camera,
+            clock,
+            schedule,
+            tether,
+            options,
+            stop: None,
+        }
+    }
+
+    /// End the run before the next frame once `stop` is set.
+    pub fn with_stop_flag(mut self, stop: &'a AtomicBool) -> Intervalometer<'a> {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Take the frames of the schedule from `state` on, reporting every frame and download
+    /// to `observer`, until the schedule ends or the run stops.
+    ///
+    /// Files are named with the tether options, with `{seq}` counting the frames taken.
+    pub fn run<F: FnMut(FrameEvent)>(
+        &self,
+        state: &mut TimelapseState,
+        mut observer: F,
+    ) -> Result<StopReason> {
+        let session = match TetherSession::start(self.camera, self.tether.clone()) {
+            Err(error) if is_disconnect(&error) => return Ok(StopReason::Disconnected),
+            session => session?,
+        };
+        let downloads = Downloads::default();
+        let (jobs, receiver) = mpsc::channel::<PendingDownload>();
+        let (done, results) = mpsc::channel();
+        for pending in state.pending_downloads.drain(..) {
+            downloads.start();
+            let _ = jobs.send(pending);
+        }
+        let reason = thread::scope(|scope| {
+            let (session, downloads) = (&session, &downloads);
+            scope.spawn(move || {
+                for pending in receiver {
+                    let result = session.download_capture(
+                        Trigger::Host,
+                        pending.sequence,
+                        pending.files.clone(),
+                    );
+                    let _ = done.send((pending, result));
+                    downloads.finish();
+                }
+            });
+            let reason = self.shoot(state, session, downloads, &jobs, &results, &mut observer);
+            drop(jobs);
+            downloads.wait_idle();
+            reason
+        });
+        for (pending, result) in results.try_iter() {
+            report_download(state, &mut observer, pending, result);
+        }
+        drop(session);
+        reason
+    }
+
+    fn shoot<F: FnMut(FrameEvent)>(
+        &self,
+        state: &mut TimelapseState,
+        session: &TetherSession,
+        downloads: &Downloads,
+        jobs: &mpsc::Sender<PendingDownload>,
+        results: &mpsc::Receiver<(PendingDownload, Result<Capture>)>,
+        observer: &mut F,
+    ) -> Result<StopReason> {
+        loop {
+            for (pending, result) in results.try_iter() {
+                report_download(state, observer, pending, result);
+            }
+            if self.stop.is_some_and(|stop| stop.load(Ordering::SeqCst)) {
+                return Ok(StopReason::Stopped);
+            }
+            let index = state.next_frame;
+            let scheduled = match self.schedule.frame_time(index, state.previous_frame_time) {
+                Some(scheduled) => scheduled,
+                None => return Ok(StopReason::Finished),
+            };
+            let late = self
+                .clock
+                .now()
+                .duration_since(scheduled)
+                .unwrap_or_default();
+            if late > self.options.late_tolerance {
+                state.advance(scheduled);
+                state.missed += 1;
+                observer(FrameEvent::Missed { index, scheduled });
+                continue;
+            }
+            self.clock.sleep_until(scheduled);
+            if let Some(reason) = self.low_resources() {
+                return Ok(reason);
+            }
+            if downloads.is_busy() {
+                match self.options.busy {
+                    BusyPolicy::Skip => {
+                        state.advance(scheduled);
+                        state.skipped += 1;
+                        observer(FrameEvent::Skipped { index, scheduled });
+                        continue;
+                    }
+                    BusyPolicy::Queue => downloads.wait_idle(),
+                }
+            }
+            let at = self.clock.now();
+            match session.trigger() {
+                Ok(files) => {
+                    let pending = PendingDownload {
+                        index,
+                        sequence: self.tether.sequence_start.wrapping_add(state.taken),
+                        files,
+                    };
+                    downloads.start();
+                    if let Err(mpsc::SendError(pending)) = jobs.send(pending) {
+                        downloads.finish();
+                        state.pending_downloads.push(pending);
+                    }
+                    state.advance(scheduled);
+                    state.taken += 1;
+                    observer(FrameEvent::Taken {
+                        index,
+                        scheduled,
+                        at,
+                    });
+                }
+                Err(error) if is_disconnect(&error) => return Ok(StopReason::Disconnected),
+                Err(error) => {
+                    state.advance(scheduled);
+                    observer(FrameEvent::Failed { index, error });
+                }
+            }
+        }
+    }
+
+    fn low_resources(&self) -> Option<StopReason> {
+        if let (Some(minimum), Some(level)) =
+            (self.options.min_battery_level, self.camera.battery_level())
+        {
+            if level < minimum {
+                return Some(StopReason::LowBattery(level));
+            }
+        }
+        if let (Some(minimum), Some(free)) = (self.options.min_free_space, self.camera.free_space())
+        {
+            if free < minimum {
+                return Some(StopReason::LowFreeSpace(free));
+            }
+        }
+        None
+    }
+}
+
+impl TimelapseState {
+    fn advance(&mut self, scheduled: SystemTime) {
+        self.next_frame += 1;
+        self.previous_frame_time = Some(scheduled);
+    }
+}
+
+/// The number of downloads running, to wait for them to finish.
+#[derive(Default)]
+struct Downloads {
+    running: Mutex<usize>,
+    finished: Condvar,
+}
+
+impl Downloads {
+    fn start(&self) {
+        *lock(&self.running) += 1;
+    }
+
+    fn finish(&self) {
+        let mut running = lock(&self.running);
+        *running = running.saturating_sub(1);
+        self.finished.notify_all();
+    }
+
+    fn is_busy(&self) -> bool {
+        *lock(&self.running) > 0
+    }
+
+    fn wait_idle(&self) {
+        let mut running = lock(&self.running);
+        while *running > 0 {
+            running = self
+                .finished
+                .wait(running)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+}
+
+/// Report a finished download, keeping its frame for the next run if the camera was
+/// disconnected.
+fn report_download<F: FnMut(FrameEvent)>(
+    state: &mut TimelapseState,
+    observer: &mut F,
+    pending: PendingDownload,
+    result: Result<Capture>,
+) {
+    let index = pending.index;
+    match result {
+        Ok(capture) => observer(FrameEvent::Downloaded { index, capture }),
+        Err(error) => {
+            if is_disconnect(&error) {
+                state.pending_downloads.push(pending);
+            }
+            observer(FrameEvent::DownloadFailed { index, error });
+        }
+    }
+}
+
+/// Whether an error means that the camera is no longer connected.
+fn is_disconnect(error: &Error) -> bool {
+    match error {
+        Error::Io(err) => matches!(
+            err.kind(),
+            io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+        ),
+        _ => false,
+    }
+}
+
+fn lock<V>(mutex: &Mutex<V>) -> MutexGuard<'_, V> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockCamera;
+    use crate::testing::ScratchDir;
+
+    fn start() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+    }
+
+    fn every_ten_seconds(frames: u32) -> Schedule {
+        Schedule::Interval {
+            start: start(),
+            interval: Duration::from_secs(10),
+            frames: Some(frames),
+        }
+    }
+
+    fn options(busy: BusyPolicy) -> IntervalometerOptions {
+        IntervalometerOptions {
+            busy,
+            ..IntervalometerOptions::default()
+        }
+    }
+
+    fn tether(hot_folder: &ScratchDir) -> TetherOptions {
+        TetherOptions {
+            settle_time: Duration::from_millis(10),
+            capture_timeout: Duration::from_secs(5),
+            ..TetherOptions::new(hot_folder.path())
+        }
+    }
+
+    /// Indices of the frames reported downloaded.
+    fn downloaded(events: &[FrameEvent]) -> Vec<u32> {
+        let mut indices: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                FrameEvent::Downloaded { index, .. } => Some(*index),
+                _ => None,
+            })
+            .collect();
+        indices.sort_unstable();
+        indices
+    }
+
+    #[test]
+    fn frames_due_during_a_download_are_skipped() {
+        let camera = MockCamera::new("Mock");
+        camera.set_download_delay(Duration::from_millis(300));
+        let clock = MockClock::new(start());
+        let hot_folder = ScratchDir::new("skip");
+        let intervalometer = Intervalometer::new(
+            &camera,
+            &clock,
+            every_ten_seconds(3),
+            tether(&hot_folder),
+            options(BusyPolicy::Skip),
+        );
+        let mut state = TimelapseState::default();
+        let mut events = Vec::new();
+        let reason = intervalometer
+            .run(&mut state, |event| events.push(event))
+            .unwrap();
+        assert_eq!(reason, StopReason::Finished);
+        assert_eq!((state.taken, state.skipped, state.missed), (1, 2, 0));
+        assert_eq!(camera.captures(), 1);
+        assert_eq!(downloaded(&events), [0]);
+        assert_eq!(hot_folder.files(), ["IMG_0001.JPG"]);
+    }
+
+    #[test]
+    fn frames_due_during_a_download_wait_for_it_when_queued() {
+        let camera = MockCamera::new("Mock");
+        camera.set_download_delay(Duration::from_millis(50));
+        let clock = MockClock::new(start());
+        let hot_folder = ScratchDir::new("queue");
+        let intervalometer = Intervalometer::new(
+            &camera,
+            &clock,
+            every_ten_seconds(3),
+            tether(&hot_folder),
+            options(BusyPolicy::Queue),
+        );
+        let mut state = TimelapseState::default();
+        let mut events = Vec::new();
+        let reason = intervalometer
+            .run(&mut state, |event| events.push(event))
+            .unwrap();
+        assert_eq!(reason, StopReason::Finished);
+        assert_eq!((state.taken, state.skipped, state.missed), (3, 0, 0));
+        assert_eq!(downloaded(&events), [0, 1, 2]);
+        assert_eq!(
+            hot_folder.files(),
+            ["IMG_0001.JPG", "IMG_0002.JPG", "IMG_0003.JPG"]
+        );
+    }
+
+    #[test]
+    fn run_stops_on_low_battery() {
+        let camera = MockCamera::new("Mock");
+        camera.set_battery_level(Some(3));
+        let clock = MockClock::new(start());
+        let hot_folder = ScratchDir::new("battery");
+        let intervalometer = Intervalometer::new(
+            &camera,
+            &clock,
+            every_ten_seconds(3),
+            tether(&hot_folder),
+            options(BusyPolicy::Skip),
+        );
+        let mut state = TimelapseState::default();
+        let reason = intervalometer.run(&mut state, |_| {}).unwrap();
+        assert_eq!(reason, StopReason::LowBattery(3));
+        assert_eq!(state.taken, 0);
+        assert_eq!(camera.captures(), 0);
+    }
+
+    #[test]
+    fn run_stops_on_low_free_space() {
+        let camera = MockCamera::new("Mock");
+        let clock = MockClock::new(start());
+        let hot_folder = ScratchDir::new("space");
+        let options = IntervalometerOptions {
+            min_free_space: Some(1000),
+            ..options(BusyPolicy::Queue)
+        };
+        let intervalometer = Intervalometer::new(
+            &camera,
+            &clock,
+            every_ten_seconds(3),
+            tether(&hot_folder),
+            options,
+        );
+        let mut state = TimelapseState::default();
+        let mut events = Vec::new();
+        let reason = intervalometer
+            .run(&mut state, |event| {
+                if let FrameEvent::Taken { index: 0, .. } = event {
+                    camera.set_free_space(Some(500));
+                }
+                events.push(event);
+            })
+            .unwrap();
+        assert_eq!(reason, StopReason::LowFreeSpace(500));
+        assert_eq!(state.taken, 1);
+        assert_eq!(state.next_frame, 1);
+        assert_eq!(downloaded(&events), [0]);
+    }
+
+    #[test]
+    fn frames_due_while_disconnected_are_missed_on_resume() {
+        let camera = MockCamera::new("Mock");
+        let clock = MockClock::new(start());
+        let hot_folder = ScratchDir::new("disconnect");
+        let intervalometer = Intervalometer::new(
+            &camera,
+            &clock,
+            every_ten_seconds(5),
+            tether(&hot_folder),
+            options(BusyPolicy::Queue),
+        );
+        let mut state = TimelapseState::default();
+        let mut events = Vec::new();
+        let reason = intervalometer
+            .run(&mut state, |event| {
+                if let FrameEvent::Taken { index: 0, .. } = event {
+                    camera.set_disconnected(true);
+                }
+                events.push(event);
+            })
+            .unwrap();
+        assert_eq!(reason, StopReason::Disconnected);
+        assert_eq!((state.taken, state.next_frame), (1, 1));
+
+        // Reconnected 35 seconds in: frames 1 to 3 are more than 2 seconds late.
+        camera.set_disconnected(false);
+        clock.set(start() + Duration::from_secs(35));
+        let reason = intervalometer
+            .run(&mut state, |event| events.push(event))
+            .unwrap();
+        assert_eq!(reason, StopReason::Finished);
+        assert_eq!((state.taken, state.skipped, state.missed), (2, 0, 3));
+        let missed: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                FrameEvent::Missed { index, .. } => Some(*index),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(missed, [1, 2, 3]);
+        // Frame 0 is downloaded once, before the disconnect or after the reconnect.
+        assert_eq!(downloaded(&events), [0, 4]);
+        assert!(state.pending_downloads.is_empty());
+        assert_eq!(hot_folder.files(), ["IMG_0001.JPG", "IMG_0002.JPG"]);
+    }
+
+    #[test]
+    fn resumed_run_downloads_pending_frames() {
+        let camera = MockCamera::new("Mock");
+        let file = camera.add_file("IMG_0001.JPG", b"first frame");
+        let clock = MockClock::new(start() + Duration::from_secs(10));
+        let hot_folder = ScratchDir::new("resume");
+        let tether = TetherOptions {
+            name_template: crate::import::Template::parse("frame-{seq}").unwrap(),
+            ..tether(&hot_folder)
+        };
+        let intervalometer = Intervalometer::new(
+            &camera,
+            &clock,
+            every_ten_seconds(2),
+            tether,
+            options(BusyPolicy::Queue),
+        );
+        let mut state = TimelapseState {
+            next_frame: 1,
+            previous_frame_time: Some(start()),
+            taken: 1,
+            pending_downloads: vec![PendingDownload {
+                index: 0,
+                sequence: 1,
+                files: vec![file],
+            }],
+            ..TimelapseState::default()
+        };
+        let mut events = Vec::new();
+        let reason = intervalometer
+            .run(&mut state, |event| events.push(event))
+            .unwrap();
+        assert_eq!(reason, StopReason::Finished);
+        assert_eq!(state.taken, 2);
+        assert!(state.pending_downloads.is_empty());
+        assert_eq!(downloaded(&events), [0, 1]);
+        assert_eq!(hot_folder.files(), ["frame-1.JPG", "frame-2.JPG"]);
+        assert_eq!(
+            std::fs::read(hot_folder.path().join("frame-1.JPG")).unwrap(),
+            b"first frame"
+        );
+    }
+}
@@ -0,0 +1,480 @@
+//! JPEG marker segments and a luminance histogram read from baseline JPEG data.
+
+use crate::error::{Error, Result};
+use std::ops::Range;
+
+pub const SOI: u8 = 0xD8;
+pub const EOI: u8 = 0xD9;
+pub const SOS: u8 = 0xDA;
+pub const DQT: u8 = 0xDB;
+pub const DHT: u8 = 0xC4;
+pub const DRI: u8 = 0xDD;
+pub const APP1: u8 = 0xE1;
+/// Baseline and extended sequential frames, the only ones `luma_histogram` decodes.
+pub const SOF0: u8 = 0xC0;
+pub const SOF1: u8 = 0xC1;
+
+/// A marker segment of a JPEG file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Segment<'a> {
+    pub marker: u8,
+    /// Offset of the marker in the file.
+    pub offset: usize,
+    /// The segment's payload, without the marker and length.
+    pub data: &'a [u8],
+}
+
+/// The marker segments of a JPEG file, up to and including the first SOS segment.
+pub fn segments(data: &[u8]) -> Result<Vec<Segment<'_>>> {
+    if data.len() < 2 || data[0] != 0xFF || data[1] != SOI {
+        return Err(Error::malformed("JPEG data does not start with SOI"));
+    }
+    let mut segments = Vec::new();
+    let mut offset = 2;
+    loop {
+        // Markers may be preceded by any number of fill bytes.
+        while data.get(offset) == Some(&0xFF) && data.get(offset + 1) == Some(&0xFF) {
+            offset += 1;
+        }
+        if data.len() < offset + 2 || data[offset] != 0xFF {
+            return Err(Error::malformed(format!(
+                "expected a JPEG marker at offset {}",
+                offset
+            )));
+        }
+        let marker = data[offset + 1];
+        if marker == EOI {
+            return Ok(segments);
+        }
+        if data.len() < offset + 4 {
+            return Err(Error::malformed("truncated JPEG segment"));
+        }
+        let length = usize::from(u16::from_be_bytes([data[offset + 2], data[offset + 3]]));
+        if length < 2 || data.len() < offset + 2 + length {
+            return Err(Error::malformed(format!(
+                "JPEG segment 0x{:02X} at offset {} overruns the data",
+                marker, offset
+            )));
+        }
+        segments.push(Segment {
+            marker,
+            offset,
+            data: &data[offset + 4..offset + 2 + length],
+        });
+        if marker == SOS {
+            return Ok(segments);
+        }
+        offset += 2 + length;
+    }
+}
+
+/// Range of the JPEG image embedded in `data`, from its SOI marker to the last EOI marker.
+pub fn find_image(data: &[u8]) -> Option<Range<usize>> {
+    let start = data.windows(2).position(|w| w == [0xFF, SOI])?;
+    let end = data[start..]
+        .windows(2)
+        .rposition(|w| w == [0xFF, EOI])
+        .map_or(data.len(), |end| start + end + 2);
+    Some(start..end)
+}
+
+/// Width and height from the frame header.
+pub fn dimensions(data: &[u8]) -> Result<(u32, u32)> {
+    for segment in segments(data)? {
+        let is_frame =
+            matches!(segment.marker, 0xC0..=0xCF) && !matches!(segment.marker, DHT | 0xC8 | 0xCC);
+        if is_frame && segment.data.len() >= 5 {
+            let d = segment.data;
+            let height = u16::from_be_bytes([d[1], d[2]]);
+            let width = u16::from_be_bytes([d[3], d[4]]);
+            return Ok((u32::from(width), u32::from(height)));
+        }
+    }
+    Err(Error::malformed("JPEG data has no frame header"))
+}
+
+/// Histogram of the average luminance of each 8×8 block of a baseline JPEG image.
+///
+/// Only the DC coefficients are used, so no inverse DCT is needed; this is coarse but
+/// enough for an exposure histogram. Progressive and arithmetic-coded images are not supported.
+pub fn luma_histogram(data: &[u8]) -> Result<[u32; 256]> {
+    let mut quant = [[0u16; 64]; 4];
+    let mut dc_tables: [Option<Huffman>; 4] = Default::default();
+    let mut ac_tables: [Option<Huffman>; 4] = Default::default();
+    let mut frame = None;
+    let mut restart_interval = 0;
+    let segments = segments(data)?;
+    for segment in &segments {
+        let mut d = segment.data;
+        match segment.marker {
+            DQT => {
+                while !d.is_empty() {
+                    let precision = d[0] >> 4;
+                    let id = usize::from(d[0] & 3);
+                    let size = if precision == 0 { 64 } else { 128 };
+                    if d.len() < 1 + size {
+                        return Err(Error::malformed("truncated quantization table"));
+                    }
+                    for (i, q) in quant[id].iter_mut().enumerate() {
+                        *q = if precision == 0 {
+                            u16::from(d[1 + i])
+                        } else {
+                            u16::from_be_bytes([d[1 + 2 * i], d[2 + 2 * i]])
+                        };
+                    }
+                    d = &d[1 + size..];
+                }
+            }
+            DHT => {
+                while d.len() >= 17 {
+                    let class = d[0] >> 4;
+                    let id = usize::from(d[0] & 3);
+                    let mut counts = [0u8; 16];
+                    counts.copy_from_slice(&d[1..17]);
+                    let total: usize = counts.iter().map(|&c| usize::from(c)).sum();
+                    if d.len() < 17 + total {
+                        return Err(Error::malformed("truncated Huffman table"));
+                    }
+                    let table = Huffman::new(&counts, &d[17..17 + total]);
+                    if class == 0 {
+                        dc_tables[id] = Some(table);
+                    } else {
+                        ac_tables[id] = Some(table);
+                    }
+                    d = &d[17 + total..];
+                }
+            }
+            DRI if d.len() >= 2 => restart_interval = u16::from_be_bytes([d[0], d[1]]),
+            SOF0 | SOF1 => frame = Some(Frame::parse(d)?),
+            0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                return Err(Error::Unsupported("non-baseline JPEG decoding".into()))
+            }
+            _ => {}
+        }
+    }
+    let frame = frame.ok_or_else(|| Error::malformed("JPEG data has no baseline frame header"))?;
+    let scan = segments
+        .last()
+        .filter(|segment| segment.marker == SOS)
+        .ok_or_else(|| Error::malformed("JPEG data has no scan"))?;
+    let start = scan.offset + 4 + scan.data.len();
+
+    // Components in scan order with their tables and block counts per MCU.
+    let count = usize::from(*scan.data.first().unwrap_or(&0));
+    if count == 0 || scan.data.len() < 1 + 2 * count {
+        return Err(Error::malformed("invalid scan header"));
+    }
+    let mut components = Vec::new();
+    for i in 0..count {
+        let id = scan.data[1 + 2 * i];
+        let tables = scan.data[2 + 2 * i];
+        let component = frame
+            .components
+            .iter()
+            .find(|c| c.id == id)
+            .ok_or_else(|| Error::malformed(format!("scan refers to unknown component {}", id)))?;
+        let dc = dc_tables[usize::from((tables >> 4) & 3)]
+            .as_ref()
+            .ok_or_else(|| Error::malformed("missing DC Huffman table"))?;
+        let ac = ac_tables[usize::from(tables & 3)]
+            .as_ref()
+            .ok_or_else(|| Error::malformed("missing AC Huffman table"))?;
+        components.push((component, dc, ac));
+    }
+    let luma_id = frame.components[0].id;
+    let luma_quant = i64::from(quant[usize::from(frame.components[0].quant_table & 3)][0]).max(1);
+
+    let (mcus, blocks_per_mcu): (usize, Vec<usize>) = if count == 1 {
+        let c = components[0].0;
+        let width = (frame.width * c.h as usize).div_ceil(frame.max_h);
+        let height = (frame.height * c.v as usize).div_ceil(frame.max_v);
+        (width.div_ceil(8) * height.div_ceil(8), vec![1])
+    } else {
+        let mcus_x = frame.width.div_ceil(8 * frame.max_h);
+        let mcus_y = frame.height.div_ceil(8 * frame.max_v);
+        (
+            mcus_x * mcus_y,
+            components
+                .iter()
+                .map(|(c, _, _)| usize::from(c.h) * usize::from(c.v))
+                .collect(),
+        )
+    };
+
+    let mut histogram = [0u32; 256];
+    let mut bits = BitReader::new(&data[start..]);
+    // In 64 bits: a 65535-pixel square image has 2^26 blocks, whose 16-bit DC differences
+    // add up to less than 2^42 before they are scaled by a quantizer below 2^16.
+    let mut predictions = vec![0i64; components.len()];
+    for mcu in 0..mcus {
+        if restart_interval > 0 && mcu > 0 && mcu % usize::from(restart_interval) == 0 {
+            bits.restart();
+            predictions.iter_mut().for_each(|p| *p = 0);
+        }
+        for (index, (component, dc, ac)) in components.iter().enumerate() {
+            for _ in 0..blocks_per_mcu[index] {
+                let size = dc.decode(&mut bits)?;
+                predictions[index] += i64::from(bits.receive_extend(size)?);
+                skip_ac(ac, &mut bits)?;
+                if component.id == luma_id {
+                    // The DC coefficient is eight times the block's mean level shifted by 128.
+                    let level = predictions[index] * luma_quant / 8 + 128;
+                    histogram[level.clamp(0, 255) as usize] += 1;
+                }
+            }
+        }
+    }
+    Ok(histogram)
+}
+
+struct Component {
+    id: u8,
+    h: u8,
+    v: u8,
+    quant_table: u8,
+}
+
+struct Frame {
+    width: usize,
+    height: usize,
+    max_h: usize,
+    max_v: usize,
+    components: Vec<Component>,
+}
+
+impl Frame {
+    fn parse(d: &[u8]) -> Result<Frame> {
+        if d.len() < 6 {
+            return Err(Error::malformed("truncated frame header"));
+        }
+        let height = usize::from(u16::from_be_bytes([d[1], d[2]]));
+        let width = usize::from(u16::from_be_bytes([d[3], d[4]]));
+        let count = usize::from(d[5]);
+        if count == 0 || d.len() < 6 + 3 * count || width == 0 || height == 0 {
+            return Err(Error::malformed("invalid frame header"));
+        }
+        let components: Vec<Component> = (0..count)
+            .map(|i| Component {
+                id: d[6 + 3 * i],
+                h: (d[7 + 3 * i] >> 4).max(1),
+                v: (d[7 + 3 * i] & 15).max(1),
+                quant_table: d[8 + 3 * i],
+            })
+            .collect();
+        Ok(Frame {
+            width,
+            height,
+            max_h: components
+                .iter()
+                .map(|c| usize::from(c.h))
+                .max()
+                .unwrap_or(1),
+            max_v: components
+                .iter()
+                .map(|c| usize::from(c.v))
+                .max()
+                .unwrap_or(1),
+            components,
+        })
+    }
+}
+
+/// A canonical Huffman table.
+struct Huffman {
+    /// Largest code of each length, or -1 if there is none.
+    max_code: [i32; 17],
+    /// Index in `symbols` of the first code of each length, minus that code.
+    offset: [i32; 17],
+    symbols: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8; 16], symbols: &[u8]) -> Huffman {
+        let mut max_code = [-1i32; 17];
+        let mut offset = [0i32; 17];
+        let mut code = 0i32;
+        let mut index = 0i32;
+        for length in 1..=16 {
+            let count = i32::from(counts[length - 1]);
+            if count > 0 {
+                offset[length] = index - code;
+                code += count;
+                index += count;
+                max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        Huffman {
+            max_code,
+            offset,
+            symbols: symbols.to_vec(),
+        }
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u8> {
+        let mut code = 0i32;
+        for length in 1..=16 {
+            code = (code << 1) | bits.bit()? as i32;
+            if code <= self.max_code[length] {
+                return self
+                    .symbols
+                    .get((code + self.offset[length]) as usize)
+                    .cloned()
+                    .ok_or_else(|| Error::malformed("invalid Huffman code"));
+            }
+        }
+        Err(Error::malformed("invalid Huffman code"))
+    }
+}
+
+fn skip_ac(table: &Huffman, bits: &mut BitReader) -> Result<()> {
+    let mut k = 1;
+    while k < 64 {
+        let rs = table.decode(bits)?;
+        let (run, size) = (rs >> 4, rs & 15);
+        if size == 0 {
+            if run != 15 {
+                break;
+            }
+            k += 16;
+        } else {
+            k += usize::from(run) + 1;
+            bits.bits(size)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the entropy-coded segment, removing stuffed zero bytes.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            position: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn bit(&mut self) -> Result<u32> {
+        if self.count == 0 {
+            let byte = match self.data.get(self.position) {
+                Some(&0xFF) => match self.data.get(self.position + 1) {
+                    Some(0x00) => {
+                        self.position += 2;
+                        0xFF
+                    }
+                    // A marker ends the data; decoders pad with one bits.
+                    _ => 0xFF,
+                },
+                Some(&byte) => {
+                    self.position += 1;
+                    byte
+                }
+                None => return Err(Error::malformed("truncated JPEG scan")),
+            };
+            self.buffer = u32::from(byte);
+            self.count = 8;
+        }
+        self.count -= 1;
+        Ok((self.buffer >> self.count) & 1)
+    }
+
+    fn bits(&mut self, count: u8) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.bit()?;
+        }
+        Ok(value)
+    }
+
+    /// Read a `size`-bit magnitude and extend it to a signed value.
+    fn receive_extend(&mut self, size: u8) -> Result<i32> {
+        if size == 0 {
+            return Ok(0);
+        }
+        if size > 16 {
+            return Err(Error::malformed("invalid DC coefficient size"));
+        }
+        let value = self.bits(size)? as i32;
+        Ok(if value < 1 << (size - 1) {
+            value - (1 << size) + 1
+        } else {
+            value
+        })
+    }
+
+    /// Skip to the byte after the next restart marker.
+    fn restart(&mut self) {
+        self.count = 0;
+        while self.position + 1 < self.data.len() {
+            let marker = self.data[self.position + 1];
+            if self.data[self.position] == 0xFF && (0xD0..=0xD7).contains(&marker) {
+                self.position += 2;
+                return;
+            }
+            self.position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grayscale baseline JPEG image `blocks` blocks wide and one block high, quantized by
+    /// `quant`, whose scan is all one bits: every block adds the largest `dc_size`-bit DC
+    /// difference and has no AC coefficients.
+    fn ones(blocks: u16, quant: u16, dc_size: u8) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend_from_slice(&[0xFF, 0xDB, 0x00, 131, 0x10]);
+        for _ in 0..64 {
+            jpeg.extend_from_slice(&quant.to_be_bytes());
+        }
+        // Code 1 is a DC difference of `dc_size` bits, and the end of block for AC.
+        for (class, symbols) in [(0x00, [0, dc_size]), (0x10, [0x01, 0x00])] {
+            jpeg.extend_from_slice(&[0xFF, 0xC4, 0x00, 21, class, 2]);
+            jpeg.extend_from_slice(&[0; 15]);
+            jpeg.extend_from_slice(&symbols);
+        }
+        jpeg.extend_from_slice(&[0xFF, 0xC0, 0x00, 11, 8, 0, 8]);
+        jpeg.extend_from_slice(&(blocks * 8).to_be_bytes());
+        jpeg.extend_from_slice(&[1, 1, 0x11, 0]);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 8, 1, 1, 0x00, 0, 63, 0]);
+        // Decoders read one bits once a marker ends the scan.
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn the_histogram_counts_block_levels() {
+        assert_eq!(dimensions(&ones(4, 8, 4)).unwrap(), (32, 8));
+        let histogram = luma_histogram(&ones(4, 8, 4)).unwrap();
+        // The DC coefficients are 15, 30, 45 and 60, each eight times the level above 128.
+        for level in [143, 158, 173, 188] {
+            assert_eq!(histogram[level], 1, "level {}", level);
+        }
+        assert_eq!(histogram.iter().sum::<u32>(), 4);
+    }
+
+    #[test]
+    fn extreme_coefficients_do_not_overflow() {
+        let histogram = luma_histogram(&ones(4096, u16::MAX, 16)).unwrap();
+        assert_eq!(histogram[255], 4096);
+    }
+
+    #[test]
+    fn progressive_images_are_not_decoded() {
+        let mut jpeg = ones(1, 1, 1);
+        let frame = jpeg.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+        jpeg[frame + 1] = 0xC2;
+        assert!(matches!(luma_histogram(&jpeg), Err(Error::Unsupported(_))));
+    }
+}
@@ -0,0 +1,675 @@
+//! A file-backed record of imported items, so that items already imported from a device
+//! are recognized when it is connected again.
+//!
+//! The ledger is an append-only text file with one entry per line. Every recorded batch is
+//! synced to disk before `record` returns, and a line left incomplete by a crash is dropped
+//! when the ledger is opened again. Pruning rewrites the file into a temporary file that
+//! replaces the ledger atomically.
+
+use crate::camera::CameraFile;
+use crate::error::{Error, Result};
+use crate::sha256;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// First line of a ledger file.
+const HEADER: &str = "image-capture-core import ledger 1";
+/// Number of fields of an entry line.
+const FIELD_COUNT: usize = 11;
+/// Number of fields of an entry line written before the saved copy was recorded.
+const OLD_FIELD_COUNT: usize = 9;
+
+/// An imported item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LedgerEntry {
+    /// The device's `persistent_id_string`.
+    pub device_id: String,
+    pub name: String,
+    pub parent_folder: Option<String>,
+    pub file_size: u64,
+    pub creation_date: Option<SystemTime>,
+    pub ptp_object_handle: Option<u32>,
+    /// SHA-256 digest of the content, if it was computed during the import.
+    pub sha256: Option<[u8; 32]>,
+    /// Where the item was saved.
+    pub destination: Option<PathBuf>,
+    pub imported_at: SystemTime,
+    /// Size of the saved copy, if post-processing such as geotagging changed it.
+    pub saved_size: Option<u64>,
+    /// SHA-256 digest of the saved copy, if post-processing changed it.
+    pub saved_sha256: Option<[u8; 32]>,
+}
+
+impl LedgerEntry {
+    /// An entry for `file` of the device `device_id`, imported now.
+    pub fn new(device_id: &str, file: &CameraFile) -> LedgerEntry {
+        LedgerEntry {
+            device_id: device_id.to_string(),
+            name: file.name.clone(),
+            parent_folder: file.parent_folder.clone(),
+            file_size: file.file_size,
+            creation_date: file.creation_date,
+            ptp_object_handle: file.ptp_object_handle,
+            sha256: None,
+            destination: None,
+            imported_at: SystemTime::now(),
+            saved_size: None,
+            saved_sha256: None,
+        }
+    }
+
+    fn key(&self) -> ItemKey {
+        ItemKey {
+            device_id: self.device_id.clone(),
+            parent_folder: self.parent_folder.clone(),
+            name: self.name.clone(),
+            file_size: self.file_size,
+            creation_date: self.creation_date.map(unix_secs),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        let fields = [
+            escape(&self.device_id),
+            escape(&self.name),
+            self.parent_folder
+                .as_deref()
+                .map_or_else(|| "-".into(), escape),
+            self.file_size.to_string(),
+            self.creation_date
+                .map_or_else(|| "-".into(), |date| unix_secs(date).to_string()),
+            self.ptp_object_handle
+                .map_or_else(|| "-".into(), |handle| handle.to_string()),
+            self.sha256
+                .map_or_else(|| "-".into(), |digest| sha256::to_hex(&digest)),
+            self.destination
+                .as_ref()
+                .map_or_else(|| "-".into(), |path| escape(&path.to_string_lossy())),
+            unix_secs(self.imported_at).to_string(),
+            self.saved_size
+                .map_or_else(|| "-".into(), |size| size.to_string()),
+            self.saved_sha256
+                .map_or_else(|| "-".into(), |digest| sha256::to_hex(&digest)),
+        ];
+        let mut line = fields.join("\t");
+        line.push('\n');
+        line
+    }
+
+    fn from_line(line: &str) -> Option<LedgerEntry> {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() != FIELD_COUNT && fields.len() != OLD_FIELD_COUNT {
+            return None;
+        }
+        let optional = |field: &str| {
+            if field == "-" {
+                None
+            } else {
+                Some(unescape(field))
+            }
+        };
+        Some(LedgerEntry {
+            device_id: unescape(fields[0]),
+            name: unescape(fields[1]),
+            parent_folder: optional(fields[2]),
+            file_size: fields[3].parse().ok()?,
+            creation_date: match fields[4] {
+                "-" => None,
+                secs => Some(from_unix_secs(secs.parse().ok()?)),
+            },
+            ptp_object_handle: match fields[5] {
+                "-" => None,
+                handle => Some(handle.parse().ok()?),
+            },
+            sha256: match fields[6] {
+                "-" => None,
+                hex => Some(parse_digest(hex)?),
+            },
+            destination: optional(fields[7]).map(PathBuf::from),
+            imported_at: from_unix_secs(fields[8].parse().ok()?),
+            saved_size: match fields.get(9) {
+                None | Some(&"-") => None,
+                Some(size) => Some(size.parse().ok()?),
+            },
+            saved_sha256: match fields.get(10) {
+                None | Some(&"-") => None,
+                Some(hex) => Some(parse_digest(hex)?),
+            },
+        })
+    }
+}
+
+/// The identity of an item on a device.
+///
+/// The folder tells apart files a camera numbers alike in different folders. PTP object
+/// handles are not part of it, as many cameras assign new handles in every session.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ItemKey {
+    device_id: String,
+    parent_folder: Option<String>,
+    name: String,
+    file_size: u64,
+    creation_date: Option<i64>,
+}
+
+/// The import history, loaded in memory and backed by a file.
+pub struct ImportLedger {
+    path: PathBuf,
+    file: File,
+    entries: HashMap<ItemKey, LedgerEntry>,
+    by_hash: HashMap<[u8; 32], ItemKey>,
+}
+
+impl ImportLedger {
+    /// Open the ledger at `path`, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ImportLedger> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut ledger = ImportLedger {
+            path,
+            file: file.try_clone()?,
+            entries: HashMap::new(),
+            by_hash: HashMap::new(),
+        };
+        let mut reader = BufReader::new(&mut file);
+        let mut line = String::new();
+        let mut valid_length = 0;
+        let mut first = true;
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                // End of file, or a line cut short by a crash.
+                break;
+            }
+            if first {
+                if line.trim_end() != HEADER {
+                    return Err(Error::malformed(format!(
+                        "{} is not an import ledger",
+                        ledger.path.display()
+                    )));
+                }
+                first = false;
+            } else if let Some(entry) = LedgerEntry::from_line(line.trim_end_matches('\n')) {
+                ledger.insert(entry);
+            }
+            valid_length += read as u64;
+        }
+        drop(reader);
+        if file.metadata()?.len() != valid_length {
+            file.set_len(valid_length)?;
+        }
+        if valid_length == 0 {
+            ledger.file.write_all(format!("{}\n", HEADER).as_bytes())?;
+            ledger.file.sync_data()?;
+        }
+        ledger.file.seek(SeekFrom::End(0))?;
+        Ok(ledger)
+    }
+
+    /// Path of the ledger file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// All entries, in no particular order.
+    pub fn entries(&self) -> impl Iterator<Item = &LedgerEntry> {
+        self.entries.values()
+    }
+
+    /// The entry of `file` on the device `device_id`, matched by folder, name, size and
+    /// creation date.
+    pub fn entry(&self, device_id: &str, file: &CameraFile) -> Option<&LedgerEntry> {
+        self.entries.get(&ItemKey {
+            device_id: device_id.to_string(),
+            parent_folder: file.parent_folder.clone(),
+            name: file.name.clone(),
+            file_size: file.file_size,
+            creation_date: file.creation_date.map(unix_secs),
+        })
+    }
+
+    /// Whether `file` of the device `device_id` was imported before.
+    pub fn is_imported(&self, device_id: &str, file: &CameraFile) -> bool {
+        self.entry(device_id, file).is_some()
+    }
+
+    /// The entry of an item with the content digest `sha256`, from any device.
+    pub fn find_by_hash(&self, sha256: &[u8; 32]) -> Option<&LedgerEntry> {
+        self.by_hash
+            .get(sha256)
+            .and_then(|key| self.entries.get(key))
+    }
+
+    /// Record an imported item, replacing any entry for the same item.
+    pub fn record(&mut self, entry: LedgerEntry) -> Result<()> {
+        self.record_all(vec![entry])
+    }
+
+    /// Record imported items with a single write to disk.
+    pub fn record_all(&mut self, entries: Vec<LedgerEntry>) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let lines = entries.iter().map(LedgerEntry::to_line).collect::<String>();
+        self.file.write_all(lines.as_bytes())?;
+        self.file.sync_data()?;
+        for entry in entries {
+            self.insert(entry);
+        }
+        Ok(())
+    }
+
+    /// Remove the entries for which `keep` returns false, returning how many were removed.
+    pub fn prune<F: FnMut(&LedgerEntry) -> bool>(&mut self, mut keep: F) -> Result<usize> {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| keep(entry));
+        let removed = before - self.entries.len();
+        if removed > 0 {
+            let entries = &self.entries;
+            self.by_hash.retain(|_, key| entries.contains_key(key));
+            self.rewrite()?;
+        }
+        Ok(removed)
+    }
+
+    /// Remove entries imported before `date`.
+    pub fn prune_before(&mut self, date: SystemTime) -> Result<usize> {
+        self.prune(|entry| entry.imported_at >= date)
+    }
+
+    /// Remove the entries of the device `device_id`.
+    pub fn prune_device(&mut self, device_id: &str) -> Result<usize> {
+        self.prune(|entry| entry.device_id != device_id)
+    }
+
+    /// Rewrite the file without entries that were replaced by later ones.
+    pub fn compact(&mut self) -> Result<()> {
+        self.rewrite()
+    }
+
+    /// Write all entries as CSV with a header row, sorted by device and import date.
+    pub fn export_csv<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writeln!(
+            writer,
+            "device_id,parent_folder,name,file_size,creation_date,ptp_object_handle,sha256,destination,imported_at,saved_size,saved_sha256"
+        )?;
+        let mut entries = self.entries.values().collect::<Vec<_>>();
+        entries.sort_by(|a, b| {
+            (&a.device_id, a.imported_at, &a.name).cmp(&(&b.device_id, b.imported_at, &b.name))
+        });
+        for entry in entries {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{}",
+                csv_field(&entry.device_id),
+                csv_field(entry.parent_folder.as_deref().unwrap_or_default()),
+                csv_field(&entry.name),
+                entry.file_size,
+                entry
+                    .creation_date
+                    .map(|date| unix_secs(date).to_string())
+                    .unwrap_or_default(),
+                entry
+                    .ptp_object_handle
+                    .map(|handle| handle.to_string())
+                    .unwrap_or_default(),
+                entry.sha256.map(|d| sha256::to_hex(&d)).unwrap_or_default(),
+                csv_field(
+                    &entry
+                        .destination
+                        .as_ref()
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                ),
+                unix_secs(entry.imported_at),
+                entry
+                    .saved_size
+                    .map(|size| size.to_string())
+                    .unwrap_or_default(),
+                entry
+                    .saved_sha256
+                    .map(|d| sha256::to_hex(&d))
+                    .unwrap_or_default()
+            )?;
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, entry: LedgerEntry) {
+        let key = entry.key();
+        if let Some(digest) = entry.sha256 {
+            self.by_hash.insert(digest, key.clone());
+        }
+        self.entries.insert(key, entry);
+    }
+
+    fn rewrite(&mut self) -> Result<()> {
+        let mut temp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = self.path.with_file_name(temp_name);
+        let mut contents = format!("{}\n", HEADER);
+        let mut entries = self.entries.values().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.imported_at);
+        for entry in entries {
+            contents.push_str(&entry.to_line());
+        }
+        {
+            let mut temp = File::create(&temp_path)?;
+            temp.write_all(contents.as_bytes())?;
+            temp.sync_all()?;
+        }
+        fs::rename(&temp_path, &self.path)?;
+        sync_parent(&self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Sync the directory holding `path`, so that a rename into it survives a crash.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+/// Directories cannot be opened as files on other systems, where the rename is trusted.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    if escaped == "-" {
+        // Keep a literal dash apart from the marker of a missing value.
+        escaped.insert(0, '\\');
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+pub(crate) fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn parse_digest(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+fn from_unix_secs(secs: i64) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDir;
+
+    fn file(folder: &str, name: &str) -> CameraFile {
+        CameraFile {
+            name: name.to_string(),
+            parent_folder: Some(folder.to_string()),
+            file_size: 1000,
+            creation_date: Some(from_unix_secs(1_600_000_000)),
+            ptp_object_handle: Some(7),
+            ..CameraFile::default()
+        }
+    }
+
+    fn entry(file: &CameraFile, imported_at: i64) -> LedgerEntry {
+        LedgerEntry {
+            imported_at: from_unix_secs(imported_at),
+            ..LedgerEntry::new("camera", file)
+        }
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        let contents = fs::read_to_string(path).unwrap();
+        contents.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn entries_are_found_again_after_reopening() {
+        let dir = ScratchDir::new("ledger-reopen");
+        let path = dir.path().join("ledger");
+        let first = file("DCIM/100CANON", "IMG_0001.JPG");
+        let odd = file("DCIM/-", "tab\there\\-");
+        let mut recorded = entry(&first, 1_700_000_000);
+        recorded.sha256 = Some([0xAB; 32]);
+        recorded.destination = Some(dir.path().join("IMG_0001.JPG"));
+        recorded.saved_size = Some(1200);
+        recorded.saved_sha256 = Some([0xCD; 32]);
+        {
+            let mut ledger = ImportLedger::open(&path).unwrap();
+            assert!(ledger.is_empty());
+            ledger
+                .record_all(vec![recorded.clone(), entry(&odd, 1_700_000_001)])
+                .unwrap();
+        }
+
+        let ledger = ImportLedger::open(&path).unwrap();
+        assert_eq!(ledger.len(), 2);
+        assert_eq!(ledger.entry("camera", &first), Some(&recorded));
+        assert_eq!(ledger.entry("camera", &odd).unwrap().name, odd.name);
+        assert_eq!(ledger.find_by_hash(&[0xAB; 32]), Some(&recorded));
+        assert_eq!(ledger.find_by_hash(&[0xCD; 32]), None);
+        // The handle may change between sessions; the size and date may not.
+        let renumbered = CameraFile {
+            ptp_object_handle: Some(8),
+            ..first.clone()
+        };
+        assert!(ledger.is_imported("camera", &renumbered));
+        assert!(!ledger.is_imported("other camera", &first));
+        let resized = CameraFile {
+            file_size: 999,
+            ..first
+        };
+        assert!(!ledger.is_imported("camera", &resized));
+    }
+
+    #[test]
+    fn files_with_the_same_name_in_other_folders_are_other_items() {
+        let dir = ScratchDir::new("ledger-folders");
+        let mut ledger = ImportLedger::open(dir.path().join("ledger")).unwrap();
+        let first = file("DCIM/100CANON", "IMG_0001.JPG");
+        let second = file("DCIM/101CANON", "IMG_0001.JPG");
+        ledger.record(entry(&first, 1_700_000_000)).unwrap();
+        assert!(ledger.is_imported("camera", &first));
+        assert!(!ledger.is_imported("camera", &second));
+        ledger.record(entry(&second, 1_700_000_001)).unwrap();
+        assert_eq!(ledger.len(), 2);
+    }
+
+    #[test]
+    fn a_line_cut_short_is_dropped_on_open() {
+        let dir = ScratchDir::new("ledger-truncated");
+        let path = dir.path().join("ledger");
+        let first = file("DCIM/100CANON", "IMG_0001.JPG");
+        let second = file("DCIM/100CANON", "IMG_0002.JPG");
+        ImportLedger::open(&path)
+            .unwrap()
+            .record(entry(&first, 1_700_000_000))
+            .unwrap();
+        let complete = fs::read(&path).unwrap();
+        let line = entry(&second, 1_700_000_001).to_line();
+        let mut cut = complete.clone();
+        cut.extend_from_slice(&line.as_bytes()[..line.len() / 2]);
+        fs::write(&path, &cut).unwrap();
+
+        let mut ledger = ImportLedger::open(&path).unwrap();
+        assert_eq!(ledger.len(), 1);
+        assert_eq!(fs::read(&path).unwrap(), complete);
+        ledger.record(entry(&second, 1_700_000_002)).unwrap();
+        drop(ledger);
+        let ledger = ImportLedger::open(&path).unwrap();
+        assert!(ledger.is_imported("camera", &first));
+        assert!(ledger.is_imported("camera", &second));
+        assert_eq!(lines(&path).len(), 3);
+    }
+
+    #[test]
+    fn the_header_is_written_and_checked() {
+        let dir = ScratchDir::new("ledger-header");
+        let path = dir.path().join("ledger");
+        ImportLedger::open(&path).unwrap();
+        assert_eq!(lines(&path), [HEADER]);
+
+        // A header cut short by a crash is written again.
+        fs::write(&path, &HEADER[..10]).unwrap();
+        ImportLedger::open(&path).unwrap();
+        assert_eq!(lines(&path), [HEADER]);
+
+        fs::write(&path, "something else\n").unwrap();
+        assert!(matches!(
+            ImportLedger::open(&path),
+            Err(Error::Malformed(_))
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "something else\n");
+    }
+
+    #[test]
+    fn lines_without_the_saved_copy_are_read() {
+        let dir = ScratchDir::new("ledger-old");
+        let path = dir.path().join("ledger");
+        let first = file("DCIM/100CANON", "IMG_0001.JPG");
+        let line = entry(&first, 1_700_000_000).to_line();
+        let old = line.trim_end().rsplitn(3, '\t').nth(2).unwrap();
+        assert_eq!(old.split('\t').count(), OLD_FIELD_COUNT);
+        fs::write(&path, format!("{}\n{}\n", HEADER, old)).unwrap();
+
+        let ledger = ImportLedger::open(&path).unwrap();
+        assert_eq!(
+            ledger.entry("camera", &first),
+            Some(&entry(&first, 1_700_000_000))
+        );
+    }
+
+    #[test]
+    fn pruning_replaces_the_file_and_keeps_recording() {
+        let dir = ScratchDir::new("ledger-prune");
+        let path = dir.path().join("ledger");
+        let old = file("DCIM/100CANON", "IMG_0001.JPG");
+        let new = file("DCIM/100CANON", "IMG_0002.JPG");
+        let mut ledger = ImportLedger::open(&path).unwrap();
+        let mut hashed = entry(&old, 1_600_000_000);
+        hashed.sha256 = Some([1; 32]);
+        ledger.record(hashed).unwrap();
+        ledger.record(entry(&new, 1_700_000_000)).unwrap();
+        ledger.record(entry(&new, 1_700_000_001)).unwrap();
+        assert_eq!(lines(&path).len(), 4);
+
+        assert_eq!(
+            ledger.prune_before(from_unix_secs(1_650_000_000)).unwrap(),
+            1
+        );
+        assert_eq!(ledger.len(), 1);
+        assert_eq!(ledger.find_by_hash(&[1; 32]), None);
+        // Replaced entries are left out of the new file, and no temporary file remains.
+        assert_eq!(
+            lines(&path),
+            [
+                HEADER.to_string(),
+                entry(&new, 1_700_000_001).to_line().trim_end().to_string()
+            ]
+        );
+        assert_eq!(dir.files(), ["ledger"]);
+
+        ledger.record(entry(&old, 1_700_000_002)).unwrap();
+        assert_eq!(ledger.prune_device("other camera").unwrap(), 0);
+        drop(ledger);
+        let mut ledger = ImportLedger::open(&path).unwrap();
+        assert_eq!(ledger.len(), 2);
+        assert_eq!(ledger.prune_device("camera").unwrap(), 2);
+        assert_eq!(lines(&path), [HEADER]);
+    }
+
+    #[test]
+    fn compacting_drops_replaced_entries() {
+        let dir = ScratchDir::new("ledger-compact");
+        let path = dir.path().join("ledger");
+        let first = file("DCIM/100CANON", "IMG_0001.JPG");
+        let mut ledger = ImportLedger::open(&path).unwrap();
+        for imported_at in 0..3 {
+            ledger
+                .record(entry(&first, 1_700_000_000 + imported_at))
+                .unwrap();
+        }
+        ledger.compact().unwrap();
+        assert_eq!(lines(&path).len(), 2);
+        assert_eq!(
+            ledger.entry("camera", &first).unwrap().imported_at,
+            from_unix_secs(1_700_000_002)
+        );
+        assert_eq!(dir.files(), ["ledger"]);
+    }
+}
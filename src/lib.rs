@@ -1,4 +1,4 @@
-#![allow(non_snake_case)]
+#![allow(non_snake_case, non_upper_case_globals)]
 extern crate bitflags;
 #[cfg(target_os = "macos")]
 extern crate cocoa;
@@ -24,6 +24,37 @@ pub mod scanner_device;
 #[cfg(target_os = "macos")]
 pub mod scanner_functional_units;
 
+pub mod asset;
+pub mod bmff;
+pub mod camera;
+pub mod datetime;
+pub mod dcf;
+pub mod delete;
+pub mod download;
+pub mod error;
+pub mod exif;
+pub mod geotag;
+pub mod import;
+pub mod intervalometer;
+pub mod jpeg;
+pub mod ledger;
+pub mod mass_storage;
+pub mod media;
+pub mod metadata;
+pub mod mjpeg;
+pub mod mock;
+pub mod preview;
+pub mod ptp;
+pub mod raw;
+pub mod reader;
+pub mod sequence;
+pub mod sha256;
+#[cfg(test)]
+mod testing;
+pub mod tether;
+pub mod timeshift;
+pub mod uti;
+
 pub mod constants {
     /// Type representing EXIF Orientation tag value
     #[repr(u64)]
@@ -0,0 +1,564 @@
+//! `CameraDevice` implementation for mounted memory cards and other mass-storage devices.
+//!
+//! Any directory holding a DCF `DCIM` folder is treated as a camera, the way Image Capture
+//! presents devices with a transport type of `ICTransportTypeMassStorage`.
+
+use crate::asset::is_sidecar;
+use crate::camera::{self, CameraCapabilities, CameraDevice, CameraFile, CameraFolder, CameraItem};
+use crate::dcf;
+use crate::error::{Error, Result};
+use crate::uti;
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// A mounted volume with a DCIM folder, presented as a camera.
+#[derive(Clone, Debug)]
+pub struct MassStorageCamera {
+    mount_point: PathBuf,
+    name: String,
+}
+
+impl MassStorageCamera {
+    /// Open the volume mounted at `mount_point`, which must contain a DCIM folder.
+    pub fn open<P: AsRef<Path>>(mount_point: P) -> Result<MassStorageCamera> {
+        let mount_point = mount_point.as_ref().to_path_buf();
+        if find_dcim(&mount_point)?.is_none() {
+            return Err(Error::InvalidValue(format!(
+                "{} has no DCIM folder",
+                mount_point.display()
+            )));
+        }
+        let name = mount_point
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| mount_point.display().to_string());
+        Ok(MassStorageCamera { mount_point, name })
+    }
+
+    /// Filesystem mount point of the volume.
+    pub fn mount_point(&self) -> &Path {
+        &self.mount_point
+    }
+
+    fn dcim(&self) -> Result<PathBuf> {
+        find_dcim(&self.mount_point)?.ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no DCIM folder", self.mount_point.display()),
+            ))
+        })
+    }
+
+    fn scan_folder(&self, dir: &Path, parent_folder: &str) -> Result<Vec<CameraItem>> {
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        let mut folders = Vec::new();
+        let mut files = Vec::new();
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                let folder_path = format!("{}/{}", parent_folder, name);
+                folders.push(CameraItem::Folder(CameraFolder {
+                    name,
+                    parent_folder: Some(parent_folder.to_string()),
+                    is_locked: metadata.permissions().readonly(),
+                    creation_date: metadata.created().ok().or_else(|| metadata.modified().ok()),
+                    modification_date: metadata.modified().ok(),
+                    ptp_object_handle: None,
+                    file_system_path: Some(path.clone()),
+                    contents: self.scan_folder(&path, &folder_path)?,
+                }));
+            } else if metadata.is_file() {
+                files.push(make_file(name, parent_folder, path, &metadata));
+            }
+        }
+        let mut items = folders;
+        items.extend(attach_sidecars(files).into_iter().map(CameraItem::File));
+        Ok(items)
+    }
+
+    /// Resolve a folder path as used in `CameraFolder::path`, with or without the volume name.
+    fn resolve_folder(&self, folder: &str) -> Result<PathBuf> {
+        let folder = folder.trim_matches('/');
+        let relative = match folder.split_once('/') {
+            Some((first, rest)) if first == self.name => rest,
+            _ if folder == self.name => "",
+            _ => folder,
+        };
+        let mut path = self.mount_point.clone();
+        for component in relative.split('/').filter(|c| !c.is_empty()) {
+            if component == "." || component == ".." {
+                return Err(Error::InvalidValue(format!(
+                    "folder {} leaves the volume",
+                    folder
+                )));
+            }
+            path.push(component);
+        }
+        Ok(path)
+    }
+
+    fn folder_path_of(&self, dir: &Path) -> String {
+        let mut path = self.name.clone();
+        if let Ok(relative) = dir.strip_prefix(&self.mount_point) {
+            for component in relative.components() {
+                path.push('/');
+                path.push_str(&component.as_os_str().to_string_lossy());
+            }
+        }
+        path
+    }
+}
+
+impl CameraDevice for MassStorageCamera {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn capabilities(&self) -> CameraCapabilities {
+        CameraCapabilities::ICCameraDeviceCanDeleteOneFile
+            | CameraCapabilities::ICCameraDeviceCanDeleteAllFiles
+            | CameraCapabilities::ICCameraDeviceCanReceiveFile
+    }
+
+    #[cfg(unix)]
+    fn free_space(&self) -> Option<u64> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = CString::new(self.mount_point.as_os_str().as_bytes()).ok()?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+            return None;
+        }
+        #[allow(clippy::unnecessary_cast)]
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+
+    fn contents(&self) -> Result<Vec<CameraItem>> {
+        let dcim = self.dcim()?;
+        let metadata = fs::metadata(&self.mount_point)?;
+        let dcim_metadata = fs::metadata(&dcim)?;
+        let dcim_name = dcim
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let dcim_folder = CameraFolder {
+            name: dcim_name.clone(),
+            parent_folder: Some(self.name.clone()),
+            is_locked: dcim_metadata.permissions().readonly(),
+            creation_date: dcim_metadata
+                .created()
+                .ok()
+                .or_else(|| dcim_metadata.modified().ok()),
+            modification_date: dcim_metadata.modified().ok(),
+            ptp_object_handle: None,
+            file_system_path: Some(dcim.clone()),
+            contents: self.scan_folder(&dcim, &format!("{}/{}", self.name, dcim_name))?,
+        };
+        Ok(vec![CameraItem::Folder(CameraFolder {
+            name: self.name.clone(),
+            parent_folder: None,
+            is_locked: metadata.permissions().readonly(),
+            creation_date: metadata.created().ok().or_else(|| metadata.modified().ok()),
+            modification_date: metadata.modified().ok(),
+            ptp_object_handle: None,
+            file_system_path: Some(self.mount_point.clone()),
+            contents: vec![CameraItem::Folder(dcim_folder)],
+        })])
+    }
+
+    fn media_files(&self) -> Result<Vec<CameraFile>> {
+        let mut files = Vec::new();
+        camera::collect_files(&self.contents()?, &mut files);
+        files.retain(|file| {
+            (file.conforms_to(uti::IMAGE) || file.conforms_to(uti::AUDIOVISUAL_CONTENT))
+                && !is_sidecar(&file.name)
+        });
+        Ok(files)
+    }
+
+    fn download_file(&self, file: &CameraFile, writer: &mut dyn Write) -> Result<u64> {
+        let mut source = File::open(file_path(file)?)?;
+        Ok(io::copy(&mut source, writer)?)
+    }
+
+    fn read_data_from_file(&self, file: &CameraFile, offset: u64, length: u64) -> Result<Vec<u8>> {
+        let mut source = File::open(file_path(file)?)?;
+        source.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        source.take(length).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Delete files and the sidecar files no other remaining file shares.
+    ///
+    /// Locked files are refused before anything is deleted.
+    fn delete_files(&self, files: &[CameraFile]) -> Result<()> {
+        for file in files {
+            let path = file_path(file)?;
+            if fs::metadata(path)?.permissions().readonly() {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} is locked", file.path()),
+                )));
+            }
+        }
+        for file in files {
+            let path = file_path(file)?;
+            fs::remove_file(path)?;
+            for sidecar in &file.sidecar_files {
+                if let Some(path) = &sidecar.file_system_path {
+                    if has_owner(path)? {
+                        continue;
+                    }
+                    match fs::remove_file(path) {
+                        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload a file into `folder` under its own name, or, without a folder, into the
+    /// DCIM folder under the next free DCF name; see `dcf::upload_target`.
+    fn upload_file(&self, path: &Path, folder: Option<&str>) -> Result<CameraFile> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| Error::InvalidValue(format!("{} is not a file", path.display())))?
+            .to_string_lossy()
+            .into_owned();
+        let (dir, name) = match folder {
+            Some(folder) => (self.resolve_folder(folder)?, name),
+            None => {
+                let target = dcf::upload_target(&self.contents()?, &name)?;
+                let dir = self.resolve_folder(&target.folder)?;
+                if target.create_folder {
+                    fs::create_dir(&dir)?;
+                }
+                (dir, target.name.to_string())
+            }
+        };
+        let destination = dir.join(&name);
+        let mut source = File::open(path)?;
+        let mut target = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&destination)?;
+        io::copy(&mut source, &mut target)?;
+        target.sync_all()?;
+        drop(target);
+        let metadata = fs::metadata(&destination)?;
+        Ok(make_file(
+            name,
+            &self.folder_path_of(&dir),
+            destination,
+            &metadata,
+        ))
+    }
+}
+
+/// The DCIM folder of a volume, matched without regard to case.
+fn find_dcim(mount_point: &Path) -> Result<Option<PathBuf>> {
+    for entry in fs::read_dir(mount_point)? {
+        let entry = entry?;
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .eq_ignore_ascii_case("DCIM")
+            && entry.file_type()?.is_dir()
+        {
+            return Ok(Some(entry.path()));
+        }
+    }
+    Ok(None)
+}
+
+fn file_path(file: &CameraFile) -> Result<&Path> {
+    file.file_system_path
+        .as_deref()
+        .ok_or_else(|| Error::Unsupported("files without a file system path".into()))
+}
+
+fn make_file(name: String, parent_folder: &str, path: PathBuf, metadata: &Metadata) -> CameraFile {
+    let (uti, is_raw) = camera::file_type(&name);
+    CameraFile {
+        name,
+        parent_folder: Some(parent_folder.to_string()),
+        uti,
+        file_size: metadata.len(),
+        creation_date: metadata.created().ok().or_else(|| metadata.modified().ok()),
+        modification_date: metadata.modified().ok(),
+        is_raw,
+        is_locked: metadata.permissions().readonly(),
+        file_system_path: Some(path),
+        ..CameraFile::default()
+    }
+}
+
+fn base_name(name: &str) -> String {
+    Path::new(name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_ascii_uppercase())
+        .unwrap_or_default()
+}
+
+/// Keys a sidecar's base name is matched against: the full name of the file, for sidecars
+/// such as `IMG_0001.CR2.xmp`, and its base name, for sidecars such as `IMG_0001.xmp`.
+fn owner_keys(name: &str) -> Vec<String> {
+    let mut keys = vec![name.to_ascii_uppercase(), base_name(name)];
+    keys.dedup();
+    keys
+}
+
+/// Whether the folder of the sidecar at `path` still holds a non-sidecar file it belongs to.
+fn has_owner(path: &Path) -> Result<bool> {
+    let dir = match path.parent() {
+        Some(dir) => dir,
+        None => return Ok(false),
+    };
+    let base = base_name(&path.to_string_lossy());
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if !is_sidecar(&name) && owner_keys(&name).contains(&base) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Move sidecar files into the `sidecar_files` of the files they belong to; see `owner_keys`.
+///
+/// Sidecars sharing a base name are attached to every such file, so that both halves of a
+/// RAW+JPEG pair list them. Sidecars without a matching file are kept as ordinary files.
+fn attach_sidecars(files: Vec<CameraFile>) -> Vec<CameraFile> {
+    let (sidecars, mut files): (Vec<_>, Vec<_>) =
+        files.into_iter().partition(|file| is_sidecar(&file.name));
+    let mut index: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, file) in files.iter().enumerate() {
+        for key in owner_keys(&file.name) {
+            index.entry(key).or_default().push(i);
+        }
+    }
+    let mut orphans = Vec::new();
+    for sidecar in sidecars {
+        match index.get(&base_name(&sidecar.name)) {
+            Some(owners) => {
+                for &owner in owners {
+                    files[owner].sidecar_files.push(sidecar.clone());
+                }
+            }
+            None => orphans.push(sidecar),
+        }
+    }
+    files.extend(orphans);
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDir;
+
+    /// A volume with `DCIM/100CANON` holding `files`, opened as a camera.
+    fn volume(dir: &ScratchDir, files: &[&str]) -> MassStorageCamera {
+        let folder = dir.path().join("DCIM").join("100CANON");
+        fs::create_dir_all(&folder).unwrap();
+        for name in files {
+            fs::write(folder.join(name), name.as_bytes()).unwrap();
+        }
+        MassStorageCamera::open(dir.path()).unwrap()
+    }
+
+    fn file(camera: &MassStorageCamera, name: &str) -> CameraFile {
+        let mut files = Vec::new();
+        camera::collect_files(&camera.contents().unwrap(), &mut files);
+        files.into_iter().find(|file| file.name == name).unwrap()
+    }
+
+    fn names(files: &[CameraFile]) -> Vec<&str> {
+        files.iter().map(|file| file.name.as_str()).collect()
+    }
+
+    #[test]
+    fn volumes_need_a_dcim_folder() {
+        let dir = ScratchDir::new("mass-storage-no-dcim");
+        assert!(matches!(
+            MassStorageCamera::open(dir.path()),
+            Err(Error::InvalidValue(_))
+        ));
+        fs::create_dir(dir.path().join("dcim")).unwrap();
+        assert!(MassStorageCamera::open(dir.path()).is_ok());
+    }
+
+    #[test]
+    fn contents_mirror_the_folder_hierarchy() {
+        let dir = ScratchDir::new("mass-storage-contents");
+        let camera = volume(&dir, &["IMG_0001.JPG", ".hidden"]);
+        let contents = camera.contents().unwrap();
+        let volume = match &contents[..] {
+            [CameraItem::Folder(volume)] => volume,
+            other => panic!("unexpected contents {:?}", other),
+        };
+        assert_eq!(volume.name, camera.name());
+        assert_eq!(volume.parent_folder, None);
+        let dcim = match &volume.contents[..] {
+            [CameraItem::Folder(dcim)] => dcim,
+            other => panic!("unexpected volume contents {:?}", other),
+        };
+        let folder = match &dcim.contents[..] {
+            [CameraItem::Folder(folder)] => folder,
+            other => panic!("unexpected DCIM contents {:?}", other),
+        };
+        assert_eq!(folder.path(), format!("{}/DCIM/100CANON", camera.name()));
+        let image = match &folder.contents[..] {
+            [CameraItem::File(image)] => image,
+            other => panic!("unexpected folder contents {:?}", other),
+        };
+        assert_eq!(image.path(), format!("{}/IMG_0001.JPG", folder.path()));
+        assert_eq!(image.uti, uti::JPEG);
+        assert_eq!(image.file_size, 12);
+        assert!(!image.is_locked);
+        assert_eq!(camera.read_data_from_file(image, 4, 4).unwrap(), b"0001");
+    }
+
+    #[test]
+    fn media_files_carry_their_sidecars() {
+        let dir = ScratchDir::new("mass-storage-sidecars");
+        let camera = volume(
+            &dir,
+            &[
+                "IMG_0001.CR2",
+                "IMG_0001.JPG",
+                "IMG_0001.xmp",
+                "IMG_0002.CR2",
+                "IMG_0002.JPG",
+                "IMG_0002.CR2.xmp",
+                "IMG_0003.xmp",
+                "NOTES.TXT",
+            ],
+        );
+        let files = camera.media_files().unwrap();
+        assert_eq!(
+            names(&files),
+            [
+                "IMG_0001.CR2",
+                "IMG_0001.JPG",
+                "IMG_0002.CR2",
+                "IMG_0002.JPG"
+            ]
+        );
+        assert_eq!(names(&files[0].sidecar_files), ["IMG_0001.xmp"]);
+        assert_eq!(names(&files[1].sidecar_files), ["IMG_0001.xmp"]);
+        assert_eq!(names(&files[2].sidecar_files), ["IMG_0002.CR2.xmp"]);
+        assert!(files[3].sidecar_files.is_empty());
+        // Sidecars without a file stay visible in the contents.
+        assert!(file(&camera, "IMG_0003.xmp").sidecar_files.is_empty());
+    }
+
+    #[test]
+    fn read_only_files_are_locked_and_not_deleted() {
+        let dir = ScratchDir::new("mass-storage-locked");
+        let camera = volume(&dir, &["IMG_0001.JPG", "IMG_0002.JPG"]);
+        let path = dir.path().join("DCIM/100CANON/IMG_0001.JPG");
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+
+        let locked = file(&camera, "IMG_0001.JPG");
+        assert!(locked.is_locked);
+        let other = file(&camera, "IMG_0002.JPG");
+        assert!(camera.delete_files(&[other, locked]).is_err());
+        assert_eq!(
+            dir.files(),
+            ["DCIM/100CANON/IMG_0001.JPG", "DCIM/100CANON/IMG_0002.JPG"]
+        );
+    }
+
+    #[test]
+    fn deleting_files_deletes_the_sidecars_no_other_file_shares() {
+        let dir = ScratchDir::new("mass-storage-delete");
+        let camera = volume(
+            &dir,
+            &[
+                "IMG_0001.CR2",
+                "IMG_0001.JPG",
+                "IMG_0001.xmp",
+                "IMG_0002.CR2",
+                "IMG_0002.JPG",
+                "IMG_0002.CR2.xmp",
+            ],
+        );
+        camera
+            .delete_files(&[file(&camera, "IMG_0001.CR2"), file(&camera, "IMG_0002.CR2")])
+            .unwrap();
+        assert_eq!(
+            dir.files(),
+            [
+                "DCIM/100CANON/IMG_0001.JPG",
+                "DCIM/100CANON/IMG_0001.xmp",
+                "DCIM/100CANON/IMG_0002.JPG",
+            ]
+        );
+        camera
+            .delete_files(&[file(&camera, "IMG_0001.JPG")])
+            .unwrap();
+        assert_eq!(dir.files(), ["DCIM/100CANON/IMG_0002.JPG"]);
+    }
+
+    #[test]
+    fn uploads_get_the_next_dcf_name() {
+        let dir = ScratchDir::new("mass-storage-upload");
+        let camera = volume(&dir, &["IMG_0007.JPG"]);
+        let source = dir.path().join("holiday.jpeg");
+        fs::write(&source, b"upload").unwrap();
+
+        let uploaded = camera.upload_file(&source, None).unwrap();
+        assert_eq!(uploaded.name, "IMG_0008.JPG");
+        assert_eq!(
+            uploaded.parent_folder,
+            Some(format!("{}/DCIM/100CANON", camera.name()))
+        );
+        assert_eq!(
+            fs::read(dir.path().join("DCIM/100CANON/IMG_0008.JPG")).unwrap(),
+            b"upload"
+        );
+
+        let folder = format!("{}/DCIM", camera.name());
+        let uploaded = camera.upload_file(&source, Some(&folder)).unwrap();
+        assert_eq!(uploaded.path(), format!("{}/holiday.jpeg", folder));
+        assert!(dir.path().join("DCIM/holiday.jpeg").is_file());
+    }
+
+    #[test]
+    fn folders_outside_the_volume_are_rejected() {
+        let dir = ScratchDir::new("mass-storage-escape");
+        let camera = volume(&dir, &[]);
+        let source = dir.path().join("IMG_0001.JPG");
+        fs::write(&source, b"upload").unwrap();
+        for folder in ["DCIM/../..", "../elsewhere", "DCIM/./100CANON"] {
+            assert!(
+                matches!(
+                    camera.upload_file(&source, Some(folder)),
+                    Err(Error::InvalidValue(_))
+                ),
+                "{}",
+                folder
+            );
+        }
+        assert_eq!(
+            camera.resolve_folder("DCIM/100CANON").unwrap(),
+            dir.path().join("DCIM").join("100CANON")
+        );
+    }
+}
@@ -0,0 +1,954 @@
+//! Duration, codecs and other container metadata of movies and audio recordings.
+//!
+//! `ICCameraFile::duration` is only available on macOS. `read` parses the headers of ISO
+//! base media files (MP4, QuickTime MOV and M4A), AVI files and WAV files without decoding
+//! any media. Like `metadata`, it reads through `Read` + `Seek`, and `read_camera_file`
+//! fetches only the headers of a file still on the camera.
+
+use crate::bmff::{self, BoxHeader};
+use crate::camera::{CameraDevice, CameraFile};
+use crate::datetime::DateTime;
+use crate::error::{Error, Result};
+use crate::exif;
+use crate::reader::CameraFileReader;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+
+/// Seconds from 1904-01-01, the epoch of ISO base media times, to 1970-01-01.
+const MAC_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Largest RIFF chunk read whole, such as a stream format.
+const MAX_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// The file structures media metadata is read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MediaContainer {
+    /// The ISO base media file format: MP4, QuickTime MOV, M4A and their relatives.
+    Bmff,
+    Avi,
+    Wav,
+}
+
+/// What a track holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TrackKind {
+    Video,
+    Audio,
+    /// Another kind, by handler or stream type, such as `meta`, `tmcd` or `txts`.
+    Other([u8; 4]),
+}
+
+/// The coding of a track.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// A four-character code: the sample entry type of ISO base media files, such as `avc1`
+    /// or `mp4a`, or the compression of AVI video streams, such as `MJPG`.
+    FourCc([u8; 4]),
+    /// The format tag of WAV files and AVI audio streams, such as 1 for PCM.
+    WaveFormat(u16),
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Codec::FourCc(code) => f.write_str(String::from_utf8_lossy(code).trim_end()),
+            Codec::WaveFormat(1) => f.write_str("PCM"),
+            Codec::WaveFormat(3) => f.write_str("IEEE float"),
+            Codec::WaveFormat(tag) => write!(f, "WAVE format 0x{:04X}", tag),
+        }
+    }
+}
+
+/// A video, audio or other track of a media file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaTrack {
+    pub kind: TrackKind,
+    /// The track ID of ISO base media files, or the stream number of AVI files, from 0.
+    pub id: Option<u32>,
+    pub codec: Option<Codec>,
+    pub duration: Option<Duration>,
+    /// Coded width of video frames in pixels.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Average frames per second of video.
+    pub frame_rate: Option<f64>,
+    /// Audio samples per second.
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    /// The transformation matrix of the track header, row by row: `a, b, u, c, d, v, x,
+    /// y, w`, as applied to the frames when displaying them.
+    pub matrix: Option<[f64; 9]>,
+}
+
+impl MediaTrack {
+    fn new(kind: TrackKind) -> MediaTrack {
+        MediaTrack {
+            kind,
+            id: None,
+            codec: None,
+            duration: None,
+            width: None,
+            height: None,
+            frame_rate: None,
+            sample_rate: None,
+            channels: None,
+            matrix: None,
+        }
+    }
+
+    /// The clockwise rotation in degrees, 0, 90, 180 or 270, that the matrix applies to the
+    /// frames. `None` for other transformations, such as mirroring.
+    pub fn rotation(&self) -> Option<u32> {
+        let matrix = self.matrix?;
+        let sign = |value: f64| {
+            if value > 1e-6 {
+                1
+            } else if value < -1e-6 {
+                -1
+            } else {
+                0
+            }
+        };
+        match (
+            sign(matrix[0]),
+            sign(matrix[1]),
+            sign(matrix[3]),
+            sign(matrix[4]),
+        ) {
+            (1, 0, 0, 1) => Some(0),
+            (0, 1, -1, 0) => Some(90),
+            (-1, 0, 0, -1) => Some(180),
+            (0, -1, 1, 0) => Some(270),
+            _ => None,
+        }
+    }
+}
+
+/// Container metadata of a movie or audio recording.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaInfo {
+    pub container: MediaContainer,
+    /// The major brand of ISO base media files, such as `isom`, `qt  ` or `M4A `. `None` for
+    /// QuickTime movies without a `ftyp` box.
+    pub brand: Option<[u8; 4]>,
+    pub duration: Option<Duration>,
+    /// When the file was created: in UTC for ISO base media files, and in the recorder's
+    /// local time for the `IDIT` chunk of AVI files and the broadcast extension of WAV files.
+    pub creation_time: Option<DateTime>,
+    pub tracks: Vec<MediaTrack>,
+}
+
+impl MediaInfo {
+    /// The first video track.
+    pub fn video(&self) -> Option<&MediaTrack> {
+        self.tracks
+            .iter()
+            .find(|track| track.kind == TrackKind::Video)
+    }
+
+    /// The first audio track.
+    pub fn audio(&self) -> Option<&MediaTrack> {
+        self.tracks
+            .iter()
+            .find(|track| track.kind == TrackKind::Audio)
+    }
+}
+
+/// Read the container metadata of an MP4, QuickTime, M4A, AVI or WAV file.
+pub fn read<R: Read + Seek>(reader: &mut R) -> Result<MediaInfo> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    let head = bmff::read_at(reader, 0, 12)?;
+    if head.starts_with(b"RIFF") || head.starts_with(b"RF64") {
+        // The RIFF size may be wrong in files whose recording was cut off.
+        let riff_end = u64::from(u32_le(&head, 4).unwrap_or(0))
+            .saturating_add(8)
+            .min(end);
+        match head.get(8..12) {
+            Some(b"AVI ") => return read_avi(reader, 12..riff_end),
+            Some(b"WAVE") => return read_wav(reader, 12..riff_end),
+            _ => {}
+        }
+    }
+    if let Some(brands) = bmff::brands(reader)? {
+        return read_bmff(reader, end, brands.first().copied());
+    }
+    // Early QuickTime movies start directly with their atoms.
+    if matches!(
+        head.get(4..8),
+        Some(b"moov") | Some(b"mdat") | Some(b"wide") | Some(b"free") | Some(b"skip")
+    ) {
+        return read_bmff(reader, end, None);
+    }
+    Err(Error::malformed("not an ISO base media, AVI or WAV file"))
+}
+
+/// Read the container metadata of the file at `path`.
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<MediaInfo> {
+    read(&mut BufReader::new(File::open(path)?))
+}
+
+/// Read the container metadata of a file on the camera, fetching only its headers.
+pub fn read_camera_file(camera: &dyn CameraDevice, file: &CameraFile) -> Result<MediaInfo> {
+    read(&mut CameraFileReader::new(camera, file))
+}
+
+fn read_bmff<R: Read + Seek>(
+    reader: &mut R,
+    end: u64,
+    brand: Option<[u8; 4]>,
+) -> Result<MediaInfo> {
+    let moov = bmff::find(reader, 0..end, b"moov")?
+        .ok_or_else(|| Error::malformed("the movie has no moov box"))?;
+    let mvhd = bmff::find(reader, moov.body(), b"mvhd")?
+        .ok_or_else(|| Error::malformed("the movie has no mvhd box"))?;
+    let mvhd = bmff::read_body(reader, &mvhd)?;
+    let (creation, timescale, duration) = if mvhd.first() == Some(&1) {
+        (
+            bmff::u64_at(&mvhd, 4),
+            bmff::u32_at(&mvhd, 20),
+            bmff::u64_at(&mvhd, 24).filter(|&duration| duration != u64::MAX),
+        )
+    } else {
+        (
+            bmff::u32_at(&mvhd, 4).map(u64::from),
+            bmff::u32_at(&mvhd, 12),
+            bmff::u32_at(&mvhd, 16)
+                .filter(|&duration| duration != u32::MAX)
+                .map(u64::from),
+        )
+    };
+    let timescale = timescale.filter(|&scale| scale > 0).unwrap_or(1);
+    let mut duration = duration.filter(|&duration| duration > 0);
+    if duration.is_none() {
+        // Fragmented movies give their duration in the movie extends header.
+        if let Some(mehd) = bmff::find_path(reader, moov.body(), &[b"mvex", b"mehd"])? {
+            let mehd = bmff::read_body(reader, &mehd)?;
+            duration = if mehd.first() == Some(&1) {
+                bmff::u64_at(&mehd, 4)
+            } else {
+                bmff::u32_at(&mehd, 4).map(u64::from)
+            };
+        }
+    }
+
+    let mut tracks = Vec::new();
+    for trak in bmff::children(reader, moov.body())? {
+        if trak.is(b"trak") {
+            tracks.push(read_bmff_track(reader, &trak, timescale)?);
+        }
+    }
+    Ok(MediaInfo {
+        container: MediaContainer::Bmff,
+        brand,
+        duration: duration.map(|duration| seconds(duration as f64 / f64::from(timescale))),
+        creation_time: creation
+            .filter(|&secs| secs > 0)
+            .map(|secs| DateTime::from_unix(secs as i64 - MAC_EPOCH_OFFSET)),
+        tracks,
+    })
+}
+
+fn read_bmff_track<R: Read + Seek>(
+    reader: &mut R,
+    trak: &BoxHeader,
+    movie_timescale: u32,
+) -> Result<MediaTrack> {
+    let handler = match bmff::find_path(reader, trak.body(), &[b"mdia", b"hdlr"])? {
+        Some(hdlr) => bmff::read_body(reader, &hdlr)?,
+        None => Vec::new(),
+    };
+    let kind = match handler.get(8..12) {
+        Some(b"vide") => TrackKind::Video,
+        Some(b"soun") => TrackKind::Audio,
+        Some(other) => TrackKind::Other([other[0], other[1], other[2], other[3]]),
+        None => TrackKind::Other(*b"    "),
+    };
+    let mut track = MediaTrack::new(kind);
+
+    if let Some(tkhd) = bmff::find(reader, trak.body(), b"tkhd")? {
+        let tkhd = bmff::read_body(reader, &tkhd)?;
+        let (id, duration) = if tkhd.first() == Some(&1) {
+            (bmff::u32_at(&tkhd, 20), bmff::u64_at(&tkhd, 28))
+        } else {
+            (
+                bmff::u32_at(&tkhd, 12),
+                bmff::u32_at(&tkhd, 20).map(u64::from),
+            )
+        };
+        track.id = id;
+        track.duration = duration
+            .filter(|&duration| duration > 0 && duration != u64::from(u32::MAX))
+            .map(|duration| seconds(duration as f64 / f64::from(movie_timescale)));
+        // The matrix, width and height end the box. The matrix holds 16.16 fixed-point
+        // numbers, except for u, v and w, which are 2.30.
+        track.matrix = tkhd.len().checked_sub(44).and_then(|start| {
+            let mut matrix = [0.0; 9];
+            for (index, value) in matrix.iter_mut().enumerate() {
+                let raw = f64::from(bmff::u32_at(&tkhd, start + index * 4)? as i32);
+                *value = raw
+                    / if index % 3 == 2 {
+                        1_073_741_824.0
+                    } else {
+                        65536.0
+                    };
+            }
+            Some(matrix)
+        });
+        let size = |from_end: usize| {
+            tkhd.len()
+                .checked_sub(from_end)
+                .and_then(|offset| bmff::u32_at(&tkhd, offset))
+                .map(|value| value >> 16)
+                .filter(|&value| value > 0)
+        };
+        track.width = size(8);
+        track.height = size(4);
+    }
+
+    let mut media_timescale = None;
+    if let Some(mdhd) = bmff::find_path(reader, trak.body(), &[b"mdia", b"mdhd"])? {
+        let mdhd = bmff::read_body(reader, &mdhd)?;
+        let (timescale, duration) = if mdhd.first() == Some(&1) {
+            (bmff::u32_at(&mdhd, 20), bmff::u64_at(&mdhd, 24))
+        } else {
+            (
+                bmff::u32_at(&mdhd, 12),
+                bmff::u32_at(&mdhd, 16).map(u64::from),
+            )
+        };
+        media_timescale = timescale.filter(|&scale| scale > 0);
+        if let (Some(timescale), Some(duration)) = (media_timescale, duration) {
+            if duration > 0 && duration != u64::from(u32::MAX) {
+                track.duration = Some(seconds(duration as f64 / f64::from(timescale)));
+            }
+        }
+    }
+
+    let stbl = match bmff::find_path(reader, trak.body(), &[b"mdia", b"minf", b"stbl"])? {
+        Some(stbl) => stbl,
+        None => return Ok(track),
+    };
+    if let Some(stsd) = bmff::find(reader, stbl.body(), b"stsd")? {
+        let stsd = bmff::read_body(reader, &stsd)?;
+        let entry = stsd.get(8..).unwrap_or_default();
+        if let Some(codec) = entry.get(4..8) {
+            track.codec = Some(Codec::FourCc([codec[0], codec[1], codec[2], codec[3]]));
+        }
+        let u16_at = |offset: usize| {
+            entry
+                .get(offset..offset + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        };
+        match track.kind {
+            TrackKind::Video => {
+                if let (Some(width), Some(height)) = (u16_at(32), u16_at(34)) {
+                    if width > 0 && height > 0 {
+                        track.width = Some(u32::from(width));
+                        track.height = Some(u32::from(height));
+                    }
+                }
+            }
+            TrackKind::Audio => {
+                // QuickTime version 2 sound descriptions move the rate and channel count
+                // after their fixed fields, the rate as a 64-bit float.
+                if u16_at(16) == Some(2) {
+                    track.sample_rate =
+                        bmff::u64_at(entry, 40).map(|bits| f64::from_bits(bits).round() as u32);
+                    track.channels = bmff::u32_at(entry, 48);
+                } else {
+                    track.channels = u16_at(24).map(u32::from);
+                    track.sample_rate = bmff::u32_at(entry, 32).map(|rate| rate >> 16);
+                }
+                track.sample_rate = track.sample_rate.filter(|&rate| rate > 0);
+                track.channels = track.channels.filter(|&channels| channels > 0);
+            }
+            TrackKind::Other(_) => {}
+        }
+    }
+    if track.kind == TrackKind::Video {
+        if let (Some(stts), Some(timescale)) =
+            (bmff::find(reader, stbl.body(), b"stts")?, media_timescale)
+        {
+            let stts = bmff::read_body(reader, &stts)?;
+            let count = bmff::u32_at(&stts, 4).unwrap_or(0) as usize;
+            let (mut samples, mut ticks) = (0u64, 0u64);
+            for index in 0..count {
+                let offset = 8 + index * 8;
+                let (run, delta) =
+                    match (bmff::u32_at(&stts, offset), bmff::u32_at(&stts, offset + 4)) {
+                        (Some(run), Some(delta)) => (u64::from(run), u64::from(delta)),
+                        _ => break,
+                    };
+                samples += run;
+                ticks += run * delta;
+            }
+            if ticks > 0 {
+                track.frame_rate = Some(samples as f64 * f64::from(timescale) / ticks as f64);
+            }
+        }
+    }
+    Ok(track)
+}
+
+/// A RIFF chunk: its identifier and the offsets of its body.
+struct Chunk {
+    id: [u8; 4],
+    body: Range<u64>,
+}
+
+/// The chunks within `range`. A chunk claiming more than the range ends with it, as when a
+/// recording was cut off or a RF64 file gives the real size in its `ds64` chunk.
+fn chunks<R: Read + Seek>(reader: &mut R, range: Range<u64>) -> Result<Vec<Chunk>> {
+    let mut chunks = Vec::new();
+    let mut offset = range.start;
+    while offset + 8 <= range.end {
+        let header = bmff::read_at(reader, offset, 8)?;
+        let size = match u32_le(&header, 4) {
+            Some(size) => u64::from(size),
+            None => break,
+        };
+        let start = offset + 8;
+        chunks.push(Chunk {
+            id: [header[0], header[1], header[2], header[3]],
+            body: start..start.saturating_add(size).min(range.end),
+        });
+        // Chunks are padded to an even size.
+        offset = start.saturating_add(size + (size & 1));
+    }
+    Ok(chunks)
+}
+
+/// The chunks of the `LIST` chunk of type `kind` among `siblings`.
+fn list<R: Read + Seek>(
+    reader: &mut R,
+    siblings: &[Chunk],
+    kind: &[u8; 4],
+) -> Result<Option<Vec<Chunk>>> {
+    for chunk in siblings {
+        if &chunk.id == b"LIST" && chunk.body.end >= chunk.body.start + 4 {
+            let list_type = bmff::read_at(reader, chunk.body.start, 4)?;
+            if list_type == kind {
+                return chunks(reader, chunk.body.start + 4..chunk.body.end).map(Some);
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn read_chunk<R: Read + Seek>(reader: &mut R, chunk: &Chunk) -> Result<Vec<u8>> {
+    let length = (chunk.body.end - chunk.body.start).min(MAX_CHUNK_SIZE);
+    Ok(bmff::read_at(reader, chunk.body.start, length)?)
+}
+
+fn find_chunk<'a>(chunks: &'a [Chunk], id: &[u8; 4]) -> Option<&'a Chunk> {
+    chunks.iter().find(|chunk| &chunk.id == id)
+}
+
+fn read_avi<R: Read + Seek>(reader: &mut R, range: Range<u64>) -> Result<MediaInfo> {
+    let top = chunks(reader, range)?;
+    let hdrl = list(reader, &top, b"hdrl")?
+        .ok_or_else(|| Error::malformed("the AVI file has no hdrl list"))?;
+    let avih = match find_chunk(&hdrl, b"avih") {
+        Some(avih) => read_chunk(reader, avih)?,
+        None => return Err(Error::malformed("the AVI file has no avih chunk")),
+    };
+    let micros_per_frame = u32_le(&avih, 0).unwrap_or(0);
+    let mut total_frames = u32_le(&avih, 16).unwrap_or(0);
+    // OpenDML files over 1 GiB count the frames of all their RIFF chunks here.
+    if let Some(odml) = list(reader, &hdrl, b"odml")? {
+        if let Some(dmlh) = find_chunk(&odml, b"dmlh") {
+            total_frames = u32_le(&read_chunk(reader, dmlh)?, 0).unwrap_or(total_frames);
+        }
+    }
+
+    let mut tracks = Vec::new();
+    let mut id = 0;
+    for chunk in &hdrl {
+        if &chunk.id != b"LIST" || bmff::read_at(reader, chunk.body.start, 4)? != b"strl" {
+            continue;
+        }
+        let strl = chunks(reader, chunk.body.start + 4..chunk.body.end)?;
+        let strh = match find_chunk(&strl, b"strh") {
+            Some(strh) => read_chunk(reader, strh)?,
+            None => continue,
+        };
+        let strf = match find_chunk(&strl, b"strf") {
+            Some(strf) => read_chunk(reader, strf)?,
+            None => Vec::new(),
+        };
+        tracks.push(avi_track(id, &strh, &strf));
+        id += 1;
+    }
+
+    let duration = tracks
+        .iter()
+        .find(|track| track.kind == TrackKind::Video)
+        .and_then(|track| track.duration)
+        .or_else(|| {
+            Some(total_frames)
+                .filter(|&frames| frames > 0 && micros_per_frame > 0)
+                .map(|frames| {
+                    Duration::from_micros(u64::from(frames) * u64::from(micros_per_frame))
+                })
+        });
+    // Cameras record the date in an IDIT chunk, in the header list or in the INFO list.
+    let mut idit = find_chunk(&hdrl, b"IDIT").map(|chunk| chunk.body.clone());
+    if idit.is_none() {
+        if let Some(info) = list(reader, &top, b"INFO")? {
+            idit = find_chunk(&info, b"IDIT").map(|chunk| chunk.body.clone());
+        }
+    }
+    let creation_time = match idit {
+        Some(body) => {
+            let data = bmff::read_at(reader, body.start, (body.end - body.start).min(64))?;
+            parse_idit(&String::from_utf8_lossy(&data))
+        }
+        None => None,
+    };
+    Ok(MediaInfo {
+        container: MediaContainer::Avi,
+        brand: None,
+        duration,
+        creation_time,
+        tracks,
+    })
+}
+
+/// A stream of an AVI file from its header and format chunks.
+fn avi_track(id: u32, strh: &[u8], strf: &[u8]) -> MediaTrack {
+    let kind = match strh.get(0..4) {
+        Some(b"vids") => TrackKind::Video,
+        Some(b"auds") => TrackKind::Audio,
+        Some(other) => TrackKind::Other([other[0], other[1], other[2], other[3]]),
+        None => TrackKind::Other(*b"    "),
+    };
+    let mut track = MediaTrack::new(kind);
+    track.id = Some(id);
+    let scale = u32_le(strh, 20).unwrap_or(0);
+    let rate = u32_le(strh, 24).unwrap_or(0);
+    let length = u32_le(strh, 32).unwrap_or(0);
+    if scale > 0 && rate > 0 {
+        track.duration = Some(seconds(
+            f64::from(length) * f64::from(scale) / f64::from(rate),
+        ))
+        .filter(|_| length > 0);
+    }
+    match kind {
+        TrackKind::Video => {
+            // A BITMAPINFOHEADER, whose height is negative for top-down images.
+            let compression = strf.get(16..20).filter(|code| code != &[0, 0, 0, 0]);
+            track.codec = compression
+                .or_else(|| strh.get(4..8))
+                .map(|code| Codec::FourCc([code[0], code[1], code[2], code[3]]));
+            track.width = u32_le(strf, 4).map(|width| (width as i32).unsigned_abs());
+            track.height = u32_le(strf, 8).map(|height| (height as i32).unsigned_abs());
+            if scale > 0 && rate > 0 {
+                track.frame_rate = Some(f64::from(rate) / f64::from(scale));
+            }
+        }
+        TrackKind::Audio => {
+            let format = WaveFormat::parse(strf);
+            track.codec = format.map(|format| Codec::WaveFormat(format.tag));
+            track.sample_rate = format.map(|format| format.sample_rate);
+            track.channels = format.map(|format| u32::from(format.channels));
+        }
+        TrackKind::Other(_) => {}
+    }
+    track
+}
+
+/// The fields of a WAVEFORMATEX structure that describe the audio.
+#[derive(Clone, Copy)]
+struct WaveFormat {
+    tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    bytes_per_second: u32,
+}
+
+impl WaveFormat {
+    fn parse(data: &[u8]) -> Option<WaveFormat> {
+        let mut tag = u16_le(data, 0)?;
+        // WAVE_FORMAT_EXTENSIBLE carries the real tag at the start of its subformat GUID.
+        if tag == 0xFFFE {
+            tag = u16_le(data, 24).unwrap_or(tag);
+        }
+        Some(WaveFormat {
+            tag,
+            channels: u16_le(data, 2)?,
+            sample_rate: u32_le(data, 4)?,
+            bytes_per_second: u32_le(data, 8)?,
+        })
+    }
+}
+
+fn read_wav<R: Read + Seek>(reader: &mut R, range: Range<u64>) -> Result<MediaInfo> {
+    let top = chunks(reader, range)?;
+    let format = match find_chunk(&top, b"fmt ") {
+        Some(fmt) => WaveFormat::parse(&read_chunk(reader, fmt)?),
+        None => None,
+    }
+    .ok_or_else(|| Error::malformed("the WAV file has no valid fmt chunk"))?;
+
+    let data_size = find_chunk(&top, b"data").map(|data| data.body.end - data.body.start);
+    let frames = match find_chunk(&top, b"fact") {
+        Some(fact) if format.tag != 1 => u32_le(&read_chunk(reader, fact)?, 0),
+        _ => None,
+    };
+    let duration = match (frames, data_size) {
+        (Some(frames), _) if format.sample_rate > 0 => {
+            Some(seconds(f64::from(frames) / f64::from(format.sample_rate)))
+        }
+        (_, Some(size)) if format.bytes_per_second > 0 => {
+            Some(seconds(size as f64 / f64::from(format.bytes_per_second)))
+        }
+        _ => None,
+    };
+    // The broadcast extension holds the origination date and time after the description,
+    // originator and reference.
+    let creation_time = match find_chunk(&top, b"bext") {
+        Some(bext) => {
+            let bext = read_chunk(reader, bext)?;
+            bext.get(320..338).and_then(|origination| {
+                let mut text = origination.to_vec();
+                // Any separators may be used: write the date in the EXIF form.
+                for &separator in &[4, 7, 12, 15] {
+                    text[separator] = b':';
+                }
+                text.insert(10, b' ');
+                exif::parse_date_time(&String::from_utf8_lossy(&text))
+            })
+        }
+        None => None,
+    };
+
+    let mut track = MediaTrack::new(TrackKind::Audio);
+    track.codec = Some(Codec::WaveFormat(format.tag));
+    track.duration = duration;
+    track.sample_rate = Some(format.sample_rate).filter(|&rate| rate > 0);
+    track.channels = Some(u32::from(format.channels)).filter(|&channels| channels > 0);
+    Ok(MediaInfo {
+        container: MediaContainer::Wav,
+        brand: None,
+        duration,
+        creation_time,
+        tracks: vec![track],
+    })
+}
+
+/// Parse the date of an AVI `IDIT` chunk, in the `ctime` form `Wed Jan 02 02:03:55 1990`
+/// or the EXIF form.
+fn parse_idit(text: &str) -> Option<DateTime> {
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if let Some(date) = exif::parse_date_time(text) {
+        return Some(date);
+    }
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    if fields.len() != 5 {
+        return None;
+    }
+    const MONTHS: [&str; 12] = [
+        "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+    ];
+    let month = MONTHS
+        .iter()
+        .position(|month| fields[1].eq_ignore_ascii_case(month))?;
+    let time: Vec<u8> = fields[3]
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    if time.len() != 3 {
+        return None;
+    }
+    DateTime::new(
+        fields[4].parse().ok()?,
+        month as u8 + 1,
+        fields[2].parse().ok()?,
+        time[0],
+        time[1],
+        time[2],
+    )
+}
+
+fn seconds(value: f64) -> Duration {
+    Duration::from_secs_f64(value.max(0.0))
+}
+
+fn u16_le(data: &[u8], offset: usize) -> Option<u16> {
+    let b = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_le(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{full_box, mp4_box, write_at};
+    use std::io::Cursor;
+
+    /// A track whose media header gives `mdhd`, its timescale and duration. The offsets
+    /// written in full boxes here and below leave out their version and flags.
+    fn track(handler: &[u8; 4], tkhd: Vec<u8>, mdhd: (u32, u32), stbl: &[u8]) -> Vec<u8> {
+        let mut mdhd_body = vec![0; 20];
+        write_at(&mut mdhd_body, 8, &mdhd.0.to_be_bytes());
+        write_at(&mut mdhd_body, 12, &mdhd.1.to_be_bytes());
+        let mut hdlr = vec![0; 20];
+        write_at(&mut hdlr, 4, handler);
+        let mdia = [
+            full_box(b"mdhd", 0, &mdhd_body),
+            full_box(b"hdlr", 0, &hdlr),
+            mp4_box(b"minf", &mp4_box(b"stbl", stbl)),
+        ]
+        .concat();
+        mp4_box(
+            b"trak",
+            &[full_box(b"tkhd", 0, &tkhd), mp4_box(b"mdia", &mdia)].concat(),
+        )
+    }
+
+    fn tkhd(id: u32, matrix: [i32; 9], width: u32, height: u32) -> Vec<u8> {
+        let mut tkhd = vec![0; 80];
+        write_at(&mut tkhd, 8, &id.to_be_bytes());
+        for (index, value) in matrix.iter().enumerate() {
+            write_at(&mut tkhd, 36 + index * 4, &value.to_be_bytes());
+        }
+        write_at(&mut tkhd, 72, &(width << 16).to_be_bytes());
+        write_at(&mut tkhd, 76, &(height << 16).to_be_bytes());
+        tkhd
+    }
+
+    fn stsd(entry: Vec<u8>) -> Vec<u8> {
+        let mut entry = entry;
+        let size = entry.len() as u32;
+        write_at(&mut entry, 0, &size.to_be_bytes());
+        full_box(b"stsd", 0, &[&1u32.to_be_bytes()[..], &entry].concat())
+    }
+
+    #[test]
+    fn reads_an_mp4_movie() {
+        let mut mvhd = vec![0; 92];
+        let created = (MAC_EPOCH_OFFSET + 1_600_000_000) as u32;
+        write_at(&mut mvhd, 0, &created.to_be_bytes());
+        write_at(&mut mvhd, 8, &600u32.to_be_bytes());
+        write_at(&mut mvhd, 12, &6006u32.to_be_bytes());
+
+        // Rotated 90 degrees clockwise: a = 0, b = 1, c = -1, d = 0, w = 1 in 2.30.
+        let rotated = [0, 0x10000, 0, -0x10000, 0, 0, 0, 0, 0x4000_0000];
+        let mut avc1 = vec![0; 86];
+        write_at(&mut avc1, 4, b"avc1");
+        write_at(&mut avc1, 32, &1920u16.to_be_bytes());
+        write_at(&mut avc1, 34, &1080u16.to_be_bytes());
+        let stts = full_box(
+            b"stts",
+            0,
+            &[1u32, 300, 1001]
+                .iter()
+                .flat_map(|value| value.to_be_bytes())
+                .collect::<Vec<_>>(),
+        );
+        let video = track(
+            b"vide",
+            tkhd(1, rotated, 1920, 1080),
+            (30000, 300_300),
+            &[stsd(avc1), stts].concat(),
+        );
+
+        let upright = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x4000_0000];
+        let mut mp4a = vec![0; 36];
+        write_at(&mut mp4a, 4, b"mp4a");
+        write_at(&mut mp4a, 24, &2u16.to_be_bytes());
+        write_at(&mut mp4a, 32, &(48000u32 << 16).to_be_bytes());
+        let audio = track(
+            b"soun",
+            tkhd(2, upright, 0, 0),
+            (48000, 480_480),
+            &stsd(mp4a),
+        );
+
+        let mut ftyp = b"mp42".to_vec();
+        ftyp.extend_from_slice(&[0, 0, 0, 0]);
+        ftyp.extend_from_slice(b"isommp42");
+        let moov = [full_box(b"mvhd", 0, &mvhd), video, audio].concat();
+        let data = [mp4_box(b"ftyp", &ftyp), mp4_box(b"moov", &moov)].concat();
+
+        let info = read(&mut Cursor::new(data)).unwrap();
+        assert_eq!(info.container, MediaContainer::Bmff);
+        assert_eq!(info.brand, Some(*b"mp42"));
+        assert_eq!(info.duration, Some(Duration::from_millis(10010)));
+        assert_eq!(info.creation_time, Some(DateTime::from_unix(1_600_000_000)));
+
+        let video = info.video().unwrap();
+        assert_eq!(video.id, Some(1));
+        assert_eq!(video.codec, Some(Codec::FourCc(*b"avc1")));
+        assert_eq!(video.duration, Some(Duration::from_millis(10010)));
+        assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+        assert!((video.frame_rate.unwrap() - 30000.0 / 1001.0).abs() < 1e-9);
+        assert_eq!(video.rotation(), Some(90));
+
+        let audio = info.audio().unwrap();
+        assert_eq!(audio.id, Some(2));
+        assert_eq!(audio.codec, Some(Codec::FourCc(*b"mp4a")));
+        assert_eq!(audio.sample_rate, Some(48000));
+        assert_eq!(audio.channels, Some(2));
+        assert_eq!(audio.rotation(), Some(0));
+    }
+
+    #[test]
+    fn rotation_of_the_track_matrix() {
+        let rotation = |a: f64, b: f64, c: f64, d: f64| {
+            let mut track = MediaTrack::new(TrackKind::Video);
+            track.matrix = Some([a, b, 0.0, c, d, 0.0, 0.0, 0.0, 1.0]);
+            track.rotation()
+        };
+        assert_eq!(rotation(-1.0, 0.0, 0.0, -1.0), Some(180));
+        assert_eq!(rotation(0.0, -1.0, 1.0, 0.0), Some(270));
+        // Mirrored.
+        assert_eq!(rotation(-1.0, 0.0, 0.0, 1.0), None);
+        assert_eq!(MediaTrack::new(TrackKind::Video).rotation(), None);
+    }
+
+    /// A RIFF chunk, padded to an even size.
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn riff(kind: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunk(b"RIFF", &[&kind[..], &chunks.concat()].concat())
+    }
+
+    fn list(kind: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunk(b"LIST", &[&kind[..], &chunks.concat()].concat())
+    }
+
+    fn pcm_format(channels: u16, sample_rate: u32) -> Vec<u8> {
+        let mut format = vec![0; 16];
+        write_at(&mut format, 0, &1u16.to_le_bytes());
+        write_at(&mut format, 2, &channels.to_le_bytes());
+        write_at(&mut format, 4, &sample_rate.to_le_bytes());
+        let bytes_per_second = sample_rate * u32::from(channels) * 2;
+        write_at(&mut format, 8, &bytes_per_second.to_le_bytes());
+        write_at(&mut format, 12, &(channels * 2).to_le_bytes());
+        write_at(&mut format, 14, &16u16.to_le_bytes());
+        format
+    }
+
+    #[test]
+    fn reads_an_avi_movie() {
+        let mut avih = vec![0; 56];
+        write_at(&mut avih, 0, &33367u32.to_le_bytes());
+        write_at(&mut avih, 16, &300u32.to_le_bytes());
+        let stream_header =
+            |kind: &[u8; 4], handler: &[u8; 4], scale: u32, rate: u32, length: u32| {
+                let mut strh = vec![0; 56];
+                write_at(&mut strh, 0, kind);
+                write_at(&mut strh, 4, handler);
+                write_at(&mut strh, 20, &scale.to_le_bytes());
+                write_at(&mut strh, 24, &rate.to_le_bytes());
+                write_at(&mut strh, 32, &length.to_le_bytes());
+                strh
+            };
+        let mut bitmap = vec![0; 40];
+        write_at(&mut bitmap, 0, &40u32.to_le_bytes());
+        write_at(&mut bitmap, 4, &640u32.to_le_bytes());
+        // Negative for a top-down image.
+        write_at(&mut bitmap, 8, &(-480i32).to_le_bytes());
+        write_at(&mut bitmap, 16, b"MJPG");
+        let hdrl = list(
+            b"hdrl",
+            &[
+                chunk(b"avih", &avih),
+                list(
+                    b"strl",
+                    &[
+                        chunk(b"strh", &stream_header(b"vids", b"mjpg", 1001, 30000, 300)),
+                        chunk(b"strf", &bitmap),
+                    ],
+                ),
+                list(
+                    b"strl",
+                    &[
+                        chunk(
+                            b"strh",
+                            &stream_header(b"auds", b"\0\0\0\0", 1, 44100, 441_441),
+                        ),
+                        chunk(b"strf", &pcm_format(2, 44100)),
+                    ],
+                ),
+                chunk(b"IDIT", b"Wed Jan 02 02:03:55 1990\n\0"),
+            ],
+        );
+        let data = riff(b"AVI ", &[hdrl, list(b"movi", &[])]);
+
+        let info = read(&mut Cursor::new(data)).unwrap();
+        assert_eq!(info.container, MediaContainer::Avi);
+        assert_eq!(info.duration, Some(Duration::from_millis(10010)));
+        assert_eq!(info.creation_time, DateTime::new(1990, 1, 2, 2, 3, 55));
+
+        let video = info.video().unwrap();
+        assert_eq!(video.id, Some(0));
+        assert_eq!(video.codec, Some(Codec::FourCc(*b"MJPG")));
+        assert_eq!((video.width, video.height), (Some(640), Some(480)));
+        assert!((video.frame_rate.unwrap() - 30000.0 / 1001.0).abs() < 1e-9);
+
+        let audio = info.audio().unwrap();
+        assert_eq!(audio.id, Some(1));
+        assert_eq!(audio.codec, Some(Codec::WaveFormat(1)));
+        assert_eq!(audio.sample_rate, Some(44100));
+        assert_eq!(audio.channels, Some(2));
+        assert_eq!(audio.duration, Some(Duration::from_millis(10010)));
+    }
+
+    #[test]
+    fn reads_a_wav_recording() {
+        let mut bext = vec![0; 338];
+        write_at(&mut bext, 320, b"2021-05-04");
+        write_at(&mut bext, 330, b"03-02-01");
+        let data = riff(
+            b"WAVE",
+            &[
+                chunk(b"bext", &bext),
+                chunk(b"fmt ", &pcm_format(1, 48000)),
+                chunk(b"data", &[0; 9600]),
+            ],
+        );
+
+        let info = read(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(info.container, MediaContainer::Wav);
+        assert_eq!(info.duration, Some(Duration::from_millis(100)));
+        assert_eq!(info.creation_time, DateTime::new(2021, 5, 4, 3, 2, 1));
+        let audio = info.audio().unwrap();
+        assert_eq!(audio.codec, Some(Codec::WaveFormat(1)));
+        assert_eq!(audio.codec.unwrap().to_string(), "PCM");
+        assert_eq!(audio.sample_rate, Some(48000));
+        assert_eq!(audio.channels, Some(1));
+
+        // A recording cut off short of the sizes in its headers lasts as long as its data.
+        let mut cut = data[..data.len() - 4800].to_vec();
+        write_at(&mut cut, 4, &u32::MAX.to_le_bytes());
+        let info = read(&mut Cursor::new(cut)).unwrap();
+        assert_eq!(info.duration, Some(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn rejects_a_wav_file_without_format() {
+        let data = riff(b"WAVE", &[chunk(b"data", &[0; 16])]);
+        assert!(matches!(
+            read(&mut Cursor::new(data)),
+            Err(Error::Malformed(_))
+        ));
+    }
+}
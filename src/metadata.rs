@@ -0,0 +1,496 @@
+//! Metadata of pictures read without the platform's image frameworks.
+//!
+//! `ICCameraItem::metadataIfAvailable` is only available on macOS and returns a dictionary.
+//! `read` finds the EXIF metadata of JPEG files, TIFF-based RAW files such as CR2, NEF, ARW,
+//! DNG and ORF, and HEIF files, and returns the commonly used fields in a `Metadata`. It
+//! reads through `Read` + `Seek`, so the same code works on downloaded files and, with
+//! `read_camera_file`, on files still on the camera, fetching only the headers.
+
+use crate::bmff::{self, HeifMeta};
+use crate::camera::{CameraDevice, CameraFile};
+use crate::datetime::{DateTime, UtcOffset};
+use crate::error::{Error, Result};
+use crate::exif::{self, gps_tag, tag, Exif, Ifd, Value};
+use crate::jpeg;
+use crate::reader::CameraFileReader;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::ops::Range;
+use std::path::Path;
+
+/// Number of bytes read from the start of a TIFF-based file for its directories.
+const TIFF_HEAD_SIZE: u64 = 256 * 1024;
+
+/// The file structures metadata is read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Container {
+    Jpeg,
+    /// TIFF and the RAW formats built on it.
+    Tiff,
+    /// HEIF, HEIC and AVIF images.
+    Heif,
+}
+
+/// Commonly used metadata of a picture.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    pub container: Container,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial_number: Option<String>,
+    pub lens_make: Option<String>,
+    pub lens_model: Option<String>,
+    /// When the picture was taken, in the camera's local time.
+    pub date_time_original: Option<DateTime>,
+    /// The time zone of `date_time_original`, if the camera recorded it.
+    pub offset_time_original: Option<UtcOffset>,
+    /// The EXIF orientation, from 1 (upright) to 8.
+    pub orientation: Option<u16>,
+    /// Pixel width of the image, before applying the orientation.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Exposure time in seconds.
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    /// Exposure compensation in EV.
+    pub exposure_bias: Option<f64>,
+    /// Focal length in millimeters.
+    pub focal_length: Option<f64>,
+    /// Focal length in millimeters equivalent on 35 mm film.
+    pub focal_length_35mm: Option<u32>,
+    pub flash_fired: Option<bool>,
+    /// Degrees north of the equator, negative south of it.
+    pub latitude: Option<f64>,
+    /// Degrees east of the prime meridian, negative west of it.
+    pub longitude: Option<f64>,
+    /// Meters above sea level.
+    pub altitude: Option<f64>,
+    /// Offsets of the maker note in the file, whose layout depends on the manufacturer.
+    pub maker_note: Option<Range<u64>>,
+    /// All fields read, with their value offsets in the file.
+    pub exif: Exif,
+}
+
+impl Metadata {
+    /// The capture date in UTC, if the time zone was recorded.
+    pub fn capture_time_utc(&self) -> Option<DateTime> {
+        Some(self.offset_time_original?.to_utc(self.date_time_original?))
+    }
+
+    fn from_exif(container: Container, exif: Exif, dimensions: Option<(u32, u32)>) -> Metadata {
+        let number = |tag| exif.get(tag).and_then(|value| value.as_f64(0));
+        let pixel_dimensions = || {
+            let width = exif.get_in(Ifd::Exif, tag::PIXEL_X_DIMENSION)?.as_u32(0)?;
+            let height = exif.get_in(Ifd::Exif, tag::PIXEL_Y_DIMENSION)?.as_u32(0)?;
+            Some((width, height))
+        };
+        let image_dimensions = || {
+            let width = exif.get_in(Ifd::Primary, tag::IMAGE_WIDTH)?.as_u32(0)?;
+            let height = exif.get_in(Ifd::Primary, tag::IMAGE_LENGTH)?.as_u32(0)?;
+            Some((width, height))
+        };
+        let dimensions = dimensions
+            .or_else(pixel_dimensions)
+            .or_else(image_dimensions);
+        let maker_note = exif
+            .fields
+            .iter()
+            .find(|field| field.ifd == Ifd::Exif && field.tag == tag::MAKER_NOTE)
+            .map(|field| {
+                let size = match &field.value {
+                    Value::Undefined(bytes) | Value::Byte(bytes) => bytes.len(),
+                    _ => 0,
+                };
+                field.offset as u64..(field.offset + size) as u64
+            });
+        Metadata {
+            container,
+            make: exif.string(tag::MAKE),
+            model: exif.string(tag::MODEL),
+            serial_number: exif.string(tag::BODY_SERIAL_NUMBER),
+            lens_make: exif.string(tag::LENS_MAKE),
+            lens_model: exif.string(tag::LENS_MODEL),
+            date_time_original: exif.date_time_original(),
+            offset_time_original: exif
+                .get(tag::OFFSET_TIME_ORIGINAL)
+                .and_then(Value::as_str)
+                .and_then(UtcOffset::parse),
+            orientation: exif
+                .get_in(Ifd::Primary, tag::ORIENTATION)
+                .and_then(|value| value.as_u32(0))
+                .filter(|orientation| (1..=8).contains(orientation))
+                .map(|orientation| orientation as u16),
+            width: dimensions.map(|(width, _)| width),
+            height: dimensions.map(|(_, height)| height),
+            exposure_time: number(tag::EXPOSURE_TIME),
+            f_number: number(tag::F_NUMBER),
+            iso: exif
+                .get(tag::ISO_SPEED_RATINGS)
+                .and_then(|value| value.as_u32(0)),
+            exposure_bias: number(tag::EXPOSURE_BIAS_VALUE),
+            focal_length: number(tag::FOCAL_LENGTH),
+            focal_length_35mm: exif
+                .get(tag::FOCAL_LENGTH_IN_35MM_FILM)
+                .and_then(|value| value.as_u32(0)),
+            flash_fired: exif
+                .get(tag::FLASH)
+                .and_then(|value| value.as_u32(0))
+                .map(|flash| flash & 1 != 0),
+            latitude: coordinate(&exif, gps_tag::GPS_LATITUDE, gps_tag::GPS_LATITUDE_REF, "S"),
+            longitude: coordinate(
+                &exif,
+                gps_tag::GPS_LONGITUDE,
+                gps_tag::GPS_LONGITUDE_REF,
+                "W",
+            ),
+            altitude: exif
+                .get_in(Ifd::Gps, gps_tag::GPS_ALTITUDE)
+                .and_then(|value| value.as_f64(0))
+                .map(|altitude| {
+                    let below = exif
+                        .get_in(Ifd::Gps, gps_tag::GPS_ALTITUDE_REF)
+                        .and_then(|value| value.as_u32(0))
+                        == Some(1);
+                    if below {
+                        -altitude
+                    } else {
+                        altitude
+                    }
+                }),
+            maker_note,
+            exif,
+        }
+    }
+}
+
+/// Read the metadata of a JPEG, TIFF-based or HEIF file.
+pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Metadata> {
+    let head = bmff::read_at(reader, 0, 16)?;
+    if head.starts_with(&[0xFF, jpeg::SOI]) {
+        return read_jpeg(reader);
+    }
+    if head.starts_with(b"II") || head.starts_with(b"MM") {
+        let data = bmff::read_at(reader, 0, TIFF_HEAD_SIZE)?;
+        let exif = exif::read_tiff(&data, 0)?;
+        return Ok(Metadata::from_exif(Container::Tiff, exif, None));
+    }
+    let is_heif = bmff::brands(reader)?.is_some_and(|brands| {
+        brands
+            .iter()
+            .any(|brand| bmff::HEIF_BRANDS.contains(&brand))
+    });
+    if is_heif {
+        return read_heif(reader);
+    }
+    Err(Error::malformed("not a JPEG, TIFF-based or HEIF file"))
+}
+
+/// Read the metadata of the file at `path`.
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Metadata> {
+    read(&mut BufReader::new(File::open(path)?))
+}
+
+/// Read the metadata of a file on the camera, fetching only the parts that hold it.
+pub fn read_camera_file(camera: &dyn CameraDevice, file: &CameraFile) -> Result<Metadata> {
+    read(&mut CameraFileReader::new(camera, file))
+}
+
+/// Find the EXIF segment and the frame header of a JPEG file, segment by segment.
+fn read_jpeg<R: Read + Seek>(reader: &mut R) -> Result<Metadata> {
+    let mut exif = None;
+    let mut dimensions = None;
+    let mut offset = 2;
+    loop {
+        let marker = bmff::read_at(reader, offset, 4)?;
+        if marker.len() < 4 || marker[0] != 0xFF {
+            break;
+        }
+        if marker[1] == 0xFF {
+            offset += 1;
+            continue;
+        }
+        if marker[1] == jpeg::SOS || marker[1] == jpeg::EOI {
+            break;
+        }
+        let length = u64::from(u16::from_be_bytes([marker[2], marker[3]]));
+        let payload = offset + 4;
+        match marker[1] {
+            jpeg::APP1 if exif.is_none() => {
+                let segment = bmff::read_at(reader, payload, length.saturating_sub(2))?;
+                if segment.starts_with(b"Exif\0\0") {
+                    exif = Some(with_file_offsets(
+                        exif::read_tiff(&segment[6..], 0)?,
+                        payload + 6,
+                    ));
+                }
+            }
+            // Start of frame markers, leaving out DHT, JPG and DAC.
+            0xC0..=0xCF if !matches!(marker[1], 0xC4 | 0xC8 | 0xCC) => {
+                let frame = bmff::read_at(reader, payload, 5)?;
+                if frame.len() == 5 {
+                    let height = u16::from_be_bytes([frame[1], frame[2]]);
+                    let width = u16::from_be_bytes([frame[3], frame[4]]);
+                    dimensions = Some((u32::from(width), u32::from(height)));
+                }
+            }
+            _ => {}
+        }
+        offset += 2 + length;
+    }
+    Ok(Metadata::from_exif(
+        Container::Jpeg,
+        exif.unwrap_or_default(),
+        dimensions,
+    ))
+}
+
+/// Read the EXIF item describing the primary image of a HEIF file and the size of that
+/// image.
+fn read_heif<R: Read + Seek>(reader: &mut R) -> Result<Metadata> {
+    let heif = HeifMeta::read(reader)?;
+    let primary = heif.primary.and_then(|id| heif.item(id));
+    let dimensions = match primary {
+        Some(primary) => heif.dimensions(reader, primary)?,
+        None => None,
+    };
+    let exif_item = primary
+        .and_then(|primary| {
+            heif.referencing(b"cdsc", primary.id)
+                .into_iter()
+                .find(|item| &item.kind == b"Exif")
+        })
+        .or_else(|| heif.items.iter().find(|item| &item.kind == b"Exif"));
+    let mut exif = Exif::default();
+    if let Some(item) = exif_item {
+        let data = heif.read_item(reader, item)?;
+        // The item starts with the offset of the TIFF header after these four bytes.
+        let tiff = bmff::u32_at(&data, 0)
+            .and_then(|skip| 4usize.checked_add(skip as usize))
+            .ok_or_else(|| Error::malformed("truncated HEIF EXIF item"))?;
+        let start = item.extents.first().map_or(0, |extent| extent.start);
+        exif = with_file_offsets(exif::read_tiff(&data, tiff)?, start);
+    }
+    Ok(Metadata::from_exif(Container::Heif, exif, dimensions))
+}
+
+/// Move the offsets of EXIF read from data starting at `base` in the file to file offsets.
+fn with_file_offsets(mut exif: Exif, base: u64) -> Exif {
+    let base = base as usize;
+    exif.tiff_offset += base;
+    for field in &mut exif.fields {
+        field.offset += base;
+    }
+    exif
+}
+
+/// Degrees of a GPS coordinate in degrees, minutes and seconds, negative when its
+/// reference is `negative`.
+fn coordinate(exif: &Exif, value_tag: u16, reference_tag: u16, negative: &str) -> Option<f64> {
+    let value = exif.get_in(Ifd::Gps, value_tag)?;
+    let degrees = value.as_f64(0)?
+        + value.as_f64(1).unwrap_or(0.0) / 60.0
+        + value.as_f64(2).unwrap_or(0.0) / 3600.0;
+    let reference = exif.get_in(Ifd::Gps, reference_tag).and_then(Value::as_str);
+    Some(if reference == Some(negative) {
+        -degrees
+    } else {
+        degrees
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        ascii, byte, exif_segment, full_box, jpeg, long, mp4_box, rational, short, srational,
+        tiff_header, undefined, write_ifd,
+    };
+    use std::io::Cursor;
+
+    const MAKER_NOTE: &[u8] = b"NOTEDATA";
+
+    /// A TIFF structure with the primary, EXIF and GPS directories filled in.
+    fn tiff() -> Vec<u8> {
+        let mut data = tiff_header(8);
+        write_ifd(
+            &mut data,
+            8,
+            vec![
+                ascii(tag::MAKE, "Canon"),
+                ascii(tag::MODEL, "Canon EOS R5"),
+                short(tag::ORIENTATION, &[6]),
+                long(tag::EXIF_IFD_POINTER, &[0x100]),
+                long(tag::GPS_INFO_IFD_POINTER, &[0x300]),
+            ],
+            0,
+        );
+        write_ifd(
+            &mut data,
+            0x100,
+            vec![
+                rational(tag::EXPOSURE_TIME, &[(1, 250)]),
+                rational(tag::F_NUMBER, &[(28, 10)]),
+                short(tag::ISO_SPEED_RATINGS, &[400]),
+                ascii(tag::DATE_TIME_ORIGINAL, "2021:05:04 03:02:01"),
+                ascii(tag::OFFSET_TIME_ORIGINAL, "+02:00"),
+                srational(tag::EXPOSURE_BIAS_VALUE, &[(-1, 3)]),
+                short(tag::FLASH, &[0x19]),
+                rational(tag::FOCAL_LENGTH, &[(50, 1)]),
+                short(tag::FOCAL_LENGTH_IN_35MM_FILM, &[50]),
+                long(tag::PIXEL_X_DIMENSION, &[6000]),
+                long(tag::PIXEL_Y_DIMENSION, &[4000]),
+                ascii(tag::LENS_MODEL, "RF50mm F1.8 STM"),
+                undefined(tag::MAKER_NOTE, MAKER_NOTE),
+            ],
+            0,
+        );
+        write_ifd(
+            &mut data,
+            0x300,
+            vec![
+                ascii(gps_tag::GPS_LATITUDE_REF, "S"),
+                rational(gps_tag::GPS_LATITUDE, &[(33, 1), (52, 1), (1200, 100)]),
+                ascii(gps_tag::GPS_LONGITUDE_REF, "E"),
+                rational(gps_tag::GPS_LONGITUDE, &[(151, 1), (12, 1), (30, 1)]),
+                byte(gps_tag::GPS_ALTITUDE_REF, &[1]),
+                rational(gps_tag::GPS_ALTITUDE, &[(15, 1)]),
+            ],
+            0,
+        );
+        data
+    }
+
+    /// A HEIF file whose primary image is `width` x `height` pixels and described by an
+    /// EXIF item holding `tiff`.
+    fn heif(width: u32, height: u32, tiff: &[u8]) -> Vec<u8> {
+        let image = b"hevc image data".to_vec();
+        let mut exif = 6u32.to_be_bytes().to_vec();
+        exif.extend_from_slice(b"Exif\0\0");
+        exif.extend_from_slice(tiff);
+        let build = |mdat_body: u32| {
+            let mut ftyp = b"heic".to_vec();
+            ftyp.extend_from_slice(&0u32.to_be_bytes());
+            ftyp.extend_from_slice(b"mif1heic");
+            let infe = |id: u16, kind: &[u8; 4]| {
+                let mut body = id.to_be_bytes().to_vec();
+                body.extend_from_slice(&[0, 0]);
+                body.extend_from_slice(kind);
+                body.push(0);
+                full_box(b"infe", 2, &body)
+            };
+            let mut iinf = 2u16.to_be_bytes().to_vec();
+            iinf.extend(infe(1, b"hvc1"));
+            iinf.extend(infe(2, b"Exif"));
+            let mut iloc = vec![0x44, 0x00, 0, 2];
+            for (id, start, length) in [
+                (1u16, mdat_body, image.len()),
+                (2, mdat_body + image.len() as u32, exif.len()),
+            ] {
+                iloc.extend_from_slice(&id.to_be_bytes());
+                iloc.extend_from_slice(&[0, 0, 0, 1]);
+                iloc.extend_from_slice(&start.to_be_bytes());
+                iloc.extend_from_slice(&(length as u32).to_be_bytes());
+            }
+            let mut ispe = width.to_be_bytes().to_vec();
+            ispe.extend_from_slice(&height.to_be_bytes());
+            let ipco = mp4_box(b"ipco", &full_box(b"ispe", 0, &ispe));
+            let ipma = full_box(b"ipma", 0, &[0, 0, 0, 1, 0, 1, 1, 0x81]);
+            let iref = full_box(b"iref", 0, &mp4_box(b"cdsc", &[0, 2, 0, 1, 0, 1]));
+            let mut meta = full_box(b"pitm", 0, &[0, 1]);
+            meta.extend(full_box(b"iinf", 0, &iinf));
+            meta.extend(full_box(b"iloc", 0, &iloc));
+            meta.extend(iref);
+            meta.extend(mp4_box(b"iprp", &[ipco, ipma].concat()));
+            let mut file = mp4_box(b"ftyp", &ftyp);
+            file.extend(full_box(b"meta", 0, &meta));
+            file
+        };
+        // The item offsets do not change the size of the boxes before the media data.
+        let mdat_body = build(0).len() as u32 + 8;
+        let mut file = build(mdat_body);
+        file.extend(mp4_box(b"mdat", &[image, exif].concat()));
+        file
+    }
+
+    fn assert_fields(metadata: &Metadata) {
+        assert_eq!(metadata.make.as_deref(), Some("Canon"));
+        assert_eq!(metadata.model.as_deref(), Some("Canon EOS R5"));
+        assert_eq!(metadata.lens_model.as_deref(), Some("RF50mm F1.8 STM"));
+        assert_eq!(metadata.orientation, Some(6));
+        assert_eq!(
+            metadata.date_time_original,
+            DateTime::new(2021, 5, 4, 3, 2, 1)
+        );
+        assert_eq!(metadata.offset_time_original, UtcOffset::new(2, 0));
+        assert_eq!(
+            metadata.capture_time_utc(),
+            DateTime::new(2021, 5, 4, 1, 2, 1)
+        );
+        assert_eq!(metadata.exposure_time, Some(0.004));
+        assert_eq!(metadata.f_number, Some(2.8));
+        assert_eq!(metadata.iso, Some(400));
+        assert_eq!(metadata.exposure_bias, Some(-1.0 / 3.0));
+        assert_eq!(metadata.focal_length, Some(50.0));
+        assert_eq!(metadata.focal_length_35mm, Some(50));
+        assert_eq!(metadata.flash_fired, Some(true));
+        let latitude = metadata.latitude.unwrap();
+        assert!((latitude + (33.0 + 52.0 / 60.0 + 12.0 / 3600.0)).abs() < 1e-9);
+        let longitude = metadata.longitude.unwrap();
+        assert!((longitude - (151.0 + 12.0 / 60.0 + 30.0 / 3600.0)).abs() < 1e-9);
+        assert_eq!(metadata.altitude, Some(-15.0));
+    }
+
+    fn maker_note(data: &[u8], metadata: &Metadata) -> Vec<u8> {
+        let range = metadata.maker_note.clone().unwrap();
+        data[range.start as usize..range.end as usize].to_vec()
+    }
+
+    #[test]
+    fn reads_a_tiff_file() {
+        let data = tiff();
+        let metadata = read(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(metadata.container, Container::Tiff);
+        assert_fields(&metadata);
+        assert_eq!((metadata.width, metadata.height), (Some(6000), Some(4000)));
+        assert_eq!(maker_note(&data, &metadata), MAKER_NOTE);
+    }
+
+    #[test]
+    fn reads_a_jpeg_file() {
+        let data = jpeg(640, 480, &exif_segment(&tiff()));
+        let metadata = read(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(metadata.container, Container::Jpeg);
+        assert_fields(&metadata);
+        // The frame header wins over the pixel dimensions of the EXIF directory.
+        assert_eq!((metadata.width, metadata.height), (Some(640), Some(480)));
+        assert_eq!(maker_note(&data, &metadata), MAKER_NOTE);
+        assert_eq!(metadata.exif.tiff_offset, 12);
+    }
+
+    #[test]
+    fn reads_a_jpeg_file_without_exif() {
+        let metadata = read(&mut Cursor::new(jpeg(320, 240, &[]))).unwrap();
+        assert_eq!(metadata.container, Container::Jpeg);
+        assert_eq!((metadata.width, metadata.height), (Some(320), Some(240)));
+        assert_eq!(metadata.make, None);
+        assert_eq!(metadata.date_time_original, None);
+    }
+
+    #[test]
+    fn reads_a_heif_file() {
+        let data = heif(4032, 3024, &tiff());
+        let metadata = read(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(metadata.container, Container::Heif);
+        assert_fields(&metadata);
+        // The `ispe` property wins over the pixel dimensions of the EXIF directory.
+        assert_eq!((metadata.width, metadata.height), (Some(4032), Some(3024)));
+        assert_eq!(maker_note(&data, &metadata), MAKER_NOTE);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let result = read(&mut Cursor::new(b"GIF89a and some more bytes".to_vec()));
+        assert!(matches!(result, Err(Error::Malformed(_))));
+    }
+}
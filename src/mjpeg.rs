@@ -0,0 +1,215 @@
+//! MJPEG over HTTP, for previewing live view in a browser.
+//!
+//! Each client receives a `multipart/x-mixed-replace` response in which every part is
+//! the latest JPEG frame, which browsers display as video in an `<img>` element.
+
+use crate::error::Result;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const BOUNDARY: &str = "mjpegframe";
+/// How long a client may take to accept a frame before it is dropped, so that a stalled
+/// client cannot hold up the others.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long dropping the server waits to connect to its own listener to wake it.
+const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An HTTP server streaming the frames passed to `send_frame` to every connected client.
+///
+/// Dropping the server disconnects the clients, stops accepting and closes the listener.
+pub struct MjpegServer {
+    address: SocketAddr,
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+    stopping: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl MjpegServer {
+    /// Listen on `address` and accept clients on a background thread.
+    pub fn bind<A: ToSocketAddrs>(address: A) -> Result<MjpegServer> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted = Arc::clone(&clients);
+        let stopping = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stopping);
+        let acceptor = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let clients = Arc::clone(&accepted);
+                thread::spawn(move || {
+                    if let Ok(stream) = handshake(stream) {
+                        lock(&clients).push(stream);
+                    }
+                });
+            }
+        });
+        Ok(MjpegServer {
+            address,
+            clients,
+            stopping,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// The address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Number of clients currently receiving frames.
+    pub fn client_count(&self) -> usize {
+        lock(&self.clients).len()
+    }
+
+    /// Send a JPEG frame to every client, dropping clients that have disconnected or stopped
+    /// reading.
+    pub fn send_frame(&self, jpeg: &[u8]) {
+        let header = format!(
+            "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            BOUNDARY,
+            jpeg.len()
+        );
+        lock(&self.clients).retain(|client| {
+            let mut client = client;
+            client
+                .write_all(header.as_bytes())
+                .and_then(|_| client.write_all(jpeg))
+                .and_then(|_| client.write_all(b"\r\n"))
+                .and_then(|_| client.flush())
+                .is_ok()
+        });
+    }
+}
+
+impl Drop for MjpegServer {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        lock(&self.clients).clear();
+        // The accept thread only sees the flag once it accepts a connection.
+        let mut wake = self.address;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(match wake.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        if TcpStream::connect_timeout(&wake, WAKE_TIMEOUT).is_ok() {
+            if let Some(acceptor) = self.acceptor.take() {
+                let _ = acceptor.join();
+            }
+        }
+    }
+}
+
+/// Read the request headers and answer with the multipart response header.
+fn handshake(stream: TcpStream) -> io::Result<TcpStream> {
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" || line == "\n" {
+            break;
+        }
+    }
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.0 200 OK\r\n\
+         Cache-Control: no-cache\r\n\
+         Connection: close\r\n\
+         Content-Type: multipart/x-mixed-replace; boundary={}\r\n\r\n",
+        BOUNDARY
+    )?;
+    stream.flush()?;
+    Ok(stream)
+}
+
+fn lock<V>(mutex: &Mutex<V>) -> std::sync::MutexGuard<'_, V> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Instant;
+
+    fn connect(server: &MjpegServer) -> TcpStream {
+        let mut client = TcpStream::connect(server.local_addr()).unwrap();
+        client.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.client_count() == 0 {
+            assert!(Instant::now() < deadline, "the client was not accepted");
+            thread::sleep(Duration::from_millis(10));
+        }
+        client
+    }
+
+    #[test]
+    fn clients_receive_frames() {
+        let server = MjpegServer::bind("127.0.0.1:0").unwrap();
+        let mut client = connect(&server);
+        server.send_frame(b"\xFF\xD8frame\xFF\xD9");
+        drop(server);
+        let mut response = Vec::new();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0; 1024];
+        while !response.ends_with(b"\xFF\xD9\r\n") {
+            let n = client.read(&mut buf).unwrap();
+            assert!(n > 0, "the response ended early");
+            response.extend_from_slice(&buf[..n]);
+        }
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("boundary=mjpegframe\r\n\r\n--mjpegframe\r\n"));
+        assert!(response.contains("Content-Length: 9\r\n\r\n"));
+    }
+
+    #[test]
+    fn dropping_the_server_releases_its_port() {
+        let server = MjpegServer::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr();
+        let mut client = connect(&server);
+        drop(server);
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert!(response.starts_with(b"HTTP/1.0 200 OK\r\n"));
+        let server = MjpegServer::bind(address).unwrap();
+        assert_eq!(server.local_addr(), address);
+    }
+
+    #[test]
+    fn a_client_that_stops_reading_is_dropped() {
+        let server = MjpegServer::bind("127.0.0.1:0").unwrap();
+        let _client = connect(&server);
+        let frame = vec![0; 1024 * 1024];
+        let started = Instant::now();
+        while server.client_count() > 0 {
+            assert!(
+                started.elapsed() < Duration::from_secs(30),
+                "the stalled client was kept"
+            );
+            server.send_frame(&frame);
+        }
+    }
+}
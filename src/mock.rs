@@ -0,0 +1,334 @@
+//! An in-memory camera for testing code built on `CameraDevice`.
+//!
+//! `MockCamera` keeps its files in memory in a single DCF folder. Captures add a new file,
+//! or a RAW+JPEG pair, and queue the `ItemsAdded` and `CaptureComplete` events a real camera
+//! sends. Device properties added with `add_property` can be read and set, and report their
+//! changes with `PropertyChanged` events. Battery level, free space, transfer delays and
+//! disconnects can be set to exercise error handling.
+
+use crate::camera::{
+    CameraCapabilities, CameraDevice, CameraEvent, CameraFile, CameraFolder, CameraItem,
+};
+use crate::error::{Error, Result};
+use crate::ptp::{DevicePropCode, DevicePropDesc, Property, PropertyValue};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Name of the storage of a mock camera.
+pub const STORAGE_NAME: &str = "MOCK";
+/// Name of the DCF folder holding the files of a mock camera.
+pub const DCF_FOLDER_NAME: &str = "100MOCK_";
+
+#[derive(Default)]
+struct State {
+    files: BTreeMap<String, (CameraFile, Vec<u8>)>,
+    events: VecDeque<CameraEvent>,
+    properties: BTreeMap<u16, DevicePropDesc>,
+    next_number: u32,
+    battery_level: Option<u8>,
+    free_space: Option<u64>,
+    tethering: bool,
+    disconnected: bool,
+    captures: u32,
+}
+
+/// A camera that keeps its files in memory.
+pub struct MockCamera {
+    name: String,
+    state: Mutex<State>,
+    event_added: Condvar,
+    capture_raw: bool,
+    download_delay: Mutex<Duration>,
+}
+
+impl MockCamera {
+    /// An empty camera named `name` with a full battery and 1 GiB of free space.
+    pub fn new(name: &str) -> MockCamera {
+        MockCamera {
+            name: name.to_string(),
+            state: Mutex::new(State {
+                next_number: 1,
+                battery_level: Some(100),
+                free_space: Some(1 << 30),
+                ..State::default()
+            }),
+            event_added: Condvar::new(),
+            capture_raw: false,
+            download_delay: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+
+    /// Make captures add a RAW file next to the JPEG file.
+    pub fn with_raw_captures(mut self) -> MockCamera {
+        self.capture_raw = true;
+        self
+    }
+
+    /// Add a file with `data` as it were on the card before the connection.
+    pub fn add_file(&self, name: &str, data: &[u8]) -> CameraFile {
+        let file = make_file(name, data, false);
+        lock(&self.state)
+            .files
+            .insert(name.to_string(), (file.clone(), data.to_vec()));
+        file
+    }
+
+    /// Add a device property, or replace the one with the same code.
+    pub fn add_property(&self, desc: DevicePropDesc) {
+        lock(&self.state)
+            .properties
+            .insert(desc.property_code.0, desc);
+    }
+
+    /// Simulate a press of the shutter release on the camera.
+    pub fn press_shutter(&self) -> Result<()> {
+        self.capture()
+    }
+
+    /// Queue an event for `next_event`.
+    pub fn push_event(&self, event: CameraEvent) {
+        lock(&self.state).events.push_back(event);
+        self.event_added.notify_all();
+    }
+
+    pub fn set_battery_level(&self, level: Option<u8>) {
+        lock(&self.state).battery_level = level;
+    }
+
+    pub fn set_free_space(&self, bytes: Option<u64>) {
+        lock(&self.state).free_space = bytes;
+    }
+
+    /// Make every download take at least `delay`.
+    pub fn set_download_delay(&self, delay: Duration) {
+        *lock(&self.download_delay) = delay;
+    }
+
+    /// Disconnect or reconnect the camera. While disconnected, operations fail with a
+    /// `NotConnected` I/O error, and a `DeviceRemoved` event is queued on disconnect. Events
+    /// still queued are dropped on reconnect, as a new connection starts without them.
+    pub fn set_disconnected(&self, disconnected: bool) {
+        let mut state = lock(&self.state);
+        if disconnected && !state.disconnected {
+            state.events.push_back(CameraEvent::DeviceRemoved);
+        } else if !disconnected && state.disconnected {
+            state.events.clear();
+        }
+        state.disconnected = disconnected;
+        self.event_added.notify_all();
+    }
+
+    /// Whether tethering is enabled.
+    pub fn is_tethering(&self) -> bool {
+        lock(&self.state).tethering
+    }
+
+    /// Number of pictures taken.
+    pub fn captures(&self) -> u32 {
+        lock(&self.state).captures
+    }
+
+    /// Names of the files on the camera, sorted.
+    pub fn file_names(&self) -> Vec<String> {
+        lock(&self.state).files.keys().cloned().collect()
+    }
+
+    fn connected(&self) -> Result<MutexGuard<'_, State>> {
+        let state = lock(&self.state);
+        if state.disconnected {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("{} is disconnected", self.name),
+            )));
+        }
+        Ok(state)
+    }
+
+    fn capture(&self) -> Result<()> {
+        let mut state = self.connected()?;
+        let number = state.next_number;
+        state.next_number += 1;
+        state.captures += 1;
+        let mut names = vec![(format!("IMG_{:04}.JPG", number), false)];
+        if self.capture_raw {
+            names.insert(0, (format!("IMG_{:04}.CR2", number), true));
+        }
+        for (name, is_raw) in names {
+            let data = format!("{} {}", self.name, name).into_bytes();
+            let mut file = make_file(&name, &data, is_raw);
+            file.was_added_after_content_catalog_completed = true;
+            let size = data.len() as u64;
+            state.free_space = state.free_space.map(|free| free.saturating_sub(size));
+            state.files.insert(name, (file.clone(), data));
+            state.events.push_back(CameraEvent::ItemsAdded(vec![file]));
+        }
+        state.events.push_back(CameraEvent::CaptureComplete);
+        self.event_added.notify_all();
+        Ok(())
+    }
+
+    fn data(&self, file: &CameraFile) -> Result<Vec<u8>> {
+        self.connected()?
+            .files
+            .get(&file.name)
+            .map(|(_, data)| data.clone())
+            .ok_or_else(|| {
+                Error::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} does not exist", file.path()),
+                ))
+            })
+    }
+}
+
+impl CameraDevice for MockCamera {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn capabilities(&self) -> CameraCapabilities {
+        CameraCapabilities::ICCameraDeviceCanTakePicture
+            | CameraCapabilities::ICCameraDeviceCanTakePictureUsingShutterReleaseOnCamera
+            | CameraCapabilities::ICCameraDeviceCanDeleteOneFile
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        lock(&self.state).battery_level
+    }
+
+    fn free_space(&self) -> Option<u64> {
+        lock(&self.state).free_space
+    }
+
+    fn contents(&self) -> Result<Vec<CameraItem>> {
+        let state = self.connected()?;
+        let files = state
+            .files
+            .values()
+            .map(|(file, _)| CameraItem::File(file.clone()))
+            .collect();
+        let dcf = CameraFolder {
+            name: DCF_FOLDER_NAME.to_string(),
+            parent_folder: Some(format!("{}/DCIM", STORAGE_NAME)),
+            contents: files,
+            ..CameraFolder::default()
+        };
+        let dcim = CameraFolder {
+            name: "DCIM".to_string(),
+            parent_folder: Some(STORAGE_NAME.to_string()),
+            contents: vec![CameraItem::Folder(dcf)],
+            ..CameraFolder::default()
+        };
+        Ok(vec![CameraItem::Folder(CameraFolder {
+            name: STORAGE_NAME.to_string(),
+            contents: vec![CameraItem::Folder(dcim)],
+            ..CameraFolder::default()
+        })])
+    }
+
+    fn download_file(&self, file: &CameraFile, writer: &mut dyn Write) -> Result<u64> {
+        let data = self.data(file)?;
+        let delay = *lock(&self.download_delay);
+        if delay > Duration::from_secs(0) {
+            thread::sleep(delay);
+        }
+        writer.write_all(&data)?;
+        Ok(data.len() as u64)
+    }
+
+    fn read_data_from_file(&self, file: &CameraFile, offset: u64, length: u64) -> Result<Vec<u8>> {
+        let data = self.data(file)?;
+        let start = (offset as usize).min(data.len());
+        let end = (offset.saturating_add(length) as usize).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
+    fn delete_files(&self, files: &[CameraFile]) -> Result<()> {
+        let mut state = self.connected()?;
+        for file in files {
+            if let Some((file, data)) = state.files.remove(&file.name) {
+                state.free_space = state.free_space.map(|free| free + data.len() as u64);
+                state
+                    .events
+                    .push_back(CameraEvent::ItemsRemoved(vec![file]));
+            }
+        }
+        Ok(())
+    }
+
+    fn property(&self, code: DevicePropCode) -> Result<Property> {
+        self.connected()?
+            .properties
+            .get(&code.0)
+            .cloned()
+            .map(Property::new)
+            .ok_or_else(|| Error::Unsupported(format!("property {}", code)))
+    }
+
+    fn set_property_value(&self, code: DevicePropCode, value: &PropertyValue) -> Result<()> {
+        let raw = self.property(code)?.validate(value)?;
+        let mut state = self.connected()?;
+        if let Some(desc) = state.properties.get_mut(&code.0) {
+            desc.current = raw;
+        }
+        state.events.push_back(CameraEvent::PropertyChanged(code.0));
+        self.event_added.notify_all();
+        Ok(())
+    }
+
+    fn take_picture(&self) -> Result<()> {
+        self.capture()
+    }
+
+    fn enable_tethering(&self) -> Result<()> {
+        self.connected()?.tethering = true;
+        Ok(())
+    }
+
+    fn disable_tethering(&self) -> Result<()> {
+        self.connected()?.tethering = false;
+        Ok(())
+    }
+
+    fn next_event(&self, timeout: Duration) -> Result<Option<CameraEvent>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = lock(&self.state);
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                return Ok(Some(event));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            state = self
+                .event_added
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+}
+
+fn make_file(name: &str, data: &[u8], is_raw: bool) -> CameraFile {
+    let now = SystemTime::now();
+    CameraFile {
+        name: name.to_string(),
+        parent_folder: Some(format!("{}/DCIM/{}", STORAGE_NAME, DCF_FOLDER_NAME)),
+        uti: crate::camera::file_type(name).0,
+        file_size: data.len() as u64,
+        creation_date: Some(now),
+        modification_date: Some(now),
+        is_raw,
+        ..CameraFile::default()
+    }
+}
+
+fn lock<V>(mutex: &Mutex<V>) -> MutexGuard<'_, V> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
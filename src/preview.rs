@@ -0,0 +1,954 @@
+//! Embedded thumbnails and previews, for browsing files before importing them.
+//!
+//! `ICCameraItem::thumbnailIfAvailable` and `largeThumbnailIfAvailable` are only available
+//! on macOS and return decoded images. `thumbnail` and `large_preview` instead return the
+//! encoded images that cameras embed in their files: the EXIF thumbnail of JPEG files, the
+//! JPEG previews of TIFF-based RAW files such as CR2, NEF, ARW and DNG and of RAF files, and
+//! the thumbnail items of HEIF files. Movies have no embedded image, but `poster_frame`
+//! locates the video sample to decode for one.
+//!
+//! Like `metadata`, everything is read through `Read` + `Seek`, so only the chosen image and
+//! the headers leading to it are fetched from a camera with `CameraFileReader`.
+
+use crate::bmff::{self, BoxHeader, HeifMeta};
+use crate::error::{Error, Result};
+use crate::jpeg;
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::time::Duration;
+
+/// Largest embedded image read, guarding against corrupt offsets.
+const MAX_IMAGE_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Maximum number of TIFF directories followed, guarding against offset loops.
+const MAX_DIRECTORIES: usize = 32;
+
+/// TIFF tags locating embedded images.
+const COMPRESSION: u16 = 0x0103;
+const STRIP_OFFSETS: u16 = 0x0111;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const SUB_IFDS: u16 = 0x014A;
+const JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+const JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+
+/// Values of `Compression` for JPEG data.
+const OLD_JPEG: u32 = 6;
+const JPEG: u32 = 7;
+
+/// Signature of Fujifilm RAF files, followed by the offset and length of the JPEG preview
+/// at offset 84.
+const RAF_SIGNATURE: &[u8] = b"FUJIFILMCCD-RAW ";
+
+/// How an embedded image is encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PreviewFormat {
+    Jpeg,
+    /// An HEVC bitstream, decoded with the configuration in `Preview::codec_config`.
+    Hevc,
+    /// An AV1 bitstream, decoded with the configuration in `Preview::codec_config`.
+    Av1,
+}
+
+/// An encoded image embedded in a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Preview {
+    pub format: PreviewFormat,
+    pub data: Vec<u8>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// The body of the `hvcC` or `av1C` box needed to decode HEVC and AV1 images.
+    pub codec_config: Option<Vec<u8>>,
+}
+
+/// The sample of a movie's video track to decode for its poster frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PosterFrame {
+    /// The poster time of the movie, 0 unless a QuickTime movie sets one.
+    pub time: Duration,
+    pub track_id: u32,
+    /// The sample entry type of the video track, such as `avc1` or `hvc1`.
+    pub codec: [u8; 4],
+    /// The body of the decoder configuration box of the sample entry, such as `avcC`.
+    pub codec_config: Option<Vec<u8>>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Number of the sample, from 1: the sync sample at or before the poster time.
+    pub sample_number: u32,
+    /// Offsets of the sample in the file.
+    pub sample: Range<u64>,
+}
+
+/// An embedded image found, before its data is read.
+struct Location {
+    format: PreviewFormat,
+    extents: Vec<Range<u64>>,
+    width: Option<u32>,
+    height: Option<u32>,
+    codec_config: Option<Vec<u8>>,
+}
+
+impl Location {
+    fn pixels(&self) -> u64 {
+        u64::from(self.width.unwrap_or(0)) * u64::from(self.height.unwrap_or(0))
+    }
+
+    fn read<R: Read + Seek>(self, reader: &mut R) -> Result<Preview> {
+        let size: u64 = self
+            .extents
+            .iter()
+            .map(|extent| extent.end - extent.start)
+            .sum();
+        if size > MAX_IMAGE_SIZE {
+            return Err(Error::malformed("the embedded image is too large to read"));
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        for extent in &self.extents {
+            let part = bmff::read_at(reader, extent.start, extent.end - extent.start)?;
+            if (part.len() as u64) < extent.end - extent.start {
+                return Err(Error::malformed("truncated embedded image"));
+            }
+            data.extend(part);
+        }
+        Ok(Preview {
+            format: self.format,
+            data,
+            width: self.width,
+            height: self.height,
+            codec_config: self.codec_config,
+        })
+    }
+}
+
+/// Every embedded image of a JPEG, TIFF-based, RAF or HEIF file, smallest first.
+pub fn previews<R: Read + Seek>(reader: &mut R) -> Result<Vec<Preview>> {
+    locate(reader)?
+        .into_iter()
+        .map(|location| location.read(reader))
+        .collect()
+}
+
+/// The smallest embedded image, like `thumbnailIfAvailable`.
+pub fn thumbnail<R: Read + Seek>(reader: &mut R) -> Result<Option<Preview>> {
+    match locate(reader)?.into_iter().next() {
+        Some(location) => location.read(reader).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// The largest embedded image, like `largeThumbnailIfAvailable`.
+pub fn large_preview<R: Read + Seek>(reader: &mut R) -> Result<Option<Preview>> {
+    match locate(reader)?.into_iter().last() {
+        Some(location) => location.read(reader).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// The embedded images, smallest first.
+fn locate<R: Read + Seek>(reader: &mut R) -> Result<Vec<Location>> {
+    let head = bmff::read_at(reader, 0, 92)?;
+    let mut locations = if head.starts_with(&[0xFF, jpeg::SOI]) {
+        jpeg_thumbnail(reader)?.into_iter().collect()
+    } else if head.starts_with(b"II") || head.starts_with(b"MM") {
+        tiff_images(reader, 0)?
+    } else if head.starts_with(RAF_SIGNATURE) {
+        raf_images(reader, &head)?
+    } else if let Some(brands) = bmff::brands(reader)? {
+        if !brands
+            .iter()
+            .any(|brand| bmff::HEIF_BRANDS.contains(&brand))
+        {
+            // Movies and other ISO base media files embed no image; see `poster_frame`.
+            return Ok(Vec::new());
+        }
+        heif_thumbnails(reader)?
+    } else {
+        return Err(Error::malformed("not a JPEG, TIFF-based, RAF or HEIF file"));
+    };
+    locations.sort_by_key(Location::pixels);
+    Ok(locations)
+}
+
+/// The EXIF thumbnail of a JPEG file.
+fn jpeg_thumbnail<R: Read + Seek>(reader: &mut R) -> Result<Option<Location>> {
+    let mut offset = 2;
+    loop {
+        let marker = bmff::read_at(reader, offset, 10)?;
+        if marker.len() < 4 || marker[0] != 0xFF {
+            return Ok(None);
+        }
+        if marker[1] == 0xFF {
+            offset += 1;
+            continue;
+        }
+        if marker[1] == jpeg::SOS || marker[1] == jpeg::EOI {
+            return Ok(None);
+        }
+        let length = u64::from(u16::from_be_bytes([marker[2], marker[3]]));
+        if marker[1] == jpeg::APP1 && marker[4..].starts_with(b"Exif\0\0") {
+            let tiff = offset + 10;
+            let end = offset + 2 + length;
+            let images = tiff_images(reader, tiff)?;
+            return Ok(images
+                .into_iter()
+                .find(|image| image.extents.iter().all(|extent| extent.end <= end)));
+        }
+        offset += 2 + length;
+    }
+}
+
+/// The JPEG images of the TIFF structure at `base`: those of the directory chain, of its
+/// sub-directories, and of the EXIF thumbnail. Lossless JPEG, used for RAW data, is left out.
+fn tiff_images<R: Read + Seek>(reader: &mut R, base: u64) -> Result<Vec<Location>> {
+    let header = bmff::read_at(reader, base, 8)?;
+    let tiff = TiffWalker {
+        base,
+        big_endian: match header.get(..2) {
+            Some(b"II") => false,
+            Some(b"MM") => true,
+            _ => return Err(Error::malformed("missing TIFF byte order mark")),
+        },
+    };
+    let first = tiff.u32_in(&header, 4).unwrap_or(0);
+    let mut queue = vec![first];
+    let mut visited = Vec::new();
+    let mut locations: Vec<Location> = Vec::new();
+    while let Some(offset) = queue.pop() {
+        if offset == 0 || visited.contains(&offset) || visited.len() >= MAX_DIRECTORIES {
+            continue;
+        }
+        visited.push(offset);
+        let directory = match tiff.read_directory(reader, offset)? {
+            Some(directory) => directory,
+            None => continue,
+        };
+        queue.extend(directory.next);
+        queue.extend(tiff.values(reader, &directory, SUB_IFDS)?);
+        let interchange = (
+            tiff.value(reader, &directory, JPEG_INTERCHANGE_FORMAT)?,
+            tiff.value(reader, &directory, JPEG_INTERCHANGE_FORMAT_LENGTH)?,
+        );
+        let compression = tiff.value(reader, &directory, COMPRESSION)?;
+        let strips = (
+            tiff.values(reader, &directory, STRIP_OFFSETS)?,
+            tiff.values(reader, &directory, STRIP_BYTE_COUNTS)?,
+        );
+        let range = match (interchange, compression, strips) {
+            ((Some(start), Some(length)), _, _) => Some((start, length)),
+            (_, Some(OLD_JPEG) | Some(JPEG), (offsets, counts))
+                if offsets.len() == 1 && counts.len() == 1 =>
+            {
+                Some((offsets[0], counts[0]))
+            }
+            _ => None,
+        };
+        let range = match range {
+            Some((start, length)) if length > 0 => {
+                let start = base + u64::from(start);
+                start..start + u64::from(length)
+            }
+            _ => continue,
+        };
+        if locations
+            .iter()
+            .any(|location| location.extents[0].start == range.start)
+        {
+            continue;
+        }
+        // Baseline, extended and progressive DCT images only, leaving out the lossless
+        // JPEG of RAW data, as in the last directory of CR2 files and in DNG files.
+        if let Some((0xC0..=0xC2, width, height)) = jpeg_frame(reader, range.clone())? {
+            locations.push(Location {
+                format: PreviewFormat::Jpeg,
+                extents: vec![range],
+                width: Some(width),
+                height: Some(height),
+                codec_config: None,
+            });
+        }
+    }
+    Ok(locations)
+}
+
+/// The JPEG preview of a RAF file and the EXIF thumbnail inside it.
+fn raf_images<R: Read + Seek>(reader: &mut R, head: &[u8]) -> Result<Vec<Location>> {
+    let (offset, length) = match (bmff::u32_at(head, 84), bmff::u32_at(head, 88)) {
+        (Some(offset), Some(length)) if length > 0 => (u64::from(offset), u64::from(length)),
+        _ => return Err(Error::malformed("truncated RAF header")),
+    };
+    let range = offset..offset + length;
+    let mut locations = Vec::new();
+    if let Some((_, width, height)) = jpeg_frame(reader, range.clone())? {
+        locations.push(Location {
+            format: PreviewFormat::Jpeg,
+            extents: vec![range.clone()],
+            width: Some(width),
+            height: Some(height),
+            codec_config: None,
+        });
+    }
+    let mut preview = Window {
+        reader,
+        range: range.clone(),
+        position: 0,
+    };
+    if let Some(mut thumbnail) = jpeg_thumbnail(&mut preview)? {
+        for extent in &mut thumbnail.extents {
+            *extent = range.start + extent.start..range.start + extent.end;
+        }
+        locations.push(thumbnail);
+    }
+    Ok(locations)
+}
+
+/// The thumbnail items of the primary image of a HEIF file.
+fn heif_thumbnails<R: Read + Seek>(reader: &mut R) -> Result<Vec<Location>> {
+    let heif = HeifMeta::read(reader)?;
+    let primary = match heif.primary {
+        Some(primary) => primary,
+        None => return Ok(Vec::new()),
+    };
+    let mut locations = Vec::new();
+    for item in heif.referencing(b"thmb", primary) {
+        let (format, config) = match &item.kind {
+            b"hvc1" => (PreviewFormat::Hevc, Some(b"hvcC")),
+            b"av01" => (PreviewFormat::Av1, Some(b"av1C")),
+            b"jpeg" => (PreviewFormat::Jpeg, None),
+            _ => continue,
+        };
+        let codec_config = match config.and_then(|kind| heif.property(item, kind)) {
+            Some(property) => Some(bmff::read_body(reader, property)?),
+            None => None,
+        };
+        let dimensions = heif.dimensions(reader, item)?;
+        locations.push(Location {
+            format,
+            extents: item.extents.clone(),
+            width: dimensions.map(|(width, _)| width),
+            height: dimensions.map(|(_, height)| height),
+            codec_config,
+        });
+    }
+    Ok(locations)
+}
+
+/// Locate the poster frame of an MP4 or QuickTime movie: the sync sample of the first video
+/// track at or before the movie's poster time. Edit lists are not applied.
+pub fn poster_frame<R: Read + Seek>(reader: &mut R) -> Result<Option<PosterFrame>> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    let moov = match bmff::find(reader, 0..end, b"moov")? {
+        Some(moov) => moov,
+        None => return Err(Error::malformed("the movie has no moov box")),
+    };
+    let mvhd = bmff::find(reader, moov.body(), b"mvhd")?
+        .ok_or_else(|| Error::malformed("the movie has no mvhd box"))?;
+    let mvhd = bmff::read_body(reader, &mvhd)?;
+    let (timescale, poster) = if mvhd.first() == Some(&1) {
+        (bmff::u32_at(&mvhd, 20), bmff::u32_at(&mvhd, 92))
+    } else {
+        (bmff::u32_at(&mvhd, 12), bmff::u32_at(&mvhd, 80))
+    };
+    let timescale = timescale.filter(|&scale| scale > 0).unwrap_or(1);
+    let poster = poster.unwrap_or(0);
+    let time = Duration::from_secs_f64(f64::from(poster) / f64::from(timescale));
+
+    for trak in bmff::children(reader, moov.body())? {
+        if !trak.is(b"trak") {
+            continue;
+        }
+        let handler = match bmff::find_path(reader, trak.body(), &[b"mdia", b"hdlr"])? {
+            Some(hdlr) => bmff::read_body(reader, &hdlr)?,
+            None => continue,
+        };
+        if handler.get(8..12) != Some(b"vide") {
+            continue;
+        }
+        return video_poster_frame(reader, &trak, time).map(Some);
+    }
+    Ok(None)
+}
+
+fn video_poster_frame<R: Read + Seek>(
+    reader: &mut R,
+    trak: &BoxHeader,
+    time: Duration,
+) -> Result<PosterFrame> {
+    let missing = |name: &str| Error::malformed(format!("the video track has no {} box", name));
+    let tkhd = bmff::find(reader, trak.body(), b"tkhd")?.ok_or_else(|| missing("tkhd"))?;
+    let tkhd = bmff::read_body(reader, &tkhd)?;
+    let track_id = bmff::u32_at(&tkhd, if tkhd.first() == Some(&1) { 20 } else { 12 }).unwrap_or(0);
+    // Width and height end the box, as 16.16 fixed-point numbers.
+    let size = |from_end: usize| {
+        tkhd.len()
+            .checked_sub(from_end)
+            .and_then(|offset| bmff::u32_at(&tkhd, offset))
+            .map(|value| value >> 16)
+            .filter(|&value| value > 0)
+    };
+    let (width, height) = (size(8), size(4));
+
+    let mdhd = bmff::find_path(reader, trak.body(), &[b"mdia", b"mdhd"])?
+        .ok_or_else(|| missing("mdhd"))?;
+    let mdhd = bmff::read_body(reader, &mdhd)?;
+    let timescale = bmff::u32_at(&mdhd, if mdhd.first() == Some(&1) { 20 } else { 12 })
+        .filter(|&scale| scale > 0)
+        .unwrap_or(1);
+    let media_time = (time.as_secs_f64() * f64::from(timescale)) as u64;
+
+    let stbl = bmff::find_path(reader, trak.body(), &[b"mdia", b"minf", b"stbl"])?
+        .ok_or_else(|| missing("stbl"))?;
+    let mut tables = SampleTables::default();
+    for table in bmff::children(reader, stbl.body())? {
+        match &table.kind {
+            b"stsd" | b"stts" | b"stss" | b"stsc" | b"stsz" | b"stco" | b"co64" => {
+                tables
+                    .boxes
+                    .push((table.kind, bmff::read_body(reader, &table)?));
+            }
+            _ => {}
+        }
+    }
+    let stsd = tables.get(b"stsd").ok_or_else(|| missing("stsd"))?;
+    let entry = stsd.get(8..).unwrap_or_default();
+    let codec = match entry.get(4..8) {
+        Some(codec) => [codec[0], codec[1], codec[2], codec[3]],
+        None => return Err(Error::malformed("the video track has no sample entry")),
+    };
+    let entry_size = bmff::u32_at(entry, 0).unwrap_or(0) as usize;
+    let codec_config = codec_config(entry.get(..entry_size.min(entry.len())).unwrap_or_default());
+
+    let sample_number = tables.sync_sample_at(media_time)?;
+    let sample = tables.sample_range(sample_number)?;
+    Ok(PosterFrame {
+        time,
+        track_id,
+        codec,
+        codec_config,
+        width,
+        height,
+        sample_number,
+        sample,
+    })
+}
+
+/// The body of the decoder configuration box among the children of a visual sample entry.
+fn codec_config(entry: &[u8]) -> Option<Vec<u8>> {
+    // The children follow the 86 bytes of the entry's header and fixed fields.
+    let mut offset = 86;
+    while let Some(size) = bmff::u32_at(entry, offset) {
+        let size = size as usize;
+        let end = offset
+            .checked_add(size)
+            .filter(|&end| size >= 8 && end <= entry.len())?;
+        if matches!(
+            &entry[offset + 4..offset + 8],
+            b"avcC" | b"hvcC" | b"av1C" | b"vpcC"
+        ) {
+            return Some(entry[offset + 8..end].to_vec());
+        }
+        offset = end;
+    }
+    None
+}
+
+/// The sample table boxes of a track, by type.
+#[derive(Default)]
+struct SampleTables {
+    boxes: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl SampleTables {
+    fn get(&self, kind: &[u8; 4]) -> Option<&[u8]> {
+        self.boxes
+            .iter()
+            .find(|(table, _)| table == kind)
+            .map(|(_, body)| body.as_slice())
+    }
+
+    /// The entries of a table whose entry count follows the version and flags.
+    fn entries(&self, kind: &[u8; 4], entry_size: usize) -> Option<Vec<&[u8]>> {
+        let body = self.get(kind)?;
+        let count = bmff::u32_at(body, 4)? as usize;
+        let entries = body
+            .get(8..)?
+            .chunks_exact(entry_size)
+            .take(count)
+            .collect();
+        Some(entries)
+    }
+
+    /// The sync sample at or before `media_time`, from 1.
+    fn sync_sample_at(&self, media_time: u64) -> Result<u32> {
+        let mut sample = 1u64;
+        let mut elapsed = 0u64;
+        for entry in self.entries(b"stts", 8).unwrap_or_default() {
+            let count = u64::from(bmff::u32_at(entry, 0).unwrap_or(0));
+            let delta = u64::from(bmff::u32_at(entry, 4).unwrap_or(0));
+            if delta > 0 && elapsed + count * delta > media_time {
+                sample += (media_time - elapsed) / delta;
+                break;
+            }
+            elapsed += count * delta;
+            sample += count;
+        }
+        let sample = u32::try_from(sample).unwrap_or(u32::MAX);
+        let sample = sample.min(self.sample_count().max(1));
+        // Without a sync sample table, every sample is a sync sample.
+        Ok(match self.entries(b"stss", 4) {
+            Some(sync) => sync
+                .iter()
+                .filter_map(|entry| bmff::u32_at(entry, 0))
+                .take_while(|&number| number <= sample)
+                .last()
+                .unwrap_or(1),
+            None => sample,
+        })
+    }
+
+    fn sample_count(&self) -> u32 {
+        self.get(b"stsz")
+            .and_then(|stsz| bmff::u32_at(stsz, 8))
+            .unwrap_or(0)
+    }
+
+    fn sample_size(&self, number: u32) -> Option<u64> {
+        let stsz = self.get(b"stsz")?;
+        match bmff::u32_at(stsz, 4)? {
+            0 => bmff::u32_at(stsz, 12 + (number as usize - 1) * 4).map(u64::from),
+            size => Some(u64::from(size)),
+        }
+    }
+
+    /// Offsets of sample `number` in the file, from the chunk holding it.
+    fn sample_range(&self, number: u32) -> Result<Range<u64>> {
+        let malformed = || Error::malformed("inconsistent sample tables");
+        let chunk_offsets: Vec<u64> = match self.entries(b"stco", 4) {
+            Some(entries) => entries
+                .iter()
+                .filter_map(|entry| bmff::u32_at(entry, 0).map(u64::from))
+                .collect(),
+            None => self
+                .entries(b"co64", 8)
+                .ok_or_else(malformed)?
+                .iter()
+                .filter_map(|entry| bmff::u64_at(entry, 0))
+                .collect(),
+        };
+        let runs = self.entries(b"stsc", 12).ok_or_else(malformed)?;
+        // Walk the runs of chunks with the same number of samples to the one holding it.
+        let mut first_sample = 1u32;
+        for (index, run) in runs.iter().enumerate() {
+            let first_chunk = bmff::u32_at(run, 0).ok_or_else(malformed)?;
+            let per_chunk = bmff::u32_at(run, 4).ok_or_else(malformed)?;
+            let last_chunk = match runs.get(index + 1) {
+                Some(next) => bmff::u32_at(next, 0).ok_or_else(malformed)?,
+                None => chunk_offsets.len() as u32 + 1,
+            };
+            let run_samples = last_chunk
+                .saturating_sub(first_chunk)
+                .saturating_mul(per_chunk);
+            if per_chunk > 0 && number < first_sample.saturating_add(run_samples) {
+                let chunk = first_chunk + (number - first_sample) / per_chunk;
+                let first_in_chunk = number - (number - first_sample) % per_chunk;
+                let mut offset = *chunk_offsets
+                    .get(chunk as usize - 1)
+                    .ok_or_else(malformed)?;
+                for previous in first_in_chunk..number {
+                    offset += self.sample_size(previous).ok_or_else(malformed)?;
+                }
+                let size = self.sample_size(number).ok_or_else(malformed)?;
+                return Ok(offset..offset + size);
+            }
+            first_sample = first_sample.saturating_add(run_samples);
+        }
+        Err(malformed())
+    }
+}
+
+/// The frame marker, width and height of the JPEG image in `range`, walking its segments.
+fn jpeg_frame<R: Read + Seek>(reader: &mut R, range: Range<u64>) -> Result<Option<(u8, u32, u32)>> {
+    if bmff::read_at(reader, range.start, 2)? != [0xFF, jpeg::SOI] {
+        return Ok(None);
+    }
+    let mut offset = range.start + 2;
+    while offset + 9 <= range.end {
+        let segment = bmff::read_at(reader, offset, 9)?;
+        if segment.len() < 9 || segment[0] != 0xFF {
+            return Ok(None);
+        }
+        let marker = segment[1];
+        if marker == 0xFF {
+            offset += 1;
+            continue;
+        }
+        if marker == jpeg::SOS || marker == jpeg::EOI {
+            return Ok(None);
+        }
+        if matches!(marker, 0xC0..=0xCF) && !matches!(marker, jpeg::DHT | 0xC8 | 0xCC) {
+            let height = u16::from_be_bytes([segment[5], segment[6]]);
+            let width = u16::from_be_bytes([segment[7], segment[8]]);
+            return Ok(Some((marker, u32::from(width), u32::from(height))));
+        }
+        offset += 2 + u64::from(u16::from_be_bytes([segment[2], segment[3]]));
+    }
+    Ok(None)
+}
+
+/// A directory of a TIFF structure, its entries unparsed.
+struct Directory {
+    entries: Vec<u8>,
+    next: Option<u32>,
+}
+
+/// Reads the directories of a TIFF structure at `base` through a reader.
+struct TiffWalker {
+    base: u64,
+    big_endian: bool,
+}
+
+impl TiffWalker {
+    fn u16_in(&self, data: &[u8], offset: usize) -> Option<u16> {
+        let b = data.get(offset..offset + 2)?;
+        Some(if self.big_endian {
+            u16::from_be_bytes([b[0], b[1]])
+        } else {
+            u16::from_le_bytes([b[0], b[1]])
+        })
+    }
+
+    fn u32_in(&self, data: &[u8], offset: usize) -> Option<u32> {
+        let b = data.get(offset..offset + 4)?;
+        Some(if self.big_endian {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        })
+    }
+
+    fn read_directory<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        offset: u32,
+    ) -> Result<Option<Directory>> {
+        let start = self.base + u64::from(offset);
+        let count = match self.u16_in(&bmff::read_at(reader, start, 2)?, 0) {
+            Some(count) => u64::from(count),
+            None => return Ok(None),
+        };
+        let data = bmff::read_at(reader, start + 2, count * 12 + 4)?;
+        let entries_size = count as usize * 12;
+        if data.len() < entries_size {
+            return Ok(None);
+        }
+        let next = self.u32_in(&data, entries_size).filter(|&next| next != 0);
+        Ok(Some(Directory {
+            entries: data[..entries_size].to_vec(),
+            next,
+        }))
+    }
+
+    /// The SHORT or LONG values of `tag` in `directory`.
+    fn values<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        directory: &Directory,
+        tag: u16,
+    ) -> Result<Vec<u32>> {
+        let entry = match directory
+            .entries
+            .chunks_exact(12)
+            .find(|entry| self.u16_in(entry, 0) == Some(tag))
+        {
+            Some(entry) => entry,
+            None => return Ok(Vec::new()),
+        };
+        let field_type = self.u16_in(entry, 2).unwrap_or(0);
+        let count = self.u32_in(entry, 4).unwrap_or(0).min(4096) as usize;
+        // Sub-directory pointers may also have the IFD type, 13, read like LONG.
+        let size = match field_type {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Ok(Vec::new()),
+        };
+        let data = if count * size <= 4 {
+            entry[8..12].to_vec()
+        } else {
+            let offset = self.base + u64::from(self.u32_in(entry, 8).unwrap_or(0));
+            bmff::read_at(reader, offset, (count * size) as u64)?
+        };
+        Ok((0..count)
+            .filter_map(|index| match size {
+                2 => self.u16_in(&data, index * 2).map(u32::from),
+                _ => self.u32_in(&data, index * 4),
+            })
+            .collect())
+    }
+
+    fn value<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        directory: &Directory,
+        tag: u16,
+    ) -> Result<Option<u32>> {
+        Ok(self.values(reader, directory, tag)?.first().copied())
+    }
+}
+
+/// A view of `range` of the data of another reader, as if it were a whole file.
+struct Window<'a, R> {
+    reader: &'a mut R,
+    range: Range<u64>,
+    position: u64,
+}
+
+impl<'a, R: Read + Seek> Read for Window<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = (self.range.end - self.range.start).saturating_sub(self.position);
+        let count = (buf.len() as u64).min(remaining) as usize;
+        self.reader
+            .seek(SeekFrom::Start(self.range.start + self.position))?;
+        let read = self.reader.read(&mut buf[..count])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<'a, R: Read + Seek> Seek for Window<'a, R> {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let length = self.range.end - self.range.start;
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid seek"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        exif_segment, full_box, jpeg, long, mp4_box, short, tiff_header, write_at, write_ifd,
+    };
+    use std::io::Cursor;
+
+    /// EXIF data whose second directory points to the JPEG thumbnail `thumbnail`.
+    fn exif_with_thumbnail(thumbnail: &[u8]) -> Vec<u8> {
+        let mut data = tiff_header(8);
+        write_ifd(&mut data, 8, vec![short(0x0112, &[1])], 0x40);
+        write_ifd(
+            &mut data,
+            0x40,
+            vec![
+                long(JPEG_INTERCHANGE_FORMAT, &[0x80]),
+                long(JPEG_INTERCHANGE_FORMAT_LENGTH, &[thumbnail.len() as u32]),
+            ],
+            0,
+        );
+        write_at(&mut data, 0x80, thumbnail);
+        data
+    }
+
+    #[test]
+    fn jpeg_files_have_their_exif_thumbnail() {
+        let thumbnail = jpeg(160, 120, &[]);
+        let data = jpeg(6000, 4000, &exif_segment(&exif_with_thumbnail(&thumbnail)));
+        let found = previews(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(
+            found,
+            vec![Preview {
+                format: PreviewFormat::Jpeg,
+                data: thumbnail,
+                width: Some(160),
+                height: Some(120),
+                codec_config: None,
+            }]
+        );
+        assert!(previews(&mut Cursor::new(jpeg(640, 480, &[])))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn tiff_files_have_their_jpeg_images_but_not_the_lossless_raw_data() {
+        let preview = jpeg(5472, 3648, &[]);
+        let exif_thumbnail = jpeg(160, 120, &[]);
+        let mut raw = jpeg(5472, 3648, &[]);
+        // Lossless JPEG, as used for the RAW data of CR2 files.
+        raw[3] = 0xC3;
+        let mut data = tiff_header(8);
+        write_ifd(
+            &mut data,
+            8,
+            vec![
+                short(COMPRESSION, &[OLD_JPEG as u16]),
+                long(STRIP_OFFSETS, &[0x200]),
+                long(STRIP_BYTE_COUNTS, &[preview.len() as u32]),
+                long(SUB_IFDS, &[0xC0]),
+            ],
+            0x80,
+        );
+        write_ifd(
+            &mut data,
+            0x80,
+            vec![
+                long(JPEG_INTERCHANGE_FORMAT, &[0x400]),
+                long(
+                    JPEG_INTERCHANGE_FORMAT_LENGTH,
+                    &[exif_thumbnail.len() as u32],
+                ),
+            ],
+            0,
+        );
+        write_ifd(
+            &mut data,
+            0xC0,
+            vec![
+                short(COMPRESSION, &[OLD_JPEG as u16]),
+                long(STRIP_OFFSETS, &[0x800]),
+                long(STRIP_BYTE_COUNTS, &[raw.len() as u32]),
+            ],
+            0,
+        );
+        write_at(&mut data, 0x200, &preview);
+        write_at(&mut data, 0x400, &exif_thumbnail);
+        write_at(&mut data, 0x800, &raw);
+
+        let found = previews(&mut Cursor::new(&data)).unwrap();
+        let sizes: Vec<_> = found
+            .iter()
+            .map(|preview| (preview.width, preview.height))
+            .collect();
+        assert_eq!(
+            sizes,
+            vec![(Some(160), Some(120)), (Some(5472), Some(3648))]
+        );
+        let small = thumbnail(&mut Cursor::new(&data)).unwrap().unwrap();
+        assert_eq!(small.data, exif_thumbnail);
+        let large = large_preview(&mut Cursor::new(&data)).unwrap().unwrap();
+        assert_eq!(large.data, preview);
+    }
+
+    #[test]
+    fn raf_files_have_their_preview_and_its_thumbnail() {
+        let thumbnail = jpeg(160, 120, &[]);
+        let preview = jpeg(6000, 4000, &exif_segment(&exif_with_thumbnail(&thumbnail)));
+        let mut data = RAF_SIGNATURE.to_vec();
+        write_at(&mut data, 84, &100u32.to_be_bytes());
+        write_at(&mut data, 88, &(preview.len() as u32).to_be_bytes());
+        write_at(&mut data, 100, &preview);
+
+        let found = previews(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].data, thumbnail);
+        assert_eq!((found[0].width, found[0].height), (Some(160), Some(120)));
+        assert_eq!(found[1].data, preview);
+        assert_eq!((found[1].width, found[1].height), (Some(6000), Some(4000)));
+
+        data.truncate(88);
+        assert!(matches!(
+            previews(&mut Cursor::new(&data)),
+            Err(Error::Malformed(_))
+        ));
+    }
+
+    /// A movie with a sound track and then a video track of ten one-second samples, sync
+    /// samples 1, 3 and 6, in chunks of four, four and two samples.
+    /// The offsets written in full boxes leave out their version and flags.
+    fn movie(poster_ms: u32) -> Vec<u8> {
+        let mut mvhd = vec![0; 92];
+        write_at(&mut mvhd, 8, &1000u32.to_be_bytes());
+        write_at(&mut mvhd, 76, &poster_ms.to_be_bytes());
+        let track = |id: u32, handler: &[u8; 4], stbl: &[u8]| {
+            let mut tkhd = vec![0; 80];
+            write_at(&mut tkhd, 8, &id.to_be_bytes());
+            write_at(&mut tkhd, 72, &(1920u32 << 16).to_be_bytes());
+            write_at(&mut tkhd, 76, &(1080u32 << 16).to_be_bytes());
+            let mut mdhd = vec![0; 20];
+            write_at(&mut mdhd, 8, &10u32.to_be_bytes());
+            let mut hdlr = vec![0; 20];
+            write_at(&mut hdlr, 4, handler);
+            let minf = mp4_box(b"minf", &mp4_box(b"stbl", stbl));
+            let mdia = [
+                full_box(b"mdhd", 0, &mdhd),
+                full_box(b"hdlr", 0, &hdlr),
+                minf,
+            ]
+            .concat();
+            mp4_box(
+                b"trak",
+                &[full_box(b"tkhd", 0, &tkhd), mp4_box(b"mdia", &mdia)].concat(),
+            )
+        };
+        let table = |kind: &[u8; 4], values: &[u32]| {
+            let body: Vec<u8> = values
+                .iter()
+                .flat_map(|value| value.to_be_bytes())
+                .collect();
+            full_box(kind, 0, &body)
+        };
+        let mut entry = vec![0; 86];
+        write_at(&mut entry, 4, b"avc1");
+        entry.extend(mp4_box(b"avcC", b"config"));
+        let size = entry.len() as u32;
+        write_at(&mut entry, 0, &size.to_be_bytes());
+        let stbl = [
+            full_box(b"stsd", 0, &[&1u32.to_be_bytes()[..], &entry].concat()),
+            table(b"stts", &[1, 10, 10]),
+            table(b"stss", &[3, 1, 3, 6]),
+            table(b"stsc", &[2, 1, 4, 1, 3, 2, 1]),
+            table(
+                b"stsz",
+                &[0, 10, 100, 110, 120, 130, 140, 150, 160, 170, 180, 190],
+            ),
+            table(b"stco", &[3, 1000, 2000, 3000]),
+        ]
+        .concat();
+        let moov = [
+            full_box(b"mvhd", 0, &mvhd),
+            track(1, b"soun", &[]),
+            track(2, b"vide", &stbl),
+        ]
+        .concat();
+        let mut ftyp = b"isom".to_vec();
+        ftyp.extend_from_slice(&[0, 0, 0, 0]);
+        ftyp.extend_from_slice(b"isomavc1");
+        [mp4_box(b"ftyp", &ftyp), mp4_box(b"moov", &moov)].concat()
+    }
+
+    #[test]
+    fn poster_frame_is_the_sync_sample_at_or_before_the_poster_time() {
+        let frame = poster_frame(&mut Cursor::new(movie(4500)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            frame,
+            PosterFrame {
+                time: Duration::from_millis(4500),
+                track_id: 2,
+                codec: *b"avc1",
+                codec_config: Some(b"config".to_vec()),
+                width: Some(1920),
+                height: Some(1080),
+                sample_number: 3,
+                sample: 1210..1330,
+            }
+        );
+        // Sample 9 is in the third chunk, and the sync sample before it in the second.
+        let frame = poster_frame(&mut Cursor::new(movie(8500)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.sample_number, 6);
+        assert_eq!(frame.sample, 2140..2290);
+        let frame = poster_frame(&mut Cursor::new(movie(0))).unwrap().unwrap();
+        assert_eq!(frame.sample, 1000..1100);
+    }
+
+    #[test]
+    fn movies_have_no_embedded_previews() {
+        assert!(previews(&mut Cursor::new(movie(0))).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,651 @@
+//! `CameraDevice` implementation for cameras driven over PTP.
+
+use super::liveview::LiveView;
+use super::properties::{Property, PropertyValue};
+use super::vendor::{self, CaptureTarget, VendorEvent, VendorExtension};
+use super::{
+    DeviceInfo, DevicePropCode, Event, EventCode, ObjectFormatCode, ObjectInfo, OperationCode,
+    Session, Transport, PROTECTION_NONE,
+};
+use crate::camera::{
+    self, CameraCapabilities, CameraDevice, CameraEvent, CameraFile, CameraFolder, CameraItem,
+};
+use crate::datetime::DateTime;
+use crate::error::{Error, Result};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Largest range requested in a single GetPartialObject transaction.
+const MAX_PARTIAL_READ: u64 = 16 * 1024 * 1024;
+
+/// Interval between polls on cameras whose vendor extension polls for events.
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A camera driven over PTP, presented through the `CameraDevice` operations.
+pub struct PtpCamera<T> {
+    session: Mutex<Session<T>>,
+    events: Mutex<Receiver<Event>>,
+    device_info: DeviceInfo,
+    objects: Mutex<HashMap<u32, ObjectInfo>>,
+    catalog_completed: Mutex<bool>,
+    property_subscribers: Mutex<Vec<Sender<(DevicePropCode, PropertyValue)>>>,
+    vendor: Option<Box<dyn VendorExtension<T>>>,
+    pending_events: Mutex<VecDeque<CameraEvent>>,
+}
+
+impl<T: Transport> PtpCamera<T> {
+    /// Wrap an open session. `events` delivers the events sent by the camera.
+    ///
+    /// The vendor extension matching the camera's DeviceInfo, if any, is used for capture.
+    pub fn new(mut session: Session<T>, events: Receiver<Event>) -> Result<PtpCamera<T>> {
+        let device_info = session.get_device_info()?;
+        Ok(PtpCamera {
+            vendor: vendor::for_device(&device_info),
+            pending_events: Mutex::new(VecDeque::new()),
+            session: Mutex::new(session),
+            events: Mutex::new(events),
+            device_info,
+            objects: Mutex::new(HashMap::new()),
+            catalog_completed: Mutex::new(false),
+            property_subscribers: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// The DeviceInfo dataset read when the camera was opened.
+    pub fn device_info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    /// Exclusive access to the session, for operations not covered by `CameraDevice`.
+    pub fn session(&self) -> MutexGuard<'_, Session<T>> {
+        self.session
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The vendor extension used for this camera.
+    pub fn vendor(&self) -> Option<&dyn VendorExtension<T>> {
+        self.vendor.as_deref()
+    }
+
+    /// Select where pictures taken with `take_picture` are stored.
+    pub fn set_capture_target(&self, target: CaptureTarget) -> Result<()> {
+        match &self.vendor {
+            Some(vendor) => vendor.set_capture_target(&mut self.session(), target),
+            None => Err(Error::Unsupported("capture target selection".into())),
+        }
+    }
+
+    /// Start live view, yielding frames at up to `frame_rate` frames per second.
+    pub fn start_live_view(&self, frame_rate: f64) -> Result<LiveView<'_, T>> {
+        LiveView::start(self, frame_rate)
+    }
+
+    /// Read a device property and the values it may take.
+    pub fn property(&self, code: DevicePropCode) -> Result<Property> {
+        if let Some(desc) = self
+            .vendor
+            .as_ref()
+            .and_then(|vendor| vendor.property(code))
+        {
+            return Ok(Property::new(desc));
+        }
+        if !self.device_info.supports_property(code) {
+            return Err(Error::Unsupported(format!("property {}", code)));
+        }
+        Ok(Property::new(self.session().get_device_prop_desc(code)?))
+    }
+
+    /// All device properties advertised in DeviceInfo.
+    pub fn properties(&self) -> Result<Vec<Property>> {
+        self.device_info
+            .device_properties_supported
+            .iter()
+            .map(|&code| self.property(code))
+            .collect()
+    }
+
+    /// The current value of a device property.
+    pub fn property_value(&self, code: DevicePropCode) -> Result<PropertyValue> {
+        self.property(code).map(|property| property.value())
+    }
+
+    /// Set a device property after checking the value against its description.
+    pub fn set_property_value(&self, code: DevicePropCode, value: &PropertyValue) -> Result<()> {
+        let raw = self.property(code)?.validate(value)?;
+        let mut session = self.session();
+        if let Some(vendor) = &self.vendor {
+            if vendor.set_property(&mut session, code, &raw)? {
+                return Ok(());
+            }
+        }
+        session.set_device_prop_value(code, &raw)
+    }
+
+    /// Receive the new value of properties reported by DevicePropChanged events.
+    ///
+    /// Changes are delivered while the camera's events are read with `next_event`.
+    pub fn subscribe_properties(&self) -> Receiver<(DevicePropCode, PropertyValue)> {
+        let (sender, receiver) = mpsc::channel();
+        lock(&self.property_subscribers).push(sender);
+        receiver
+    }
+
+    fn object_info(&self, handle: u32) -> Result<ObjectInfo> {
+        if let Some(info) = lock(&self.objects).get(&handle) {
+            return Ok(info.clone());
+        }
+        let info = self.session().get_object_info(handle)?;
+        lock(&self.objects).insert(handle, info.clone());
+        Ok(info)
+    }
+
+    fn handle(file: &CameraFile) -> Result<u32> {
+        file.ptp_object_handle
+            .ok_or_else(|| Error::Unsupported("files without a PTP object handle".into()))
+    }
+
+    fn folder_path(
+        &self,
+        objects: &HashMap<u32, ObjectInfo>,
+        mut parent: u32,
+        storage: &str,
+    ) -> String {
+        let mut names = Vec::new();
+        // A parent chain looping back on itself, from a buggy camera, ends at the loop.
+        let mut visited = HashSet::new();
+        while parent != 0 && visited.insert(parent) {
+            match objects.get(&parent) {
+                Some(info) => {
+                    names.push(info.filename.clone());
+                    parent = info.parent_object;
+                }
+                None => break,
+            }
+        }
+        names.push(storage.to_string());
+        names.reverse();
+        names.join("/")
+    }
+
+    fn make_file(&self, handle: u32, info: &ObjectInfo, parent_folder: String) -> CameraFile {
+        let (uti, is_raw) = camera::file_type(&info.filename);
+        CameraFile {
+            name: info.filename.clone(),
+            parent_folder: Some(parent_folder),
+            uti,
+            // Objects of 4 GiB and more report 0xFFFFFFFF: their size is unknown.
+            file_size: match info.object_compressed_size {
+                u32::MAX => 0,
+                size => u64::from(size),
+            },
+            creation_date: DateTime::parse_ptp(&info.capture_date)
+                .map(|date| date.to_system_time()),
+            modification_date: DateTime::parse_ptp(&info.modification_date)
+                .map(|date| date.to_system_time()),
+            is_raw,
+            is_locked: info.protection_status != PROTECTION_NONE,
+            ptp_object_handle: Some(handle),
+            ..CameraFile::default()
+        }
+    }
+
+    fn file_for_handle(&self, handle: u32) -> Result<CameraFile> {
+        let info = self.object_info(handle)?;
+        let storage = storage_name(info.storage_id);
+        let objects = lock(&self.objects).clone();
+        let parent = self.folder_path(&objects, info.parent_object, &storage);
+        Ok(self.make_file(handle, &info, parent))
+    }
+
+    fn supports(&self, code: OperationCode) -> bool {
+        self.device_info.supports_operation(code)
+    }
+
+    fn translate_vendor(&self, event: VendorEvent) -> Result<Option<CameraEvent>> {
+        match event {
+            VendorEvent::Standard(event) => self.translate(event),
+            VendorEvent::ObjectAdded { handle, info } => {
+                if let Some(info) = info {
+                    lock(&self.objects).insert(handle, info);
+                }
+                self.translate(Event::new(EventCode::ObjectAdded, &[handle]))
+            }
+            VendorEvent::PropertyChanged { code, .. } => self.translate(Event::new(
+                EventCode::DevicePropChanged,
+                &[u32::from(code.0)],
+            )),
+            VendorEvent::CaptureComplete => Ok(Some(CameraEvent::CaptureComplete)),
+            VendorEvent::AllowedValuesChanged { .. } | VendorEvent::Other { .. } => Ok(None),
+        }
+    }
+
+    fn translate(&self, event: Event) -> Result<Option<CameraEvent>> {
+        let param = event.params.first().cloned();
+        Ok(match (event.code, param) {
+            (EventCode::ObjectAdded, Some(handle))
+            | (EventCode::RequestObjectTransfer, Some(handle)) => {
+                let info = self.object_info(handle)?;
+                if info.object_format == ObjectFormatCode::Association {
+                    None
+                } else {
+                    let mut file = self.file_for_handle(handle)?;
+                    file.was_added_after_content_catalog_completed = *lock(&self.catalog_completed);
+                    Some(CameraEvent::ItemsAdded(vec![file]))
+                }
+            }
+            (EventCode::ObjectRemoved, Some(handle)) => {
+                let known = lock(&self.objects).contains_key(&handle);
+                if known {
+                    let file = self.file_for_handle(handle)?;
+                    lock(&self.objects).remove(&handle);
+                    Some(CameraEvent::ItemsRemoved(vec![file]))
+                } else {
+                    None
+                }
+            }
+            (EventCode::DevicePropChanged, Some(code)) => {
+                let code = DevicePropCode(code as u16);
+                if !lock(&self.property_subscribers).is_empty() {
+                    let value = self.property_value(code)?;
+                    lock(&self.property_subscribers)
+                        .retain(|subscriber| subscriber.send((code, value.clone())).is_ok());
+                }
+                Some(CameraEvent::PropertyChanged(code.0))
+            }
+            (EventCode::CaptureComplete, _) => Some(CameraEvent::CaptureComplete),
+            _ => None,
+        })
+    }
+}
+
+impl<T: Transport> CameraDevice for PtpCamera<T> {
+    fn name(&self) -> String {
+        self.device_info.model.clone()
+    }
+
+    fn capabilities(&self) -> CameraCapabilities {
+        let mut capabilities = CameraCapabilities::ICCameraDeviceCanAcceptPTPCommands;
+        if self.vendor.is_some() || self.supports(OperationCode::InitiateCapture) {
+            capabilities |= CameraCapabilities::ICCameraDeviceCanTakePicture;
+        }
+        if self
+            .device_info
+            .events_supported
+            .contains(&EventCode::ObjectAdded)
+        {
+            capabilities |=
+                CameraCapabilities::ICCameraDeviceCanTakePictureUsingShutterReleaseOnCamera;
+        }
+        if self.supports(OperationCode::DeleteObject) {
+            capabilities |= CameraCapabilities::ICCameraDeviceCanDeleteOneFile;
+        }
+        if self.supports(OperationCode::SendObject) {
+            capabilities |= CameraCapabilities::ICCameraDeviceCanReceiveFile;
+        }
+        if self.device_info.supports_property(DevicePropCode::DateTime) {
+            capabilities |= CameraCapabilities::ICCameraDeviceCanSyncClock;
+        }
+        capabilities
+    }
+
+    fn serial_number_string(&self) -> Option<String> {
+        if self.device_info.serial_number.is_empty() {
+            None
+        } else {
+            Some(self.device_info.serial_number.clone())
+        }
+    }
+
+    fn battery_level(&self) -> Option<u8> {
+        match self.property_value(DevicePropCode::BatteryLevel).ok()? {
+            PropertyValue::BatteryLevel(level) => Some(level),
+            _ => None,
+        }
+    }
+
+    fn free_space(&self) -> Option<u64> {
+        if !self.supports(OperationCode::GetStorageInfo) {
+            return None;
+        }
+        let mut session = self.session();
+        let mut free_space = 0;
+        for storage_id in session.get_storage_ids().ok()? {
+            free_space += session
+                .get_storage_info(storage_id)
+                .ok()?
+                .free_space_in_bytes;
+        }
+        Some(free_space)
+    }
+
+    fn contents(&self) -> Result<Vec<CameraItem>> {
+        let mut storages = Vec::new();
+        let storage_ids = self.session().get_storage_ids()?;
+        for storage_id in storage_ids {
+            let handles = self.session().get_object_handles(storage_id, None, 0)?;
+            let mut infos = BTreeMap::new();
+            for handle in handles {
+                infos.insert(handle, self.object_info(handle)?);
+            }
+            let objects = lock(&self.objects).clone();
+            let storage = storage_name(storage_id);
+            let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+            for (&handle, info) in &infos {
+                children.entry(info.parent_object).or_default().push(handle);
+            }
+            let mut visited = HashSet::new();
+            let root = self.build_folder(&infos, &children, &objects, 0, &storage, &mut visited);
+            storages.push(CameraItem::Folder(CameraFolder {
+                name: storage,
+                contents: root,
+                ..CameraFolder::default()
+            }));
+        }
+        *lock(&self.catalog_completed) = true;
+        Ok(storages)
+    }
+
+    fn download_file(&self, file: &CameraFile, writer: &mut dyn Write) -> Result<u64> {
+        let mut counter = CountingWriter {
+            inner: writer,
+            count: 0,
+        };
+        let handle = Self::handle(file)?;
+        let mut session = self.session();
+        session.get_object(handle, &mut counter)?;
+        if let Some(vendor) = &self.vendor {
+            vendor.object_downloaded(&mut session, handle)?;
+        }
+        Ok(counter.count)
+    }
+
+    fn read_data_from_file(&self, file: &CameraFile, offset: u64, length: u64) -> Result<Vec<u8>> {
+        let handle = Self::handle(file)?;
+        if !self.supports(OperationCode::GetPartialObject) {
+            return Err(Error::Unsupported("partial reads".into()));
+        }
+        let mut end = offset.saturating_add(length);
+        if file.file_size > 0 {
+            end = end.min(file.file_size.max(offset));
+        }
+        let mut data = Vec::new();
+        let mut position = offset;
+        while position < end {
+            let chunk = (end - position).min(MAX_PARTIAL_READ);
+            if position > u64::from(u32::MAX) {
+                return Err(Error::Unsupported("partial reads beyond 4 GiB".into()));
+            }
+            let bytes = self
+                .session()
+                .get_partial_object(handle, position as u32, chunk as u32)?;
+            if bytes.is_empty() {
+                break;
+            }
+            position += bytes.len() as u64;
+            data.extend_from_slice(&bytes);
+        }
+        Ok(data)
+    }
+
+    fn delete_files(&self, files: &[CameraFile]) -> Result<()> {
+        for file in files {
+            let handle = Self::handle(file)?;
+            self.session().delete_object(handle)?;
+            lock(&self.objects).remove(&handle);
+        }
+        Ok(())
+    }
+
+    fn take_picture(&self) -> Result<()> {
+        if let Some(vendor) = &self.vendor {
+            return vendor.release(&mut self.session());
+        }
+        if !self.supports(OperationCode::InitiateCapture) {
+            return Err(Error::Unsupported("take picture".into()));
+        }
+        self.session().initiate_capture(0, None)
+    }
+
+    fn enable_tethering(&self) -> Result<()> {
+        // Standard PTP cameras accept InitiateCapture without a separate tethering mode.
+        match &self.vendor {
+            Some(vendor) => vendor.start_remote(&mut self.session()),
+            None => Ok(()),
+        }
+    }
+
+    fn disable_tethering(&self) -> Result<()> {
+        match &self.vendor {
+            Some(vendor) => vendor.stop_remote(&mut self.session()),
+            None => Ok(()),
+        }
+    }
+
+    fn property(&self, code: DevicePropCode) -> Result<Property> {
+        PtpCamera::property(self, code)
+    }
+
+    fn set_property_value(&self, code: DevicePropCode, value: &PropertyValue) -> Result<()> {
+        PtpCamera::set_property_value(self, code, value)
+    }
+
+    fn sync_clock(&self) -> Result<()> {
+        let now = DateTime::from_system_time(std::time::SystemTime::now());
+        self.session().set_device_prop_value(
+            DevicePropCode::DateTime,
+            &super::Value::String(now.to_ptp_string()),
+        )
+    }
+
+    fn next_event(&self, timeout: Duration) -> Result<Option<CameraEvent>> {
+        if let Some(event) = lock(&self.pending_events).pop_front() {
+            return Ok(Some(event));
+        }
+        let vendor = match &self.vendor {
+            Some(vendor) if vendor.polls_events() => vendor,
+            _ => {
+                let event = match lock(&self.events).recv_timeout(timeout) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => return Ok(None),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Ok(Some(CameraEvent::DeviceRemoved))
+                    }
+                };
+                return match &self.vendor {
+                    Some(vendor) => self.translate_vendor(vendor.map_event(event)),
+                    None => self.translate(event),
+                };
+            }
+        };
+        let deadline = Instant::now() + timeout;
+        loop {
+            let events = vendor.poll_events(&mut self.session())?;
+            for event in events {
+                if let Some(event) = self.translate_vendor(event)? {
+                    lock(&self.pending_events).push_back(event);
+                }
+            }
+            if let Some(event) = lock(&self.pending_events).pop_front() {
+                return Ok(Some(event));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            // Events that still arrive on the event channel are handled between polls.
+            match lock(&self.events).recv_timeout(EVENT_POLL_INTERVAL.min(deadline - now)) {
+                Ok(event) => {
+                    if let Some(event) = self.translate(event)? {
+                        return Ok(Some(event));
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(Some(CameraEvent::DeviceRemoved)),
+            }
+        }
+    }
+}
+
+impl<T: Transport> PtpCamera<T> {
+    fn build_folder(
+        &self,
+        infos: &BTreeMap<u32, ObjectInfo>,
+        children: &HashMap<u32, Vec<u32>>,
+        objects: &HashMap<u32, ObjectInfo>,
+        parent: u32,
+        storage: &str,
+        visited: &mut HashSet<u32>,
+    ) -> Vec<CameraItem> {
+        let mut items = Vec::new();
+        let handles = match children.get(&parent) {
+            Some(handles) => handles,
+            None => return items,
+        };
+        let path = self.folder_path(objects, parent, storage);
+        for &handle in handles {
+            // Each object is listed once, even if a camera reports one as its own parent.
+            if !visited.insert(handle) {
+                continue;
+            }
+            let info = &infos[&handle];
+            if info.object_format == ObjectFormatCode::Association {
+                items.push(CameraItem::Folder(CameraFolder {
+                    name: info.filename.clone(),
+                    parent_folder: Some(path.clone()),
+                    is_locked: info.protection_status != PROTECTION_NONE,
+                    creation_date: DateTime::parse_ptp(&info.capture_date)
+                        .map(|date| date.to_system_time()),
+                    modification_date: DateTime::parse_ptp(&info.modification_date)
+                        .map(|date| date.to_system_time()),
+                    ptp_object_handle: Some(handle),
+                    file_system_path: None,
+                    contents: self.build_folder(infos, children, objects, handle, storage, visited),
+                }));
+            } else {
+                items.push(CameraItem::File(self.make_file(handle, info, path.clone())));
+            }
+        }
+        items
+    }
+}
+
+/// Name used for the top-level folder representing a storage.
+fn storage_name(storage_id: u32) -> String {
+    format!("Storage {:08X}", storage_id)
+}
+
+fn lock<V>(mutex: &Mutex<V>) -> MutexGuard<'_, V> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+struct CountingWriter<'a> {
+    inner: &'a mut dyn Write,
+    count: u64,
+}
+
+impl<'a> Write for CountingWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ptp::responder::VirtualCamera;
+    use crate::ptp::stream::{Duplex, StreamTransport};
+
+    fn connect(camera: &VirtualCamera) -> PtpCamera<StreamTransport<Duplex>> {
+        let (transport, events) = camera.connect_in_memory();
+        let session = Session::open(transport, 1).unwrap();
+        PtpCamera::new(session, events).unwrap()
+    }
+
+    #[test]
+    fn folder_paths_end_at_a_parent_cycle() {
+        let virtual_camera = VirtualCamera::new("Test", "Virtual");
+        let storage = virtual_camera.add_storage("Card");
+        // Each folder names the other as its parent.
+        let a = virtual_camera.add_folder(storage, Some(2), "A");
+        let b = virtual_camera.add_folder(storage, Some(a), "B");
+        assert_eq!(b, 2);
+        virtual_camera.add_folder(storage, Some(3), "Self");
+        let camera = connect(&virtual_camera);
+        camera.contents().unwrap();
+
+        virtual_camera.add_file(storage, Some(a), "IMG_0001.JPG", vec![0; 16]);
+        match camera.next_event(Duration::from_secs(5)).unwrap() {
+            Some(CameraEvent::ItemsAdded(files)) => {
+                assert_eq!(files.len(), 1);
+                assert_eq!(
+                    files[0].parent_folder.as_deref(),
+                    Some("Storage 00010001/B/A")
+                );
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn objects_of_4_gib_and_more_have_an_unknown_size() {
+        let camera = connect(&VirtualCamera::new("Test", "Virtual"));
+        let info = |size| ObjectInfo {
+            filename: "MVI_0001.MP4".to_string(),
+            object_compressed_size: size,
+            ..ObjectInfo::default()
+        };
+        let file = camera.make_file(1, &info(u32::MAX), "Card".to_string());
+        assert_eq!(file.file_size, 0);
+        let file = camera.make_file(1, &info(u32::MAX - 1), "Card".to_string());
+        assert_eq!(file.file_size, u64::from(u32::MAX - 1));
+    }
+
+    #[test]
+    fn vendor_events_on_the_event_channel_are_decoded() {
+        let virtual_camera = VirtualCamera::new("Nikon", "Virtual");
+        virtual_camera.set_vendor_extension(vendor::VENDOR_NIKON, "");
+        let storage = virtual_camera.add_storage("Card");
+        let handle = virtual_camera.add_file(storage, None, "DSC_0001.JPG", vec![0; 16]);
+        let (transport, _) = virtual_camera.connect_in_memory();
+        let (sender, events) = mpsc::channel();
+        let camera = PtpCamera::new(Session::open(transport, 1).unwrap(), events).unwrap();
+        assert_eq!(camera.vendor().map(|vendor| vendor.name()), Some("Nikon"));
+
+        sender
+            .send(Event::new(
+                vendor::nikon::event::ObjectAddedInSdram,
+                &[handle],
+            ))
+            .unwrap();
+        match camera.next_event(Duration::from_secs(5)).unwrap() {
+            Some(CameraEvent::ItemsAdded(files)) => {
+                assert_eq!(files.len(), 1);
+                assert_eq!(files[0].name, "DSC_0001.JPG");
+                assert_eq!(files[0].ptp_object_handle, Some(handle));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        sender
+            .send(Event::new(
+                vendor::nikon::event::CaptureCompleteRecInSdram,
+                &[],
+            ))
+            .unwrap();
+        assert_eq!(
+            camera.next_event(Duration::from_secs(5)).unwrap(),
+            Some(CameraEvent::CaptureComplete)
+        );
+    }
+}
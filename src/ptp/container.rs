@@ -0,0 +1,290 @@
+//! PTP containers: the framing used for operations, data phases, responses and events.
+//!
+//! The layout is the generic container of ISO 15740 (as used by the USB still image class
+//! and by `requestSendPTPCommand`): a 12-byte header of length, type, code and transaction ID,
+//! followed by the parameters or the data payload.
+
+use super::{EventCode, OperationCode, ResponseCode};
+use crate::error::{Error, Result};
+use std::io::{self, Read, Write};
+
+/// Size of the container header.
+pub const HEADER_LEN: usize = 12;
+
+/// Maximum number of parameters carried by an operation or response.
+pub const MAX_PARAMS: usize = 5;
+
+/// Maximum number of parameters carried by an event.
+pub const MAX_EVENT_PARAMS: usize = 3;
+
+/// Type of a PTP container.
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContainerType {
+    Command = 1,
+    Data = 2,
+    Response = 3,
+    Event = 4,
+}
+
+impl ContainerType {
+    pub fn from_u16(value: u16) -> Option<ContainerType> {
+        match value {
+            1 => Some(ContainerType::Command),
+            2 => Some(ContainerType::Data),
+            3 => Some(ContainerType::Response),
+            4 => Some(ContainerType::Event),
+            _ => None,
+        }
+    }
+}
+
+/// An operation request sent by the initiator.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Operation {
+    pub code: OperationCode,
+    pub transaction_id: u32,
+    pub params: Vec<u32>,
+}
+
+impl Operation {
+    pub fn new(code: OperationCode, params: &[u32]) -> Operation {
+        Operation {
+            code,
+            transaction_id: 0,
+            params: params.to_vec(),
+        }
+    }
+
+    /// Encode the operation as a command container, the form expected by `requestSendPTPCommand`.
+    pub fn encode(&self) -> Vec<u8> {
+        Container::Operation(self.clone()).encode()
+    }
+}
+
+/// A response sent by the responder at the end of a transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub code: ResponseCode,
+    pub transaction_id: u32,
+    pub params: Vec<u32>,
+}
+
+impl Response {
+    pub fn new(code: ResponseCode, transaction_id: u32, params: &[u32]) -> Response {
+        Response {
+            code,
+            transaction_id,
+            params: params.to_vec(),
+        }
+    }
+
+    /// Indicates whether the response code is `OK`.
+    pub fn is_ok(&self) -> bool {
+        self.code == ResponseCode::OK
+    }
+
+    /// Decode a response container, such as the one returned to a `requestSendPTPCommand` delegate.
+    pub fn decode(bytes: &[u8]) -> Result<Response> {
+        match Container::decode(bytes)? {
+            Container::Response(response) => Ok(response),
+            other => Err(Error::malformed(format!(
+                "expected a response container, got {:?}",
+                other.container_type()
+            ))),
+        }
+    }
+}
+
+/// An asynchronous event sent by the responder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub code: EventCode,
+    pub transaction_id: u32,
+    pub params: Vec<u32>,
+}
+
+impl Event {
+    pub fn new(code: EventCode, params: &[u32]) -> Event {
+        Event {
+            code,
+            transaction_id: 0,
+            params: params.to_vec(),
+        }
+    }
+}
+
+/// The data phase of a transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataContainer {
+    pub code: OperationCode,
+    pub transaction_id: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Any PTP container.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Container {
+    Operation(Operation),
+    Data(DataContainer),
+    Response(Response),
+    Event(Event),
+}
+
+impl Container {
+    pub fn container_type(&self) -> ContainerType {
+        match self {
+            Container::Operation(_) => ContainerType::Command,
+            Container::Data(_) => ContainerType::Data,
+            Container::Response(_) => ContainerType::Response,
+            Container::Event(_) => ContainerType::Event,
+        }
+    }
+
+    /// Encode the container including its header.
+    pub fn encode(&self) -> Vec<u8> {
+        let (code, transaction_id, body) = match self {
+            Container::Operation(op) => (op.code.0, op.transaction_id, encode_params(&op.params)),
+            Container::Data(data) => (data.code.0, data.transaction_id, data.payload.clone()),
+            Container::Response(res) => {
+                (res.code.0, res.transaction_id, encode_params(&res.params))
+            }
+            Container::Event(event) => (
+                event.code.0,
+                event.transaction_id,
+                encode_params(&event.params),
+            ),
+        };
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+        bytes.extend_from_slice(&((HEADER_LEN + body.len()) as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.container_type() as u16).to_le_bytes());
+        bytes.extend_from_slice(&code.to_le_bytes());
+        bytes.extend_from_slice(&transaction_id.to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    /// Decode a complete container. Trailing bytes beyond the encoded length are rejected.
+    pub fn decode(bytes: &[u8]) -> Result<Container> {
+        if bytes.len() < HEADER_LEN {
+            return Err(Error::malformed(format!(
+                "container of {} bytes is shorter than its header",
+                bytes.len()
+            )));
+        }
+        let length = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        if length != bytes.len() {
+            return Err(Error::malformed(format!(
+                "container length field is {} but {} bytes were supplied",
+                length,
+                bytes.len()
+            )));
+        }
+        let kind = u16::from_le_bytes([bytes[4], bytes[5]]);
+        let code = u16::from_le_bytes([bytes[6], bytes[7]]);
+        let transaction_id = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+        Container::from_parts(kind, code, transaction_id, bytes[HEADER_LEN..].to_vec())
+    }
+
+    fn from_parts(kind: u16, code: u16, transaction_id: u32, body: Vec<u8>) -> Result<Container> {
+        let kind = ContainerType::from_u16(kind)
+            .ok_or_else(|| Error::malformed(format!("unknown container type {}", kind)))?;
+        Ok(match kind {
+            ContainerType::Command => Container::Operation(Operation {
+                code: OperationCode(code),
+                transaction_id,
+                params: decode_params(&body, MAX_PARAMS)?,
+            }),
+            ContainerType::Data => Container::Data(DataContainer {
+                code: OperationCode(code),
+                transaction_id,
+                payload: body,
+            }),
+            ContainerType::Response => Container::Response(Response {
+                code: ResponseCode(code),
+                transaction_id,
+                params: decode_params(&body, MAX_PARAMS)?,
+            }),
+            ContainerType::Event => Container::Event(Event {
+                code: EventCode(code),
+                transaction_id,
+                params: decode_params(&body, MAX_EVENT_PARAMS)?,
+            }),
+        })
+    }
+
+    /// Read one container from a byte stream.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Container> {
+        let mut header = [0u8; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        if length < HEADER_LEN {
+            return Err(Error::malformed(format!(
+                "container length field {} is shorter than its header",
+                length
+            )));
+        }
+        // Data phases may legitimately be large, so the body grows with the bytes received
+        // rather than trusting the length field with one allocation.
+        let body_length = (length - HEADER_LEN) as u64;
+        let mut body = Vec::new();
+        reader.take(body_length).read_to_end(&mut body)?;
+        if (body.len() as u64) < body_length {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "container ended after {} of {} bytes",
+                    body.len(),
+                    body_length
+                ),
+            )));
+        }
+        Container::from_parts(
+            u16::from_le_bytes([header[4], header[5]]),
+            u16::from_le_bytes([header[6], header[7]]),
+            u32::from_le_bytes([header[8], header[9], header[10], header[11]]),
+            body,
+        )
+    }
+
+    /// Write this container to a byte stream.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.encode())?;
+        Ok(())
+    }
+}
+
+fn encode_params(params: &[u32]) -> Vec<u8> {
+    params
+        .iter()
+        .flat_map(|param| param.to_le_bytes().to_vec())
+        .collect()
+}
+
+fn decode_params(body: &[u8], max: usize) -> Result<Vec<u32>> {
+    if !body.len().is_multiple_of(4) || body.len() / 4 > max {
+        return Err(Error::malformed(format!(
+            "{} bytes is not a valid parameter block",
+            body.len()
+        )));
+    }
+    Ok(body
+        .chunks(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_from_fails_on_a_length_beyond_the_data() {
+        let mut data = vec![0xF0, 0xFF, 0xFF, 0xFF, 2, 0, 0x09, 0x10, 1, 0, 0, 0];
+        data.extend_from_slice(&[0; 16]);
+        match Container::read_from(&mut &data[..]) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,593 @@
+//! PTP data types and their little-endian wire encoding.
+
+use crate::error::{Error, Result};
+use std::fmt;
+
+/// Data type codes used by DevicePropDesc datasets and other typed PTP values.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DataType(pub u16);
+
+impl DataType {
+    pub const UNDEF: DataType = DataType(0x0000);
+    pub const INT8: DataType = DataType(0x0001);
+    pub const UINT8: DataType = DataType(0x0002);
+    pub const INT16: DataType = DataType(0x0003);
+    pub const UINT16: DataType = DataType(0x0004);
+    pub const INT32: DataType = DataType(0x0005);
+    pub const UINT32: DataType = DataType(0x0006);
+    pub const INT64: DataType = DataType(0x0007);
+    pub const UINT64: DataType = DataType(0x0008);
+    pub const INT128: DataType = DataType(0x0009);
+    pub const UINT128: DataType = DataType(0x000A);
+    pub const AINT8: DataType = DataType(0x4001);
+    pub const AUINT8: DataType = DataType(0x4002);
+    pub const AINT16: DataType = DataType(0x4003);
+    pub const AUINT16: DataType = DataType(0x4004);
+    pub const AINT32: DataType = DataType(0x4005);
+    pub const AUINT32: DataType = DataType(0x4006);
+    pub const AINT64: DataType = DataType(0x4007);
+    pub const AUINT64: DataType = DataType(0x4008);
+    pub const AINT128: DataType = DataType(0x4009);
+    pub const AUINT128: DataType = DataType(0x400A);
+    pub const STR: DataType = DataType(0xFFFF);
+
+    /// Indicates whether this is an array type.
+    pub fn is_array(self) -> bool {
+        self.0 & 0x4000 != 0 && self != DataType::STR
+    }
+
+    /// The element type of an array type, or `self` for scalar types.
+    pub fn element_type(self) -> DataType {
+        if self.is_array() {
+            DataType(self.0 & !0x4000)
+        } else {
+            self
+        }
+    }
+
+    /// The array type whose elements are of this type.
+    pub fn array_of(self) -> DataType {
+        DataType(self.0 | 0x4000)
+    }
+
+    /// Size in bytes of a scalar integer value of this type.
+    pub fn size(self) -> Option<usize> {
+        match self {
+            DataType::INT8 | DataType::UINT8 => Some(1),
+            DataType::INT16 | DataType::UINT16 => Some(2),
+            DataType::INT32 | DataType::UINT32 => Some(4),
+            DataType::INT64 | DataType::UINT64 => Some(8),
+            DataType::INT128 | DataType::UINT128 => Some(16),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            DataType::UNDEF => "UNDEF",
+            DataType::INT8 => "INT8",
+            DataType::UINT8 => "UINT8",
+            DataType::INT16 => "INT16",
+            DataType::UINT16 => "UINT16",
+            DataType::INT32 => "INT32",
+            DataType::UINT32 => "UINT32",
+            DataType::INT64 => "INT64",
+            DataType::UINT64 => "UINT64",
+            DataType::INT128 => "INT128",
+            DataType::UINT128 => "UINT128",
+            DataType::AINT8 => "AINT8",
+            DataType::AUINT8 => "AUINT8",
+            DataType::AINT16 => "AINT16",
+            DataType::AUINT16 => "AUINT16",
+            DataType::AINT32 => "AINT32",
+            DataType::AUINT32 => "AUINT32",
+            DataType::AINT64 => "AINT64",
+            DataType::AUINT64 => "AUINT64",
+            DataType::AINT128 => "AINT128",
+            DataType::AUINT128 => "AUINT128",
+            DataType::STR => "STR",
+            _ => return write!(f, "DataType(0x{:04X})", self.0),
+        };
+        f.write_str(name)
+    }
+}
+
+/// A typed PTP value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Undefined,
+    Int8(i8),
+    UInt8(u8),
+    Int16(i16),
+    UInt16(u16),
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    UInt64(u64),
+    Int128(i128),
+    UInt128(u128),
+    /// An array with the given element type.
+    Array(DataType, Vec<Value>),
+    String(String),
+}
+
+impl Value {
+    /// The PTP data type of this value.
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Value::Undefined => DataType::UNDEF,
+            Value::Int8(_) => DataType::INT8,
+            Value::UInt8(_) => DataType::UINT8,
+            Value::Int16(_) => DataType::INT16,
+            Value::UInt16(_) => DataType::UINT16,
+            Value::Int32(_) => DataType::INT32,
+            Value::UInt32(_) => DataType::UINT32,
+            Value::Int64(_) => DataType::INT64,
+            Value::UInt64(_) => DataType::UINT64,
+            Value::Int128(_) => DataType::INT128,
+            Value::UInt128(_) => DataType::UINT128,
+            Value::Array(element, _) => element.array_of(),
+            Value::String(_) => DataType::STR,
+        }
+    }
+
+    /// The value as a signed integer, if it is a scalar integer that fits.
+    pub fn as_i64(&self) -> Option<i64> {
+        use std::convert::TryFrom;
+        match *self {
+            Value::Int8(v) => Some(i64::from(v)),
+            Value::UInt8(v) => Some(i64::from(v)),
+            Value::Int16(v) => Some(i64::from(v)),
+            Value::UInt16(v) => Some(i64::from(v)),
+            Value::Int32(v) => Some(i64::from(v)),
+            Value::UInt32(v) => Some(i64::from(v)),
+            Value::Int64(v) => Some(v),
+            Value::UInt64(v) => i64::try_from(v).ok(),
+            Value::Int128(v) => i64::try_from(v).ok(),
+            Value::UInt128(v) => i64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    /// The value as a string, if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Build a scalar integer value of the given type, failing if `value` does not fit.
+    pub fn from_i64(data_type: DataType, value: i64) -> Option<Value> {
+        use std::convert::TryFrom;
+        Some(match data_type {
+            DataType::INT8 => Value::Int8(i8::try_from(value).ok()?),
+            DataType::UINT8 => Value::UInt8(u8::try_from(value).ok()?),
+            DataType::INT16 => Value::Int16(i16::try_from(value).ok()?),
+            DataType::UINT16 => Value::UInt16(u16::try_from(value).ok()?),
+            DataType::INT32 => Value::Int32(i32::try_from(value).ok()?),
+            DataType::UINT32 => Value::UInt32(u32::try_from(value).ok()?),
+            DataType::INT64 => Value::Int64(value),
+            DataType::UINT64 => Value::UInt64(u64::try_from(value).ok()?),
+            DataType::INT128 => Value::Int128(i128::from(value)),
+            DataType::UINT128 => Value::UInt128(u128::try_from(value).ok()?),
+            _ => return None,
+        })
+    }
+
+    /// Encode this value on its own, as sent in a SetDevicePropValue data phase.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.write_value(self);
+        writer.into_bytes()
+    }
+}
+
+/// Cursor decoding little-endian PTP data from a byte slice.
+#[derive(Clone, Debug)]
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+macro_rules! read_le {
+    ($($name:ident -> $ty:ty;)*) => {
+        $(
+            pub fn $name(&mut self) -> Result<$ty> {
+                const SIZE: usize = ::std::mem::size_of::<$ty>();
+                let mut bytes = [0u8; SIZE];
+                bytes.copy_from_slice(self.take(SIZE)?);
+                Ok(<$ty>::from_le_bytes(bytes))
+            }
+        )*
+    };
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader { buf, pos: 0 }
+    }
+
+    /// Current offset from the start of the buffer.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Number of bytes not yet consumed.
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// The bytes not yet consumed.
+    pub fn rest(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Consume exactly `len` bytes.
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(Error::malformed(format!(
+                "expected {} bytes at offset {}, only {} left",
+                len,
+                self.pos,
+                self.remaining()
+            )));
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    read_le! {
+        read_u8 -> u8;
+        read_i8 -> i8;
+        read_u16 -> u16;
+        read_i16 -> i16;
+        read_u32 -> u32;
+        read_i32 -> i32;
+        read_u64 -> u64;
+        read_i64 -> i64;
+        read_u128 -> u128;
+        read_i128 -> i128;
+    }
+
+    /// Read a PTP string: a character count (including the terminator) followed by UCS-2 characters.
+    pub fn read_string(&mut self) -> Result<String> {
+        let count = self.read_u8()? as usize;
+        if count == 0 {
+            return Ok(String::new());
+        }
+        let mut units = Vec::with_capacity(count);
+        for _ in 0..count {
+            units.push(self.read_u16()?);
+        }
+        if let Some(end) = units.iter().position(|&unit| unit == 0) {
+            units.truncate(end);
+        }
+        Ok(String::from_utf16_lossy(&units))
+    }
+
+    /// Read an array of 32-bit values, such as object handles or storage IDs.
+    pub fn read_u32_array(&mut self) -> Result<Vec<u32>> {
+        let count = self.read_array_len(4)?;
+        (0..count).map(|_| self.read_u32()).collect()
+    }
+
+    /// Read an array of 16-bit values, such as operation or property codes.
+    pub fn read_u16_array(&mut self) -> Result<Vec<u16>> {
+        let count = self.read_array_len(2)?;
+        (0..count).map(|_| self.read_u16()).collect()
+    }
+
+    fn read_array_len(&mut self, element_size: usize) -> Result<usize> {
+        let count = self.read_u32()? as usize;
+        if count.saturating_mul(element_size) > self.remaining() {
+            return Err(Error::malformed(format!(
+                "array of {} elements does not fit in {} remaining bytes",
+                count,
+                self.remaining()
+            )));
+        }
+        Ok(count)
+    }
+
+    /// Read a value of the given type.
+    pub fn read_value(&mut self, data_type: DataType) -> Result<Value> {
+        if data_type.is_array() {
+            let element = data_type.element_type();
+            let size = element.size().ok_or_else(|| {
+                Error::malformed(format!("unsupported array type {:?}", data_type))
+            })?;
+            let count = self.read_array_len(size)?;
+            let values = (0..count)
+                .map(|_| self.read_value(element))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(Value::Array(element, values));
+        }
+        Ok(match data_type {
+            DataType::UNDEF => Value::Undefined,
+            DataType::INT8 => Value::Int8(self.read_i8()?),
+            DataType::UINT8 => Value::UInt8(self.read_u8()?),
+            DataType::INT16 => Value::Int16(self.read_i16()?),
+            DataType::UINT16 => Value::UInt16(self.read_u16()?),
+            DataType::INT32 => Value::Int32(self.read_i32()?),
+            DataType::UINT32 => Value::UInt32(self.read_u32()?),
+            DataType::INT64 => Value::Int64(self.read_i64()?),
+            DataType::UINT64 => Value::UInt64(self.read_u64()?),
+            DataType::INT128 => Value::Int128(self.read_i128()?),
+            DataType::UINT128 => Value::UInt128(self.read_u128()?),
+            DataType::STR => Value::String(self.read_string()?),
+            _ => {
+                return Err(Error::malformed(format!(
+                    "unsupported data type {:?}",
+                    data_type
+                )))
+            }
+        })
+    }
+}
+
+/// Buffer encoding little-endian PTP data.
+#[derive(Clone, Debug, Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+macro_rules! write_le {
+    ($($name:ident($ty:ty);)*) => {
+        $(
+            pub fn $name(&mut self, value: $ty) {
+                self.buf.extend_from_slice(&value.to_le_bytes());
+            }
+        )*
+    };
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::default()
+    }
+
+    /// The encoded bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Number of bytes written so far.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Indicates whether nothing has been written yet.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    write_le! {
+        write_u8(u8);
+        write_i8(i8);
+        write_u16(u16);
+        write_i16(i16);
+        write_u32(u32);
+        write_i32(i32);
+        write_u64(u64);
+        write_i64(i64);
+        write_u128(u128);
+        write_i128(i128);
+    }
+
+    /// Write a PTP string. Strings longer than 254 UCS-2 characters are truncated.
+    pub fn write_string(&mut self, value: &str) {
+        if value.is_empty() {
+            self.write_u8(0);
+            return;
+        }
+        let units: Vec<u16> = value.encode_utf16().take(254).collect();
+        self.write_u8(units.len() as u8 + 1);
+        for unit in units {
+            self.write_u16(unit);
+        }
+        self.write_u16(0);
+    }
+
+    pub fn write_u32_array(&mut self, values: &[u32]) {
+        self.write_u32(values.len() as u32);
+        for &value in values {
+            self.write_u32(value);
+        }
+    }
+
+    pub fn write_u16_array(&mut self, values: &[u16]) {
+        self.write_u32(values.len() as u32);
+        for &value in values {
+            self.write_u16(value);
+        }
+    }
+
+    pub fn write_value(&mut self, value: &Value) {
+        match value {
+            Value::Undefined => {}
+            Value::Int8(v) => self.write_i8(*v),
+            Value::UInt8(v) => self.write_u8(*v),
+            Value::Int16(v) => self.write_i16(*v),
+            Value::UInt16(v) => self.write_u16(*v),
+            Value::Int32(v) => self.write_i32(*v),
+            Value::UInt32(v) => self.write_u32(*v),
+            Value::Int64(v) => self.write_i64(*v),
+            Value::UInt64(v) => self.write_u64(*v),
+            Value::Int128(v) => self.write_i128(*v),
+            Value::UInt128(v) => self.write_u128(*v),
+            Value::Array(_, values) => {
+                self.write_u32(values.len() as u32);
+                for value in values {
+                    self.write_value(value);
+                }
+            }
+            Value::String(s) => self.write_string(s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(value: &Value) -> Value {
+        let bytes = value.to_bytes();
+        let mut r = Reader::new(&bytes);
+        let decoded = r.read_value(value.data_type()).unwrap();
+        assert_eq!(r.remaining(), 0, "{:?} left bytes unread", value);
+        decoded
+    }
+
+    fn scalars() -> Vec<Value> {
+        vec![
+            Value::Int8(i8::MIN),
+            Value::Int8(i8::MAX),
+            Value::UInt8(u8::MAX),
+            Value::Int16(i16::MIN),
+            Value::UInt16(u16::MAX),
+            Value::Int32(i32::MIN),
+            Value::UInt32(u32::MAX),
+            Value::Int64(i64::MIN),
+            Value::UInt64(u64::MAX),
+            Value::Int128(i128::MIN),
+            Value::UInt128(u128::MAX),
+        ]
+    }
+
+    #[test]
+    fn scalars_round_trip() {
+        for value in scalars() {
+            let size = value.data_type().size().unwrap();
+            assert_eq!(value.to_bytes().len(), size, "{:?}", value);
+            assert_eq!(round_trip(&value), value);
+        }
+        assert_eq!(Value::UInt32(0x0403_0201).to_bytes(), [1, 2, 3, 4]);
+        assert_eq!(Value::Int16(-2).to_bytes(), [0xFE, 0xFF]);
+        assert_eq!(Value::Undefined.to_bytes(), []);
+        assert_eq!(round_trip(&Value::Undefined), Value::Undefined);
+    }
+
+    #[test]
+    fn arrays_round_trip() {
+        for value in scalars() {
+            let element = value.data_type();
+            let array = Value::Array(element, vec![value.clone(), value.clone(), value]);
+            assert_eq!(array.data_type(), element.array_of());
+            assert_eq!(array.data_type().element_type(), element);
+            assert!(array.data_type().is_array());
+            assert_eq!(round_trip(&array), array);
+            let empty = Value::Array(element, Vec::new());
+            assert_eq!(empty.to_bytes(), [0; 4]);
+            assert_eq!(round_trip(&empty), empty);
+        }
+        assert_eq!(
+            Value::Array(DataType::UINT16, vec![Value::UInt16(1), Value::UInt16(2)]).to_bytes(),
+            [2, 0, 0, 0, 1, 0, 2, 0]
+        );
+
+        let mut w = Writer::new();
+        w.write_u32_array(&[7, 8]);
+        w.write_u16_array(&[9]);
+        let bytes = w.into_bytes();
+        let mut r = Reader::new(&bytes);
+        assert_eq!(r.read_u32_array().unwrap(), [7, 8]);
+        assert_eq!(r.read_u16_array().unwrap(), [9]);
+        assert_eq!(r.remaining(), 0);
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        for text in ["", "A", "Canon EOS R5", "Ünïcödé", "emoji \u{1F4F7}"] {
+            let value = Value::String(text.to_string());
+            assert_eq!(value.data_type(), DataType::STR);
+            assert!(!DataType::STR.is_array());
+            assert_eq!(round_trip(&value), value);
+        }
+        // A count including the terminator, then UCS-2 characters.
+        assert_eq!(
+            Value::String("AB".into()).to_bytes(),
+            [3, b'A', 0, b'B', 0, 0, 0]
+        );
+        assert_eq!(Value::String(String::new()).to_bytes(), [0]);
+
+        let long = "x".repeat(300);
+        let bytes = Value::String(long.clone()).to_bytes();
+        assert_eq!(bytes[0], 255);
+        assert_eq!(
+            Reader::new(&bytes).read_string().unwrap(),
+            long[..254].to_string()
+        );
+        // Characters after an early terminator are dropped.
+        let bytes = [3, b'A', 0, 0, 0, b'B', 0];
+        assert_eq!(Reader::new(&bytes).read_string().unwrap(), "A");
+    }
+
+    #[test]
+    fn truncated_values_are_malformed() {
+        let mut values = scalars();
+        values.push(Value::String("Nikon".into()));
+        values.push(Value::Array(
+            DataType::UINT32,
+            vec![Value::UInt32(1), Value::UInt32(2)],
+        ));
+        for value in values {
+            let bytes = value.to_bytes();
+            for length in 0..bytes.len() {
+                let result = Reader::new(&bytes[..length]).read_value(value.data_type());
+                assert!(
+                    matches!(result, Err(Error::Malformed(_))),
+                    "{:?} cut to {} bytes gave {:?}",
+                    value,
+                    length,
+                    result
+                );
+            }
+        }
+        // An element count larger than the data is rejected before reading the elements.
+        let bytes = [0xFF, 0xFF, 0xFF, 0xFF, 1, 2];
+        assert!(Reader::new(&bytes).read_u16_array().is_err());
+        assert!(Reader::new(&bytes).read_u32_array().is_err());
+        assert!(Reader::new(&bytes).read_value(DataType::AUINT8).is_err());
+    }
+
+    #[test]
+    fn unknown_types_are_malformed() {
+        for data_type in [DataType(0x000B), DataType(0x4000), DataType(0x400B)] {
+            let result = Reader::new(&[0; 16]).read_value(data_type);
+            assert!(
+                matches!(result, Err(Error::Malformed(_))),
+                "{:?}",
+                data_type
+            );
+        }
+        assert_eq!(format!("{:?}", DataType(0x000B)), "DataType(0x000B)");
+        assert_eq!(format!("{:?}", DataType::AUINT16), "AUINT16");
+    }
+
+    #[test]
+    fn integers_convert_within_their_range() {
+        assert_eq!(
+            Value::from_i64(DataType::UINT8, 255),
+            Some(Value::UInt8(255))
+        );
+        assert_eq!(Value::from_i64(DataType::UINT8, 256), None);
+        assert_eq!(Value::from_i64(DataType::UINT64, -1), None);
+        assert_eq!(
+            Value::from_i64(DataType::INT128, -1),
+            Some(Value::Int128(-1))
+        );
+        assert_eq!(Value::from_i64(DataType::STR, 1), None);
+        assert_eq!(Value::UInt64(u64::MAX).as_i64(), None);
+        assert_eq!(Value::Int8(-5).as_i64(), Some(-5));
+        assert_eq!(Value::String("5".into()).as_i64(), None);
+        assert_eq!(Value::String("5".into()).as_str(), Some("5"));
+    }
+}
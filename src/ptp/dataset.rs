@@ -0,0 +1,525 @@
+//! PTP datasets returned by GetDeviceInfo, GetStorageInfo, GetObjectInfo and GetDevicePropDesc.
+
+use super::data::{DataType, Reader, Value, Writer};
+use super::{DevicePropCode, EventCode, ObjectFormatCode, OperationCode};
+use crate::error::{Error, Result};
+
+/// Dataset describing a device, returned by GetDeviceInfo.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceInfo {
+    pub standard_version: u16,
+    pub vendor_extension_id: u32,
+    pub vendor_extension_version: u16,
+    pub vendor_extension_desc: String,
+    pub functional_mode: u16,
+    pub operations_supported: Vec<OperationCode>,
+    pub events_supported: Vec<EventCode>,
+    pub device_properties_supported: Vec<DevicePropCode>,
+    pub capture_formats: Vec<ObjectFormatCode>,
+    pub playback_formats: Vec<ObjectFormatCode>,
+    pub manufacturer: String,
+    pub model: String,
+    pub device_version: String,
+    pub serial_number: String,
+}
+
+impl DeviceInfo {
+    pub fn decode(bytes: &[u8]) -> Result<DeviceInfo> {
+        let mut r = Reader::new(bytes);
+        Ok(DeviceInfo {
+            standard_version: r.read_u16()?,
+            vendor_extension_id: r.read_u32()?,
+            vendor_extension_version: r.read_u16()?,
+            vendor_extension_desc: r.read_string()?,
+            functional_mode: r.read_u16()?,
+            operations_supported: codes(r.read_u16_array()?),
+            events_supported: codes(r.read_u16_array()?),
+            device_properties_supported: codes(r.read_u16_array()?),
+            capture_formats: codes(r.read_u16_array()?),
+            playback_formats: codes(r.read_u16_array()?),
+            manufacturer: r.read_string()?,
+            model: r.read_string()?,
+            device_version: r.read_string()?,
+            serial_number: r.read_string()?,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.write_u16(self.standard_version);
+        w.write_u32(self.vendor_extension_id);
+        w.write_u16(self.vendor_extension_version);
+        w.write_string(&self.vendor_extension_desc);
+        w.write_u16(self.functional_mode);
+        w.write_u16_array(&raw(&self.operations_supported, |c| c.0));
+        w.write_u16_array(&raw(&self.events_supported, |c| c.0));
+        w.write_u16_array(&raw(&self.device_properties_supported, |c| c.0));
+        w.write_u16_array(&raw(&self.capture_formats, |c| c.0));
+        w.write_u16_array(&raw(&self.playback_formats, |c| c.0));
+        w.write_string(&self.manufacturer);
+        w.write_string(&self.model);
+        w.write_string(&self.device_version);
+        w.write_string(&self.serial_number);
+        w.into_bytes()
+    }
+
+    /// Indicates whether the device reports support for an operation.
+    pub fn supports_operation(&self, code: OperationCode) -> bool {
+        self.operations_supported.contains(&code)
+    }
+
+    /// Indicates whether the device reports support for a device property.
+    pub fn supports_property(&self, code: DevicePropCode) -> bool {
+        self.device_properties_supported.contains(&code)
+    }
+}
+
+/// Dataset describing a storage, returned by GetStorageInfo.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StorageInfo {
+    pub storage_type: u16,
+    pub filesystem_type: u16,
+    pub access_capability: u16,
+    pub max_capacity: u64,
+    pub free_space_in_bytes: u64,
+    pub free_space_in_images: u32,
+    pub storage_description: String,
+    pub volume_label: String,
+}
+
+impl StorageInfo {
+    pub fn decode(bytes: &[u8]) -> Result<StorageInfo> {
+        let mut r = Reader::new(bytes);
+        Ok(StorageInfo {
+            storage_type: r.read_u16()?,
+            filesystem_type: r.read_u16()?,
+            access_capability: r.read_u16()?,
+            max_capacity: r.read_u64()?,
+            free_space_in_bytes: r.read_u64()?,
+            free_space_in_images: r.read_u32()?,
+            storage_description: r.read_string()?,
+            volume_label: r.read_string()?,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.write_u16(self.storage_type);
+        w.write_u16(self.filesystem_type);
+        w.write_u16(self.access_capability);
+        w.write_u64(self.max_capacity);
+        w.write_u64(self.free_space_in_bytes);
+        w.write_u32(self.free_space_in_images);
+        w.write_string(&self.storage_description);
+        w.write_string(&self.volume_label);
+        w.into_bytes()
+    }
+}
+
+/// Dataset describing an object, returned by GetObjectInfo and sent with SendObjectInfo.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjectInfo {
+    pub storage_id: u32,
+    pub object_format: ObjectFormatCode,
+    pub protection_status: u16,
+    pub object_compressed_size: u32,
+    pub thumb_format: ObjectFormatCode,
+    pub thumb_compressed_size: u32,
+    pub thumb_pix_width: u32,
+    pub thumb_pix_height: u32,
+    pub image_pix_width: u32,
+    pub image_pix_height: u32,
+    pub image_bit_depth: u32,
+    pub parent_object: u32,
+    pub association_type: u16,
+    pub association_desc: u32,
+    pub sequence_number: u32,
+    pub filename: String,
+    /// Capture date in the PTP `YYYYMMDDThhmmss` form.
+    pub capture_date: String,
+    /// Modification date in the PTP `YYYYMMDDThhmmss` form.
+    pub modification_date: String,
+    pub keywords: String,
+}
+
+impl ObjectInfo {
+    pub fn decode(bytes: &[u8]) -> Result<ObjectInfo> {
+        let mut r = Reader::new(bytes);
+        Ok(ObjectInfo {
+            storage_id: r.read_u32()?,
+            object_format: ObjectFormatCode(r.read_u16()?),
+            protection_status: r.read_u16()?,
+            object_compressed_size: r.read_u32()?,
+            thumb_format: ObjectFormatCode(r.read_u16()?),
+            thumb_compressed_size: r.read_u32()?,
+            thumb_pix_width: r.read_u32()?,
+            thumb_pix_height: r.read_u32()?,
+            image_pix_width: r.read_u32()?,
+            image_pix_height: r.read_u32()?,
+            image_bit_depth: r.read_u32()?,
+            parent_object: r.read_u32()?,
+            association_type: r.read_u16()?,
+            association_desc: r.read_u32()?,
+            sequence_number: r.read_u32()?,
+            filename: r.read_string()?,
+            capture_date: r.read_string()?,
+            modification_date: r.read_string()?,
+            keywords: r.read_string()?,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.write_u32(self.storage_id);
+        w.write_u16(self.object_format.0);
+        w.write_u16(self.protection_status);
+        w.write_u32(self.object_compressed_size);
+        w.write_u16(self.thumb_format.0);
+        w.write_u32(self.thumb_compressed_size);
+        w.write_u32(self.thumb_pix_width);
+        w.write_u32(self.thumb_pix_height);
+        w.write_u32(self.image_pix_width);
+        w.write_u32(self.image_pix_height);
+        w.write_u32(self.image_bit_depth);
+        w.write_u32(self.parent_object);
+        w.write_u16(self.association_type);
+        w.write_u32(self.association_desc);
+        w.write_u32(self.sequence_number);
+        w.write_string(&self.filename);
+        w.write_string(&self.capture_date);
+        w.write_string(&self.modification_date);
+        w.write_string(&self.keywords);
+        w.into_bytes()
+    }
+
+    /// Indicates whether the object is a folder (an association of the generic folder type).
+    pub fn is_folder(&self) -> bool {
+        self.object_format == ObjectFormatCode::Association
+            && self.association_type == super::ASSOCIATION_GENERIC_FOLDER
+    }
+}
+
+/// Set of values a device property may take.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DevicePropForm {
+    None,
+    Range { min: Value, max: Value, step: Value },
+    Enumeration(Vec<Value>),
+}
+
+impl DevicePropForm {
+    /// Whether `value` is one of the values allowed by this form.
+    pub fn accepts(&self, value: &Value) -> bool {
+        match self {
+            DevicePropForm::None => true,
+            DevicePropForm::Enumeration(values) => values.contains(value),
+            DevicePropForm::Range { min, max, step } => {
+                match (value.as_i64(), min.as_i64(), max.as_i64(), step.as_i64()) {
+                    (Some(v), Some(min), Some(max), Some(step)) => {
+                        // In 128 bits, as `v - min` may not fit in 64.
+                        let offset = i128::from(v) - i128::from(min);
+                        v >= min && v <= max && (step <= 0 || offset % i128::from(step) == 0)
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+/// Dataset describing a device property, returned by GetDevicePropDesc.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DevicePropDesc {
+    pub property_code: DevicePropCode,
+    pub data_type: DataType,
+    /// Indicates whether the property can be set by the initiator.
+    pub writable: bool,
+    pub factory_default: Value,
+    pub current: Value,
+    pub form: DevicePropForm,
+}
+
+impl DevicePropDesc {
+    pub fn decode(bytes: &[u8]) -> Result<DevicePropDesc> {
+        let mut r = Reader::new(bytes);
+        DevicePropDesc::read(&mut r)
+    }
+
+    /// Read a property description from a reader, leaving it positioned after the dataset.
+    pub fn read(r: &mut Reader) -> Result<DevicePropDesc> {
+        let property_code = DevicePropCode(r.read_u16()?);
+        let data_type = DataType(r.read_u16()?);
+        let writable = match r.read_u8()? {
+            0 => false,
+            1 => true,
+            other => return Err(Error::malformed(format!("invalid GetSet flag {}", other))),
+        };
+        let factory_default = r.read_value(data_type)?;
+        let current = r.read_value(data_type)?;
+        let form = match r.read_u8()? {
+            0 => DevicePropForm::None,
+            1 => DevicePropForm::Range {
+                min: r.read_value(data_type)?,
+                max: r.read_value(data_type)?,
+                step: r.read_value(data_type)?,
+            },
+            2 => {
+                let count = r.read_u16()?;
+                let values = (0..count)
+                    .map(|_| r.read_value(data_type))
+                    .collect::<Result<Vec<_>>>()?;
+                DevicePropForm::Enumeration(values)
+            }
+            other => return Err(Error::malformed(format!("invalid form flag {}", other))),
+        };
+        Ok(DevicePropDesc {
+            property_code,
+            data_type,
+            writable,
+            factory_default,
+            current,
+            form,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.write_u16(self.property_code.0);
+        w.write_u16(self.data_type.0);
+        w.write_u8(self.writable as u8);
+        w.write_value(&self.factory_default);
+        w.write_value(&self.current);
+        match &self.form {
+            DevicePropForm::None => w.write_u8(0),
+            DevicePropForm::Range { min, max, step } => {
+                w.write_u8(1);
+                w.write_value(min);
+                w.write_value(max);
+                w.write_value(step);
+            }
+            DevicePropForm::Enumeration(values) => {
+                w.write_u8(2);
+                w.write_u16(values.len() as u16);
+                for value in values {
+                    w.write_value(value);
+                }
+            }
+        }
+        w.into_bytes()
+    }
+
+    /// Decode the current value of this property as sent by GetDevicePropValue.
+    pub fn decode_value(&self, bytes: &[u8]) -> Result<Value> {
+        Reader::new(bytes).read_value(self.data_type)
+    }
+}
+
+fn codes<T: From<u16>>(raw: Vec<u16>) -> Vec<T> {
+    raw.into_iter().map(T::from).collect()
+}
+
+fn raw<T, F: Fn(&T) -> u16>(codes: &[T], f: F) -> Vec<u16> {
+    codes.iter().map(f).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every strict prefix of `bytes` fails to decode as malformed.
+    fn assert_truncations_fail<T: std::fmt::Debug>(bytes: &[u8], decode: fn(&[u8]) -> Result<T>) {
+        for length in 0..bytes.len() {
+            let result = decode(&bytes[..length]);
+            assert!(
+                matches!(result, Err(Error::Malformed(_))),
+                "{} of {} bytes gave {:?}",
+                length,
+                bytes.len(),
+                result
+            );
+        }
+    }
+
+    fn device_info() -> DeviceInfo {
+        DeviceInfo {
+            standard_version: 100,
+            vendor_extension_id: 11,
+            vendor_extension_version: 100,
+            vendor_extension_desc: "microsoft.com: 1.0; canon.com: 1.0".into(),
+            functional_mode: 0,
+            operations_supported: vec![OperationCode::GetDeviceInfo, OperationCode(0x9116)],
+            events_supported: vec![EventCode::ObjectAdded],
+            device_properties_supported: vec![
+                DevicePropCode::FNumber,
+                DevicePropCode::ExposureTime,
+            ],
+            capture_formats: vec![ObjectFormatCode::EXIF_JPEG],
+            playback_formats: vec![ObjectFormatCode::EXIF_JPEG, ObjectFormatCode::Association],
+            manufacturer: "Canon Inc.".into(),
+            model: "Canon EOS R5".into(),
+            device_version: "1-1.8.1".into(),
+            serial_number: "012345678901".into(),
+        }
+    }
+
+    fn object_info() -> ObjectInfo {
+        ObjectInfo {
+            storage_id: 0x0001_0001,
+            object_format: ObjectFormatCode::EXIF_JPEG,
+            protection_status: 1,
+            object_compressed_size: 8_123_456,
+            thumb_format: ObjectFormatCode::JFIF,
+            thumb_compressed_size: 12_000,
+            thumb_pix_width: 160,
+            thumb_pix_height: 120,
+            image_pix_width: 8192,
+            image_pix_height: 5464,
+            image_bit_depth: 24,
+            parent_object: 0x9000_0001,
+            association_type: 0,
+            association_desc: 0,
+            sequence_number: 0,
+            filename: "IMG_0001.JPG".into(),
+            capture_date: "20210504T120000".into(),
+            modification_date: "20210504T120001.5".into(),
+            keywords: String::new(),
+        }
+    }
+
+    #[test]
+    fn device_info_round_trips() {
+        let info = device_info();
+        let bytes = info.encode();
+        assert_eq!(DeviceInfo::decode(&bytes).unwrap(), info);
+        assert!(info.supports_operation(OperationCode::GetDeviceInfo));
+        assert!(!info.supports_operation(OperationCode(0x1002)));
+        assert!(info.supports_property(DevicePropCode::ExposureTime));
+        assert_eq!(
+            DeviceInfo::decode(&DeviceInfo::default().encode()).unwrap(),
+            DeviceInfo::default()
+        );
+        assert_truncations_fail(&bytes, DeviceInfo::decode);
+    }
+
+    #[test]
+    fn storage_info_round_trips() {
+        let info = StorageInfo {
+            storage_type: 4,
+            filesystem_type: 2,
+            access_capability: 0,
+            max_capacity: 256 << 30,
+            free_space_in_bytes: 100 << 30,
+            free_space_in_images: u32::MAX,
+            storage_description: "SD".into(),
+            volume_label: "EOS_DIGITAL".into(),
+        };
+        let bytes = info.encode();
+        assert_eq!(bytes.len(), 26 + 7 + 25);
+        assert_eq!(StorageInfo::decode(&bytes).unwrap(), info);
+        assert_truncations_fail(&bytes, StorageInfo::decode);
+    }
+
+    #[test]
+    fn object_info_round_trips() {
+        let info = object_info();
+        let bytes = info.encode();
+        assert_eq!(ObjectInfo::decode(&bytes).unwrap(), info);
+        assert!(!info.is_folder());
+        assert_truncations_fail(&bytes, ObjectInfo::decode);
+
+        let folder = ObjectInfo {
+            object_format: ObjectFormatCode::Association,
+            association_type: super::super::ASSOCIATION_GENERIC_FOLDER,
+            filename: "100CANON".into(),
+            ..ObjectInfo::default()
+        };
+        let decoded = ObjectInfo::decode(&folder.encode()).unwrap();
+        assert!(decoded.is_folder());
+    }
+
+    #[test]
+    fn device_prop_descs_round_trip() {
+        let descs = [
+            DevicePropDesc {
+                property_code: DevicePropCode::FNumber,
+                data_type: DataType::UINT16,
+                writable: true,
+                factory_default: Value::UInt16(400),
+                current: Value::UInt16(560),
+                form: DevicePropForm::Enumeration(vec![
+                    Value::UInt16(400),
+                    Value::UInt16(560),
+                    Value::UInt16(800),
+                ]),
+            },
+            DevicePropDesc {
+                property_code: DevicePropCode::ExposureBiasCompensation,
+                data_type: DataType::INT16,
+                writable: true,
+                factory_default: Value::Int16(0),
+                current: Value::Int16(-333),
+                form: DevicePropForm::Range {
+                    min: Value::Int16(-3000),
+                    max: Value::Int16(3000),
+                    step: Value::Int16(333),
+                },
+            },
+            DevicePropDesc {
+                property_code: DevicePropCode::DateTime,
+                data_type: DataType::STR,
+                writable: false,
+                factory_default: Value::String(String::new()),
+                current: Value::String("20210504T120000".into()),
+                form: DevicePropForm::None,
+            },
+            DevicePropDesc {
+                property_code: DevicePropCode(0xD1D3),
+                data_type: DataType::AUINT8,
+                writable: false,
+                factory_default: Value::Array(DataType::UINT8, Vec::new()),
+                current: Value::Array(DataType::UINT8, vec![Value::UInt8(1), Value::UInt8(2)]),
+                form: DevicePropForm::None,
+            },
+        ];
+        for desc in &descs {
+            let bytes = desc.encode();
+            assert_eq!(DevicePropDesc::decode(&bytes).unwrap(), *desc);
+            assert_truncations_fail(&bytes, DevicePropDesc::decode);
+            assert_eq!(
+                desc.decode_value(&desc.current.to_bytes()).unwrap(),
+                desc.current
+            );
+        }
+
+        // Descriptions follow one another in vendor property lists.
+        let mut list = descs[0].encode();
+        list.extend(descs[1].encode());
+        let mut r = Reader::new(&list);
+        assert_eq!(DevicePropDesc::read(&mut r).unwrap(), descs[0]);
+        assert_eq!(DevicePropDesc::read(&mut r).unwrap(), descs[1]);
+        assert_eq!(r.remaining(), 0);
+    }
+
+    #[test]
+    fn invalid_flags_are_malformed() {
+        let bytes = DevicePropDesc {
+            property_code: DevicePropCode::FNumber,
+            data_type: DataType::UINT16,
+            writable: true,
+            factory_default: Value::UInt16(400),
+            current: Value::UInt16(400),
+            form: DevicePropForm::None,
+        }
+        .encode();
+        let mut get_set = bytes.clone();
+        get_set[4] = 2;
+        let mut form = bytes;
+        *form.last_mut().unwrap() = 3;
+        for bytes in [get_set, form] {
+            assert!(matches!(
+                DevicePropDesc::decode(&bytes),
+                Err(Error::Malformed(_))
+            ));
+        }
+    }
+}
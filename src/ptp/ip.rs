@@ -0,0 +1,552 @@
+//! PTP/IP (CIPA DC-005) transport for cameras tethered over Wi-Fi or Ethernet.
+//!
+//! A PTP/IP connection uses two TCP connections to the same port: a command channel
+//! carrying operations, data and responses, and an event channel carrying events.
+
+use super::{
+    Event, EventCode, Operation, OperationCode, PtpCamera, Response, ResponseCode, Session,
+    Transport,
+};
+use crate::error::{Error, Result};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// TCP port assigned to PTP/IP.
+pub const PTPIP_PORT: u16 = 15740;
+
+/// PTP/IP protocol version 1.0.
+pub const PROTOCOL_VERSION: u32 = 0x0001_0000;
+
+/// Data phase announced in an operation request.
+pub const DATA_PHASE_NONE_OR_IN: u32 = 1;
+pub const DATA_PHASE_OUT: u32 = 2;
+
+/// Size of the data packets used when sending a data phase.
+const DATA_CHUNK_LEN: usize = 64 * 1024;
+
+/// Largest packet accepted from the peer.
+const MAX_PACKET_LEN: usize = 64 * 1024 * 1024;
+
+/// A PTP/IP packet.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+    InitCommandRequest {
+        guid: [u8; 16],
+        name: String,
+        version: u32,
+    },
+    InitCommandAck {
+        connection_number: u32,
+        guid: [u8; 16],
+        name: String,
+        version: u32,
+    },
+    InitEventRequest {
+        connection_number: u32,
+    },
+    InitEventAck,
+    InitFail {
+        reason: u32,
+    },
+    OperationRequest {
+        data_phase: u32,
+        operation: Operation,
+    },
+    OperationResponse(Response),
+    Event(Event),
+    StartData {
+        transaction_id: u32,
+        total_length: u64,
+    },
+    Data {
+        transaction_id: u32,
+        payload: Vec<u8>,
+    },
+    Cancel {
+        transaction_id: u32,
+    },
+    EndData {
+        transaction_id: u32,
+        payload: Vec<u8>,
+    },
+    ProbeRequest,
+    ProbeResponse,
+}
+
+impl Packet {
+    fn packet_type(&self) -> u32 {
+        match self {
+            Packet::InitCommandRequest { .. } => 1,
+            Packet::InitCommandAck { .. } => 2,
+            Packet::InitEventRequest { .. } => 3,
+            Packet::InitEventAck => 4,
+            Packet::InitFail { .. } => 5,
+            Packet::OperationRequest { .. } => 6,
+            Packet::OperationResponse(_) => 7,
+            Packet::Event(_) => 8,
+            Packet::StartData { .. } => 9,
+            Packet::Data { .. } => 10,
+            Packet::Cancel { .. } => 11,
+            Packet::EndData { .. } => 12,
+            Packet::ProbeRequest => 13,
+            Packet::ProbeResponse => 14,
+        }
+    }
+
+    /// Encode the packet including its length and type header.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            Packet::InitCommandRequest {
+                guid,
+                name,
+                version,
+            } => {
+                body.extend_from_slice(guid);
+                write_name(&mut body, name);
+                body.extend_from_slice(&version.to_le_bytes());
+            }
+            Packet::InitCommandAck {
+                connection_number,
+                guid,
+                name,
+                version,
+            } => {
+                body.extend_from_slice(&connection_number.to_le_bytes());
+                body.extend_from_slice(guid);
+                write_name(&mut body, name);
+                body.extend_from_slice(&version.to_le_bytes());
+            }
+            Packet::InitEventRequest { connection_number } => {
+                body.extend_from_slice(&connection_number.to_le_bytes())
+            }
+            Packet::InitEventAck | Packet::ProbeRequest | Packet::ProbeResponse => {}
+            Packet::InitFail { reason } => body.extend_from_slice(&reason.to_le_bytes()),
+            Packet::OperationRequest {
+                data_phase,
+                operation,
+            } => {
+                body.extend_from_slice(&data_phase.to_le_bytes());
+                body.extend_from_slice(&operation.code.0.to_le_bytes());
+                body.extend_from_slice(&operation.transaction_id.to_le_bytes());
+                write_params(&mut body, &operation.params);
+            }
+            Packet::OperationResponse(response) => {
+                body.extend_from_slice(&response.code.0.to_le_bytes());
+                body.extend_from_slice(&response.transaction_id.to_le_bytes());
+                write_params(&mut body, &response.params);
+            }
+            Packet::Event(event) => {
+                body.extend_from_slice(&event.code.0.to_le_bytes());
+                body.extend_from_slice(&event.transaction_id.to_le_bytes());
+                write_params(&mut body, &event.params);
+            }
+            Packet::StartData {
+                transaction_id,
+                total_length,
+            } => {
+                body.extend_from_slice(&transaction_id.to_le_bytes());
+                body.extend_from_slice(&total_length.to_le_bytes());
+            }
+            Packet::Data {
+                transaction_id,
+                payload,
+            }
+            | Packet::EndData {
+                transaction_id,
+                payload,
+            } => {
+                body.extend_from_slice(&transaction_id.to_le_bytes());
+                body.extend_from_slice(payload);
+            }
+            Packet::Cancel { transaction_id } => {
+                body.extend_from_slice(&transaction_id.to_le_bytes())
+            }
+        }
+        let mut bytes = Vec::with_capacity(8 + body.len());
+        bytes.extend_from_slice(&((8 + body.len()) as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.packet_type().to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    /// Decode a packet body of the given type.
+    pub fn decode(packet_type: u32, body: &[u8]) -> Result<Packet> {
+        let mut r = super::Reader::new(body);
+        let packet = match packet_type {
+            1 => Packet::InitCommandRequest {
+                guid: read_guid(&mut r)?,
+                name: read_name(&mut r)?,
+                version: r.read_u32()?,
+            },
+            2 => Packet::InitCommandAck {
+                connection_number: r.read_u32()?,
+                guid: read_guid(&mut r)?,
+                name: read_name(&mut r)?,
+                version: r.read_u32()?,
+            },
+            3 => Packet::InitEventRequest {
+                connection_number: r.read_u32()?,
+            },
+            4 => Packet::InitEventAck,
+            5 => Packet::InitFail {
+                reason: r.read_u32()?,
+            },
+            6 => Packet::OperationRequest {
+                data_phase: r.read_u32()?,
+                operation: Operation {
+                    code: OperationCode(r.read_u16()?),
+                    transaction_id: r.read_u32()?,
+                    params: read_params(&mut r)?,
+                },
+            },
+            7 => Packet::OperationResponse(Response {
+                code: ResponseCode(r.read_u16()?),
+                transaction_id: r.read_u32()?,
+                params: read_params(&mut r)?,
+            }),
+            8 => Packet::Event(Event {
+                code: EventCode(r.read_u16()?),
+                transaction_id: r.read_u32()?,
+                params: read_params(&mut r)?,
+            }),
+            9 => Packet::StartData {
+                transaction_id: r.read_u32()?,
+                total_length: r.read_u64()?,
+            },
+            10 => Packet::Data {
+                transaction_id: r.read_u32()?,
+                payload: r.rest().to_vec(),
+            },
+            11 => Packet::Cancel {
+                transaction_id: r.read_u32()?,
+            },
+            12 => Packet::EndData {
+                transaction_id: r.read_u32()?,
+                payload: r.rest().to_vec(),
+            },
+            13 => Packet::ProbeRequest,
+            14 => Packet::ProbeResponse,
+            other => {
+                return Err(Error::malformed(format!(
+                    "unknown PTP/IP packet type {}",
+                    other
+                )))
+            }
+        };
+        Ok(packet)
+    }
+
+    /// Read one packet from a stream.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Packet> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let packet_type = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if !(8..=MAX_PACKET_LEN).contains(&length) {
+            return Err(Error::malformed(format!(
+                "invalid PTP/IP packet length {}",
+                length
+            )));
+        }
+        let mut body = vec![0u8; length - 8];
+        reader.read_exact(&mut body)?;
+        Packet::decode(packet_type, &body)
+    }
+
+    /// Write this packet to a stream.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.encode())?;
+        Ok(())
+    }
+}
+
+fn write_name(body: &mut Vec<u8>, name: &str) {
+    for unit in name.encode_utf16() {
+        body.extend_from_slice(&unit.to_le_bytes());
+    }
+    body.extend_from_slice(&[0, 0]);
+}
+
+fn read_name(r: &mut super::Reader) -> Result<String> {
+    let mut units = Vec::new();
+    loop {
+        match r.read_u16()? {
+            0 => break,
+            unit => units.push(unit),
+        }
+    }
+    Ok(String::from_utf16_lossy(&units))
+}
+
+fn read_guid(r: &mut super::Reader) -> Result<[u8; 16]> {
+    let mut guid = [0u8; 16];
+    guid.copy_from_slice(r.take(16)?);
+    Ok(guid)
+}
+
+fn write_params(body: &mut Vec<u8>, params: &[u32]) {
+    for param in params {
+        body.extend_from_slice(&param.to_le_bytes());
+    }
+}
+
+fn read_params(r: &mut super::Reader) -> Result<Vec<u32>> {
+    if !r.remaining().is_multiple_of(4) || r.remaining() / 4 > super::container::MAX_PARAMS {
+        return Err(Error::malformed("invalid PTP/IP parameter block"));
+    }
+    let mut params = Vec::new();
+    while r.remaining() > 0 {
+        params.push(r.read_u32()?);
+    }
+    Ok(params)
+}
+
+/// The command channel of a PTP/IP connection.
+#[derive(Debug)]
+pub struct PtpIpTransport {
+    stream: TcpStream,
+    /// The event channel, shared with the thread reading from it.
+    events: TcpStream,
+    connection_number: u32,
+    device_guid: [u8; 16],
+    device_name: String,
+}
+
+impl PtpIpTransport {
+    /// Perform the Init Command and Init Event handshakes with a responder.
+    ///
+    /// `guid` and `friendly_name` identify this initiator; cameras usually ask the user to
+    /// approve a new initiator on first connection. Events are delivered on the returned
+    /// receiver by a background thread until the connection is closed.
+    pub fn connect<A: ToSocketAddrs>(
+        addr: A,
+        guid: [u8; 16],
+        friendly_name: &str,
+    ) -> Result<(PtpIpTransport, Receiver<Event>)> {
+        let addrs: Vec<_> = addr.to_socket_addrs()?.collect();
+        let mut stream = TcpStream::connect(&addrs[..])?;
+        stream.set_nodelay(true)?;
+        Packet::InitCommandRequest {
+            guid,
+            name: friendly_name.to_string(),
+            version: PROTOCOL_VERSION,
+        }
+        .write_to(&mut stream)?;
+        let (connection_number, device_guid, device_name) = match Packet::read_from(&mut stream)? {
+            Packet::InitCommandAck {
+                connection_number,
+                guid,
+                name,
+                ..
+            } => (connection_number, guid, name),
+            Packet::InitFail { reason } => {
+                return Err(Error::malformed(format!(
+                    "responder rejected the connection (reason {})",
+                    reason
+                )))
+            }
+            other => return Err(unexpected(&other)),
+        };
+
+        let mut events = TcpStream::connect(stream.peer_addr()?)?;
+        Packet::InitEventRequest { connection_number }.write_to(&mut events)?;
+        match Packet::read_from(&mut events)? {
+            Packet::InitEventAck => {}
+            Packet::InitFail { reason } => {
+                return Err(Error::malformed(format!(
+                    "responder rejected the event channel (reason {})",
+                    reason
+                )))
+            }
+            other => return Err(unexpected(&other)),
+        }
+        let (sender, receiver) = mpsc::channel();
+        let event_channel = events.try_clone()?;
+        thread::spawn(move || pump_events(events, sender));
+
+        Ok((
+            PtpIpTransport {
+                stream,
+                events: event_channel,
+                connection_number,
+                device_guid,
+                device_name,
+            },
+            receiver,
+        ))
+    }
+
+    /// Connection number assigned by the responder.
+    pub fn connection_number(&self) -> u32 {
+        self.connection_number
+    }
+
+    /// GUID reported by the responder.
+    pub fn device_guid(&self) -> [u8; 16] {
+        self.device_guid
+    }
+
+    /// Friendly name reported by the responder.
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
+    /// Limit how long a transaction may wait for the responder.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.stream.set_read_timeout(timeout)?;
+        self.stream.set_write_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Close both channels of the connection.
+    pub fn shutdown(&self) -> Result<()> {
+        let command = self.stream.shutdown(Shutdown::Both);
+        self.events.shutdown(Shutdown::Both)?;
+        command?;
+        Ok(())
+    }
+}
+
+impl Drop for PtpIpTransport {
+    fn drop(&mut self) {
+        // The event thread holds its own handle on the socket; end it so the thread exits.
+        let _ = self.events.shutdown(Shutdown::Both);
+    }
+}
+
+impl Transport for PtpIpTransport {
+    fn execute(
+        &mut self,
+        operation: &Operation,
+        data_out: Option<&[u8]>,
+        data_in: &mut dyn Write,
+    ) -> Result<Response> {
+        let transaction_id = operation.transaction_id;
+        Packet::OperationRequest {
+            data_phase: if data_out.is_some() {
+                DATA_PHASE_OUT
+            } else {
+                DATA_PHASE_NONE_OR_IN
+            },
+            operation: operation.clone(),
+        }
+        .write_to(&mut self.stream)?;
+
+        if let Some(data) = data_out {
+            Packet::StartData {
+                transaction_id,
+                total_length: data.len() as u64,
+            }
+            .write_to(&mut self.stream)?;
+            let mut chunks = data.chunks(DATA_CHUNK_LEN).peekable();
+            if chunks.peek().is_none() {
+                Packet::EndData {
+                    transaction_id,
+                    payload: Vec::new(),
+                }
+                .write_to(&mut self.stream)?;
+            }
+            while let Some(chunk) = chunks.next() {
+                let payload = chunk.to_vec();
+                let packet = if chunks.peek().is_some() {
+                    Packet::Data {
+                        transaction_id,
+                        payload,
+                    }
+                } else {
+                    Packet::EndData {
+                        transaction_id,
+                        payload,
+                    }
+                };
+                packet.write_to(&mut self.stream)?;
+            }
+        }
+
+        loop {
+            match Packet::read_from(&mut self.stream)? {
+                Packet::StartData { .. } => {}
+                Packet::Data { payload, .. } | Packet::EndData { payload, .. } => {
+                    data_in.write_all(&payload)?
+                }
+                Packet::OperationResponse(response) => return Ok(response),
+                Packet::ProbeRequest => Packet::ProbeResponse.write_to(&mut self.stream)?,
+                other => return Err(unexpected(&other)),
+            }
+        }
+    }
+}
+
+/// Connect to a PTP/IP camera and open a session on it.
+pub fn connect<A: ToSocketAddrs>(
+    addr: A,
+    guid: [u8; 16],
+    friendly_name: &str,
+) -> Result<PtpCamera<PtpIpTransport>> {
+    let (transport, events) = PtpIpTransport::connect(addr, guid, friendly_name)?;
+    let session = Session::open(transport, 1)?;
+    PtpCamera::new(session, events)
+}
+
+fn pump_events(mut stream: TcpStream, sender: Sender<Event>) {
+    loop {
+        match Packet::read_from(&mut stream) {
+            Ok(Packet::Event(event)) => {
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+            Ok(Packet::ProbeRequest) => {
+                if Packet::ProbeResponse.write_to(&mut stream).is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+}
+
+fn unexpected(packet: &Packet) -> Error {
+    Error::malformed(format!("unexpected PTP/IP packet {:?}", packet))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ptp::responder::VirtualCamera;
+    use std::net::TcpListener;
+    use std::sync::mpsc::RecvTimeoutError;
+
+    fn serve() -> std::net::SocketAddr {
+        let camera = VirtualCamera::new("Test", "Virtual");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || camera.serve_ptpip(listener));
+        address
+    }
+
+    #[test]
+    fn shutdown_closes_the_event_channel() {
+        let (transport, events) = PtpIpTransport::connect(serve(), [1; 16], "test").unwrap();
+        transport.shutdown().unwrap();
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn drop_closes_the_event_channel() {
+        let (transport, events) = PtpIpTransport::connect(serve(), [1; 16], "test").unwrap();
+        drop(transport);
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Live view frames pulled from a tethered camera at a target frame rate.
+
+use super::{PtpCamera, Transport};
+use crate::error::{Error, Result};
+use crate::jpeg;
+use std::convert::TryFrom;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval between requests while the camera has no frame ready.
+const NOT_READY_INTERVAL: Duration = Duration::from_millis(20);
+/// Time without a frame after which the camera is considered stalled.
+const NOT_READY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The autofocus area shown over a live view frame.
+///
+/// Coordinates are in pixels of a `frame_width` × `frame_height` image, which is the
+/// full sensor area and may be larger than the JPEG frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FocusArea {
+    /// Center of the area.
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub frame_width: u32,
+    pub frame_height: u32,
+}
+
+/// A live view frame and the overlay information sent with it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LiveViewFrame {
+    /// The frame as a JPEG image.
+    pub jpeg: Vec<u8>,
+    /// The autofocus area, if the camera reports it.
+    pub focus: Option<FocusArea>,
+    /// The luminance histogram, if the camera reports it.
+    pub camera_histogram: Option<Vec<u32>>,
+    /// Position of the frame in the stream, starting at 0.
+    pub sequence: u64,
+}
+
+impl LiveViewFrame {
+    /// A frame carrying a JPEG image and no overlay information.
+    pub fn new(jpeg: Vec<u8>) -> LiveViewFrame {
+        LiveViewFrame {
+            jpeg,
+            focus: None,
+            camera_histogram: None,
+            sequence: 0,
+        }
+    }
+
+    /// Width and height of the JPEG image.
+    pub fn dimensions(&self) -> Result<(u32, u32)> {
+        jpeg::dimensions(&self.jpeg)
+    }
+
+    /// Luminance histogram of the frame: the camera's, if it has 256 levels, or else one
+    /// computed from the image; see `jpeg::luma_histogram`.
+    pub fn histogram(&self) -> Result<[u32; 256]> {
+        let reported = self.camera_histogram.as_deref().map(<[u32; 256]>::try_from);
+        match reported {
+            Some(Ok(histogram)) => Ok(histogram),
+            _ => jpeg::luma_histogram(&self.jpeg),
+        }
+    }
+}
+
+/// A running live view, yielding frames no faster than the target frame rate.
+///
+/// Live view is stopped when this value is dropped. The iterator ends after the first error.
+pub struct LiveView<'a, T: Transport> {
+    camera: &'a PtpCamera<T>,
+    interval: Duration,
+    next_due: Instant,
+    sequence: u64,
+    running: bool,
+}
+
+impl<'a, T: Transport> LiveView<'a, T> {
+    pub(crate) fn start(camera: &'a PtpCamera<T>, frame_rate: f64) -> Result<LiveView<'a, T>> {
+        if frame_rate.is_nan() || frame_rate <= 0.0 {
+            return Err(Error::InvalidValue(format!(
+                "frame rate {} is not positive",
+                frame_rate
+            )));
+        }
+        let vendor = camera
+            .vendor()
+            .ok_or_else(|| Error::Unsupported("live view".into()))?;
+        vendor.start_live_view(&mut camera.session())?;
+        Ok(LiveView {
+            camera,
+            interval: Duration::from_secs_f64(1.0 / frame_rate),
+            next_due: Instant::now(),
+            sequence: 0,
+            running: true,
+        })
+    }
+
+    /// Change the target frame rate.
+    pub fn set_frame_rate(&mut self, frame_rate: f64) {
+        if frame_rate > 0.0 {
+            self.interval = Duration::from_secs_f64(1.0 / frame_rate);
+        }
+    }
+
+    /// Fetch the next frame, waiting for the frame interval and for the camera to have one ready.
+    pub fn next_frame(&mut self) -> Result<LiveViewFrame> {
+        if !self.running {
+            return Err(Error::InvalidValue("live view is stopped".into()));
+        }
+        let now = Instant::now();
+        if self.next_due > now {
+            thread::sleep(self.next_due - now);
+        }
+        let vendor = self
+            .camera
+            .vendor()
+            .ok_or_else(|| Error::Unsupported("live view".into()))?;
+        let started = Instant::now();
+        loop {
+            if let Some(mut frame) = vendor.live_view_frame(&mut self.camera.session())? {
+                frame.sequence = self.sequence;
+                self.sequence += 1;
+                // Do not try to catch up on frames lost to a slow camera.
+                self.next_due = (self.next_due + self.interval).max(Instant::now());
+                return Ok(frame);
+            }
+            if started.elapsed() > NOT_READY_TIMEOUT {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the camera did not deliver a live view frame",
+                )));
+            }
+            thread::sleep(NOT_READY_INTERVAL);
+        }
+    }
+
+    /// Stop live view.
+    pub fn stop(mut self) -> Result<()> {
+        self.stop_inner()
+    }
+
+    fn stop_inner(&mut self) -> Result<()> {
+        if !self.running {
+            return Ok(());
+        }
+        self.running = false;
+        match self.camera.vendor() {
+            Some(vendor) => vendor.stop_live_view(&mut self.camera.session()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a, T: Transport> Iterator for LiveView<'a, T> {
+    type Item = Result<LiveViewFrame>;
+
+    fn next(&mut self) -> Option<Result<LiveViewFrame>> {
+        if !self.running {
+            return None;
+        }
+        let frame = self.next_frame();
+        if frame.is_err() {
+            let _ = self.stop_inner();
+        }
+        Some(frame)
+    }
+}
+
+impl<'a, T: Transport> Drop for LiveView<'a, T> {
+    fn drop(&mut self) {
+        let _ = self.stop_inner();
+    }
+}
@@ -0,0 +1,272 @@
+//! Picture Transfer Protocol (ISO 15740) codec.
+//!
+//! These types can be used to build the command and data blobs passed to
+//! `ICCameraDevice::requestSendPTPCommand`, and to decode what the camera sends back.
+
+use std::fmt;
+
+pub mod camera;
+pub mod container;
+pub mod data;
+pub mod dataset;
+pub mod ip;
+pub mod liveview;
+pub mod properties;
+pub mod responder;
+pub mod session;
+pub mod stream;
+pub mod vendor;
+
+pub use self::camera::PtpCamera;
+pub use self::container::{Container, ContainerType, DataContainer, Event, Operation, Response};
+pub use self::data::{DataType, Reader, Value, Writer};
+pub use self::dataset::{DeviceInfo, DevicePropDesc, DevicePropForm, ObjectInfo, StorageInfo};
+pub use self::properties::{Property, PropertyValue};
+pub use self::responder::VirtualCamera;
+pub use self::session::{Session, Transport};
+pub use self::stream::StreamTransport;
+
+macro_rules! ptp_codes {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident;
+        $(
+            $(#[$doc:meta])*
+            const $konst:ident = $value:literal;
+        )*
+    ) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(pub u16);
+
+        impl $name {
+            $(
+                $(#[$doc])*
+                pub const $konst: $name = $name($value);
+            )*
+
+            /// Name of the code as given in the PTP specification, if it is a standard code.
+            pub fn name(self) -> Option<&'static str> {
+                match self.0 {
+                    $($value => Some(stringify!($konst)),)*
+                    _ => None,
+                }
+            }
+
+            /// Indicates whether the code is vendor-defined (its most significant bit is set).
+            pub fn is_vendor(self) -> bool {
+                self.0 & 0x8000 != 0
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self.name() {
+                    Some(name) => write!(f, "{}(0x{:04X})", name, self.0),
+                    None => write!(f, "{}(0x{:04X})", stringify!($name), self.0),
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self.name() {
+                    Some(name) => f.write_str(name),
+                    None => write!(f, "0x{:04X}", self.0),
+                }
+            }
+        }
+
+        impl From<u16> for $name {
+            fn from(code: u16) -> $name {
+                $name(code)
+            }
+        }
+    };
+}
+
+ptp_codes! {
+    /// Code identifying a PTP operation.
+    pub struct OperationCode;
+    const Undefined = 0x1000;
+    const GetDeviceInfo = 0x1001;
+    const OpenSession = 0x1002;
+    const CloseSession = 0x1003;
+    const GetStorageIDs = 0x1004;
+    const GetStorageInfo = 0x1005;
+    const GetNumObjects = 0x1006;
+    const GetObjectHandles = 0x1007;
+    const GetObjectInfo = 0x1008;
+    const GetObject = 0x1009;
+    const GetThumb = 0x100A;
+    const DeleteObject = 0x100B;
+    const SendObjectInfo = 0x100C;
+    const SendObject = 0x100D;
+    const InitiateCapture = 0x100E;
+    const FormatStore = 0x100F;
+    const ResetDevice = 0x1010;
+    const SelfTest = 0x1011;
+    const SetObjectProtection = 0x1012;
+    const PowerDown = 0x1013;
+    const GetDevicePropDesc = 0x1014;
+    const GetDevicePropValue = 0x1015;
+    const SetDevicePropValue = 0x1016;
+    const ResetDevicePropValue = 0x1017;
+    const TerminateOpenCapture = 0x1018;
+    const MoveObject = 0x1019;
+    const CopyObject = 0x101A;
+    const GetPartialObject = 0x101B;
+    const InitiateOpenCapture = 0x101C;
+    const StartEnumHandles = 0x101D;
+    const EnumHandles = 0x101E;
+    const StopEnumHandles = 0x101F;
+    const GetVendorExtensionMaps = 0x1020;
+    const GetVendorDeviceInfo = 0x1021;
+    const GetResizedImageObject = 0x1022;
+    const GetFilesystemManifest = 0x1023;
+    const GetStreamInfo = 0x1024;
+    const GetStream = 0x1025;
+}
+
+ptp_codes! {
+    /// Code identifying the result of a PTP operation.
+    pub struct ResponseCode;
+    const Undefined = 0x2000;
+    const OK = 0x2001;
+    const GeneralError = 0x2002;
+    const SessionNotOpen = 0x2003;
+    const InvalidTransactionID = 0x2004;
+    const OperationNotSupported = 0x2005;
+    const ParameterNotSupported = 0x2006;
+    const IncompleteTransfer = 0x2007;
+    const InvalidStorageID = 0x2008;
+    const InvalidObjectHandle = 0x2009;
+    const DevicePropNotSupported = 0x200A;
+    const InvalidObjectFormatCode = 0x200B;
+    const StoreFull = 0x200C;
+    const ObjectWriteProtected = 0x200D;
+    const StoreReadOnly = 0x200E;
+    const AccessDenied = 0x200F;
+    const NoThumbnailPresent = 0x2010;
+    const SelfTestFailed = 0x2011;
+    const PartialDeletion = 0x2012;
+    const StoreNotAvailable = 0x2013;
+    const SpecificationByFormatUnsupported = 0x2014;
+    const NoValidObjectInfo = 0x2015;
+    const InvalidCodeFormat = 0x2016;
+    const UnknownVendorCode = 0x2017;
+    const CaptureAlreadyTerminated = 0x2018;
+    const DeviceBusy = 0x2019;
+    const InvalidParentObject = 0x201A;
+    const InvalidDevicePropFormat = 0x201B;
+    const InvalidDevicePropValue = 0x201C;
+    const InvalidParameter = 0x201D;
+    const SessionAlreadyOpen = 0x201E;
+    const TransactionCancelled = 0x201F;
+    const SpecificationOfDestinationUnsupported = 0x2020;
+}
+
+ptp_codes! {
+    /// Code identifying an event sent by a PTP device.
+    pub struct EventCode;
+    const Undefined = 0x4000;
+    const CancelTransaction = 0x4001;
+    const ObjectAdded = 0x4002;
+    const ObjectRemoved = 0x4003;
+    const StoreAdded = 0x4004;
+    const StoreRemoved = 0x4005;
+    const DevicePropChanged = 0x4006;
+    const ObjectInfoChanged = 0x4007;
+    const DeviceInfoChanged = 0x4008;
+    const RequestObjectTransfer = 0x4009;
+    const StoreFull = 0x400A;
+    const DeviceReset = 0x400B;
+    const StorageInfoChanged = 0x400C;
+    const CaptureComplete = 0x400D;
+    const UnreportedStatus = 0x400E;
+}
+
+ptp_codes! {
+    /// Code identifying the format of an object stored on a PTP device.
+    pub struct ObjectFormatCode;
+    const Undefined = 0x3000;
+    const Association = 0x3001;
+    const Script = 0x3002;
+    const Executable = 0x3003;
+    const Text = 0x3004;
+    const HTML = 0x3005;
+    const DPOF = 0x3006;
+    const AIFF = 0x3007;
+    const WAV = 0x3008;
+    const MP3 = 0x3009;
+    const AVI = 0x300A;
+    const MPEG = 0x300B;
+    const ASF = 0x300C;
+    const UndefinedImage = 0x3800;
+    const EXIF_JPEG = 0x3801;
+    const TIFF_EP = 0x3802;
+    const FlashPix = 0x3803;
+    const BMP = 0x3804;
+    const CIFF = 0x3805;
+    const GIF = 0x3807;
+    const JFIF = 0x3808;
+    const PCD = 0x3809;
+    const PICT = 0x380A;
+    const PNG = 0x380B;
+    const TIFF = 0x380D;
+    const TIFF_IT = 0x380E;
+    const JP2 = 0x380F;
+    const JPX = 0x3810;
+    const DNG = 0x3811;
+}
+
+ptp_codes! {
+    /// Code identifying a device property.
+    pub struct DevicePropCode;
+    const Undefined = 0x5000;
+    const BatteryLevel = 0x5001;
+    const FunctionalMode = 0x5002;
+    const ImageSize = 0x5003;
+    const CompressionSetting = 0x5004;
+    const WhiteBalance = 0x5005;
+    const RGBGain = 0x5006;
+    const FNumber = 0x5007;
+    const FocalLength = 0x5008;
+    const FocusDistance = 0x5009;
+    const FocusMode = 0x500A;
+    const ExposureMeteringMode = 0x500B;
+    const FlashMode = 0x500C;
+    const ExposureTime = 0x500D;
+    const ExposureProgramMode = 0x500E;
+    const ExposureIndex = 0x500F;
+    const ExposureBiasCompensation = 0x5010;
+    const DateTime = 0x5011;
+    const CaptureDelay = 0x5012;
+    const StillCaptureMode = 0x5013;
+    const Contrast = 0x5014;
+    const Sharpness = 0x5015;
+    const DigitalZoom = 0x5016;
+    const EffectMode = 0x5017;
+    const BurstNumber = 0x5018;
+    const BurstInterval = 0x5019;
+    const TimelapseNumber = 0x501A;
+    const TimelapseInterval = 0x501B;
+    const FocusMeteringMode = 0x501C;
+    const UploadURL = 0x501D;
+    const Artist = 0x501E;
+    const CopyrightInfo = 0x501F;
+}
+
+/// Association type of a generic folder object.
+pub const ASSOCIATION_GENERIC_FOLDER: u16 = 0x0001;
+
+/// Protection status of an object that may be deleted.
+pub const PROTECTION_NONE: u16 = 0x0000;
+/// Protection status of a read-only object.
+pub const PROTECTION_READ_ONLY: u16 = 0x0001;
+
+/// Storage ID meaning "all storages" in GetNumObjects and GetObjectHandles.
+pub const ALL_STORAGES: u32 = 0xFFFF_FFFF;
+/// Parent handle meaning "root of the storage" in GetObjectHandles.
+/// Objects at the root of a storage report a parent of 0 in their ObjectInfo.
+pub const ROOT_PARENT: u32 = 0xFFFF_FFFF;
@@ -0,0 +1,609 @@
+//! Shooting settings exposed as PTP device properties, decoded into photographic units.
+
+use super::{DataType, DevicePropCode, DevicePropDesc, DevicePropForm, Value};
+use crate::error::{Error, Result};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// An aperture, stored as in the FNumber property: the f-number multiplied by 100.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FNumber(pub u16);
+
+impl FNumber {
+    pub fn from_f64(f_number: f64) -> FNumber {
+        FNumber((f_number * 100.0).round() as u16)
+    }
+
+    pub fn as_f64(self) -> f64 {
+        f64::from(self.0) / 100.0
+    }
+}
+
+impl fmt::Display for FNumber {
+    /// Formats as `f/5.6`, or `f/8` for whole f-numbers.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_multiple_of(100) {
+            write!(f, "f/{}", self.0 / 100)
+        } else if self.0.is_multiple_of(10) {
+            write!(f, "f/{}.{}", self.0 / 100, self.0 % 100 / 10)
+        } else {
+            write!(f, "f/{}.{:02}", self.0 / 100, self.0 % 100)
+        }
+    }
+}
+
+/// A shutter speed, stored as in the ExposureTime property: in units of 1/10000 second.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExposureTime(pub u32);
+
+impl ExposureTime {
+    const UNITS_PER_SECOND: u32 = 10_000;
+
+    pub fn from_secs_f64(secs: f64) -> ExposureTime {
+        ExposureTime((secs * f64::from(Self::UNITS_PER_SECOND)).round() as u32)
+    }
+
+    pub fn as_secs_f64(self) -> f64 {
+        f64::from(self.0) / f64::from(Self::UNITS_PER_SECOND)
+    }
+
+    /// The exposure time as a fraction of a second `1/n`, if it is one.
+    pub fn denominator(self) -> Option<u32> {
+        if self.0 == 0 || self.0 >= Self::UNITS_PER_SECOND {
+            return None;
+        }
+        let n = (f64::from(Self::UNITS_PER_SECOND) / f64::from(self.0)).round() as u32;
+        // `1/n` is stored rounded to a unit, so within n/2 units of a second once multiplied.
+        let error = (u64::from(self.0) * u64::from(n)).abs_diff(u64::from(Self::UNITS_PER_SECOND));
+        if 2 * error <= u64::from(n) {
+            Some(n)
+        } else {
+            None
+        }
+    }
+
+    /// Parse `1/125`, `0.5`, `2` or `2.5s`.
+    pub fn parse(text: &str) -> Option<ExposureTime> {
+        let text = text.trim().trim_end_matches('s');
+        match text.split_once('/') {
+            Some((num, den)) => {
+                let num: f64 = num.trim().parse().ok()?;
+                let den: f64 = den.trim().parse().ok()?;
+                if den <= 0.0 {
+                    return None;
+                }
+                Some(ExposureTime::from_secs_f64(num / den))
+            }
+            None => text
+                .parse::<f64>()
+                .ok()
+                .filter(|secs| *secs >= 0.0)
+                .map(ExposureTime::from_secs_f64),
+        }
+    }
+}
+
+impl fmt::Display for ExposureTime {
+    /// Formats as `1/125` for fractions of a second, otherwise as seconds such as `0.8s` or
+    /// `2.5s`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.denominator() {
+            Some(denominator) => write!(f, "1/{}", denominator),
+            None => write!(f, "{}s", self.as_secs_f64()),
+        }
+    }
+}
+
+/// A sensitivity, stored as in the ExposureIndex property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Iso(pub u16);
+
+impl Iso {
+    /// The value many cameras use for automatic ISO.
+    pub const AUTO: Iso = Iso(0xFFFF);
+
+    pub fn is_auto(self) -> bool {
+        self == Iso::AUTO
+    }
+}
+
+impl fmt::Display for Iso {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_auto() {
+            f.write_str("ISO Auto")
+        } else {
+            write!(f, "ISO {}", self.0)
+        }
+    }
+}
+
+/// An exposure compensation, stored as in the ExposureBiasCompensation property: in 1/1000 EV.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExposureBias(pub i16);
+
+impl ExposureBias {
+    pub fn from_ev(ev: f64) -> ExposureBias {
+        ExposureBias((ev * 1000.0).round() as i16)
+    }
+
+    pub fn as_ev(self) -> f64 {
+        f64::from(self.0) / 1000.0
+    }
+}
+
+impl fmt::Display for ExposureBias {
+    /// Formats as `+0.7 EV`, rounded to one decimal.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:+.1} EV", self.as_ev())
+    }
+}
+
+/// A focus distance, stored as in the FocusDistance property: in millimeters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FocusDistance(pub u16);
+
+impl FocusDistance {
+    /// The value standing for focus at infinity.
+    pub const INFINITY: FocusDistance = FocusDistance(0xFFFF);
+
+    /// The distance in meters, or infinity for distances beyond the largest finite value.
+    pub fn from_meters(meters: f64) -> FocusDistance {
+        if meters.is_finite() && meters * 1000.0 < f64::from(u16::MAX) {
+            FocusDistance((meters.max(0.0) * 1000.0).round() as u16)
+        } else {
+            FocusDistance::INFINITY
+        }
+    }
+
+    /// The distance in meters, or `None` at infinity.
+    pub fn as_meters(self) -> Option<f64> {
+        if self.is_infinity() {
+            None
+        } else {
+            Some(f64::from(self.0) / 1000.0)
+        }
+    }
+
+    /// The focus distance as the power of a lens focused at it: 1/meters, zero at infinity.
+    pub fn diopters(self) -> f64 {
+        match self.as_meters() {
+            Some(meters) if meters > 0.0 => 1.0 / meters,
+            Some(_) => f64::INFINITY,
+            None => 0.0,
+        }
+    }
+
+    /// The distance a lens with a power of `diopters` is focused at.
+    pub fn from_diopters(diopters: f64) -> FocusDistance {
+        if diopters <= 0.0 {
+            FocusDistance::INFINITY
+        } else {
+            FocusDistance::from_meters(1.0 / diopters)
+        }
+    }
+
+    pub fn is_infinity(self) -> bool {
+        self == FocusDistance::INFINITY
+    }
+}
+
+impl fmt::Display for FocusDistance {
+    /// Formats as `0.35 m`, or `∞` at infinity.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.as_meters() {
+            Some(meters) => write!(f, "{} m", meters),
+            None => f.write_str("∞"),
+        }
+    }
+}
+
+/// White balance modes of the WhiteBalance property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WhiteBalance {
+    Manual,
+    Automatic,
+    OnePushAutomatic,
+    Daylight,
+    Fluorescent,
+    Tungsten,
+    Flash,
+    /// A mode not defined by the PTP standard, with its raw code.
+    Other(u16),
+}
+
+impl WhiteBalance {
+    pub fn from_u16(code: u16) -> WhiteBalance {
+        match code {
+            1 => WhiteBalance::Manual,
+            2 => WhiteBalance::Automatic,
+            3 => WhiteBalance::OnePushAutomatic,
+            4 => WhiteBalance::Daylight,
+            5 => WhiteBalance::Fluorescent,
+            6 => WhiteBalance::Tungsten,
+            7 => WhiteBalance::Flash,
+            other => WhiteBalance::Other(other),
+        }
+    }
+
+    pub fn to_u16(self) -> u16 {
+        match self {
+            WhiteBalance::Manual => 1,
+            WhiteBalance::Automatic => 2,
+            WhiteBalance::OnePushAutomatic => 3,
+            WhiteBalance::Daylight => 4,
+            WhiteBalance::Fluorescent => 5,
+            WhiteBalance::Tungsten => 6,
+            WhiteBalance::Flash => 7,
+            WhiteBalance::Other(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for WhiteBalance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WhiteBalance::Manual => f.write_str("Manual"),
+            WhiteBalance::Automatic => f.write_str("Auto"),
+            WhiteBalance::OnePushAutomatic => f.write_str("One-push auto"),
+            WhiteBalance::Daylight => f.write_str("Daylight"),
+            WhiteBalance::Fluorescent => f.write_str("Fluorescent"),
+            WhiteBalance::Tungsten => f.write_str("Tungsten"),
+            WhiteBalance::Flash => f.write_str("Flash"),
+            WhiteBalance::Other(code) => write!(f, "0x{:04X}", code),
+        }
+    }
+}
+
+/// The value of a device property, decoded according to its property code.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyValue {
+    FNumber(FNumber),
+    ExposureTime(ExposureTime),
+    Iso(Iso),
+    ExposureBias(ExposureBias),
+    WhiteBalance(WhiteBalance),
+    FocusDistance(FocusDistance),
+    /// Battery charge from 0 to 100.
+    BatteryLevel(u8),
+    /// A property without a typed representation.
+    Other(Value),
+}
+
+impl PropertyValue {
+    /// Decode a raw value of the property `code`.
+    ///
+    /// Values of the wrong type for a standard property are returned as `Other`.
+    pub fn decode(code: DevicePropCode, value: &Value) -> PropertyValue {
+        let typed = match (code, value) {
+            (DevicePropCode::FNumber, Value::UInt16(v)) => {
+                Some(PropertyValue::FNumber(FNumber(*v)))
+            }
+            (DevicePropCode::ExposureTime, Value::UInt32(v)) => {
+                Some(PropertyValue::ExposureTime(ExposureTime(*v)))
+            }
+            (DevicePropCode::ExposureIndex, Value::UInt16(v)) => Some(PropertyValue::Iso(Iso(*v))),
+            (DevicePropCode::ExposureBiasCompensation, Value::Int16(v)) => {
+                Some(PropertyValue::ExposureBias(ExposureBias(*v)))
+            }
+            (DevicePropCode::WhiteBalance, Value::UInt16(v)) => {
+                Some(PropertyValue::WhiteBalance(WhiteBalance::from_u16(*v)))
+            }
+            (DevicePropCode::FocusDistance, Value::UInt16(v)) => {
+                Some(PropertyValue::FocusDistance(FocusDistance(*v)))
+            }
+            (DevicePropCode::BatteryLevel, Value::UInt8(v)) => {
+                Some(PropertyValue::BatteryLevel((*v).min(100)))
+            }
+            _ => None,
+        };
+        typed.unwrap_or_else(|| PropertyValue::Other(value.clone()))
+    }
+
+    /// Encode this value with the data type of a property.
+    pub fn to_value(&self, data_type: DataType) -> Option<Value> {
+        let raw = match self {
+            PropertyValue::FNumber(v) => i64::from(v.0),
+            PropertyValue::ExposureTime(v) => i64::from(v.0),
+            PropertyValue::Iso(v) => i64::from(v.0),
+            PropertyValue::ExposureBias(v) => i64::from(v.0),
+            PropertyValue::WhiteBalance(v) => i64::from(v.to_u16()),
+            PropertyValue::FocusDistance(v) => i64::from(v.0),
+            PropertyValue::BatteryLevel(v) => i64::from(*v),
+            PropertyValue::Other(value) => {
+                return if value.data_type() == data_type {
+                    Some(value.clone())
+                } else {
+                    value
+                        .as_i64()
+                        .and_then(|raw| Value::from_i64(data_type, raw))
+                };
+            }
+        };
+        Value::from_i64(data_type, raw)
+    }
+}
+
+impl fmt::Display for PropertyValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PropertyValue::FNumber(v) => v.fmt(f),
+            PropertyValue::ExposureTime(v) => v.fmt(f),
+            PropertyValue::Iso(v) => v.fmt(f),
+            PropertyValue::ExposureBias(v) => v.fmt(f),
+            PropertyValue::WhiteBalance(v) => v.fmt(f),
+            PropertyValue::FocusDistance(v) => v.fmt(f),
+            PropertyValue::BatteryLevel(v) => write!(f, "{}%", v),
+            PropertyValue::Other(Value::String(s)) => f.write_str(s),
+            PropertyValue::Other(value) => match value.as_i64() {
+                Some(raw) => write!(f, "{}", raw),
+                None => write!(f, "{:?}", value),
+            },
+        }
+    }
+}
+
+/// A device property and the values it may take, read from its DevicePropDesc.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Property {
+    pub desc: DevicePropDesc,
+}
+
+impl Property {
+    pub fn new(desc: DevicePropDesc) -> Property {
+        Property { desc }
+    }
+
+    pub fn code(&self) -> DevicePropCode {
+        self.desc.property_code
+    }
+
+    /// Indicates whether the property can be set by the host.
+    pub fn is_writable(&self) -> bool {
+        self.desc.writable
+    }
+
+    /// The current value.
+    pub fn value(&self) -> PropertyValue {
+        self.decode(&self.desc.current)
+    }
+
+    /// The factory default value.
+    pub fn default_value(&self) -> PropertyValue {
+        self.decode(&self.desc.factory_default)
+    }
+
+    /// The values allowed by an enumeration form, or `None` for other forms.
+    pub fn allowed_values(&self) -> Option<Vec<PropertyValue>> {
+        match &self.desc.form {
+            DevicePropForm::Enumeration(values) => {
+                Some(values.iter().map(|value| self.decode(value)).collect())
+            }
+            _ => None,
+        }
+    }
+
+    /// The minimum, maximum and step of a range form, or `None` for other forms.
+    pub fn range(&self) -> Option<(PropertyValue, PropertyValue, PropertyValue)> {
+        match &self.desc.form {
+            DevicePropForm::Range { min, max, step } => {
+                Some((self.decode(min), self.decode(max), self.decode(step)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Encode `value` for SetDevicePropValue, failing if the property does not accept it.
+    pub fn validate(&self, value: &PropertyValue) -> Result<Value> {
+        if !self.desc.writable {
+            return Err(Error::InvalidValue(format!(
+                "property {} is read-only",
+                self.desc.property_code
+            )));
+        }
+        let raw = value.to_value(self.desc.data_type).ok_or_else(|| {
+            Error::InvalidValue(format!(
+                "{} cannot be stored in property {} of type {:?}",
+                value, self.desc.property_code, self.desc.data_type
+            ))
+        })?;
+        if !self.desc.form.accepts(&raw) {
+            return Err(Error::InvalidValue(format!(
+                "{} is not allowed for property {}",
+                value, self.desc.property_code
+            )));
+        }
+        Ok(raw)
+    }
+
+    /// The allowed value closest to `value`, comparing raw values. Returns `value` itself
+    /// for properties without a form, and `None` if it cannot be stored in the property.
+    pub fn nearest_allowed(&self, value: &PropertyValue) -> Option<PropertyValue> {
+        let target = value.to_value(self.desc.data_type)?.as_i64()?;
+        let nearest = match &self.desc.form {
+            DevicePropForm::None => return Some(value.clone()),
+            DevicePropForm::Enumeration(values) => values
+                .iter()
+                .filter_map(|value| value.as_i64())
+                .min_by_key(|raw| (raw - target).abs())?,
+            DevicePropForm::Range { min, max, step } => {
+                // In 128 bits, so that ranges spanning all of 64 bits do not overflow.
+                let (min, max) = (i128::from(min.as_i64()?), i128::from(max.as_i64()?));
+                if max < min {
+                    return None;
+                }
+                let step = step.as_i64().filter(|step| *step > 0).map_or(1, i128::from);
+                let target = i128::from(target).clamp(min, max);
+                let steps = (target - min + step / 2) / step;
+                let nearest = (min + steps * step).min(min + (max - min) / step * step);
+                i64::try_from(nearest).ok()?
+            }
+        };
+        Value::from_i64(self.desc.data_type, nearest).map(|raw| self.decode(&raw))
+    }
+
+    fn decode(&self, value: &Value) -> PropertyValue {
+        if self.desc.property_code == DevicePropCode::BatteryLevel {
+            // The level is relative to the range the camera declares, often 0–100 or 0–3.
+            if let (DevicePropForm::Range { min, max, .. }, Some(v)) =
+                (&self.desc.form, value.as_i64())
+            {
+                if let (Some(min), Some(max)) = (min.as_i64(), max.as_i64()) {
+                    if max > min {
+                        let percent = ((v - min) * 100 / (max - min)).clamp(0, 100);
+                        return PropertyValue::BatteryLevel(percent as u8);
+                    }
+                }
+            }
+        }
+        PropertyValue::decode(self.desc.property_code, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn property(code: DevicePropCode, current: Value, form: DevicePropForm) -> Property {
+        Property::new(DevicePropDesc {
+            property_code: code,
+            data_type: current.data_type(),
+            writable: true,
+            factory_default: current.clone(),
+            current,
+            form,
+        })
+    }
+
+    fn range(min: Value, max: Value, step: Value) -> DevicePropForm {
+        DevicePropForm::Range { min, max, step }
+    }
+
+    #[test]
+    fn exposure_times_print_as_they_parse() {
+        for text in [
+            "1/5000", "1/1000", "1/250", "1/125", "1/60", "1/30", "1/3", "1/2", "0.8s", "0.3s",
+            "1s", "2.5s", "30s",
+        ] {
+            let time = ExposureTime::parse(text).unwrap();
+            assert_eq!(time.to_string(), text);
+            assert_eq!(ExposureTime::parse(&time.to_string()), Some(time));
+        }
+        assert_eq!(ExposureTime(8000).to_string(), "0.8s");
+        assert_eq!(ExposureTime(8000).denominator(), None);
+        assert_eq!(ExposureTime(80).denominator(), Some(125));
+        assert_eq!(ExposureTime::parse(" 1 / 125 "), Some(ExposureTime(80)));
+        assert_eq!(ExposureTime::parse("2"), Some(ExposureTime(20_000)));
+        for invalid in ["1/0", "1/-2", "-1", "fast", ""] {
+            assert_eq!(ExposureTime::parse(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn forms_accept_the_values_they_allow() {
+        let form = range(Value::UInt8(0), Value::UInt8(10), Value::UInt8(2));
+        assert!(form.accepts(&Value::UInt8(4)));
+        assert!(!form.accepts(&Value::UInt8(5)));
+        assert!(!form.accepts(&Value::UInt8(12)));
+        assert!(!form.accepts(&Value::String("4".into())));
+        let any_step = range(Value::UInt8(0), Value::UInt8(10), Value::UInt8(0));
+        assert!(any_step.accepts(&Value::UInt8(5)));
+
+        // Offsets across all of 64 bits do not overflow.
+        let wide = range(
+            Value::Int64(i64::MIN),
+            Value::Int64(i64::MAX),
+            Value::Int64(2),
+        );
+        assert!(wide.accepts(&Value::Int64(0)));
+        assert!(!wide.accepts(&Value::Int64(i64::MAX)));
+        let backwards = range(Value::Int64(0), Value::Int64(10), Value::Int64(-1));
+        assert!(backwards.accepts(&Value::Int64(3)));
+
+        let values = DevicePropForm::Enumeration(vec![Value::UInt16(100), Value::UInt16(200)]);
+        assert!(values.accepts(&Value::UInt16(200)));
+        assert!(!values.accepts(&Value::UInt16(150)));
+        assert!(DevicePropForm::None.accepts(&Value::UInt16(150)));
+    }
+
+    #[test]
+    fn nearest_allowed_rounds_to_the_form() {
+        let iso = |v| PropertyValue::Iso(Iso(v));
+        let stepped = property(
+            DevicePropCode::ExposureIndex,
+            Value::UInt16(100),
+            range(Value::UInt16(100), Value::UInt16(1100), Value::UInt16(300)),
+        );
+        assert_eq!(stepped.nearest_allowed(&iso(260)), Some(iso(400)));
+        assert_eq!(stepped.nearest_allowed(&iso(0)), Some(iso(100)));
+        // The last step below the maximum, which is not on a step.
+        assert_eq!(stepped.nearest_allowed(&iso(5000)), Some(iso(1000)));
+
+        let listed = property(
+            DevicePropCode::ExposureIndex,
+            Value::UInt16(100),
+            DevicePropForm::Enumeration(vec![
+                Value::UInt16(100),
+                Value::UInt16(400),
+                Value::UInt16(1600),
+            ]),
+        );
+        assert_eq!(listed.nearest_allowed(&iso(900)), Some(iso(400)));
+        assert_eq!(listed.nearest_allowed(&iso(1100)), Some(iso(1600)));
+
+        let free = property(
+            DevicePropCode::ExposureIndex,
+            Value::UInt16(100),
+            DevicePropForm::None,
+        );
+        assert_eq!(free.nearest_allowed(&iso(123)), Some(iso(123)));
+
+        let wide = property(
+            DevicePropCode(0xD001),
+            Value::Int64(0),
+            range(
+                Value::Int64(i64::MIN),
+                Value::Int64(i64::MAX),
+                Value::Int64(2),
+            ),
+        );
+        let other = |v| PropertyValue::Other(Value::Int64(v));
+        assert_eq!(
+            wide.nearest_allowed(&other(i64::MAX)),
+            Some(other(i64::MAX - 1))
+        );
+        assert_eq!(
+            wide.nearest_allowed(&other(i64::MIN)),
+            Some(other(i64::MIN))
+        );
+    }
+
+    #[test]
+    fn validate_encodes_allowed_values_only() {
+        let aperture = property(
+            DevicePropCode::FNumber,
+            Value::UInt16(280),
+            DevicePropForm::Enumeration(vec![Value::UInt16(280), Value::UInt16(400)]),
+        );
+        assert_eq!(
+            aperture
+                .validate(&PropertyValue::FNumber(FNumber::from_f64(4.0)))
+                .unwrap(),
+            Value::UInt16(400)
+        );
+        assert!(matches!(
+            aperture.validate(&PropertyValue::FNumber(FNumber(560))),
+            Err(Error::InvalidValue(_))
+        ));
+        assert!(matches!(
+            aperture.validate(&PropertyValue::Other(Value::Int32(-1))),
+            Err(Error::InvalidValue(_))
+        ));
+
+        let mut read_only = aperture.clone();
+        read_only.desc.writable = false;
+        assert!(matches!(
+            read_only.validate(&PropertyValue::FNumber(FNumber(400))),
+            Err(Error::InvalidValue(_))
+        ));
+    }
+}
@@ -0,0 +1,1011 @@
+//! A virtual PTP camera for testing tethering and import code without hardware.
+//!
+//! `VirtualCamera` serves a configurable tree of storages, folders and files and answers
+//! the standard PTP operations. It can be reached in-process over in-memory pipes or over
+//! the network as a PTP/IP responder.
+
+use super::ip::{Packet, PROTOCOL_VERSION};
+use super::stream::{self, Duplex, StreamTransport};
+use super::{
+    Container, DataContainer, DataType, DeviceInfo, DevicePropCode, DevicePropDesc, DevicePropForm,
+    Event, EventCode, ObjectFormatCode, ObjectInfo, Operation, OperationCode, Reader, Response,
+    ResponseCode, StorageInfo, Value, Writer, ALL_STORAGES, ASSOCIATION_GENERIC_FOLDER,
+    PROTECTION_NONE, ROOT_PARENT,
+};
+use crate::datetime::DateTime;
+use crate::error::{Error, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::SystemTime;
+
+/// Operations answered by the virtual camera.
+const OPERATIONS: &[OperationCode] = &[
+    OperationCode::GetDeviceInfo,
+    OperationCode::OpenSession,
+    OperationCode::CloseSession,
+    OperationCode::GetStorageIDs,
+    OperationCode::GetStorageInfo,
+    OperationCode::GetNumObjects,
+    OperationCode::GetObjectHandles,
+    OperationCode::GetObjectInfo,
+    OperationCode::GetObject,
+    OperationCode::GetPartialObject,
+    OperationCode::DeleteObject,
+    OperationCode::InitiateCapture,
+    OperationCode::GetDevicePropDesc,
+    OperationCode::GetDevicePropValue,
+    OperationCode::SetDevicePropValue,
+];
+
+/// Events sent by the virtual camera.
+const EVENTS: &[EventCode] = &[
+    EventCode::ObjectAdded,
+    EventCode::ObjectRemoved,
+    EventCode::DevicePropChanged,
+    EventCode::CaptureComplete,
+];
+
+enum Content {
+    Folder,
+    Bytes(Arc<Vec<u8>>),
+    File(PathBuf),
+}
+
+struct Object {
+    info: ObjectInfo,
+    content: Content,
+}
+
+struct State {
+    device_info: DeviceInfo,
+    storages: BTreeMap<u32, StorageInfo>,
+    objects: BTreeMap<u32, Object>,
+    next_handle: u32,
+    properties: BTreeMap<DevicePropCode, DevicePropDesc>,
+    capture_data: Arc<Vec<u8>>,
+    capture_count: u32,
+    subscribers: Vec<Sender<Event>>,
+}
+
+impl State {
+    fn emit(&mut self, event: Event) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+/// A PTP responder serving a configurable object tree.
+///
+/// Clones share the same state, so a test can keep a handle to add files or change
+/// properties while clients are connected; such changes are announced with events.
+#[derive(Clone)]
+pub struct VirtualCamera {
+    state: Arc<Mutex<State>>,
+}
+
+impl VirtualCamera {
+    /// Create a camera with no storages and a default set of shooting properties.
+    pub fn new(manufacturer: &str, model: &str) -> VirtualCamera {
+        let device_info = DeviceInfo {
+            standard_version: 100,
+            vendor_extension_desc: String::new(),
+            operations_supported: OPERATIONS.to_vec(),
+            events_supported: EVENTS.to_vec(),
+            capture_formats: vec![ObjectFormatCode::EXIF_JPEG],
+            playback_formats: vec![ObjectFormatCode::Association, ObjectFormatCode::EXIF_JPEG],
+            manufacturer: manufacturer.to_string(),
+            model: model.to_string(),
+            device_version: env!("CARGO_PKG_VERSION").to_string(),
+            serial_number: "0000000001".to_string(),
+            ..DeviceInfo::default()
+        };
+        let camera = VirtualCamera {
+            state: Arc::new(Mutex::new(State {
+                device_info,
+                storages: BTreeMap::new(),
+                objects: BTreeMap::new(),
+                next_handle: 1,
+                properties: BTreeMap::new(),
+                capture_data: Arc::new(Vec::new()),
+                capture_count: 0,
+                subscribers: Vec::new(),
+            })),
+        };
+        for desc in default_properties() {
+            camera.add_property(desc);
+        }
+        camera
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Set the serial number reported in DeviceInfo.
+    pub fn set_serial_number(&self, serial_number: &str) {
+        self.state().device_info.serial_number = serial_number.to_string();
+    }
+
+    /// Set the vendor extension reported in DeviceInfo.
+    pub fn set_vendor_extension(&self, vendor_extension_id: u32, desc: &str) {
+        let mut state = self.state();
+        state.device_info.vendor_extension_id = vendor_extension_id;
+        state.device_info.vendor_extension_desc = desc.to_string();
+    }
+
+    /// Add a storage and return its ID.
+    pub fn add_storage(&self, description: &str) -> u32 {
+        let mut state = self.state();
+        let id = 0x0001_0001 + state.storages.len() as u32;
+        state.storages.insert(
+            id,
+            StorageInfo {
+                storage_type: 4,
+                filesystem_type: 3,
+                access_capability: 0,
+                max_capacity: 32 * 1024 * 1024 * 1024,
+                free_space_in_bytes: 16 * 1024 * 1024 * 1024,
+                free_space_in_images: 0xFFFF_FFFF,
+                storage_description: description.to_string(),
+                volume_label: description.to_string(),
+            },
+        );
+        id
+    }
+
+    /// Change the free space reported for a storage.
+    pub fn set_free_space(&self, storage_id: u32, free_space_in_bytes: u64) {
+        if let Some(storage) = self.state().storages.get_mut(&storage_id) {
+            storage.free_space_in_bytes = free_space_in_bytes;
+        }
+    }
+
+    fn add_object(
+        &self,
+        storage_id: u32,
+        parent: Option<u32>,
+        info: ObjectInfo,
+        content: Content,
+    ) -> u32 {
+        let mut state = self.state();
+        let handle = state.next_handle;
+        state.next_handle += 1;
+        let info = ObjectInfo {
+            storage_id,
+            parent_object: parent.unwrap_or(0),
+            ..info
+        };
+        state.objects.insert(handle, Object { info, content });
+        state.emit(Event::new(EventCode::ObjectAdded, &[handle]));
+        handle
+    }
+
+    /// Add a folder and return its handle. `parent` is `None` for the root of the storage.
+    pub fn add_folder(&self, storage_id: u32, parent: Option<u32>, name: &str) -> u32 {
+        let info = ObjectInfo {
+            object_format: ObjectFormatCode::Association,
+            association_type: ASSOCIATION_GENERIC_FOLDER,
+            filename: name.to_string(),
+            capture_date: now(),
+            modification_date: now(),
+            ..ObjectInfo::default()
+        };
+        self.add_object(storage_id, parent, info, Content::Folder)
+    }
+
+    /// Add a file with the given contents and return its handle.
+    pub fn add_file(&self, storage_id: u32, parent: Option<u32>, name: &str, data: Vec<u8>) -> u32 {
+        let info = file_info(name, data.len() as u64, now());
+        self.add_object(storage_id, parent, info, Content::Bytes(Arc::new(data)))
+    }
+
+    /// Add a file whose contents are read from `path` when downloaded, and return its handle.
+    pub fn add_local_file(&self, storage_id: u32, parent: Option<u32>, path: &Path) -> Result<u32> {
+        let metadata = fs::metadata(path)?;
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| {
+                Error::malformed(format!("{} has no usable file name", path.display()))
+            })?;
+        let date = metadata
+            .modified()
+            .map(|time| DateTime::from_system_time(time).to_ptp_string())
+            .unwrap_or_else(|_| now());
+        let info = file_info(name, metadata.len(), date);
+        Ok(self.add_object(storage_id, parent, info, Content::File(path.to_path_buf())))
+    }
+
+    /// Mirror a local directory tree, adding its folders and files under `parent`.
+    pub fn add_directory(&self, storage_id: u32, parent: Option<u32>, dir: &Path) -> Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let folder = self.add_folder(storage_id, parent, &name);
+                self.add_directory(storage_id, Some(folder), &path)?;
+            } else {
+                self.add_local_file(storage_id, parent, &path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove an object and its descendants, as if deleted on the camera.
+    pub fn remove_object(&self, handle: u32) {
+        let mut state = self.state();
+        remove_tree(&mut state, handle);
+    }
+
+    /// Handles of all objects named `name`.
+    pub fn find(&self, name: &str) -> Vec<u32> {
+        self.state()
+            .objects
+            .iter()
+            .filter(|(_, object)| object.info.filename == name)
+            .map(|(&handle, _)| handle)
+            .collect()
+    }
+
+    /// Add or replace a device property.
+    pub fn add_property(&self, desc: DevicePropDesc) {
+        let mut state = self.state();
+        if !state
+            .device_info
+            .device_properties_supported
+            .contains(&desc.property_code)
+        {
+            state
+                .device_info
+                .device_properties_supported
+                .push(desc.property_code);
+        }
+        state.properties.insert(desc.property_code, desc);
+    }
+
+    /// Change the current value of a property, as if changed on the camera body.
+    pub fn set_property_value(&self, code: DevicePropCode, value: Value) {
+        let mut state = self.state();
+        if let Some(desc) = state.properties.get_mut(&code) {
+            desc.current = value;
+            state.emit(Event::new(
+                EventCode::DevicePropChanged,
+                &[u32::from(code.0)],
+            ));
+        }
+    }
+
+    /// Current value of a property.
+    pub fn property_value(&self, code: DevicePropCode) -> Option<Value> {
+        self.state()
+            .properties
+            .get(&code)
+            .map(|desc| desc.current.clone())
+    }
+
+    /// Set the image returned for pictures taken with InitiateCapture or `press_shutter`.
+    pub fn set_capture_data(&self, data: Vec<u8>) {
+        self.state().capture_data = Arc::new(data);
+    }
+
+    /// Simulate a picture taken with the shutter release on the camera, returning its handle.
+    pub fn press_shutter(&self) -> Option<u32> {
+        let mut state = self.state();
+        capture(&mut state)
+    }
+
+    /// Receive the events sent by the camera.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.state().subscribers.push(sender);
+        receiver
+    }
+
+    /// Connect an initiator over in-memory pipes.
+    ///
+    /// Returns the initiator side of the command channel and the events it will receive.
+    pub fn connect_in_memory(&self) -> (StreamTransport<Duplex>, Receiver<Event>) {
+        let (initiator, responder) = stream::duplex();
+        let (event_writer, event_reader) = stream::pipe();
+        stream::spawn_event_writer(event_writer, self.subscribe());
+        let camera = self.clone();
+        thread::spawn(move || camera.serve_stream(responder));
+        (
+            StreamTransport::new(initiator),
+            stream::spawn_event_reader(event_reader),
+        )
+    }
+
+    /// Serve one initiator on a stream carrying generic containers, until it is closed.
+    pub fn serve_stream<S: Read + Write>(&self, mut stream: S) -> Result<()> {
+        let mut session = None;
+        loop {
+            let operation = match Container::read_from(&mut stream) {
+                Ok(Container::Operation(operation)) => operation,
+                Ok(other) => {
+                    return Err(Error::malformed(format!(
+                        "expected a command container, got {:?}",
+                        other.container_type()
+                    )))
+                }
+                Err(Error::Io(ref err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(())
+                }
+                Err(err) => return Err(err),
+            };
+            let data_out = if expects_data(operation.code) {
+                match Container::read_from(&mut stream)? {
+                    Container::Data(data) => Some(data.payload),
+                    other => {
+                        return Err(Error::malformed(format!(
+                            "expected a data container, got {:?}",
+                            other.container_type()
+                        )))
+                    }
+                }
+            } else {
+                None
+            };
+            let (response, data_in) = self.handle(&mut session, &operation, data_out.as_deref());
+            if let Some(payload) = data_in {
+                Container::Data(DataContainer {
+                    code: operation.code,
+                    transaction_id: operation.transaction_id,
+                    payload,
+                })
+                .write_to(&mut stream)?;
+            }
+            Container::Response(response).write_to(&mut stream)?;
+            stream.flush()?;
+        }
+    }
+
+    /// Accept PTP/IP initiators on `listener`, serving each connection on its own thread.
+    ///
+    /// This call blocks for as long as the listener accepts connections.
+    pub fn serve_ptpip(&self, listener: TcpListener) -> Result<()> {
+        let mut connection_number = 0;
+        loop {
+            // A client failing its handshake only loses its own connection.
+            let (mut command, _) = listener.accept()?;
+            connection_number += 1;
+            if !matches!(
+                self.accept_command_channel(&mut command, connection_number),
+                Ok(true)
+            ) {
+                continue;
+            }
+            let (mut events, _) = listener.accept()?;
+            if !matches!(
+                accept_event_channel(&mut events, connection_number),
+                Ok(true)
+            ) {
+                continue;
+            }
+            let subscription = self.subscribe();
+            thread::spawn(move || {
+                for event in subscription {
+                    if Packet::Event(event).write_to(&mut events).is_err() {
+                        break;
+                    }
+                }
+            });
+            let camera = self.clone();
+            thread::spawn(move || camera.serve_ptpip_connection(command));
+        }
+    }
+
+    /// Answer the Init Command Request opening a connection. Returns whether it was accepted.
+    fn accept_command_channel(
+        &self,
+        command: &mut TcpStream,
+        connection_number: u32,
+    ) -> Result<bool> {
+        match Packet::read_from(command)? {
+            Packet::InitCommandRequest { .. } => {}
+            _ => {
+                Packet::InitFail { reason: 1 }.write_to(command)?;
+                return Ok(false);
+            }
+        }
+        let model = self.state().device_info.model.clone();
+        Packet::InitCommandAck {
+            connection_number,
+            guid: guid_for(&model),
+            name: model,
+            version: PROTOCOL_VERSION,
+        }
+        .write_to(command)?;
+        Ok(true)
+    }
+
+    fn serve_ptpip_connection(&self, mut stream: TcpStream) -> Result<()> {
+        let mut session = None;
+        loop {
+            let (data_phase, operation) = match Packet::read_from(&mut stream) {
+                Ok(Packet::OperationRequest {
+                    data_phase,
+                    operation,
+                }) => (data_phase, operation),
+                Ok(Packet::ProbeRequest) => {
+                    Packet::ProbeResponse.write_to(&mut stream)?;
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(_) => return Ok(()),
+            };
+            let data_out = if data_phase == super::ip::DATA_PHASE_OUT {
+                let mut data = Vec::new();
+                loop {
+                    match Packet::read_from(&mut stream)? {
+                        Packet::StartData { .. } => {}
+                        Packet::Data { payload, .. } => data.extend_from_slice(&payload),
+                        Packet::EndData { payload, .. } => {
+                            data.extend_from_slice(&payload);
+                            break;
+                        }
+                        other => {
+                            return Err(Error::malformed(format!(
+                                "unexpected PTP/IP packet {:?} in data phase",
+                                other
+                            )))
+                        }
+                    }
+                }
+                Some(data)
+            } else {
+                None
+            };
+            let (response, data_in) = self.handle(&mut session, &operation, data_out.as_deref());
+            if let Some(payload) = data_in {
+                let transaction_id = operation.transaction_id;
+                Packet::StartData {
+                    transaction_id,
+                    total_length: payload.len() as u64,
+                }
+                .write_to(&mut stream)?;
+                Packet::EndData {
+                    transaction_id,
+                    payload,
+                }
+                .write_to(&mut stream)?;
+            }
+            Packet::OperationResponse(response).write_to(&mut stream)?;
+        }
+    }
+
+    /// Answer one operation. `session` holds the ID of the session open on this connection.
+    fn handle(
+        &self,
+        session: &mut Option<u32>,
+        operation: &Operation,
+        data_out: Option<&[u8]>,
+    ) -> (Response, Option<Vec<u8>>) {
+        let transaction_id = operation.transaction_id;
+        let result = if operation.code == OperationCode::GetDeviceInfo {
+            Ok((Vec::new(), Some(self.state().device_info.encode())))
+        } else if operation.code == OperationCode::OpenSession {
+            match (*session, param(operation, 0)) {
+                (Some(id), _) => Err((ResponseCode::SessionAlreadyOpen, vec![id])),
+                (None, 0) => Err((ResponseCode::InvalidParameter, Vec::new())),
+                (None, id) => {
+                    *session = Some(id);
+                    Ok((Vec::new(), None))
+                }
+            }
+        } else if session.is_none() {
+            Err((ResponseCode::SessionNotOpen, Vec::new()))
+        } else if operation.code == OperationCode::CloseSession {
+            *session = None;
+            Ok((Vec::new(), None))
+        } else {
+            let mut state = self.state();
+            handle_in_session(&mut state, operation, data_out)
+        };
+        match result {
+            Ok((params, data)) => (
+                Response::new(ResponseCode::OK, transaction_id, &params),
+                data,
+            ),
+            Err((code, params)) => (Response::new(code, transaction_id, &params), None),
+        }
+    }
+}
+
+type Outcome = std::result::Result<(Vec<u32>, Option<Vec<u8>>), (ResponseCode, Vec<u32>)>;
+
+fn handle_in_session(state: &mut State, operation: &Operation, data_out: Option<&[u8]>) -> Outcome {
+    let fail = |code| Err((code, Vec::new()));
+    match operation.code {
+        OperationCode::GetStorageIDs => {
+            let mut w = Writer::new();
+            w.write_u32_array(&state.storages.keys().cloned().collect::<Vec<_>>());
+            Ok((Vec::new(), Some(w.into_bytes())))
+        }
+        OperationCode::GetStorageInfo => match state.storages.get(&param(operation, 0)) {
+            Some(info) => Ok((Vec::new(), Some(info.encode()))),
+            None => fail(ResponseCode::InvalidStorageID),
+        },
+        OperationCode::GetNumObjects | OperationCode::GetObjectHandles => {
+            let storage_id = param(operation, 0);
+            if storage_id != ALL_STORAGES && !state.storages.contains_key(&storage_id) {
+                return fail(ResponseCode::InvalidStorageID);
+            }
+            let format = param(operation, 1);
+            let parent = param(operation, 2);
+            if parent != 0 && parent != ROOT_PARENT && !state.objects.contains_key(&parent) {
+                return fail(ResponseCode::InvalidParentObject);
+            }
+            let handles: Vec<u32> = state
+                .objects
+                .iter()
+                .filter(|(_, object)| {
+                    storage_id == ALL_STORAGES || object.info.storage_id == storage_id
+                })
+                .filter(|(_, object)| {
+                    format == 0 || u32::from(object.info.object_format.0) == format
+                })
+                .filter(|(_, object)| match parent {
+                    0 => true,
+                    ROOT_PARENT => object.info.parent_object == 0,
+                    parent => object.info.parent_object == parent,
+                })
+                .map(|(&handle, _)| handle)
+                .collect();
+            if operation.code == OperationCode::GetNumObjects {
+                Ok((vec![handles.len() as u32], None))
+            } else {
+                let mut w = Writer::new();
+                w.write_u32_array(&handles);
+                Ok((Vec::new(), Some(w.into_bytes())))
+            }
+        }
+        OperationCode::GetObjectInfo => match state.objects.get(&param(operation, 0)) {
+            Some(object) => Ok((Vec::new(), Some(object.info.encode()))),
+            None => fail(ResponseCode::InvalidObjectHandle),
+        },
+        OperationCode::GetObject | OperationCode::GetPartialObject => {
+            let object = match state.objects.get(&param(operation, 0)) {
+                Some(object) => object,
+                None => return fail(ResponseCode::InvalidObjectHandle),
+            };
+            let data = match &object.content {
+                Content::Folder => return fail(ResponseCode::InvalidObjectHandle),
+                Content::Bytes(bytes) => bytes.to_vec(),
+                Content::File(path) => match fs::read(path) {
+                    Ok(bytes) => bytes,
+                    Err(_) => return fail(ResponseCode::GeneralError),
+                },
+            };
+            if operation.code == OperationCode::GetObject {
+                return Ok((Vec::new(), Some(data)));
+            }
+            let offset = (param(operation, 1) as usize).min(data.len());
+            let end = offset
+                .saturating_add(param(operation, 2) as usize)
+                .min(data.len());
+            let part = data[offset..end].to_vec();
+            Ok((vec![part.len() as u32], Some(part)))
+        }
+        OperationCode::DeleteObject => {
+            let handle = param(operation, 0);
+            match state.objects.get(&handle) {
+                None => fail(ResponseCode::InvalidObjectHandle),
+                Some(object) if object.info.protection_status != PROTECTION_NONE => {
+                    fail(ResponseCode::ObjectWriteProtected)
+                }
+                Some(_) => {
+                    remove_tree(state, handle);
+                    Ok((Vec::new(), None))
+                }
+            }
+        }
+        OperationCode::InitiateCapture => match capture(state) {
+            Some(_) => {
+                state.emit(Event::new(EventCode::CaptureComplete, &[]));
+                Ok((Vec::new(), None))
+            }
+            None => fail(ResponseCode::StoreNotAvailable),
+        },
+        OperationCode::GetDevicePropDesc => match state.properties.get(&prop_code(operation)) {
+            Some(desc) => Ok((Vec::new(), Some(desc.encode()))),
+            None => fail(ResponseCode::DevicePropNotSupported),
+        },
+        OperationCode::GetDevicePropValue => match state.properties.get(&prop_code(operation)) {
+            Some(desc) => Ok((Vec::new(), Some(desc.current.to_bytes()))),
+            None => fail(ResponseCode::DevicePropNotSupported),
+        },
+        OperationCode::SetDevicePropValue => {
+            let code = prop_code(operation);
+            let desc = match state.properties.get(&code) {
+                Some(desc) => desc,
+                None => return fail(ResponseCode::DevicePropNotSupported),
+            };
+            if !desc.writable {
+                return fail(ResponseCode::AccessDenied);
+            }
+            let value = match data_out.map(|data| Reader::new(data).read_value(desc.data_type)) {
+                Some(Ok(value)) => value,
+                _ => return fail(ResponseCode::InvalidDevicePropFormat),
+            };
+            if !desc.form.accepts(&value) {
+                return fail(ResponseCode::InvalidDevicePropValue);
+            }
+            if let Some(desc) = state.properties.get_mut(&code) {
+                desc.current = value;
+            }
+            state.emit(Event::new(
+                EventCode::DevicePropChanged,
+                &[u32::from(code.0)],
+            ));
+            Ok((Vec::new(), None))
+        }
+        _ => fail(ResponseCode::OperationNotSupported),
+    }
+}
+
+fn param(operation: &Operation, index: usize) -> u32 {
+    operation.params.get(index).cloned().unwrap_or(0)
+}
+
+fn prop_code(operation: &Operation) -> DevicePropCode {
+    DevicePropCode(param(operation, 0) as u16)
+}
+
+fn expects_data(code: OperationCode) -> bool {
+    code == OperationCode::SetDevicePropValue
+        || code == OperationCode::SendObjectInfo
+        || code == OperationCode::SendObject
+}
+
+fn capture(state: &mut State) -> Option<u32> {
+    let storage_id = *state.storages.keys().next()?;
+    // Captures go to the most recently created folder, like a camera's current DCIM folder.
+    let parent = state
+        .objects
+        .iter()
+        .rev()
+        .find(|(_, object)| object.info.storage_id == storage_id && object.info.is_folder())
+        .map_or(0, |(&handle, _)| handle);
+    state.capture_count += 1;
+    let name = format!("CAP_{:04}.JPG", state.capture_count);
+    let data = state.capture_data.clone();
+    let info = ObjectInfo {
+        storage_id,
+        parent_object: parent,
+        ..file_info(&name, data.len() as u64, now())
+    };
+    let handle = state.next_handle;
+    state.next_handle += 1;
+    state.objects.insert(
+        handle,
+        Object {
+            info,
+            content: Content::Bytes(data),
+        },
+    );
+    state.emit(Event::new(EventCode::ObjectAdded, &[handle]));
+    Some(handle)
+}
+
+fn remove_tree(state: &mut State, handle: u32) {
+    let children: Vec<u32> = state
+        .objects
+        .iter()
+        .filter(|(_, object)| object.info.parent_object == handle)
+        .map(|(&child, _)| child)
+        .collect();
+    for child in children {
+        remove_tree(state, child);
+    }
+    if state.objects.remove(&handle).is_some() {
+        state.emit(Event::new(EventCode::ObjectRemoved, &[handle]));
+    }
+}
+
+fn file_info(name: &str, size: u64, date: String) -> ObjectInfo {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_uppercase())
+        .unwrap_or_default();
+    let object_format = match extension.as_str() {
+        "JPG" | "JPEG" => ObjectFormatCode::EXIF_JPEG,
+        "TIF" | "TIFF" => ObjectFormatCode::TIFF,
+        "DNG" => ObjectFormatCode::DNG,
+        "PNG" => ObjectFormatCode::PNG,
+        "AVI" => ObjectFormatCode::AVI,
+        "WAV" => ObjectFormatCode::WAV,
+        "MP3" => ObjectFormatCode::MP3,
+        "TXT" => ObjectFormatCode::Text,
+        _ => ObjectFormatCode::Undefined,
+    };
+    ObjectInfo {
+        object_format,
+        protection_status: PROTECTION_NONE,
+        object_compressed_size: size.min(u64::from(u32::MAX)) as u32,
+        filename: name.to_string(),
+        capture_date: date.clone(),
+        modification_date: date,
+        ..ObjectInfo::default()
+    }
+}
+
+fn now() -> String {
+    DateTime::from_system_time(SystemTime::now()).to_ptp_string()
+}
+
+fn guid_for(model: &str) -> [u8; 16] {
+    let mut guid = [0u8; 16];
+    for (i, byte) in model.bytes().enumerate() {
+        guid[i % 16] ^= byte;
+    }
+    guid
+}
+
+/// Answer the Init Event Request of connection `connection_number`. Returns whether it was
+/// accepted.
+fn accept_event_channel(events: &mut TcpStream, connection_number: u32) -> Result<bool> {
+    match Packet::read_from(events)? {
+        Packet::InitEventRequest {
+            connection_number: number,
+        } if number == connection_number => {
+            Packet::InitEventAck.write_to(events)?;
+            Ok(true)
+        }
+        _ => {
+            Packet::InitFail { reason: 1 }.write_to(events)?;
+            Ok(false)
+        }
+    }
+}
+
+fn default_properties() -> Vec<DevicePropDesc> {
+    let u16_values = |values: &[u16]| values.iter().map(|&v| Value::UInt16(v)).collect();
+    let u32_values = |values: &[u32]| values.iter().map(|&v| Value::UInt32(v)).collect();
+    let i16_values = |values: &[i16]| values.iter().map(|&v| Value::Int16(v)).collect();
+    vec![
+        DevicePropDesc {
+            property_code: DevicePropCode::BatteryLevel,
+            data_type: DataType::UINT8,
+            writable: false,
+            factory_default: Value::UInt8(100),
+            current: Value::UInt8(100),
+            form: DevicePropForm::Range {
+                min: Value::UInt8(0),
+                max: Value::UInt8(100),
+                step: Value::UInt8(1),
+            },
+        },
+        DevicePropDesc {
+            property_code: DevicePropCode::FNumber,
+            data_type: DataType::UINT16,
+            writable: true,
+            factory_default: Value::UInt16(560),
+            current: Value::UInt16(560),
+            form: DevicePropForm::Enumeration(u16_values(&[280, 400, 560, 800, 1100, 1600, 2200])),
+        },
+        DevicePropDesc {
+            property_code: DevicePropCode::ExposureTime,
+            data_type: DataType::UINT32,
+            writable: true,
+            factory_default: Value::UInt32(80),
+            current: Value::UInt32(80),
+            form: DevicePropForm::Enumeration(u32_values(&[
+                10, 20, 40, 80, 125, 250, 500, 1000, 2500, 5000, 10000, 20000, 40000,
+            ])),
+        },
+        DevicePropDesc {
+            property_code: DevicePropCode::ExposureIndex,
+            data_type: DataType::UINT16,
+            writable: true,
+            factory_default: Value::UInt16(100),
+            current: Value::UInt16(100),
+            form: DevicePropForm::Enumeration(u16_values(&[100, 200, 400, 800, 1600, 3200, 6400])),
+        },
+        DevicePropDesc {
+            property_code: DevicePropCode::ExposureBiasCompensation,
+            data_type: DataType::INT16,
+            writable: true,
+            factory_default: Value::Int16(0),
+            current: Value::Int16(0),
+            // Thirds of a stop from -3 to +3 EV, in thousandths.
+            form: DevicePropForm::Enumeration(i16_values(&[
+                -3000, -2667, -2333, -2000, -1667, -1333, -1000, -667, -333, 0, 333, 667, 1000,
+                1333, 1667, 2000, 2333, 2667, 3000,
+            ])),
+        },
+        DevicePropDesc {
+            property_code: DevicePropCode::WhiteBalance,
+            data_type: DataType::UINT16,
+            writable: true,
+            factory_default: Value::UInt16(2),
+            current: Value::UInt16(2),
+            form: DevicePropForm::Enumeration(u16_values(&[1, 2, 4, 5, 6, 7])),
+        },
+        DevicePropDesc {
+            property_code: DevicePropCode::DateTime,
+            data_type: DataType::STR,
+            writable: true,
+            factory_default: Value::String(String::new()),
+            current: Value::String(now()),
+            form: DevicePropForm::None,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{CameraDevice, CameraEvent, CameraFile, CameraItem};
+    use crate::ptp::{PtpCamera, Session};
+    use std::time::Duration;
+
+    /// A camera with one card holding `DCIM/100TEST/IMG_0001.JPG` and `IMG_0002.JPG`.
+    fn card() -> VirtualCamera {
+        let camera = VirtualCamera::new("Test", "Virtual");
+        let storage = camera.add_storage("Card");
+        let dcim = camera.add_folder(storage, None, "DCIM");
+        let folder = camera.add_folder(storage, Some(dcim), "100TEST");
+        camera.add_file(storage, Some(folder), "IMG_0001.JPG", (0..=255).collect());
+        camera.add_file(storage, Some(folder), "IMG_0002.JPG", vec![2; 64]);
+        camera
+    }
+
+    fn connect(camera: &VirtualCamera) -> PtpCamera<StreamTransport<Duplex>> {
+        let (transport, events) = camera.connect_in_memory();
+        let session = Session::open(transport, 1).unwrap();
+        PtpCamera::new(session, events).unwrap()
+    }
+
+    fn file(camera: &PtpCamera<StreamTransport<Duplex>>, name: &str) -> CameraFile {
+        camera
+            .media_files()
+            .unwrap()
+            .into_iter()
+            .find(|file| file.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn open_session_reports_device_info() {
+        let camera = connect(&card());
+        assert_eq!(camera.device_info().manufacturer, "Test");
+        assert_eq!(camera.device_info().model, "Virtual");
+    }
+
+    #[test]
+    fn contents_lists_the_folder_tree() {
+        let camera = connect(&card());
+        let storages = camera.contents().unwrap();
+        assert_eq!(storages.len(), 1);
+        let storage = match &storages[0] {
+            CameraItem::Folder(folder) => folder,
+            other => panic!("unexpected item {:?}", other),
+        };
+        assert_eq!(storage.name, "Storage 00010001");
+        let dcim = match &storage.contents[..] {
+            [CameraItem::Folder(dcim)] => dcim,
+            other => panic!("unexpected items {:?}", other),
+        };
+        assert_eq!(dcim.name, "DCIM");
+        let folder = match &dcim.contents[..] {
+            [CameraItem::Folder(folder)] => folder,
+            other => panic!("unexpected items {:?}", other),
+        };
+        let names: Vec<_> = folder
+            .contents
+            .iter()
+            .map(|item| match item {
+                CameraItem::File(file) => file.name.as_str(),
+                CameraItem::Folder(folder) => folder.name.as_str(),
+            })
+            .collect();
+        assert_eq!(names, ["IMG_0001.JPG", "IMG_0002.JPG"]);
+
+        let file = file(&camera, "IMG_0001.JPG");
+        assert_eq!(file.file_size, 256);
+        assert_eq!(
+            file.parent_folder.as_deref(),
+            Some("Storage 00010001/DCIM/100TEST")
+        );
+    }
+
+    #[test]
+    fn get_object_and_get_partial_object_return_the_file_data() {
+        let camera = connect(&card());
+        let file = file(&camera, "IMG_0001.JPG");
+        let mut data = Vec::new();
+        assert_eq!(camera.download_file(&file, &mut data).unwrap(), 256);
+        assert_eq!(data, (0..=255).collect::<Vec<u8>>());
+        assert_eq!(
+            camera.read_data_from_file(&file, 250, 16).unwrap(),
+            [250, 251, 252, 253, 254, 255]
+        );
+        assert_eq!(
+            camera.read_data_from_file(&file, 16, 4).unwrap(),
+            [16, 17, 18, 19]
+        );
+    }
+
+    #[test]
+    fn delete_files_removes_the_object() {
+        let virtual_camera = card();
+        let camera = connect(&virtual_camera);
+        let file = file(&camera, "IMG_0002.JPG");
+        camera.delete_files(&[file]).unwrap();
+        assert!(virtual_camera.find("IMG_0002.JPG").is_empty());
+        let names: Vec<_> = camera
+            .media_files()
+            .unwrap()
+            .into_iter()
+            .map(|file| file.name)
+            .collect();
+        assert_eq!(names, ["IMG_0001.JPG"]);
+    }
+
+    #[test]
+    fn initiate_capture_reports_the_new_object() {
+        let virtual_camera = card();
+        virtual_camera.set_capture_data(vec![7; 32]);
+        let camera = connect(&virtual_camera);
+        camera.contents().unwrap();
+        camera.take_picture().unwrap();
+        let added = loop {
+            match camera.next_event(Duration::from_secs(5)).unwrap() {
+                Some(CameraEvent::ItemsAdded(files)) => break files,
+                Some(_) => {}
+                None => panic!("no ObjectAdded event after InitiateCapture"),
+            }
+        };
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].name, "CAP_0001.JPG");
+        assert_eq!(
+            added[0].parent_folder.as_deref(),
+            Some("Storage 00010001/DCIM/100TEST")
+        );
+        assert!(added[0].was_added_after_content_catalog_completed);
+        let mut data = Vec::new();
+        camera.download_file(&added[0], &mut data).unwrap();
+        assert_eq!(data, [7; 32]);
+    }
+
+    #[test]
+    fn serve_ptpip_survives_a_failed_handshake() {
+        let camera = VirtualCamera::new("Test", "Virtual");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = camera.clone();
+        thread::spawn(move || server.serve_ptpip(listener));
+
+        drop(TcpStream::connect(address).unwrap());
+        let mut garbage = TcpStream::connect(address).unwrap();
+        garbage.write_all(&[0xFF; 8]).unwrap();
+        drop(garbage);
+
+        let initiator = crate::ptp::ip::connect(address, [1; 16], "test").unwrap();
+        assert_eq!(initiator.device_info().model, "Virtual");
+    }
+
+    #[test]
+    fn default_properties_accept_their_own_values() {
+        for desc in default_properties() {
+            assert!(desc.form.accepts(&desc.current), "{:?}", desc.property_code);
+            assert!(
+                desc.form.accepts(&desc.factory_default),
+                "{:?}",
+                desc.property_code
+            );
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! PTP sessions: transaction ID management and the standard operations.
+
+use super::{
+    DeviceInfo, DevicePropCode, DevicePropDesc, ObjectFormatCode, ObjectInfo, Operation,
+    OperationCode, Reader, Response, ResponseCode, StorageInfo, Value,
+};
+use crate::error::{Error, Result};
+use std::io::Write;
+
+/// A channel able to carry PTP transactions, such as a PTP/IP connection.
+pub trait Transport: Send {
+    /// Run one transaction.
+    ///
+    /// `data_out` is sent in the data phase if present; otherwise any data sent by the
+    /// responder is written to `data_in`. The responder's response is returned.
+    fn execute(
+        &mut self,
+        operation: &Operation,
+        data_out: Option<&[u8]>,
+        data_in: &mut dyn Write,
+    ) -> Result<Response>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn execute(
+        &mut self,
+        operation: &Operation,
+        data_out: Option<&[u8]>,
+        data_in: &mut dyn Write,
+    ) -> Result<Response> {
+        (**self).execute(operation, data_out, data_in)
+    }
+}
+
+/// An open PTP session over a transport.
+#[derive(Debug)]
+pub struct Session<T> {
+    transport: T,
+    session_id: u32,
+    next_transaction_id: u32,
+    open: bool,
+}
+
+impl<T: Transport> Session<T> {
+    /// Open a session with the given ID, which must be non-zero.
+    pub fn open(transport: T, session_id: u32) -> Result<Session<T>> {
+        let mut session = Session {
+            transport,
+            session_id,
+            next_transaction_id: 0,
+            open: false,
+        };
+        // OpenSession is the only operation sent with a transaction ID of zero.
+        session.request(OperationCode::OpenSession, &[session_id])?;
+        session.open = true;
+        Ok(session)
+    }
+
+    /// The ID this session was opened with.
+    pub fn session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    /// The underlying transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    fn transaction_id(&mut self) -> u32 {
+        let id = self.next_transaction_id;
+        self.next_transaction_id = match id.wrapping_add(1) {
+            0 | 0xFFFF_FFFF => 1,
+            next => next,
+        };
+        id
+    }
+
+    /// Run a transaction and return the response whatever its code.
+    pub fn execute(
+        &mut self,
+        code: OperationCode,
+        params: &[u32],
+        data_out: Option<&[u8]>,
+        data_in: &mut dyn Write,
+    ) -> Result<Response> {
+        let operation = Operation {
+            code,
+            transaction_id: self.transaction_id(),
+            params: params.to_vec(),
+        };
+        let response = self.transport.execute(&operation, data_out, data_in)?;
+        if response.transaction_id != operation.transaction_id {
+            return Err(Error::malformed(format!(
+                "response to transaction {} carries transaction ID {}",
+                operation.transaction_id, response.transaction_id
+            )));
+        }
+        Ok(response)
+    }
+
+    /// Run a transaction, failing unless the response is `OK`, and return the data received.
+    pub fn request(&mut self, code: OperationCode, params: &[u32]) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let response = self.execute(code, params, None, &mut data)?;
+        check(&response)?;
+        Ok(data)
+    }
+
+    /// Run a transaction with a data phase sent to the responder, failing unless the response is `OK`.
+    pub fn send(&mut self, code: OperationCode, params: &[u32], data: &[u8]) -> Result<Response> {
+        let response = self.execute(code, params, Some(data), &mut Vec::new())?;
+        check(&response)?;
+        Ok(response)
+    }
+
+    pub fn get_device_info(&mut self) -> Result<DeviceInfo> {
+        DeviceInfo::decode(&self.request(OperationCode::GetDeviceInfo, &[])?)
+    }
+
+    pub fn get_storage_ids(&mut self) -> Result<Vec<u32>> {
+        Reader::new(&self.request(OperationCode::GetStorageIDs, &[])?).read_u32_array()
+    }
+
+    pub fn get_storage_info(&mut self, storage_id: u32) -> Result<StorageInfo> {
+        StorageInfo::decode(&self.request(OperationCode::GetStorageInfo, &[storage_id])?)
+    }
+
+    /// Handles of objects in a storage; `parent` is 0 for all objects or `ROOT_PARENT` for the root.
+    pub fn get_object_handles(
+        &mut self,
+        storage_id: u32,
+        format: Option<ObjectFormatCode>,
+        parent: u32,
+    ) -> Result<Vec<u32>> {
+        let format = format.map_or(0, |format| u32::from(format.0));
+        let data = self.request(
+            OperationCode::GetObjectHandles,
+            &[storage_id, format, parent],
+        )?;
+        Reader::new(&data).read_u32_array()
+    }
+
+    pub fn get_object_info(&mut self, handle: u32) -> Result<ObjectInfo> {
+        ObjectInfo::decode(&self.request(OperationCode::GetObjectInfo, &[handle])?)
+    }
+
+    /// Stream an object into `writer`.
+    pub fn get_object(&mut self, handle: u32, writer: &mut dyn Write) -> Result<()> {
+        let response = self.execute(OperationCode::GetObject, &[handle], None, writer)?;
+        check(&response)
+    }
+
+    /// Read part of an object. Offsets beyond 4 GiB are not expressible with this operation.
+    pub fn get_partial_object(&mut self, handle: u32, offset: u32, length: u32) -> Result<Vec<u8>> {
+        self.request(OperationCode::GetPartialObject, &[handle, offset, length])
+    }
+
+    pub fn get_thumb(&mut self, handle: u32) -> Result<Vec<u8>> {
+        self.request(OperationCode::GetThumb, &[handle])
+    }
+
+    pub fn delete_object(&mut self, handle: u32) -> Result<()> {
+        self.request(OperationCode::DeleteObject, &[handle])
+            .map(|_| ())
+    }
+
+    /// Start a capture into the given storage (0 lets the device choose).
+    pub fn initiate_capture(
+        &mut self,
+        storage_id: u32,
+        format: Option<ObjectFormatCode>,
+    ) -> Result<()> {
+        let format = format.map_or(0, |format| u32::from(format.0));
+        self.request(OperationCode::InitiateCapture, &[storage_id, format])
+            .map(|_| ())
+    }
+
+    pub fn get_device_prop_desc(&mut self, code: DevicePropCode) -> Result<DevicePropDesc> {
+        DevicePropDesc::decode(
+            &self.request(OperationCode::GetDevicePropDesc, &[u32::from(code.0)])?,
+        )
+    }
+
+    /// Raw value of a device property; decode it with `DevicePropDesc::decode_value`.
+    pub fn get_device_prop_value(&mut self, code: DevicePropCode) -> Result<Vec<u8>> {
+        self.request(OperationCode::GetDevicePropValue, &[u32::from(code.0)])
+    }
+
+    pub fn set_device_prop_value(&mut self, code: DevicePropCode, value: &Value) -> Result<()> {
+        self.send(
+            OperationCode::SetDevicePropValue,
+            &[u32::from(code.0)],
+            &value.to_bytes(),
+        )
+        .map(|_| ())
+    }
+
+    /// Close the session, returning the transport.
+    pub fn close(mut self) -> Result<T> {
+        if self.open {
+            self.request(OperationCode::CloseSession, &[])?;
+            self.open = false;
+        }
+        Ok(self.transport)
+    }
+}
+
+/// Fail with `Error::Response` unless the response code is `OK`.
+pub fn check(response: &Response) -> Result<()> {
+    if response.code == ResponseCode::OK {
+        Ok(())
+    } else {
+        Err(Error::Response(response.code))
+    }
+}
@@ -0,0 +1,187 @@
+//! PTP over plain byte streams using container framing, and in-memory pipes to carry it.
+//!
+//! Operations, data and responses travel on a command stream; events travel on a separate
+//! stream, as they do on the interrupt pipe of a USB camera.
+
+use super::{Container, DataContainer, Event, Operation, Response, Transport};
+use crate::error::{Error, Result};
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+/// A PTP transport over a byte stream carrying generic containers.
+#[derive(Debug)]
+pub struct StreamTransport<S> {
+    stream: S,
+}
+
+impl<S: Read + Write + Send> StreamTransport<S> {
+    pub fn new(stream: S) -> StreamTransport<S> {
+        StreamTransport { stream }
+    }
+
+    /// The underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read + Write + Send> Transport for StreamTransport<S> {
+    fn execute(
+        &mut self,
+        operation: &Operation,
+        data_out: Option<&[u8]>,
+        data_in: &mut dyn Write,
+    ) -> Result<Response> {
+        Container::Operation(operation.clone()).write_to(&mut self.stream)?;
+        if let Some(data) = data_out {
+            Container::Data(DataContainer {
+                code: operation.code,
+                transaction_id: operation.transaction_id,
+                payload: data.to_vec(),
+            })
+            .write_to(&mut self.stream)?;
+        }
+        self.stream.flush()?;
+        loop {
+            match Container::read_from(&mut self.stream)? {
+                Container::Data(data) => data_in.write_all(&data.payload)?,
+                Container::Response(response) => return Ok(response),
+                other => {
+                    return Err(Error::malformed(format!(
+                        "unexpected {:?} container during a transaction",
+                        other.container_type()
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// Read event containers from `stream` on a background thread until it is closed.
+pub fn spawn_event_reader<R: Read + Send + 'static>(mut stream: R) -> Receiver<Event> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || loop {
+        match Container::read_from(&mut stream) {
+            Ok(Container::Event(event)) => {
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    });
+    receiver
+}
+
+/// Write events received on `events` to `stream` as event containers, on a background thread.
+pub fn spawn_event_writer<W: Write + Send + 'static>(mut stream: W, events: Receiver<Event>) {
+    thread::spawn(move || {
+        for event in events {
+            let written = Container::Event(event)
+                .write_to(&mut stream)
+                .and_then(|_| stream.flush().map_err(Error::from));
+            if written.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// The writing end of an in-memory pipe.
+#[derive(Debug, Clone)]
+pub struct PipeWriter {
+    sender: Sender<Vec<u8>>,
+}
+
+/// The reading end of an in-memory pipe. Reads return end of file once the writer is dropped.
+#[derive(Debug)]
+pub struct PipeReader {
+    receiver: Receiver<Vec<u8>>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+/// Create a one-way in-memory pipe.
+pub fn pipe() -> (PipeWriter, PipeReader) {
+    let (sender, receiver) = mpsc::channel();
+    (
+        PipeWriter { sender },
+        PipeReader {
+            receiver,
+            buffer: Vec::new(),
+            position: 0,
+        },
+    )
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sender
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "pipe reader was dropped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.buffer = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let count = buf.len().min(self.buffer.len() - self.position);
+        buf[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// One end of an in-memory two-way stream.
+#[derive(Debug)]
+pub struct Duplex {
+    reader: PipeReader,
+    writer: PipeWriter,
+}
+
+/// Create a connected pair of in-memory two-way streams.
+pub fn duplex() -> (Duplex, Duplex) {
+    let (a_writer, b_reader) = pipe();
+    let (b_writer, a_reader) = pipe();
+    (
+        Duplex {
+            reader: a_reader,
+            writer: a_writer,
+        },
+        Duplex {
+            reader: b_reader,
+            writer: b_writer,
+        },
+    )
+}
+
+impl Read for Duplex {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for Duplex {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
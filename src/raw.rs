@@ -0,0 +1,645 @@
+//! Recognition of camera RAW formats from the first bytes of a file.
+//!
+//! `ICCameraItem::isRaw` is only available on macOS, and file extensions are not always
+//! reliable. `classify` identifies a RAW format by its signature and, for the many formats
+//! built on TIFF, by the structure and camera make recorded in it. It works on however much
+//! of the start of the file is given, such as one ranged read from a camera; facts that lie
+//! past its end are reported as unknown.
+
+use crate::exif;
+use crate::jpeg;
+use crate::uti;
+use std::fmt;
+
+/// Signature of Fujifilm RAF files.
+const RAF_SIGNATURE: &[u8] = b"FUJIFILMCCD-RAW ";
+
+/// Maximum number of TIFF directories followed, guarding against offset loops.
+const MAX_DIRECTORIES: usize = 32;
+
+const COMPRESSION: u16 = 0x0103;
+const STRIP_OFFSETS: u16 = 0x0111;
+const SUB_IFDS: u16 = 0x014A;
+const DNG_VERSION: u16 = 0xC612;
+
+/// A camera RAW format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RawFormat {
+    /// Canon RAW 2, based on TIFF.
+    Cr2,
+    /// Canon RAW 3, based on the ISO base media file format.
+    Cr3,
+    Nef,
+    /// Nikon's format for Coolpix cameras.
+    Nrw,
+    Arw,
+    /// Sony's format for the DSC-R1.
+    Sr2,
+    /// Adobe Digital Negative, written by cameras of several makes.
+    Dng,
+    Raf,
+    Orf,
+    Rw2,
+    Pef,
+    Srw,
+    /// Hasselblad 3FR.
+    ThreeFr,
+    /// Phase One IIQ.
+    Iiq,
+}
+
+impl RawFormat {
+    /// The usual file extension, in lowercase.
+    pub fn extension(self) -> &'static str {
+        match self {
+            RawFormat::Cr2 => "cr2",
+            RawFormat::Cr3 => "cr3",
+            RawFormat::Nef => "nef",
+            RawFormat::Nrw => "nrw",
+            RawFormat::Arw => "arw",
+            RawFormat::Sr2 => "sr2",
+            RawFormat::Dng => "dng",
+            RawFormat::Raf => "raf",
+            RawFormat::Orf => "orf",
+            RawFormat::Rw2 => "rw2",
+            RawFormat::Pef => "pef",
+            RawFormat::Srw => "srw",
+            RawFormat::ThreeFr => "3fr",
+            RawFormat::Iiq => "iiq",
+        }
+    }
+
+    /// The UTI of the format, such as `com.canon.cr2-raw-image`.
+    pub fn uti(self) -> &'static str {
+        uti::for_extension(self.extension()).map_or(uti::CAMERA_RAW_IMAGE, |ty| ty.identifier)
+    }
+
+    /// The company that defined the format.
+    pub fn vendor(self) -> &'static str {
+        match self {
+            RawFormat::Cr2 | RawFormat::Cr3 => "Canon",
+            RawFormat::Nef | RawFormat::Nrw => "Nikon",
+            RawFormat::Arw | RawFormat::Sr2 => "Sony",
+            RawFormat::Dng => "Adobe",
+            RawFormat::Raf => "Fujifilm",
+            RawFormat::Orf => "Olympus",
+            RawFormat::Rw2 => "Panasonic",
+            RawFormat::Pef => "Pentax",
+            RawFormat::Srw => "Samsung",
+            RawFormat::ThreeFr => "Hasselblad",
+            RawFormat::Iiq => "Phase One",
+        }
+    }
+
+    /// The format of a file extension, in any case.
+    pub fn from_extension(extension: &str) -> Option<RawFormat> {
+        ALL.iter()
+            .copied()
+            .find(|format| format.extension().eq_ignore_ascii_case(extension))
+    }
+}
+
+impl fmt::Display for RawFormat {
+    /// Formats as the extension in uppercase, such as `CR2`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.extension().to_ascii_uppercase())
+    }
+}
+
+const ALL: &[RawFormat] = &[
+    RawFormat::Cr2,
+    RawFormat::Cr3,
+    RawFormat::Nef,
+    RawFormat::Nrw,
+    RawFormat::Arw,
+    RawFormat::Sr2,
+    RawFormat::Dng,
+    RawFormat::Raf,
+    RawFormat::Orf,
+    RawFormat::Rw2,
+    RawFormat::Pef,
+    RawFormat::Srw,
+    RawFormat::ThreeFr,
+    RawFormat::Iiq,
+];
+
+/// What the start of a RAW file tells about it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawInfo {
+    pub format: RawFormat,
+    /// The version of the format recorded in the file: `2.0` for CR2, the `DNGVersion` such
+    /// as `1.4.0.0`, the header version of RAF files such as `0201`, or the
+    /// `CanonCR3_001/...` identifier of CR3 files.
+    pub version: Option<String>,
+    /// The camera make, which for DNG files differs from the vendor of the format.
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// Whether the file embeds a JPEG preview, if the data given tells.
+    pub has_preview: Option<bool>,
+}
+
+impl RawInfo {
+    pub fn vendor(&self) -> &'static str {
+        self.format.vendor()
+    }
+}
+
+/// Whether `head`, the start of a file, is the start of a RAW file.
+pub fn is_raw(head: &[u8]) -> bool {
+    classify(head).is_some()
+}
+
+/// Identify the RAW format of the file starting with `head`. Returns `None` for other files,
+/// including TIFF files of unknown cameras.
+pub fn classify(head: &[u8]) -> Option<RawInfo> {
+    if head.starts_with(RAF_SIGNATURE) {
+        return Some(classify_raf(head));
+    }
+    if head.get(4..12) == Some(b"ftypcrx ") {
+        return Some(classify_cr3(head));
+    }
+    let (big_endian, magic) = match head.get(..4)? {
+        [b'I', b'I', a, b] => (false, u16::from_le_bytes([*a, *b])),
+        [b'M', b'M', a, b] => (true, u16::from_be_bytes([*a, *b])),
+        _ => return None,
+    };
+    let tiff = Tiff {
+        data: head,
+        big_endian,
+    };
+    let summary = tiff.summarize();
+    let make = summary
+        .make
+        .clone()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    let model = summary.model.clone().unwrap_or_default();
+    let (format, version) = match magic {
+        // ORF files replace the TIFF magic number with "RO", or "RS" for some models.
+        0x4F52 | 0x5352 => (RawFormat::Orf, None),
+        0x0055 => (RawFormat::Rw2, None),
+        42 if head.get(8..10) == Some(b"CR") => (
+            RawFormat::Cr2,
+            head.get(10..12)
+                .map(|version| format!("{}.{}", version[0], version[1])),
+        ),
+        42 if summary.dng_version.is_some() => (RawFormat::Dng, summary.dng_version.clone()),
+        // Phase One files head their RAW data with a block marked "IIII" or "MMMM".
+        42 if head.get(8..12) == Some(b"IIII")
+            || head.get(8..12) == Some(b"MMMM")
+            || make.starts_with("PHASE ONE") =>
+        {
+            (RawFormat::Iiq, None)
+        }
+        42 if make.starts_with("NIKON") => {
+            // Coolpix NRW files compress the image of their first directory as JPEG,
+            // where NEF files hold an uncompressed thumbnail.
+            if summary.first_compression == Some(6) {
+                (RawFormat::Nrw, None)
+            } else {
+                (RawFormat::Nef, None)
+            }
+        }
+        42 if make.starts_with("SONY") => {
+            if model.starts_with("DSC-R1") {
+                (RawFormat::Sr2, None)
+            } else {
+                (RawFormat::Arw, None)
+            }
+        }
+        42 if make.starts_with("PENTAX") || make.starts_with("RICOH") => (RawFormat::Pef, None),
+        42 if make.starts_with("SAMSUNG") => (RawFormat::Srw, None),
+        42 if make.starts_with("HASSELBLAD") => (RawFormat::ThreeFr, None),
+        _ => return None,
+    };
+    Some(RawInfo {
+        format,
+        version,
+        make: summary.make,
+        model: summary.model,
+        has_preview: summary.has_preview,
+    })
+}
+
+fn classify_raf(head: &[u8]) -> RawInfo {
+    let text = |range: std::ops::Range<usize>| {
+        head.get(range)
+            .map(|bytes| {
+                String::from_utf8_lossy(bytes.split(|&b| b == 0).next().unwrap_or_default())
+                    .trim()
+                    .to_string()
+            })
+            .filter(|text| !text.is_empty())
+    };
+    // The offset and length of the JPEG preview follow the camera name.
+    let preview_length = head
+        .get(88..92)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    RawInfo {
+        format: RawFormat::Raf,
+        version: text(16..20),
+        make: Some("FUJIFILM".to_string()),
+        model: text(28..60),
+        has_preview: preview_length.map(|length| length > 0),
+    }
+}
+
+fn classify_cr3(head: &[u8]) -> RawInfo {
+    let find = |needle: &[u8]| {
+        head.windows(needle.len())
+            .position(|window| window == needle)
+    };
+    let version = find(b"CanonCR3_").map(|start| {
+        let end = head[start..]
+            .iter()
+            .position(|&b| b == 0 || !b.is_ascii_graphic())
+            .map_or(head.len(), |end| start + end);
+        String::from_utf8_lossy(&head[start..end]).into_owned()
+    });
+    // The CMT1 box holds the first TIFF directory, with the make and model.
+    let exif = find(b"CMT1").and_then(|start| exif::read_tiff(head, start + 4).ok());
+    // Every CR3 file has a THMB thumbnail near its start.
+    RawInfo {
+        format: RawFormat::Cr3,
+        version,
+        make: exif.as_ref().and_then(|exif| exif.string(exif::tag::MAKE)),
+        model: exif.as_ref().and_then(|exif| exif.string(exif::tag::MODEL)),
+        has_preview: find(b"THMB").map(|_| true),
+    }
+}
+
+/// The facts `classify` needs from a TIFF structure.
+#[derive(Default)]
+struct Summary {
+    make: Option<String>,
+    model: Option<String>,
+    dng_version: Option<String>,
+    first_compression: Option<u32>,
+    has_preview: Option<bool>,
+}
+
+/// A TIFF structure, possibly truncated.
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+/// An entry of a directory: tag, type, count and the value or its offset.
+type Entry<'a> = (u16, u16, u32, &'a [u8]);
+
+impl<'a> Tiff<'a> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        self.u16_in(self.data.get(offset..)?)
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        self.u32_in(self.data.get(offset..)?)
+    }
+
+    fn u16_in(&self, bytes: &[u8]) -> Option<u16> {
+        let b = bytes.get(..2)?;
+        Some(if self.big_endian {
+            u16::from_be_bytes([b[0], b[1]])
+        } else {
+            u16::from_le_bytes([b[0], b[1]])
+        })
+    }
+
+    fn u32_in(&self, bytes: &[u8]) -> Option<u32> {
+        let b = bytes.get(..4)?;
+        Some(if self.big_endian {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        })
+    }
+
+    /// The entries of the directory at `offset` and the offset of the next one, or `None`
+    /// if the directory lies past the end of the data.
+    fn directory(&self, offset: usize) -> Option<(Vec<Entry<'a>>, Option<usize>)> {
+        let count = usize::from(self.u16(offset)?);
+        let mut entries = Vec::with_capacity(count);
+        for index in 0..count {
+            let entry = offset + 2 + index * 12;
+            let value = self.data.get(entry + 8..entry + 12)?;
+            entries.push((
+                self.u16(entry)?,
+                self.u16(entry + 2)?,
+                self.u32(entry + 4)?,
+                value,
+            ));
+        }
+        let next = self.u32(offset + 2 + count * 12).filter(|&next| next != 0);
+        Some((entries, next.map(|next| next as usize)))
+    }
+
+    /// The SHORT or LONG values of an entry, or `None` if they lie past the end of the data.
+    fn numbers(&self, entry: &Entry) -> Option<Vec<u32>> {
+        let &(_, field_type, count, value) = entry;
+        let count = count.min(256) as usize;
+        let size = match field_type {
+            1 | 7 => 1,
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Some(Vec::new()),
+        };
+        let start = if count * size <= 4 {
+            None
+        } else {
+            Some(self.u32_in(value)? as usize)
+        };
+        (0..count)
+            .map(|index| {
+                let offset = index * size;
+                match (start, size) {
+                    (None, 1) => value.get(offset).map(|&b| u32::from(b)),
+                    (None, 2) => self.u16_in(value.get(offset..)?).map(u32::from),
+                    (None, _) => self.u32_in(value),
+                    (Some(start), 1) => self.data.get(start + offset).map(|&b| u32::from(b)),
+                    (Some(start), 2) => self.u16(start + offset).map(u32::from),
+                    (Some(start), _) => self.u32(start + offset),
+                }
+            })
+            .collect()
+    }
+
+    fn text(&self, entry: &Entry) -> Option<String> {
+        let &(_, field_type, count, value) = entry;
+        if field_type != 2 {
+            return None;
+        }
+        let count = count as usize;
+        let bytes = if count <= 4 {
+            value.get(..count)?
+        } else {
+            let start = self.u32_in(value)? as usize;
+            self.data.get(start..start.checked_add(count)?)?
+        };
+        let text = String::from_utf8_lossy(bytes.split(|&b| b == 0).next().unwrap_or_default());
+        Some(text.trim().to_string()).filter(|text| !text.is_empty())
+    }
+
+    /// Walk the directory chain and sub-directories, looking for the make, model and DNG
+    /// version, and for JPEG previews: baseline JPEG images, as opposed to the lossless JPEG
+    /// of RAW data.
+    fn summarize(&self) -> Summary {
+        let mut summary = Summary::default();
+        let mut queue = match self.u32(4) {
+            Some(first) => vec![first as usize],
+            None => return summary,
+        };
+        let mut visited = Vec::new();
+        let mut complete = true;
+        let mut found_preview = false;
+        while let Some(offset) = queue.pop() {
+            if offset == 0 || visited.contains(&offset) || visited.len() >= MAX_DIRECTORIES {
+                continue;
+            }
+            let first = visited.is_empty();
+            visited.push(offset);
+            let (entries, next) = match self.directory(offset) {
+                Some(directory) => directory,
+                None => {
+                    complete = false;
+                    continue;
+                }
+            };
+            queue.extend(next);
+            let find = |tag| entries.iter().find(|entry| entry.0 == tag);
+            let number = |tag| {
+                find(tag)
+                    .and_then(|entry| self.numbers(entry))
+                    .and_then(|values| values.first().copied())
+            };
+            if summary.make.is_none() {
+                summary.make = find(exif::tag::MAKE).and_then(|entry| self.text(entry));
+            }
+            if summary.model.is_none() {
+                summary.model = find(exif::tag::MODEL).and_then(|entry| self.text(entry));
+            }
+            if first {
+                summary.first_compression = number(COMPRESSION);
+                summary.dng_version = find(DNG_VERSION)
+                    .and_then(|entry| self.numbers(entry))
+                    .filter(|version| !version.is_empty())
+                    .map(|version| {
+                        version
+                            .iter()
+                            .map(u32::to_string)
+                            .collect::<Vec<_>>()
+                            .join(".")
+                    });
+            }
+            match find(SUB_IFDS).map(|entry| self.numbers(entry)) {
+                Some(Some(sub_directories)) => {
+                    queue.extend(sub_directories.into_iter().map(|offset| offset as usize))
+                }
+                Some(None) => complete = false,
+                None => {}
+            }
+            let compression = number(COMPRESSION);
+            let start = number(exif::tag::JPEG_INTERCHANGE_FORMAT).or_else(|| match compression {
+                Some(6) | Some(7) => number(STRIP_OFFSETS),
+                _ => None,
+            });
+            if let Some(start) = start {
+                match self.jpeg_frame(start as usize) {
+                    Some(0xC0..=0xC2) => found_preview = true,
+                    Some(_) => {}
+                    None => complete = false,
+                }
+            }
+        }
+        summary.has_preview = if found_preview {
+            Some(true)
+        } else if complete {
+            Some(false)
+        } else {
+            None
+        };
+        summary
+    }
+
+    /// The frame marker of the JPEG image at `offset`, or `None` if its frame header lies
+    /// past the end of the data. Not a JPEG image gives marker 0.
+    fn jpeg_frame(&self, offset: usize) -> Option<u8> {
+        if self.data.get(offset..offset.checked_add(2)?)? != [0xFF, jpeg::SOI] {
+            return Some(0);
+        }
+        let mut offset = offset + 2;
+        loop {
+            let segment = self.data.get(offset..offset.checked_add(4)?)?;
+            if segment[0] != 0xFF {
+                return Some(0);
+            }
+            let marker = segment[1];
+            if marker == 0xFF {
+                offset += 1;
+                continue;
+            }
+            if marker == jpeg::SOS || marker == jpeg::EOI {
+                return Some(0);
+            }
+            if matches!(marker, 0xC0..=0xCF) && !matches!(marker, jpeg::DHT | 0xC8 | 0xCC) {
+                return Some(marker);
+            }
+            offset += 2 + usize::from(u16::from_be_bytes([segment[2], segment[3]]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        self, ascii, byte, jpeg, long, mp4_box, short, tiff_header, write_at, write_ifd,
+    };
+
+    /// A CR2 header: TIFF, then the "CR" marker, version 2.0, and the first directory at 16.
+    fn cr2(entries: Vec<testing::Entry>) -> Vec<u8> {
+        let mut data = tiff_header(16);
+        data.extend_from_slice(b"CR\x02\x00\0\0\0\0");
+        write_ifd(&mut data, 16, entries, 0);
+        data
+    }
+
+    #[test]
+    fn classifies_cr2_with_its_preview() {
+        let preview = jpeg(5472, 3648, &[]);
+        let mut data = cr2(vec![
+            ascii(exif::tag::MAKE, "Canon"),
+            ascii(exif::tag::MODEL, "Canon EOS 5D Mark IV"),
+            short(COMPRESSION, &[6]),
+            long(STRIP_OFFSETS, &[0x100]),
+        ]);
+        write_at(&mut data, 0x100, &preview);
+        assert_eq!(
+            classify(&data),
+            Some(RawInfo {
+                format: RawFormat::Cr2,
+                version: Some("2.0".to_string()),
+                make: Some("Canon".to_string()),
+                model: Some("Canon EOS 5D Mark IV".to_string()),
+                has_preview: Some(true),
+            })
+        );
+        // Without the frame header of the preview, whether there is one is unknown.
+        data.truncate(0x100);
+        assert_eq!(classify(&data).unwrap().has_preview, None);
+    }
+
+    #[test]
+    fn classifies_dng_by_its_version_whatever_the_make() {
+        let mut raw = jpeg(16, 16, &[]);
+        raw[3] = 0xC3;
+        let mut data = tiff_header(8);
+        write_ifd(
+            &mut data,
+            8,
+            vec![
+                ascii(exif::tag::MAKE, "Leica"),
+                short(COMPRESSION, &[7]),
+                long(STRIP_OFFSETS, &[0x100]),
+                byte(DNG_VERSION, &[1, 4, 0, 0]),
+            ],
+            0,
+        );
+        write_at(&mut data, 0x100, &raw);
+        let info = classify(&data).unwrap();
+        assert_eq!(info.format, RawFormat::Dng);
+        assert_eq!(info.version.as_deref(), Some("1.4.0.0"));
+        assert_eq!(info.make.as_deref(), Some("Leica"));
+        assert_eq!(info.vendor(), "Adobe");
+        // The lossless JPEG of the RAW data is not a preview.
+        assert_eq!(info.has_preview, Some(false));
+    }
+
+    #[test]
+    fn classifies_nikon_files_by_their_first_compression() {
+        let nikon = |compression| {
+            let mut data = tiff_header(8);
+            write_ifd(
+                &mut data,
+                8,
+                vec![
+                    ascii(exif::tag::MAKE, "NIKON CORPORATION"),
+                    short(COMPRESSION, &[compression]),
+                    long(SUB_IFDS, &[0x80]),
+                ],
+                0,
+            );
+            write_ifd(
+                &mut data,
+                0x80,
+                vec![
+                    long(exif::tag::JPEG_INTERCHANGE_FORMAT, &[100_000]),
+                    long(exif::tag::JPEG_INTERCHANGE_FORMAT_LENGTH, &[5000]),
+                ],
+                0,
+            );
+            data
+        };
+        let nef = classify(&nikon(1)).unwrap();
+        assert_eq!(nef.format, RawFormat::Nef);
+        // The preview of the sub-directory lies past the data given.
+        assert_eq!(nef.has_preview, None);
+        assert_eq!(classify(&nikon(6)).unwrap().format, RawFormat::Nrw);
+    }
+
+    #[test]
+    fn classifies_cr3_from_its_boxes() {
+        let mut tiff = tiff_header(8);
+        write_ifd(
+            &mut tiff,
+            8,
+            vec![
+                ascii(exif::tag::MAKE, "Canon"),
+                ascii(exif::tag::MODEL, "Canon EOS R"),
+            ],
+            0,
+        );
+        let mut ftyp = b"crx ".to_vec();
+        ftyp.extend_from_slice(&1u32.to_be_bytes());
+        ftyp.extend_from_slice(b"crx isom");
+        let moov = [
+            mp4_box(b"CNCV", b"CanonCR3_001/01.09.00/00.00.00"),
+            mp4_box(b"CMT1", &tiff),
+            mp4_box(b"THMB", &[0; 8]),
+        ]
+        .concat();
+        let data = [mp4_box(b"ftyp", &ftyp), mp4_box(b"moov", &moov)].concat();
+        assert_eq!(
+            classify(&data),
+            Some(RawInfo {
+                format: RawFormat::Cr3,
+                version: Some("CanonCR3_001/01.09.00/00.00.00".to_string()),
+                make: Some("Canon".to_string()),
+                model: Some("Canon EOS R".to_string()),
+                has_preview: Some(true),
+            })
+        );
+    }
+
+    #[test]
+    fn classifies_raf_from_its_header() {
+        let mut data = RAF_SIGNATURE.to_vec();
+        write_at(&mut data, 16, b"0201");
+        write_at(&mut data, 28, b"X-T4\0");
+        write_at(&mut data, 84, &200u32.to_be_bytes());
+        write_at(&mut data, 88, &5000u32.to_be_bytes());
+        let info = classify(&data).unwrap();
+        assert_eq!(info.format, RawFormat::Raf);
+        assert_eq!(info.version.as_deref(), Some("0201"));
+        assert_eq!(info.model.as_deref(), Some("X-T4"));
+        assert_eq!(info.has_preview, Some(true));
+    }
+
+    #[test]
+    fn other_files_are_not_raw() {
+        let mut tiff = tiff_header(8);
+        write_ifd(&mut tiff, 8, vec![ascii(exif::tag::MAKE, "Scanner Co")], 0);
+        assert!(!is_raw(&tiff));
+        assert!(!is_raw(&jpeg(16, 16, &[])));
+        assert!(!is_raw(b"II"));
+    }
+}
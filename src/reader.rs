@@ -0,0 +1,368 @@
+//! Random access to files on a camera without downloading them.
+//!
+//! `CameraFileReader` implements `Read`, `BufRead` and `Seek` on top of
+//! `CameraDevice::read_data_from_file`, the counterpart of `requestReadDataFromFile`. Data is
+//! requested in chunks that are kept in a small cache, and sequential reads fetch several
+//! chunks ahead in one request, so parsers can walk the headers of a large video or RAW
+//! file with a handful of transfers.
+
+use crate::camera::{CameraDevice, CameraFile};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+/// Default number of bytes per chunk.
+pub const DEFAULT_CHUNK_SIZE: u64 = 64 * 1024;
+/// Default number of chunks kept in the cache.
+pub const DEFAULT_CACHE_CHUNKS: usize = 16;
+/// Default number of chunks fetched at once while reading sequentially.
+pub const DEFAULT_READ_AHEAD: usize = 4;
+
+/// A cached chunk of the file.
+struct Chunk {
+    index: u64,
+    data: Vec<u8>,
+}
+
+/// A `Read` + `Seek` view of a file on a camera.
+pub struct CameraFileReader<'a, C: CameraDevice + ?Sized = dyn CameraDevice> {
+    camera: &'a C,
+    file: CameraFile,
+    length: u64,
+    position: u64,
+    chunk_size: u64,
+    cache_chunks: usize,
+    read_ahead: usize,
+    /// Cached chunks, least recently used first.
+    cache: VecDeque<Chunk>,
+    /// The chunk following the last one fetched, which starts a sequential read.
+    next_chunk: Option<u64>,
+    requests: u64,
+    bytes_fetched: u64,
+}
+
+impl<'a, C: CameraDevice + ?Sized> CameraFileReader<'a, C> {
+    /// A reader of `file` positioned at its start, with the default chunk size, cache size
+    /// and read-ahead.
+    pub fn new(camera: &'a C, file: &CameraFile) -> CameraFileReader<'a, C> {
+        CameraFileReader {
+            camera,
+            length: file.file_size,
+            file: file.clone(),
+            position: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            cache_chunks: DEFAULT_CACHE_CHUNKS,
+            read_ahead: DEFAULT_READ_AHEAD,
+            cache: VecDeque::new(),
+            next_chunk: None,
+            requests: 0,
+            bytes_fetched: 0,
+        }
+    }
+
+    /// Request data in chunks of `bytes`, at least one byte. Clears the cache.
+    pub fn chunk_size(mut self, bytes: u64) -> CameraFileReader<'a, C> {
+        self.chunk_size = bytes.max(1);
+        self.cache.clear();
+        self.next_chunk = None;
+        self
+    }
+
+    /// Keep up to `chunks` chunks in the cache, at least one. Evicts the least recently used
+    /// chunks beyond that.
+    pub fn cache_chunks(mut self, chunks: usize) -> CameraFileReader<'a, C> {
+        self.cache_chunks = chunks.max(1);
+        self.evict();
+        self
+    }
+
+    /// Fetch `chunks` chunks at once when reading sequentially. Zero or one disables
+    /// read-ahead. The cache size limits how far ahead is read.
+    pub fn read_ahead(mut self, chunks: usize) -> CameraFileReader<'a, C> {
+        self.read_ahead = chunks;
+        self
+    }
+
+    /// The file being read.
+    pub fn file(&self) -> &CameraFile {
+        &self.file
+    }
+
+    /// Size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Number of ranged reads sent to the camera so far.
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// Number of bytes received from the camera so far.
+    pub fn bytes_fetched(&self) -> u64 {
+        self.bytes_fetched
+    }
+
+    /// Read exactly `length` bytes at `offset` without moving the position.
+    pub fn read_at(&mut self, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+        let position = self.position;
+        self.position = offset;
+        let mut data = vec![0; length];
+        let result = self.read_exact(&mut data);
+        self.position = position;
+        result.map(|()| data)
+    }
+
+    /// The cached chunk `index`, fetching it and any read-ahead chunks if needed.
+    fn chunk(&mut self, index: u64) -> io::Result<&[u8]> {
+        if let Some(found) = self.cache.iter().position(|chunk| chunk.index == index) {
+            let chunk = self.cache.remove(found).expect("cached chunk");
+            self.cache.push_back(chunk);
+        } else {
+            self.fetch(index)?;
+        }
+        Ok(&self.cache.back().expect("cached chunk").data)
+    }
+
+    /// Fetch chunk `index`, and the chunks after it if reading sequentially, in one request.
+    /// Leaves chunk `index` as the most recently used.
+    fn fetch(&mut self, index: u64) -> io::Result<()> {
+        let count = if self.next_chunk == Some(index) {
+            self.read_ahead.clamp(1, self.cache_chunks) as u64
+        } else {
+            1
+        };
+        let start = index * self.chunk_size;
+        let end = self
+            .length
+            .min(start.saturating_add(count.saturating_mul(self.chunk_size)));
+        let data = self
+            .camera
+            .read_data_from_file(&self.file, start, end.saturating_sub(start))?;
+        self.requests += 1;
+        self.bytes_fetched += data.len() as u64;
+        if (data.len() as u64) < end - start {
+            // The file is shorter than reported; treat the end of the data as the end.
+            self.length = start + data.len() as u64;
+        }
+        let mut chunks = data
+            .chunks(self.chunk_size as usize)
+            .zip(index..)
+            .map(|(data, index)| Chunk {
+                index,
+                data: data.to_vec(),
+            })
+            .collect::<Vec<_>>();
+        if chunks.is_empty() {
+            chunks.push(Chunk {
+                index,
+                data: Vec::new(),
+            });
+        }
+        self.next_chunk = Some(index + chunks.len() as u64);
+        self.cache
+            .retain(|cached| !chunks.iter().any(|chunk| chunk.index == cached.index));
+        // The requested chunk goes last so that it is the most recently used.
+        let first = chunks.remove(0);
+        self.cache.extend(chunks.into_iter().rev());
+        self.cache.push_back(first);
+        self.evict();
+        Ok(())
+    }
+
+    /// Drop the least recently used chunks until the cache fits its size.
+    fn evict(&mut self) {
+        while self.cache.len() > self.cache_chunks {
+            self.cache.pop_front();
+        }
+    }
+}
+
+impl<'a, C: CameraDevice + ?Sized> Read for CameraFileReader<'a, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<'a, C: CameraDevice + ?Sized> BufRead for CameraFileReader<'a, C> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position >= self.length {
+            return Ok(&[]);
+        }
+        let index = self.position / self.chunk_size;
+        let offset = (self.position % self.chunk_size) as usize;
+        let chunk = self.chunk(index)?;
+        Ok(chunk.get(offset..).unwrap_or_default())
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = self.position.saturating_add(amount as u64);
+    }
+}
+
+impl<'a, C: CameraDevice + ?Sized> Seek for CameraFileReader<'a, C> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.length, offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+        match base.checked_add_signed(offset) {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockCamera;
+
+    /// A camera holding a 1000-byte file of the bytes 0, 1, 2 and so on.
+    fn camera() -> (MockCamera, CameraFile, Vec<u8>) {
+        let camera = MockCamera::new("Mock");
+        let data = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        let file = camera.add_file("CLIP0001.MP4", &data);
+        (camera, file, data)
+    }
+
+    #[test]
+    fn sequential_reads_fetch_chunks_ahead() {
+        let (camera, file, data) = camera();
+        let mut reader = CameraFileReader::new(&camera, &file)
+            .chunk_size(100)
+            .read_ahead(4);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        // Chunk 0, then chunks 1 to 4, 5 to 8 and 9.
+        assert_eq!(reader.requests(), 4);
+        assert_eq!(reader.bytes_fetched(), 1000);
+
+        let mut reader = CameraFileReader::new(&camera, &file)
+            .chunk_size(100)
+            .read_ahead(0);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        assert_eq!(reader.requests(), 10);
+    }
+
+    #[test]
+    fn read_ahead_is_limited_by_the_cache() {
+        let (camera, file, _) = camera();
+        let mut reader = CameraFileReader::new(&camera, &file)
+            .chunk_size(100)
+            .cache_chunks(2)
+            .read_ahead(8);
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        // Chunk 0, then two chunks at a time.
+        assert_eq!(reader.requests(), 6);
+        assert_eq!(reader.bytes_fetched(), 1000);
+    }
+
+    #[test]
+    fn the_least_recently_used_chunk_is_evicted() {
+        let (camera, file, data) = camera();
+        let mut reader = CameraFileReader::new(&camera, &file)
+            .chunk_size(100)
+            .cache_chunks(2)
+            .read_ahead(1);
+        for (offset, requests) in [(0, 1), (500, 2), (10, 2), (900, 3), (20, 3), (510, 4)] {
+            assert_eq!(
+                reader.read_at(offset, 10).unwrap(),
+                data[offset as usize..][..10]
+            );
+            assert_eq!(reader.requests(), requests, "reading at {}", offset);
+        }
+    }
+
+    #[test]
+    fn shrinking_the_cache_keeps_the_most_recent_chunks() {
+        let (camera, file, _) = camera();
+        let mut reader = CameraFileReader::new(&camera, &file)
+            .chunk_size(100)
+            .read_ahead(1);
+        for offset in [0, 200, 400, 600] {
+            reader.read_at(offset, 1).unwrap();
+        }
+        let mut reader = reader.cache_chunks(2);
+        reader.read_at(400, 1).unwrap();
+        reader.read_at(600, 1).unwrap();
+        assert_eq!(reader.requests(), 4);
+        reader.read_at(0, 1).unwrap();
+        assert_eq!(reader.requests(), 5);
+    }
+
+    #[test]
+    fn reads_span_chunks() {
+        let (camera, file, data) = camera();
+        let mut reader = CameraFileReader::new(&camera, &file).chunk_size(64);
+        assert_eq!(reader.read_at(60, 200).unwrap(), data[60..260]);
+        assert_eq!(reader.stream_position().unwrap(), 0);
+        let err = reader.read_at(990, 20).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(reader.stream_position().unwrap(), 0);
+    }
+
+    #[test]
+    fn seeks_are_relative_to_the_start_position_and_end() {
+        let (camera, file, data) = camera();
+        let mut reader = CameraFileReader::new(&camera, &file).chunk_size(100);
+        let mut buf = [0; 10];
+
+        assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 990);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[990..]);
+        assert_eq!(reader.seek(SeekFrom::Current(-500)).unwrap(), 500);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[500..510]);
+
+        // Past the end, reads return nothing.
+        assert_eq!(reader.seek(SeekFrom::Start(5000)).unwrap(), 5000);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert_eq!(reader.seek(SeekFrom::End(10)).unwrap(), 1010);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        for invalid in [SeekFrom::End(-1001), SeekFrom::Current(i64::MIN)] {
+            let err = reader.seek(invalid).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        reader.seek(SeekFrom::Start(u64::MAX)).unwrap();
+        assert!(reader.seek(SeekFrom::Current(1)).is_err());
+        assert_eq!(reader.stream_position().unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn files_shorter_than_reported_end_where_the_data_ends() {
+        let (camera, file, data) = camera();
+        let reported = CameraFile {
+            file_size: 4000,
+            ..file
+        };
+        let mut reader = CameraFileReader::new(&camera, &reported).chunk_size(300);
+        assert_eq!(reader.len(), 4000);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        assert_eq!(reader.len(), 1000);
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), 1000);
+    }
+}
@@ -0,0 +1,716 @@
+//! Capture sequences that change camera settings between shots.
+//!
+//! A `Sequence` lists the settings of each frame, such as an exposure bracket of shots at
+//! increasing exposure compensation or a focus stack stepping the focus distance from near to
+//! far. Running it sets the properties of each frame, waits for the camera to confirm the
+//! change with a `PropertyChanged` event, takes the picture and downloads its files into a
+//! folder of their own, next to a `manifest.csv` describing the settings of every frame.
+
+use crate::camera::{CameraDevice, CameraEvent, CameraFile};
+use crate::error::{Error, Result};
+use crate::import::Template;
+use crate::ledger::csv_field;
+use crate::ptp::properties::{ExposureBias, ExposureTime, FocusDistance};
+use crate::ptp::{DevicePropCode, PropertyValue};
+use crate::tether::{Capture, TetherOptions, TetherSession};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Name of the manifest written into the folder of a sequence.
+pub const MANIFEST_NAME: &str = "manifest.csv";
+
+/// A value of a device property.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Setting {
+    pub code: DevicePropCode,
+    pub value: PropertyValue,
+}
+
+/// The settings of one shot of a sequence.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+    pub settings: Vec<Setting>,
+}
+
+/// Shots taken one after the other with different settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sequence {
+    /// Prefix of the name of the folder the files are downloaded into, such as `bracket`.
+    pub name: String,
+    pub frames: Vec<Frame>,
+}
+
+impl Sequence {
+    pub fn new(name: &str, frames: Vec<Frame>) -> Sequence {
+        Sequence {
+            name: name.to_string(),
+            frames,
+        }
+    }
+
+    /// `shots` shots `step` EV of exposure compensation apart around the current value,
+    /// in the order 0, −1, +1, −2, +2 steps and so on. Values are rounded to the closest
+    /// the camera allows.
+    pub fn exposure_bracket(
+        camera: &dyn CameraDevice,
+        shots: usize,
+        step: f64,
+    ) -> Result<Sequence> {
+        let code = DevicePropCode::ExposureBiasCompensation;
+        let center = match camera.property(code)?.value() {
+            PropertyValue::ExposureBias(bias) => bias.as_ev(),
+            other => return Err(unexpected_value(code, &other)),
+        };
+        let values = bracket_offsets(shots)
+            .map(|offset| {
+                PropertyValue::ExposureBias(ExposureBias::from_ev(center + offset * step))
+            })
+            .collect();
+        Sequence::from_values(camera, "bracket", code, values)
+    }
+
+    /// `shots` shots `step` EV of exposure time apart around the current shutter speed, for
+    /// cameras in manual exposure mode, in the same order as `exposure_bracket`.
+    pub fn shutter_bracket(camera: &dyn CameraDevice, shots: usize, step: f64) -> Result<Sequence> {
+        let code = DevicePropCode::ExposureTime;
+        let center = match camera.property(code)?.value() {
+            PropertyValue::ExposureTime(time) => time.as_secs_f64(),
+            other => return Err(unexpected_value(code, &other)),
+        };
+        let values = bracket_offsets(shots)
+            .map(|offset| {
+                let time = center * (offset * step).exp2();
+                PropertyValue::ExposureTime(ExposureTime::from_secs_f64(time))
+            })
+            .collect();
+        Sequence::from_values(camera, "bracket", code, values)
+    }
+
+    /// `shots` shots focused from `near` to `far`, evenly spaced in diopters so that the
+    /// depth of field of neighbouring shots overlaps about equally. Distances are rounded
+    /// to the closest the camera allows.
+    pub fn focus_stack(
+        camera: &dyn CameraDevice,
+        near: FocusDistance,
+        far: FocusDistance,
+        shots: usize,
+    ) -> Result<Sequence> {
+        let (near, far) = (near.diopters(), far.diopters());
+        if !near.is_finite() {
+            return Err(Error::InvalidValue(
+                "a focus stack cannot start at a distance of zero".into(),
+            ));
+        }
+        let values = (0..shots)
+            .map(|shot| {
+                let t = if shots > 1 {
+                    shot as f64 / (shots - 1) as f64
+                } else {
+                    0.0
+                };
+                PropertyValue::FocusDistance(FocusDistance::from_diopters(near + (far - near) * t))
+            })
+            .collect();
+        Sequence::from_values(camera, "stack", DevicePropCode::FocusDistance, values)
+    }
+
+    /// One frame for each of `values` of the property `code`, rounded to the closest the
+    /// camera allows. Values that round to one already used are left out.
+    fn from_values(
+        camera: &dyn CameraDevice,
+        name: &str,
+        code: DevicePropCode,
+        values: Vec<PropertyValue>,
+    ) -> Result<Sequence> {
+        if values.is_empty() {
+            return Err(Error::InvalidValue(
+                "a sequence needs at least one shot".into(),
+            ));
+        }
+        let property = camera.property(code)?;
+        let mut frames: Vec<Frame> = Vec::new();
+        for value in values {
+            let value = property.nearest_allowed(&value).ok_or_else(|| {
+                Error::InvalidValue(format!("{} cannot be stored in property {}", value, code))
+            })?;
+            let frame = Frame {
+                settings: vec![Setting { code, value }],
+            };
+            if !frames.contains(&frame) {
+                frames.push(frame);
+            }
+        }
+        Ok(Sequence::new(name, frames))
+    }
+
+    /// The properties set by the frames, in the order they first appear.
+    pub fn property_codes(&self) -> Vec<DevicePropCode> {
+        let mut codes = Vec::new();
+        for setting in self.frames.iter().flat_map(|frame| &frame.settings) {
+            if !codes.contains(&setting.code) {
+                codes.push(setting.code);
+            }
+        }
+        codes
+    }
+
+    /// Take the shots of the sequence and download them into a new folder in the
+    /// destination of `options`.
+    ///
+    /// Every setting is checked against the values the camera allows before the first shot.
+    /// If a shot fails, the manifest lists the frames taken before it and the error is
+    /// returned.
+    pub fn run(
+        &self,
+        camera: &dyn CameraDevice,
+        options: &SequenceOptions,
+    ) -> Result<SequenceResult> {
+        if self.frames.is_empty() {
+            return Err(Error::InvalidValue("the sequence has no frames".into()));
+        }
+        let codes = self.property_codes();
+        let mut originals = Vec::new();
+        for &code in &codes {
+            let property = camera.property(code)?;
+            for setting in self.frames.iter().flat_map(|frame| &frame.settings) {
+                if setting.code == code {
+                    property.validate(&setting.value)?;
+                }
+            }
+            originals.push(Setting {
+                code,
+                value: property.value(),
+            });
+        }
+
+        let folder = create_folder(&options.destination, &self.name)?;
+        let mut tether = TetherOptions::new(&folder);
+        tether.name_template = options.name_template.clone();
+        tether.delete_after_download = options.delete_after_download;
+        tether.capture_timeout = options.capture_timeout;
+        let mut session = TetherSession::start(camera, tether)?;
+        let mut frames = Vec::new();
+        let shot = self.shoot(camera, options, &mut session, &mut frames);
+        let mut late = Vec::new();
+        if options.restore_settings {
+            for setting in &originals {
+                // Best effort: the sequence itself succeeded or failed already.
+                let _ = apply(camera, setting, options.confirm_timeout, &mut late);
+            }
+        }
+        let downloaded = download_late(&session, &mut frames, late);
+        let stopped = session.stop();
+        let manifest = folder.join(MANIFEST_NAME);
+        let written = fs::write(&manifest, manifest_csv(&codes, &frames));
+        shot?;
+        downloaded?;
+        stopped?;
+        written?;
+        Ok(SequenceResult {
+            folder,
+            manifest,
+            frames,
+        })
+    }
+
+    fn shoot(
+        &self,
+        camera: &dyn CameraDevice,
+        options: &SequenceOptions,
+        session: &mut TetherSession,
+        frames: &mut Vec<FrameResult>,
+    ) -> Result<()> {
+        for frame in &self.frames {
+            let mut settings = Vec::new();
+            let mut late = Vec::new();
+            for setting in &frame.settings {
+                settings.push(Setting {
+                    code: setting.code,
+                    value: apply(camera, setting, options.confirm_timeout, &mut late)?,
+                });
+            }
+            download_late(session, frames, late)?;
+            let capture = session.capture()?;
+            frames.push(FrameResult { settings, capture });
+        }
+        Ok(())
+    }
+}
+
+/// Settings of a sequence run.
+#[derive(Clone, Debug)]
+pub struct SequenceOptions {
+    /// Folder the folder of the sequence is created in.
+    pub destination: PathBuf,
+    /// Name of downloaded files, without the extension, which is kept from the original.
+    /// `{seq}` is the number of the frame, starting at 1.
+    pub name_template: Template,
+    /// Whether the files are deleted from the camera once downloaded.
+    pub delete_after_download: bool,
+    /// How long to wait for the camera to confirm a property change.
+    pub confirm_timeout: Duration,
+    /// How long to wait for the files of a shot.
+    pub capture_timeout: Duration,
+    /// Whether the properties are set back to their values from before the sequence.
+    pub restore_settings: bool,
+}
+
+impl SequenceOptions {
+    /// Download into a new folder in `destination` under the original file names, and
+    /// restore the settings afterwards.
+    pub fn new<P: AsRef<Path>>(destination: P) -> SequenceOptions {
+        SequenceOptions {
+            destination: destination.as_ref().to_path_buf(),
+            name_template: Template::parse("{name}").expect("valid template"),
+            delete_after_download: false,
+            confirm_timeout: Duration::from_secs(5),
+            capture_timeout: Duration::from_secs(30),
+            restore_settings: true,
+        }
+    }
+}
+
+/// A shot of a sequence.
+#[derive(Debug)]
+pub struct FrameResult {
+    /// The settings as the camera reported them after the change.
+    pub settings: Vec<Setting>,
+    pub capture: Capture,
+}
+
+/// The shots of a sequence and where they were downloaded.
+#[derive(Debug)]
+pub struct SequenceResult {
+    /// The folder holding the files of the sequence.
+    pub folder: PathBuf,
+    pub manifest: PathBuf,
+    pub frames: Vec<FrameResult>,
+}
+
+/// Offsets in steps of the shots of a bracket: 0, −1, +1, −2, +2 and so on.
+fn bracket_offsets(shots: usize) -> impl Iterator<Item = f64> {
+    (0..shots).map(|shot| {
+        let distance = shot.div_ceil(2) as f64;
+        if shot % 2 == 1 {
+            -distance
+        } else {
+            distance
+        }
+    })
+}
+
+/// Set a property unless it has the value already, and wait for the camera to confirm the
+/// change. Returns the value the camera reports.
+///
+/// Cameras report values on the way to the target while a setting such as the focus
+/// distance changes, so events with other values are skipped. When no event confirms the
+/// change within `timeout`, the value is read once more, since some cameras report only
+/// changes made with their own controls. Files the previous shot adds meanwhile are
+/// appended to `late`.
+fn apply(
+    camera: &dyn CameraDevice,
+    setting: &Setting,
+    timeout: Duration,
+    late: &mut Vec<CameraFile>,
+) -> Result<PropertyValue> {
+    let code = setting.code;
+    if camera.property(code)?.value() == setting.value {
+        return Ok(setting.value.clone());
+    }
+    camera.set_property_value(code, &setting.value)?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        match camera.next_event(deadline - now)? {
+            Some(CameraEvent::PropertyChanged(changed)) if changed == code.0 => {
+                let value = camera.property(code)?.value();
+                if value == setting.value {
+                    return Ok(value);
+                }
+            }
+            Some(CameraEvent::ItemsAdded(added)) => late.extend(added),
+            Some(CameraEvent::DeviceRemoved) => {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "the camera was disconnected",
+                )))
+            }
+            // As in `TetherSession`, only the deadline ends the wait.
+            Some(_) | None => {}
+        }
+    }
+    let value = camera.property(code)?.value();
+    if value == setting.value {
+        Ok(value)
+    } else {
+        Err(Error::Io(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "the camera did not set {} to {}, it reports {}",
+                code, setting.value, value
+            ),
+        )))
+    }
+}
+
+/// Download files added after the last shot was collected into that shot, numbered as it.
+/// Files added before the first shot are not part of the sequence.
+fn download_late(
+    session: &TetherSession,
+    frames: &mut [FrameResult],
+    files: Vec<CameraFile>,
+) -> Result<()> {
+    if let (Some(frame), false) = (frames.last_mut(), files.is_empty()) {
+        let capture = &mut frame.capture;
+        let late = session.download_capture(capture.trigger, capture.sequence, files)?;
+        capture.files.extend(late.files);
+    }
+    Ok(())
+}
+
+/// Create the first of `{name}-001`, `{name}-002` and so on that does not exist yet.
+fn create_folder(destination: &Path, name: &str) -> Result<PathBuf> {
+    fs::create_dir_all(destination)?;
+    for number in 1.. {
+        let folder = destination.join(format!("{}-{:03}", name, number));
+        match fs::create_dir(&folder) {
+            Ok(()) => return Ok(folder),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    unreachable!("a free folder name")
+}
+
+/// One row for every downloaded file with the frame number, the names on the camera and
+/// on disk, and the value of each property in `codes`.
+fn manifest_csv(codes: &[DevicePropCode], frames: &[FrameResult]) -> String {
+    let mut csv = String::from("frame,file,saved_as");
+    for code in codes {
+        let _ = write!(csv, ",{}", csv_field(&code.to_string()));
+    }
+    csv.push('\n');
+    for (index, frame) in frames.iter().enumerate() {
+        for file in &frame.capture.files {
+            let _ = write!(
+                csv,
+                "{},{},{}",
+                index + 1,
+                csv_field(&file.file.name),
+                csv_field(&file.download.saved_filename)
+            );
+            for code in codes {
+                let value = frame
+                    .settings
+                    .iter()
+                    .find(|setting| setting.code == *code)
+                    .map(|setting| setting.value.to_string())
+                    .unwrap_or_default();
+                let _ = write!(csv, ",{}", csv_field(&value));
+            }
+            csv.push('\n');
+        }
+    }
+    csv
+}
+
+fn unexpected_value(code: DevicePropCode, value: &PropertyValue) -> Error {
+    Error::Malformed(format!(
+        "property {} has the unexpected value {}",
+        code, value
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{CameraCapabilities, CameraItem};
+    use crate::mock::MockCamera;
+    use crate::ptp::properties::Property;
+    use crate::ptp::{DataType, DevicePropDesc, DevicePropForm, Value};
+    use crate::testing::ScratchDir;
+    use std::collections::VecDeque;
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    const BIAS: DevicePropCode = DevicePropCode::ExposureBiasCompensation;
+
+    /// Exposure compensation in thirds of a stop from −1 to +1 EV, set to `current`.
+    fn bias_property(current: i16) -> DevicePropDesc {
+        let thirds = [-1000, -667, -333, 0, 333, 667, 1000];
+        DevicePropDesc {
+            property_code: BIAS,
+            data_type: DataType::INT16,
+            writable: true,
+            factory_default: Value::Int16(0),
+            current: Value::Int16(current),
+            form: DevicePropForm::Enumeration(thirds.iter().map(|&v| Value::Int16(v)).collect()),
+        }
+    }
+
+    fn bias(thousandths: i16) -> PropertyValue {
+        PropertyValue::ExposureBias(ExposureBias(thousandths))
+    }
+
+    fn values(sequence: &Sequence) -> Vec<PropertyValue> {
+        sequence
+            .frames
+            .iter()
+            .map(|frame| frame.settings[0].value.clone())
+            .collect()
+    }
+
+    /// A mock camera that may ignore property changes, and that reports files from
+    /// `late` only after a capture has completed.
+    struct Camera {
+        mock: MockCamera,
+        applies_changes: bool,
+        late: Mutex<VecDeque<CameraFile>>,
+    }
+
+    impl Camera {
+        fn new(current: i16) -> Camera {
+            let mock = MockCamera::new("Mock");
+            mock.add_property(bias_property(current));
+            Camera {
+                mock,
+                applies_changes: true,
+                late: Mutex::new(VecDeque::new()),
+            }
+        }
+    }
+
+    impl CameraDevice for Camera {
+        fn name(&self) -> String {
+            self.mock.name()
+        }
+
+        fn capabilities(&self) -> CameraCapabilities {
+            self.mock.capabilities()
+        }
+
+        fn contents(&self) -> Result<Vec<CameraItem>> {
+            self.mock.contents()
+        }
+
+        fn download_file(&self, file: &CameraFile, writer: &mut dyn Write) -> Result<u64> {
+            self.mock.download_file(file, writer)
+        }
+
+        fn read_data_from_file(
+            &self,
+            file: &CameraFile,
+            offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>> {
+            self.mock.read_data_from_file(file, offset, length)
+        }
+
+        fn take_picture(&self) -> Result<()> {
+            self.mock.take_picture()?;
+            if let Some(file) = self.late.lock().unwrap().pop_front() {
+                self.mock.push_event(CameraEvent::ItemsAdded(vec![file]));
+            }
+            Ok(())
+        }
+
+        fn enable_tethering(&self) -> Result<()> {
+            self.mock.enable_tethering()
+        }
+
+        fn disable_tethering(&self) -> Result<()> {
+            self.mock.disable_tethering()
+        }
+
+        fn property(&self, code: DevicePropCode) -> Result<Property> {
+            self.mock.property(code)
+        }
+
+        fn set_property_value(&self, code: DevicePropCode, value: &PropertyValue) -> Result<()> {
+            if self.applies_changes {
+                self.mock.set_property_value(code, value)
+            } else {
+                Ok(())
+            }
+        }
+
+        fn next_event(&self, timeout: Duration) -> Result<Option<CameraEvent>> {
+            self.mock.next_event(timeout)
+        }
+    }
+
+    fn options(dir: &ScratchDir) -> SequenceOptions {
+        let mut options = SequenceOptions::new(dir.path());
+        options.confirm_timeout = Duration::from_millis(200);
+        options.capture_timeout = Duration::from_secs(5);
+        options
+    }
+
+    #[test]
+    fn brackets_alternate_around_the_current_value() {
+        let camera = Camera::new(333);
+        let sequence = Sequence::exposure_bracket(&camera, 5, 1.0 / 3.0).unwrap();
+        assert_eq!(sequence.name, "bracket");
+        assert_eq!(sequence.property_codes(), [BIAS]);
+        assert_eq!(
+            values(&sequence),
+            [bias(333), bias(0), bias(667), bias(-333), bias(1000)]
+        );
+    }
+
+    #[test]
+    fn bracket_values_are_rounded_and_deduplicated() {
+        let camera = Camera::new(0);
+        let sequence = Sequence::exposure_bracket(&camera, 5, 0.2).unwrap();
+        assert_eq!(values(&sequence), [bias(0), bias(-333), bias(333)]);
+        assert!(Sequence::exposure_bracket(&camera, 0, 0.2).is_err());
+    }
+
+    #[test]
+    fn runs_shoot_every_frame_and_restore_the_settings() {
+        let dir = ScratchDir::new("sequence-run");
+        let camera = Camera::new(0);
+        let sequence = Sequence::exposure_bracket(&camera, 3, 1.0).unwrap();
+        let result = sequence.run(&camera, &options(&dir)).unwrap();
+
+        assert_eq!(camera.mock.captures(), 3);
+        assert!(!camera.mock.is_tethering());
+        assert_eq!(camera.property(BIAS).unwrap().value(), bias(0));
+        assert_eq!(result.folder, dir.path().join("bracket-001"));
+        let settings: Vec<_> = result.frames.iter().map(|f| f.settings.clone()).collect();
+        assert_eq!(
+            settings,
+            [bias(0), bias(-1000), bias(1000)]
+                .iter()
+                .map(|value| vec![Setting {
+                    code: BIAS,
+                    value: value.clone()
+                }])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            dir.files(),
+            [
+                "bracket-001/IMG_0001.JPG",
+                "bracket-001/IMG_0002.JPG",
+                "bracket-001/IMG_0003.JPG",
+                "bracket-001/manifest.csv",
+            ]
+        );
+        let manifest = fs::read_to_string(&result.manifest).unwrap();
+        assert_eq!(
+            manifest,
+            format!(
+                "frame,file,saved_as,{}\n\
+                 1,IMG_0001.JPG,IMG_0001.JPG,+0.0 EV\n\
+                 2,IMG_0002.JPG,IMG_0002.JPG,-1.0 EV\n\
+                 3,IMG_0003.JPG,IMG_0003.JPG,+1.0 EV\n",
+                csv_field(&BIAS.to_string())
+            )
+        );
+
+        // A second run gets a folder of its own.
+        let result = sequence.run(&camera, &options(&dir)).unwrap();
+        assert_eq!(result.folder, dir.path().join("bracket-002"));
+    }
+
+    #[test]
+    fn settings_can_be_kept_after_the_run() {
+        let dir = ScratchDir::new("sequence-keep");
+        let camera = Camera::new(0);
+        let sequence = Sequence::new(
+            "keep",
+            vec![Frame {
+                settings: vec![Setting {
+                    code: BIAS,
+                    value: bias(667),
+                }],
+            }],
+        );
+        let mut options = options(&dir);
+        options.restore_settings = false;
+        sequence.run(&camera, &options).unwrap();
+        assert_eq!(camera.property(BIAS).unwrap().value(), bias(667));
+    }
+
+    #[test]
+    fn settings_are_checked_before_the_first_shot() {
+        let dir = ScratchDir::new("sequence-invalid");
+        let camera = Camera::new(0);
+        let sequence = Sequence::new(
+            "invalid",
+            vec![Frame {
+                settings: vec![Setting {
+                    code: BIAS,
+                    value: bias(500),
+                }],
+            }],
+        );
+        assert!(matches!(
+            sequence.run(&camera, &options(&dir)),
+            Err(Error::InvalidValue(_))
+        ));
+        assert_eq!(camera.mock.captures(), 0);
+    }
+
+    #[test]
+    fn unconfirmed_changes_fail_the_run() {
+        let dir = ScratchDir::new("sequence-unconfirmed");
+        let mut camera = Camera::new(0);
+        camera.applies_changes = false;
+        let sequence = Sequence::exposure_bracket(&camera, 2, 1.0).unwrap();
+        let started = Instant::now();
+        match sequence.run(&camera, &options(&dir)) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::TimedOut),
+            other => panic!("unexpected result {:?}", other),
+        }
+        // Events such as the files of the first shot do not end the wait early.
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(camera.mock.captures(), 1);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("bracket-001/manifest.csv"))
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn files_reported_after_a_shot_are_downloaded_with_it() {
+        let dir = ScratchDir::new("sequence-late");
+        let camera = Camera::new(0);
+        for name in ["IMG_0001.WAV", "IMG_0002.WAV"] {
+            let file = camera.mock.add_file(name, name.as_bytes());
+            camera.late.lock().unwrap().push_back(file);
+        }
+        let sequence = Sequence::exposure_bracket(&camera, 2, 1.0).unwrap();
+        let result = sequence.run(&camera, &options(&dir)).unwrap();
+
+        let names: Vec<Vec<&str>> = result
+            .frames
+            .iter()
+            .map(|frame| {
+                let files = &frame.capture.files;
+                files.iter().map(|file| file.file.name.as_str()).collect()
+            })
+            .collect();
+        // The second is reported while the settings are restored.
+        assert_eq!(
+            names,
+            [
+                ["IMG_0001.JPG", "IMG_0001.WAV"],
+                ["IMG_0002.JPG", "IMG_0002.WAV"]
+            ]
+        );
+        assert!(dir.path().join("bracket-001/IMG_0002.WAV").is_file());
+        let manifest = fs::read_to_string(&result.manifest).unwrap();
+        assert!(manifest.contains("\n1,IMG_0001.WAV,IMG_0001.WAV,+0.0 EV\n"));
+    }
+}
@@ -0,0 +1,220 @@
+//! Helpers shared by the unit tests.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// An empty directory under the system temporary directory, removed when dropped.
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    pub fn new(name: &str) -> ScratchDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "image-capture-core-{}-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst),
+            name
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("create scratch directory");
+        ScratchDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Names of the files under the directory, relative to it with `/` separators, sorted.
+    pub fn files(&self) -> Vec<String> {
+        let mut files = Vec::new();
+        let mut folders = vec![self.path.clone()];
+        while let Some(folder) = folders.pop() {
+            for entry in fs::read_dir(&folder).expect("read scratch directory") {
+                let path = entry.expect("read scratch directory").path();
+                if path.is_dir() {
+                    folders.push(path);
+                } else {
+                    let relative = path.strip_prefix(&self.path).expect("inside the directory");
+                    files.push(relative.to_string_lossy().replace('\\', "/"));
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A little-endian TIFF directory entry.
+pub struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value: Vec<u8>,
+}
+
+/// An ASCII entry, NUL-terminated.
+pub fn ascii(tag: u16, text: &str) -> Entry {
+    let mut value = text.as_bytes().to_vec();
+    value.push(0);
+    Entry {
+        tag,
+        kind: 2,
+        count: value.len() as u32,
+        value,
+    }
+}
+
+pub fn byte(tag: u16, values: &[u8]) -> Entry {
+    Entry {
+        tag,
+        kind: 1,
+        count: values.len() as u32,
+        value: values.to_vec(),
+    }
+}
+
+pub fn undefined(tag: u16, values: &[u8]) -> Entry {
+    Entry {
+        kind: 7,
+        ..byte(tag, values)
+    }
+}
+
+pub fn short(tag: u16, values: &[u16]) -> Entry {
+    Entry {
+        tag,
+        kind: 3,
+        count: values.len() as u32,
+        value: values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect(),
+    }
+}
+
+pub fn long(tag: u16, values: &[u32]) -> Entry {
+    Entry {
+        tag,
+        kind: 4,
+        count: values.len() as u32,
+        value: values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect(),
+    }
+}
+
+pub fn rational(tag: u16, values: &[(u32, u32)]) -> Entry {
+    Entry {
+        tag,
+        kind: 5,
+        count: values.len() as u32,
+        value: values
+            .iter()
+            .flat_map(|(n, d)| [n.to_le_bytes(), d.to_le_bytes()].concat())
+            .collect(),
+    }
+}
+
+pub fn srational(tag: u16, values: &[(i32, i32)]) -> Entry {
+    Entry {
+        tag,
+        kind: 10,
+        count: values.len() as u32,
+        value: values
+            .iter()
+            .flat_map(|(n, d)| [n.to_le_bytes(), d.to_le_bytes()].concat())
+            .collect(),
+    }
+}
+
+/// A little-endian TIFF header pointing to the first directory at `first`.
+pub fn tiff_header(first: u32) -> Vec<u8> {
+    let mut header = b"II*\0".to_vec();
+    header.extend_from_slice(&first.to_le_bytes());
+    header
+}
+
+/// Write `bytes` at `offset` in `data`, growing it with zeros as needed.
+pub fn write_at(data: &mut Vec<u8>, offset: usize, bytes: &[u8]) {
+    if data.len() < offset + bytes.len() {
+        data.resize(offset + bytes.len(), 0);
+    }
+    data[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+/// Write a little-endian TIFF directory at `offset` in `data`, relative to a TIFF header at
+/// the start of `data`, followed by the values that do not fit in their entries. `next` is
+/// the offset of the next directory, or 0.
+pub fn write_ifd(data: &mut Vec<u8>, offset: u32, mut entries: Vec<Entry>, next: u32) {
+    entries.sort_by_key(|entry| entry.tag);
+    let values_start = offset + 2 + 12 * entries.len() as u32 + 4;
+    let mut table = (entries.len() as u16).to_le_bytes().to_vec();
+    let mut values = Vec::new();
+    for entry in &entries {
+        table.extend_from_slice(&entry.tag.to_le_bytes());
+        table.extend_from_slice(&entry.kind.to_le_bytes());
+        table.extend_from_slice(&entry.count.to_le_bytes());
+        if entry.value.len() <= 4 {
+            let mut inline = entry.value.clone();
+            inline.resize(4, 0);
+            table.extend_from_slice(&inline);
+        } else {
+            table.extend_from_slice(&(values_start + values.len() as u32).to_le_bytes());
+            values.extend_from_slice(&entry.value);
+            if values.len() % 2 == 1 {
+                values.push(0);
+            }
+        }
+    }
+    table.extend_from_slice(&next.to_le_bytes());
+    table.extend_from_slice(&values);
+    write_at(data, offset as usize, &table);
+}
+
+/// A baseline JPEG image of `width` x `height` pixels without image data, with `segments`
+/// between the start of image and the frame header.
+pub fn jpeg(width: u16, height: u16, segments: &[u8]) -> Vec<u8> {
+    let mut jpeg = vec![0xFF, 0xD8];
+    jpeg.extend_from_slice(segments);
+    jpeg.extend_from_slice(&[0xFF, 0xC0, 0x00, 0x11, 0x08]);
+    jpeg.extend_from_slice(&height.to_be_bytes());
+    jpeg.extend_from_slice(&width.to_be_bytes());
+    jpeg.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    jpeg.extend_from_slice(&[0xFF, 0xD9]);
+    jpeg
+}
+
+/// A JPEG APP1 segment holding the EXIF data `tiff`.
+pub fn exif_segment(tiff: &[u8]) -> Vec<u8> {
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&(2 + 6 + tiff.len() as u16).to_be_bytes());
+    segment.extend_from_slice(b"Exif\0\0");
+    segment.extend_from_slice(tiff);
+    segment
+}
+
+/// An ISO base media box of type `kind` holding `body`.
+pub fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut data = (8 + body.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(kind);
+    data.extend_from_slice(body);
+    data
+}
+
+/// A full box of type `kind` with version `version`, no flags, and `body`.
+pub fn full_box(kind: &[u8; 4], version: u8, body: &[u8]) -> Vec<u8> {
+    let mut full = vec![version, 0, 0, 0];
+    full.extend_from_slice(body);
+    mp4_box(kind, &full)
+}
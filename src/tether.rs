@@ -0,0 +1,305 @@
+//! Tethered capture into a hot folder.
+//!
+//! A `TetherSession` enables tethering, triggers captures and waits for the files they add
+//! to the camera, then downloads them into a hot folder with the naming templates of an
+//! import. Pictures taken with the shutter release on the camera are reported the same way.
+//! Tethering is disabled again when the session is dropped.
+
+use crate::camera::{CameraCapabilities, CameraDevice, CameraEvent, CameraFile};
+use crate::download::{self, CollisionPolicy, DownloadOptions, DownloadResult, Verification};
+use crate::error::{Error, Result};
+use crate::import::{self, Template};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Settings of a tethered capture session.
+#[derive(Clone, Debug)]
+pub struct TetherOptions {
+    /// Folder captured files are downloaded into.
+    pub hot_folder: PathBuf,
+    /// Layout of sub-folders of the hot folder. An empty template downloads all files into
+    /// the hot folder itself.
+    pub folder_template: Template,
+    /// Name of downloaded files, without the extension, which is kept from the original.
+    pub name_template: Template,
+    /// Value of `{seq}` for the first capture. Files of one capture share the number.
+    pub sequence_start: u32,
+    /// Whether captured files are deleted from the camera once downloaded.
+    pub delete_after_download: bool,
+    /// What to do when a file in the hot folder already has the name.
+    pub collisions: CollisionPolicy,
+    /// How long to wait for the files of a capture triggered with `TetherSession::capture`.
+    pub capture_timeout: Duration,
+    /// How long to wait for more files of a capture after the last one, such as the JPEG
+    /// of a RAW+JPEG capture, when the camera does not report the capture complete.
+    pub settle_time: Duration,
+}
+
+impl TetherOptions {
+    /// Download captures into `hot_folder` under their original names, keeping them on the
+    /// camera.
+    pub fn new<P: AsRef<Path>>(hot_folder: P) -> TetherOptions {
+        TetherOptions {
+            hot_folder: hot_folder.as_ref().to_path_buf(),
+            folder_template: Template::parse("").expect("valid template"),
+            name_template: Template::parse("{name}").expect("valid template"),
+            sequence_start: 1,
+            delete_after_download: false,
+            collisions: CollisionPolicy::RenameWithCounter,
+            capture_timeout: Duration::from_secs(30),
+            settle_time: Duration::from_millis(500),
+        }
+    }
+}
+
+/// What started a capture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// `TetherSession::capture`.
+    Host,
+    /// The shutter release on the camera.
+    Camera,
+}
+
+/// A file of a capture and where it was downloaded.
+#[derive(Debug)]
+pub struct CapturedFile {
+    pub file: CameraFile,
+    pub download: DownloadResult,
+}
+
+/// The files added to the camera by one capture.
+#[derive(Debug)]
+pub struct Capture {
+    pub trigger: Trigger,
+    /// Value of `{seq}` for the capture.
+    pub sequence: u32,
+    pub files: Vec<CapturedFile>,
+}
+
+/// A camera with tethering enabled.
+pub struct TetherSession<'a> {
+    camera: &'a dyn CameraDevice,
+    camera_name: String,
+    options: TetherOptions,
+    sequence: u32,
+    enabled: bool,
+}
+
+impl<'a> TetherSession<'a> {
+    /// Enable tethering on `camera`.
+    pub fn start(
+        camera: &'a dyn CameraDevice,
+        options: TetherOptions,
+    ) -> Result<TetherSession<'a>> {
+        camera.enable_tethering()?;
+        Ok(TetherSession {
+            camera,
+            camera_name: camera.name(),
+            sequence: options.sequence_start,
+            options,
+            enabled: true,
+        })
+    }
+
+    pub fn options(&self) -> &TetherOptions {
+        &self.options
+    }
+
+    /// Take a picture and download the files it adds.
+    pub fn capture(&mut self) -> Result<Capture> {
+        let files = self.trigger()?;
+        self.download(Trigger::Host, files)
+    }
+
+    /// Take a picture and wait for the files it adds.
+    pub(crate) fn trigger(&self) -> Result<Vec<CameraFile>> {
+        if !self
+            .camera
+            .capabilities()
+            .contains(CameraCapabilities::ICCameraDeviceCanTakePicture)
+        {
+            return Err(Error::Unsupported("take picture".into()));
+        }
+        self.camera.take_picture()?;
+        let files = self.collect(self.options.capture_timeout, true)?;
+        if files.is_empty() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the capture added no files",
+            )));
+        }
+        Ok(files)
+    }
+
+    /// Wait up to `timeout` for a picture taken with the shutter release on the camera and
+    /// download the files it adds.
+    pub fn next_capture(&mut self, timeout: Duration) -> Result<Option<Capture>> {
+        let files = self.collect(timeout, false)?;
+        if files.is_empty() {
+            return Ok(None);
+        }
+        self.download(Trigger::Camera, files).map(Some)
+    }
+
+    /// Disable tethering, reporting whether that failed.
+    pub fn stop(mut self) -> Result<()> {
+        self.enabled = false;
+        self.camera.disable_tethering()
+    }
+
+    /// Files added to the camera, waiting up to `timeout` for the first and `settle_time`
+    /// for more, or until the camera reports a host-triggered capture complete.
+    fn collect(&self, timeout: Duration, host: bool) -> Result<Vec<CameraFile>> {
+        let mut files: Vec<CameraFile> = Vec::new();
+        let mut deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(files);
+            }
+            match self.camera.next_event(deadline - now)? {
+                // Every file added while the session runs is new, whether or not the
+                // camera has listed its contents before.
+                Some(CameraEvent::ItemsAdded(added)) if !added.is_empty() => {
+                    files.extend(added);
+                    deadline = Instant::now() + self.options.settle_time;
+                }
+                Some(CameraEvent::CaptureComplete) if host && !files.is_empty() => {
+                    return Ok(files)
+                }
+                Some(CameraEvent::DeviceRemoved) => {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::NotConnected,
+                        "the camera was disconnected",
+                    )))
+                }
+                // Cameras also report events they ignore, such as a new folder, without
+                // waiting out the timeout, so only the deadline ends the wait.
+                Some(_) | None => {}
+            }
+        }
+    }
+
+    fn download(&mut self, trigger: Trigger, files: Vec<CameraFile>) -> Result<Capture> {
+        let sequence = self.sequence;
+        self.sequence += 1;
+        self.download_capture(trigger, sequence, files)
+    }
+
+    /// Download the files of a capture numbered `sequence`.
+    pub(crate) fn download_capture(
+        &self,
+        trigger: Trigger,
+        sequence: u32,
+        files: Vec<CameraFile>,
+    ) -> Result<Capture> {
+        let options = &self.options;
+        let needs_exif = options.folder_template.needs_exif() || options.name_template.needs_exif();
+        let mut capture = Capture {
+            trigger,
+            sequence,
+            files: Vec::new(),
+        };
+        for file in files {
+            let exif = if needs_exif {
+                import::read_exif(self.camera, &file)
+            } else {
+                None
+            };
+            let date = import::capture_date(&file, exif.as_ref());
+            let render = |template: &Template| {
+                template.render_file(&file, &self.camera_name, date, exif.as_ref(), sequence)
+            };
+            let extension = download::split_extension(&file.name).1;
+            let name = match extension {
+                "" => render(&options.name_template),
+                extension => format!("{}.{}", render(&options.name_template), extension),
+            };
+            let download_options =
+                DownloadOptions::new(options.hot_folder.join(render(&options.folder_template)))
+                    .save_as(&name)
+                    .collision_policy(options.collisions)
+                    .download_sidecar_files(true)
+                    .delete_after_download(options.delete_after_download)
+                    .verification(Verification::Size);
+            let download = download::download(self.camera, &file, &download_options)?;
+            capture.files.push(CapturedFile { file, download });
+        }
+        Ok(capture)
+    }
+}
+
+impl<'a> Drop for TetherSession<'a> {
+    fn drop(&mut self) {
+        if self.enabled {
+            let _ = self.camera.disable_tethering();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ptp::responder::VirtualCamera;
+    use crate::ptp::{PtpCamera, Session};
+    use crate::testing::ScratchDir;
+
+    /// A virtual camera with an empty DCIM folder, and a fresh connection to it whose
+    /// contents have never been listed.
+    fn connect() -> (VirtualCamera, u32, u32, impl CameraDevice) {
+        let virtual_camera = VirtualCamera::new("Test", "Virtual");
+        let storage = virtual_camera.add_storage("Card");
+        let dcim = virtual_camera.add_folder(storage, None, "DCIM");
+        let (transport, events) = virtual_camera.connect_in_memory();
+        let camera = PtpCamera::new(Session::open(transport, 1).unwrap(), events).unwrap();
+        (virtual_camera, storage, dcim, camera)
+    }
+
+    fn options(hot_folder: &ScratchDir) -> TetherOptions {
+        TetherOptions {
+            capture_timeout: Duration::from_secs(5),
+            settle_time: Duration::from_millis(100),
+            ..TetherOptions::new(hot_folder.path())
+        }
+    }
+
+    #[test]
+    fn host_captures_are_downloaded_from_a_ptp_camera() {
+        let hot_folder = ScratchDir::new("tether-host");
+        let (virtual_camera, _, _, camera) = connect();
+        virtual_camera.set_capture_data(vec![7; 32]);
+        let mut session = TetherSession::start(&camera, options(&hot_folder)).unwrap();
+        let capture = session.capture().unwrap();
+        assert_eq!(capture.trigger, Trigger::Host);
+        assert_eq!(capture.sequence, 1);
+        assert_eq!(capture.files.len(), 1);
+        assert_eq!(capture.files[0].file.name, "CAP_0001.JPG");
+        assert_eq!(hot_folder.files(), ["CAP_0001.JPG"]);
+        assert_eq!(
+            std::fs::read(hot_folder.path().join("CAP_0001.JPG")).unwrap(),
+            [7; 32]
+        );
+    }
+
+    #[test]
+    fn ignored_events_do_not_end_the_wait_for_a_capture() {
+        let hot_folder = ScratchDir::new("tether-camera");
+        let (virtual_camera, storage, dcim, camera) = connect();
+        let mut session = TetherSession::start(&camera, options(&hot_folder)).unwrap();
+        // The camera reports the new folder, which is no file, before the picture.
+        virtual_camera.add_folder(storage, Some(dcim), "101TEST");
+        virtual_camera.press_shutter().unwrap();
+        let capture = session
+            .next_capture(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert_eq!(capture.trigger, Trigger::Camera);
+        assert_eq!(capture.files.len(), 1);
+        assert_eq!(
+            hot_folder.files(),
+            [capture.files[0].download.saved_filename.as_str()]
+        );
+    }
+}
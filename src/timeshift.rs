@@ -0,0 +1,495 @@
+//! Correction of the dates of pictures taken with a camera whose clock was off.
+//!
+//! `CameraDevice::time_offset` tells how far the camera's clock is from the computer's, and
+//! `sync_clock` only fixes the dates of pictures taken afterwards. A `ClockCorrection`
+//! shifts the dates of downloaded files instead: the EXIF `DateTimeOriginal`,
+//! `DateTimeDigitized` (CreateDate) and `DateTime` fields of JPEG, TIFF and DNG files, the
+//! XMP sidecars of other RAW files, which are left unmodified, and the modification date of
+//! every file. It can also move the dates into another time zone and record the zone in the
+//! EXIF `OffsetTime` fields, so that pictures from several cameras line up on one timeline.
+//! Every change is listed in a `CorrectionLog`.
+
+use crate::camera::CameraDevice;
+use crate::datetime::{DateTime, UtcOffset};
+use crate::error::{Error, Result};
+use crate::exif::{self, tag, ExifEditor, Ifd, Value, EXIF_HEAD_SIZE};
+use crate::geotag;
+use crate::jpeg;
+use crate::ledger::csv_field;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The date fields corrected, each with the field recording its time zone.
+const DATE_FIELDS: &[(Ifd, u16, u16)] = &[
+    (
+        Ifd::Exif,
+        tag::DATE_TIME_ORIGINAL,
+        tag::OFFSET_TIME_ORIGINAL,
+    ),
+    (
+        Ifd::Exif,
+        tag::DATE_TIME_DIGITIZED,
+        tag::OFFSET_TIME_DIGITIZED,
+    ),
+    (Ifd::Primary, tag::DATE_TIME, tag::OFFSET_TIME),
+];
+
+/// Name of the modification date in a `Change`.
+pub const MODIFICATION_DATE: &str = "FileModifyDate";
+
+/// How to correct the dates recorded by a camera.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClockCorrection {
+    /// Seconds the camera's clock was ahead of the true time, negative if it was behind.
+    pub camera_offset: i64,
+    /// Time zone the camera's clock was set to, if known.
+    pub camera_zone: Option<UtcOffset>,
+    /// Time zone to express the dates in. Dates stay in the camera's time zone without it.
+    pub zone: Option<UtcOffset>,
+}
+
+impl ClockCorrection {
+    /// Correct a clock `camera_offset` seconds ahead of the true time.
+    pub fn new(camera_offset: i64) -> ClockCorrection {
+        ClockCorrection {
+            camera_offset,
+            ..ClockCorrection::default()
+        }
+    }
+
+    /// Correct the clock by the offset the camera reports, if it reports one.
+    pub fn from_camera(camera: &dyn CameraDevice) -> Option<ClockCorrection> {
+        camera
+            .time_offset()
+            .filter(|offset| offset.is_finite())
+            .map(|offset| ClockCorrection::new(offset.round() as i64))
+    }
+
+    /// Correct the clock by the offset measured with a picture of a reference clock: the
+    /// camera recorded `camera_time` when the reference showed `true_time`.
+    pub fn from_reference(camera_time: DateTime, true_time: DateTime) -> ClockCorrection {
+        ClockCorrection::new(camera_time.to_unix() - true_time.to_unix())
+    }
+
+    /// Seconds added to the dates recorded by the camera: the clock correction plus the
+    /// difference between the time zones.
+    pub fn shift(&self) -> i64 {
+        let zones = match (self.camera_zone, self.zone) {
+            (Some(camera_zone), Some(zone)) => i64::from(zone.seconds - camera_zone.seconds),
+            _ => 0,
+        };
+        zones - self.camera_offset
+    }
+
+    /// The corrected date of a date recorded by the camera.
+    pub fn correct(&self, date: DateTime) -> DateTime {
+        date.add_seconds(self.shift())
+    }
+
+    /// The time zone of the corrected dates, if known.
+    pub fn target_zone(&self) -> Option<UtcOffset> {
+        self.zone.or(self.camera_zone)
+    }
+
+    /// The changes `apply` would make to the file at `path`, without making them.
+    pub fn plan(&self, path: &Path) -> Result<Vec<Change>> {
+        self.edit(path).map(|edit| edit.changes)
+    }
+
+    /// Correct the dates of the file at `path`, returning the changes made.
+    ///
+    /// The EXIF fields of JPEG, TIFF and DNG files are rewritten. Other RAW files are left
+    /// unmodified, as geotagging leaves them, and their corrected capture dates are written
+    /// to their XMP sidecar instead. Other files, such as movies and sidecars, only have their
+    /// modification date corrected.
+    pub fn apply(&self, path: &Path) -> Result<Vec<Change>> {
+        let edit = self.edit(path)?;
+        if !edit.editor.is_empty() {
+            edit.editor.apply_to_file(path)?;
+        }
+        if let Some((sidecar, properties)) = &edit.sidecar {
+            let names = properties.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            geotag::write_sidecar_properties(sidecar, &names, properties)?;
+        }
+        if let Some(modified) = edit.modified {
+            File::options()
+                .write(true)
+                .open(path)?
+                .set_modified(modified)?;
+        }
+        Ok(edit.changes)
+    }
+
+    /// The changes `apply_all` would make, without making them.
+    pub fn plan_all<I, P>(&self, paths: I) -> CorrectionLog
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.log(paths, |path| self.plan(path))
+    }
+
+    /// Correct the dates of every file of `paths`, logging the changes and failures.
+    pub fn apply_all<I, P>(&self, paths: I) -> CorrectionLog
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.log(paths, |path| self.apply(path))
+    }
+
+    fn log<I, P, F>(&self, paths: I, mut correct: F) -> CorrectionLog
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+        F: FnMut(&Path) -> Result<Vec<Change>>,
+    {
+        let mut log = CorrectionLog::default();
+        for path in paths {
+            let path = path.as_ref();
+            match correct(path) {
+                Ok(changes) => log.changes.extend(changes),
+                Err(error) => log.failed.push(FailedCorrection {
+                    path: path.to_path_buf(),
+                    error,
+                }),
+            }
+        }
+        log
+    }
+
+    /// The EXIF changes and the modification date of the file at `path`.
+    fn edit(&self, path: &Path) -> Result<Edit> {
+        let mut edit = Edit {
+            changes: Vec::new(),
+            editor: ExifEditor::new(),
+            sidecar: None,
+            modified: None,
+        };
+        let change = |field: &str, old: String, new: String| Change {
+            path: path.to_path_buf(),
+            field: field.to_string(),
+            old,
+            new,
+        };
+        let mut head = Vec::new();
+        File::open(path)?
+            .take(EXIF_HEAD_SIZE)
+            .read_to_end(&mut head)?;
+        let has_exif = head.starts_with(&[0xFF, jpeg::SOI])
+            || head.starts_with(b"II")
+            || head.starts_with(b"MM");
+        let exif = if has_exif {
+            exif::read(&head).ok()
+        } else {
+            None
+        };
+        drop(head);
+
+        let embed = geotag::is_embeddable(path);
+        let sidecar = geotag::sidecar_path(path);
+        let recorded = if embed {
+            String::new()
+        } else {
+            fs::read_to_string(&sidecar).unwrap_or_default()
+        };
+        let mut properties = Vec::new();
+        let mut original = None;
+        if let Some(exif) = &exif {
+            for &(ifd, date_tag, offset_tag) in DATE_FIELDS {
+                let old = match exif.get_in(ifd, date_tag).and_then(Value::as_str) {
+                    Some(old) => old.to_string(),
+                    None => continue,
+                };
+                let date = match exif::parse_date_time(&old) {
+                    Some(date) => self.correct(date),
+                    None => continue,
+                };
+                if date_tag == tag::DATE_TIME_ORIGINAL {
+                    original = Some(date);
+                }
+                if !embed {
+                    // XMP has no EXIF property for the `DateTime` of the primary directory.
+                    if ifd == Ifd::Exif {
+                        let name = field_name(ifd, date_tag);
+                        let new = xmp_date(date, self.target_zone());
+                        if !recorded.contains(&format!("exif:{}=\"{}\"", name, new)) {
+                            properties.push((name, new.clone()));
+                            edit.changes.push(Change {
+                                path: sidecar.clone(),
+                                field: name.to_string(),
+                                old,
+                                new,
+                            });
+                        }
+                    }
+                    continue;
+                }
+                let new = exif::format_date_time(&date);
+                if new != old {
+                    edit.editor = edit.editor.set(ifd, date_tag, Value::Ascii(new.clone()));
+                    edit.changes
+                        .push(change(field_name(ifd, date_tag), old, new));
+                }
+                if let Some(zone) = self.target_zone() {
+                    let old = exif
+                        .get_in(Ifd::Exif, offset_tag)
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string();
+                    let new = zone.to_string();
+                    if new != old {
+                        edit.editor =
+                            edit.editor
+                                .set(Ifd::Exif, offset_tag, Value::Ascii(new.clone()));
+                        edit.changes
+                            .push(change(field_name(Ifd::Exif, offset_tag), old, new));
+                    }
+                }
+            }
+        }
+
+        if !properties.is_empty() {
+            edit.sidecar = Some((sidecar, properties));
+        }
+
+        // The capture date in a known time zone gives the exact time; otherwise the
+        // modification date moves by the clock correction alone.
+        let modified = fs::metadata(path)?.modified()?;
+        let corrected = match (original, self.target_zone()) {
+            (Some(date), Some(zone)) => zone.to_utc(date).to_system_time(),
+            _ => add_seconds(modified, -self.camera_offset),
+        };
+        if corrected != modified {
+            edit.modified = Some(corrected);
+            edit.changes.push(change(
+                MODIFICATION_DATE,
+                format_time(modified),
+                format_time(corrected),
+            ));
+        }
+        Ok(edit)
+    }
+}
+
+struct Edit {
+    changes: Vec<Change>,
+    editor: ExifEditor,
+    /// The XMP sidecar of a RAW file and the date properties to set in it.
+    sidecar: Option<(PathBuf, Vec<(&'static str, String)>)>,
+    modified: Option<SystemTime>,
+}
+
+/// A date changed by a correction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub path: PathBuf,
+    /// Name of the changed field, such as `DateTimeOriginal`, or `MODIFICATION_DATE`.
+    pub field: String,
+    /// The previous value, empty if the field was added.
+    pub old: String,
+    pub new: String,
+}
+
+/// A file whose dates could not be corrected.
+#[derive(Debug)]
+pub struct FailedCorrection {
+    pub path: PathBuf,
+    pub error: Error,
+}
+
+/// The changes made by a correction, in the order they were made.
+#[derive(Debug, Default)]
+pub struct CorrectionLog {
+    pub changes: Vec<Change>,
+    pub failed: Vec<FailedCorrection>,
+}
+
+impl CorrectionLog {
+    /// Whether every file was corrected.
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Write the changes as CSV with a header row, followed by the failures with the error
+    /// in place of the new value.
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writeln!(writer, "path,field,old,new")?;
+        for change in &self.changes {
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                csv_field(&change.path.to_string_lossy()),
+                csv_field(&change.field),
+                csv_field(&change.old),
+                csv_field(&change.new)
+            )?;
+        }
+        for failed in &self.failed {
+            writeln!(
+                writer,
+                "{},error,,{}",
+                csv_field(&failed.path.to_string_lossy()),
+                csv_field(&failed.error.to_string())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CorrectionLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut files = self
+            .changes
+            .iter()
+            .map(|change| &change.path)
+            .collect::<Vec<_>>();
+        files.sort();
+        files.dedup();
+        write!(
+            f,
+            "changed {} dates in {} files, failed {}",
+            self.changes.len(),
+            files.len(),
+            self.failed.len()
+        )
+    }
+}
+
+fn field_name(ifd: Ifd, tag: u16) -> &'static str {
+    exif::tag_name(ifd, tag).unwrap_or("unknown")
+}
+
+/// A date in the form of XMP, with its time zone if known.
+fn xmp_date(date: DateTime, zone: Option<UtcOffset>) -> String {
+    let mut text = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        date.year, date.month, date.day, date.hour, date.minute, date.second
+    );
+    if let Some(zone) = zone {
+        text.push_str(&zone.to_string());
+    }
+    text
+}
+
+fn add_seconds(time: SystemTime, secs: i64) -> SystemTime {
+    if secs >= 0 {
+        time + Duration::from_secs(secs as u64)
+    } else {
+        time - Duration::from_secs(secs.unsigned_abs())
+    }
+}
+
+/// Format as `YYYY-MM-DD hh:mm:ss UTC`.
+fn format_time(time: SystemTime) -> String {
+    let date = DateTime::from_system_time(time);
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) if since.subsec_nanos() > 0 => {
+            format!("{}.{:03} UTC", date, since.subsec_millis())
+        }
+        _ => format!("{} UTC", date),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ascii, exif_segment, jpeg, long, tiff_header, write_ifd, ScratchDir};
+
+    fn tiff() -> Vec<u8> {
+        let mut tiff = tiff_header(8);
+        write_ifd(
+            &mut tiff,
+            8,
+            vec![
+                ascii(tag::DATE_TIME, "2021:05:04 03:02:01"),
+                long(tag::EXIF_IFD_POINTER, &[0x40]),
+            ],
+            0,
+        );
+        write_ifd(
+            &mut tiff,
+            0x40,
+            vec![ascii(tag::DATE_TIME_ORIGINAL, "2021:05:04 03:02:01")],
+            0,
+        );
+        tiff
+    }
+
+    /// A camera one hour ahead, set to UTC, with dates moved to UTC+2.
+    fn correction() -> ClockCorrection {
+        ClockCorrection {
+            camera_offset: 3600,
+            camera_zone: Some(UtcOffset::UTC),
+            zone: UtcOffset::new(2, 0),
+        }
+    }
+
+    fn fields(changes: &[Change]) -> Vec<(&str, &str, &str)> {
+        changes
+            .iter()
+            .map(|c| (c.field.as_str(), c.old.as_str(), c.new.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn jpeg_files_get_corrected_exif_dates() {
+        let dir = ScratchDir::new("timeshift-jpeg");
+        let path = dir.path().join("IMG_0001.JPG");
+        fs::write(&path, jpeg(8, 8, &exif_segment(&tiff()))).unwrap();
+        let changes = correction().apply(&path).unwrap();
+        assert_eq!(
+            fields(&changes),
+            [
+                (
+                    "DateTimeOriginal",
+                    "2021:05:04 03:02:01",
+                    "2021:05:04 04:02:01"
+                ),
+                ("OffsetTimeOriginal", "", "+02:00"),
+                ("DateTime", "2021:05:04 03:02:01", "2021:05:04 04:02:01"),
+                ("OffsetTime", "", "+02:00"),
+                (
+                    MODIFICATION_DATE,
+                    &changes[4].old,
+                    "2021-05-04 02:02:01 UTC"
+                ),
+            ]
+        );
+        let exif = exif::read(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(
+            exif.get(tag::DATE_TIME_ORIGINAL).and_then(Value::as_str),
+            Some("2021:05:04 04:02:01")
+        );
+        assert_eq!(
+            exif.get(tag::OFFSET_TIME_ORIGINAL).and_then(Value::as_str),
+            Some("+02:00")
+        );
+    }
+
+    #[test]
+    fn raw_files_are_left_unmodified_and_get_corrected_dates_in_a_sidecar() {
+        let dir = ScratchDir::new("timeshift-raw");
+        let path = dir.path().join("IMG_0001.CR2");
+        fs::write(&path, tiff()).unwrap();
+        let changes = correction().apply(&path).unwrap();
+        assert_eq!(changes[0].path, dir.path().join("IMG_0001.xmp"));
+        assert_eq!(
+            fields(&changes[..1]),
+            [(
+                "DateTimeOriginal",
+                "2021:05:04 03:02:01",
+                "2021-05-04T04:02:01+02:00"
+            )]
+        );
+        assert_eq!(changes[1].field, MODIFICATION_DATE);
+        assert_eq!(fs::read(&path).unwrap(), tiff());
+        let xmp = fs::read_to_string(dir.path().join("IMG_0001.xmp")).unwrap();
+        assert!(xmp.contains("exif:DateTimeOriginal=\"2021-05-04T04:02:01+02:00\""));
+        assert_eq!(correction().apply(&path).unwrap(), []);
+        assert_eq!(dir.files(), ["IMG_0001.CR2", "IMG_0001.xmp"]);
+    }
+}
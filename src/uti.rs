@@ -0,0 +1,592 @@
+//! Uniform Type Identifiers of camera files, on every platform.
+//!
+//! `ICCameraItem::UTI`, `ICCameraDevice::filesOfType` and `ICScannerDevice::setDocumentUTI`
+//! identify file types by UTI, which only Apple platforms can resolve. This registry covers
+//! the images, RAW formats, movies, audio recordings and sidecars cameras write: it maps
+//! UTIs to and from file extensions and MIME types, and knows which types conform to which,
+//! so that `public.camera-raw-image` conforms to `public.image` and in turn to
+//! `public.data`.
+
+use std::path::Path;
+
+pub const ITEM: &str = "public.item";
+pub const DATA: &str = "public.data";
+pub const CONTENT: &str = "public.content";
+pub const IMAGE: &str = "public.image";
+pub const CAMERA_RAW_IMAGE: &str = "public.camera-raw-image";
+pub const AUDIOVISUAL_CONTENT: &str = "public.audiovisual-content";
+pub const MOVIE: &str = "public.movie";
+pub const AUDIO: &str = "public.audio";
+pub const TEXT: &str = "public.text";
+pub const XML: &str = "public.xml";
+pub const JPEG: &str = "public.jpeg";
+pub const TIFF: &str = "public.tiff";
+pub const HEIC: &str = "public.heic";
+pub const XMP: &str = "public.xmp";
+pub const THM: &str = "com.canon.thm-thumbnail";
+
+/// A type of the registry.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct UtType {
+    /// The identifier, such as `public.jpeg`.
+    pub identifier: &'static str,
+    pub description: &'static str,
+    /// File extensions in lowercase, the preferred one first.
+    pub extensions: &'static [&'static str],
+    /// MIME types, the preferred one first.
+    pub mime_types: &'static [&'static str],
+    /// The types this type directly conforms to.
+    pub conforms_to: &'static [&'static str],
+}
+
+impl UtType {
+    pub fn preferred_extension(&self) -> Option<&'static str> {
+        self.extensions.first().copied()
+    }
+
+    pub fn preferred_mime_type(&self) -> Option<&'static str> {
+        self.mime_types.first().copied()
+    }
+
+    /// Whether this type is `identifier` or conforms to it, directly or through its parents.
+    pub fn conforms_to(&self, identifier: &str) -> bool {
+        self.identifier.eq_ignore_ascii_case(identifier)
+            || self
+                .conforms_to
+                .iter()
+                .filter_map(|parent| lookup(parent))
+                .any(|parent| parent.conforms_to(identifier))
+    }
+
+    /// All types this type conforms to, nearest first, without itself.
+    pub fn supertypes(&self) -> Vec<&'static str> {
+        let mut supertypes: Vec<&'static str> = Vec::new();
+        let mut index = 0;
+        let mut pending: Vec<&'static str> = self.conforms_to.to_vec();
+        while index < pending.len() {
+            let identifier = pending[index];
+            index += 1;
+            if supertypes.contains(&identifier) {
+                continue;
+            }
+            supertypes.push(identifier);
+            if let Some(parent) = lookup(identifier) {
+                pending.extend_from_slice(parent.conforms_to);
+            }
+        }
+        supertypes
+    }
+}
+
+/// The registered type with `identifier`, compared without regard to case.
+pub fn lookup(identifier: &str) -> Option<&'static UtType> {
+    TYPES
+        .iter()
+        .find(|ty| ty.identifier.eq_ignore_ascii_case(identifier))
+}
+
+/// The type of a file extension, with or without its leading dot, in any case.
+pub fn for_extension(extension: &str) -> Option<&'static UtType> {
+    let extension = extension.trim_start_matches('.');
+    TYPES.iter().find(|ty| {
+        ty.extensions
+            .iter()
+            .any(|candidate| candidate.eq_ignore_ascii_case(extension))
+    })
+}
+
+/// The type of a MIME type, ignoring parameters such as `charset`.
+pub fn for_mime_type(mime_type: &str) -> Option<&'static UtType> {
+    let mime_type = mime_type.split(';').next().unwrap_or_default().trim();
+    TYPES.iter().find(|ty| {
+        ty.mime_types
+            .iter()
+            .any(|candidate| candidate.eq_ignore_ascii_case(mime_type))
+    })
+}
+
+/// The type of a file name, from its extension.
+pub fn for_file_name(name: &str) -> Option<&'static UtType> {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(for_extension)
+}
+
+/// Whether the type `identifier` is `parent` or conforms to it. Types missing from the
+/// registry, such as dynamic types, only conform to themselves.
+pub fn conforms_to(identifier: &str, parent: &str) -> bool {
+    match lookup(identifier) {
+        Some(ty) => ty.conforms_to(parent),
+        None => identifier.eq_ignore_ascii_case(parent),
+    }
+}
+
+/// All registered types that are `identifier` or conform to it.
+pub fn conforming_types(identifier: &str) -> Vec<&'static UtType> {
+    TYPES
+        .iter()
+        .filter(|ty| ty.conforms_to(identifier))
+        .collect()
+}
+
+const RAW: &[&str] = &[CAMERA_RAW_IMAGE];
+
+static TYPES: &[UtType] = &[
+    // Abstract types.
+    UtType {
+        identifier: ITEM,
+        description: "item",
+        extensions: &[],
+        mime_types: &[],
+        conforms_to: &[],
+    },
+    UtType {
+        identifier: DATA,
+        description: "data",
+        extensions: &[],
+        mime_types: &["application/octet-stream"],
+        conforms_to: &[ITEM],
+    },
+    UtType {
+        identifier: CONTENT,
+        description: "content",
+        extensions: &[],
+        mime_types: &[],
+        conforms_to: &[],
+    },
+    UtType {
+        identifier: IMAGE,
+        description: "image",
+        extensions: &[],
+        mime_types: &[],
+        conforms_to: &[DATA, CONTENT],
+    },
+    UtType {
+        identifier: CAMERA_RAW_IMAGE,
+        description: "camera RAW image",
+        extensions: &[],
+        mime_types: &[],
+        conforms_to: &[IMAGE],
+    },
+    UtType {
+        identifier: AUDIOVISUAL_CONTENT,
+        description: "audiovisual content",
+        extensions: &[],
+        mime_types: &[],
+        conforms_to: &[DATA, CONTENT],
+    },
+    UtType {
+        identifier: MOVIE,
+        description: "movie",
+        extensions: &[],
+        mime_types: &[],
+        conforms_to: &[AUDIOVISUAL_CONTENT],
+    },
+    UtType {
+        identifier: AUDIO,
+        description: "audio",
+        extensions: &[],
+        mime_types: &[],
+        conforms_to: &[AUDIOVISUAL_CONTENT],
+    },
+    UtType {
+        identifier: TEXT,
+        description: "text",
+        extensions: &[],
+        mime_types: &[],
+        conforms_to: &[DATA, CONTENT],
+    },
+    UtType {
+        identifier: "public.heif-standard",
+        description: "HEIF image",
+        extensions: &[],
+        mime_types: &[],
+        conforms_to: &[IMAGE],
+    },
+    // Images.
+    UtType {
+        identifier: JPEG,
+        description: "JPEG image",
+        extensions: &["jpg", "jpeg", "jpe"],
+        mime_types: &["image/jpeg", "image/pjpeg"],
+        conforms_to: &[IMAGE],
+    },
+    UtType {
+        identifier: TIFF,
+        description: "TIFF image",
+        extensions: &["tif", "tiff"],
+        mime_types: &["image/tiff"],
+        conforms_to: &[IMAGE],
+    },
+    UtType {
+        identifier: "public.png",
+        description: "PNG image",
+        extensions: &["png"],
+        mime_types: &["image/png"],
+        conforms_to: &[IMAGE],
+    },
+    UtType {
+        identifier: HEIC,
+        description: "HEIC image",
+        extensions: &["heic", "hif"],
+        mime_types: &["image/heic"],
+        conforms_to: &["public.heif-standard"],
+    },
+    UtType {
+        identifier: "public.heif",
+        description: "HEIF image",
+        extensions: &["heif"],
+        mime_types: &["image/heif"],
+        conforms_to: &["public.heif-standard"],
+    },
+    UtType {
+        identifier: "public.avif",
+        description: "AVIF image",
+        extensions: &["avif"],
+        mime_types: &["image/avif"],
+        conforms_to: &[IMAGE],
+    },
+    UtType {
+        identifier: "com.compuserve.gif",
+        description: "GIF image",
+        extensions: &["gif"],
+        mime_types: &["image/gif"],
+        conforms_to: &[IMAGE],
+    },
+    UtType {
+        identifier: "com.microsoft.bmp",
+        description: "BMP image",
+        extensions: &["bmp"],
+        mime_types: &["image/bmp", "image/x-ms-bmp"],
+        conforms_to: &[IMAGE],
+    },
+    UtType {
+        identifier: "org.webmproject.webp",
+        description: "WebP image",
+        extensions: &["webp"],
+        mime_types: &["image/webp"],
+        conforms_to: &[IMAGE],
+    },
+    // RAW formats.
+    UtType {
+        identifier: "com.adobe.raw-image",
+        description: "Digital Negative",
+        extensions: &["dng"],
+        mime_types: &["image/x-adobe-dng"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.canon.cr2-raw-image",
+        description: "Canon CR2 RAW image",
+        extensions: &["cr2"],
+        mime_types: &["image/x-canon-cr2"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.canon.cr3-raw-image",
+        description: "Canon CR3 RAW image",
+        extensions: &["cr3"],
+        mime_types: &["image/x-canon-cr3"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.nikon.raw-image",
+        description: "Nikon NEF RAW image",
+        extensions: &["nef"],
+        mime_types: &["image/x-nikon-nef"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.nikon.nrw-raw-image",
+        description: "Nikon NRW RAW image",
+        extensions: &["nrw"],
+        mime_types: &["image/x-nikon-nrw"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.sony.arw-raw-image",
+        description: "Sony ARW RAW image",
+        extensions: &["arw"],
+        mime_types: &["image/x-sony-arw"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.sony.sr2-raw-image",
+        description: "Sony SR2 RAW image",
+        extensions: &["sr2"],
+        mime_types: &["image/x-sony-sr2"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.fuji.raw-image",
+        description: "Fujifilm RAF RAW image",
+        extensions: &["raf"],
+        mime_types: &["image/x-fuji-raf"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.olympus.raw-image",
+        description: "Olympus ORF RAW image",
+        extensions: &["orf"],
+        mime_types: &["image/x-olympus-orf"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.panasonic.rw2-raw-image",
+        description: "Panasonic RW2 RAW image",
+        extensions: &["rw2"],
+        mime_types: &["image/x-panasonic-rw2"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.pentax.raw-image",
+        description: "Pentax PEF RAW image",
+        extensions: &["pef"],
+        mime_types: &["image/x-pentax-pef"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.samsung.raw-image",
+        description: "Samsung SRW RAW image",
+        extensions: &["srw"],
+        mime_types: &["image/x-samsung-srw"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.hasselblad.3fr-raw-image",
+        description: "Hasselblad 3FR RAW image",
+        extensions: &["3fr"],
+        mime_types: &["image/x-hasselblad-3fr"],
+        conforms_to: RAW,
+    },
+    UtType {
+        identifier: "com.phaseone.raw-image",
+        description: "Phase One IIQ RAW image",
+        extensions: &["iiq"],
+        mime_types: &["image/x-phaseone-iiq"],
+        conforms_to: RAW,
+    },
+    // Movies.
+    UtType {
+        identifier: "com.apple.quicktime-movie",
+        description: "QuickTime movie",
+        extensions: &["mov", "qt"],
+        mime_types: &["video/quicktime"],
+        conforms_to: &[MOVIE],
+    },
+    UtType {
+        identifier: "public.mpeg-4",
+        description: "MPEG-4 movie",
+        extensions: &["mp4", "mpg4"],
+        mime_types: &["video/mp4"],
+        conforms_to: &[MOVIE],
+    },
+    UtType {
+        identifier: "com.apple.m4v-video",
+        description: "M4V movie",
+        extensions: &["m4v"],
+        mime_types: &["video/x-m4v"],
+        conforms_to: &[MOVIE],
+    },
+    UtType {
+        identifier: "public.avi",
+        description: "AVI movie",
+        extensions: &["avi"],
+        mime_types: &["video/x-msvideo", "video/avi"],
+        conforms_to: &[MOVIE],
+    },
+    UtType {
+        identifier: "public.mpeg-2-transport-stream",
+        description: "AVCHD movie",
+        extensions: &["mts", "m2ts"],
+        mime_types: &["video/mp2t"],
+        conforms_to: &[MOVIE],
+    },
+    // Audio.
+    UtType {
+        identifier: "com.microsoft.waveform-audio",
+        description: "WAV audio",
+        extensions: &["wav", "wave"],
+        mime_types: &["audio/wav", "audio/x-wav", "audio/vnd.wave"],
+        conforms_to: &[AUDIO],
+    },
+    UtType {
+        identifier: "public.mpeg-4-audio",
+        description: "MPEG-4 audio",
+        extensions: &["m4a"],
+        mime_types: &["audio/mp4", "audio/x-m4a"],
+        conforms_to: &[AUDIO],
+    },
+    UtType {
+        identifier: "public.mp3",
+        description: "MP3 audio",
+        extensions: &["mp3"],
+        mime_types: &["audio/mpeg"],
+        conforms_to: &[AUDIO],
+    },
+    UtType {
+        identifier: "public.aiff-audio",
+        description: "AIFF audio",
+        extensions: &["aiff", "aif"],
+        mime_types: &["audio/aiff", "audio/x-aiff"],
+        conforms_to: &[AUDIO],
+    },
+    // Sidecars and text.
+    UtType {
+        identifier: XML,
+        description: "XML",
+        extensions: &["xml"],
+        mime_types: &["application/xml", "text/xml"],
+        conforms_to: &[TEXT],
+    },
+    UtType {
+        identifier: XMP,
+        description: "XMP metadata",
+        extensions: &["xmp"],
+        mime_types: &["application/rdf+xml"],
+        conforms_to: &[XML],
+    },
+    // Canon's THM thumbnails of movies are JPEG images, but they describe the movie rather
+    // than being photos of their own.
+    UtType {
+        identifier: THM,
+        description: "THM thumbnail",
+        extensions: &["thm"],
+        mime_types: &[],
+        conforms_to: &[DATA],
+    },
+    UtType {
+        identifier: "com.apple.photos.apple-adjustment-envelope",
+        description: "Photos adjustments",
+        extensions: &["aae"],
+        mime_types: &[],
+        conforms_to: &[XML],
+    },
+    UtType {
+        identifier: "public.plain-text",
+        description: "text",
+        extensions: &["txt"],
+        mime_types: &["text/plain"],
+        conforms_to: &[TEXT],
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraDevice;
+    use crate::mock::MockCamera;
+
+    #[test]
+    fn extensions_and_mime_types_map_to_types() {
+        assert_eq!(for_extension("JPG").unwrap().identifier, JPEG);
+        assert_eq!(for_extension(".jpeg").unwrap().identifier, JPEG);
+        assert_eq!(
+            for_extension("Cr2").unwrap().identifier,
+            "com.canon.cr2-raw-image"
+        );
+        assert_eq!(for_extension("hif").unwrap().identifier, HEIC);
+        assert_eq!(for_extension("xyz"), None);
+        assert_eq!(for_extension(""), None);
+
+        assert_eq!(for_mime_type("IMAGE/JPEG").unwrap().identifier, JPEG);
+        assert_eq!(
+            for_mime_type("text/xml; charset=utf-8").unwrap().identifier,
+            XML
+        );
+        assert_eq!(for_mime_type("image/x-unknown"), None);
+
+        assert_eq!(for_file_name("IMG_0001.CR2.xmp").unwrap().identifier, XMP);
+        assert_eq!(
+            for_file_name("MVI_0001.MOV").unwrap().identifier,
+            "com.apple.quicktime-movie"
+        );
+        assert_eq!(for_file_name("README"), None);
+
+        let tiff = lookup("PUBLIC.TIFF").unwrap();
+        assert_eq!(tiff.preferred_extension(), Some("tif"));
+        assert_eq!(tiff.preferred_mime_type(), Some("image/tiff"));
+        assert_eq!(lookup(IMAGE).unwrap().preferred_extension(), None);
+    }
+
+    #[test]
+    fn raw_images_conform_to_images_and_data() {
+        let cr2 = "com.canon.cr2-raw-image";
+        for parent in [cr2, CAMERA_RAW_IMAGE, IMAGE, DATA, CONTENT, ITEM] {
+            assert!(conforms_to(cr2, parent), "{}", parent);
+        }
+        assert!(!conforms_to(cr2, JPEG));
+        assert!(!conforms_to(IMAGE, CAMERA_RAW_IMAGE));
+        assert!(!conforms_to(cr2, MOVIE));
+        assert!(conforms_to(HEIC, IMAGE));
+        assert!(conforms_to(XMP, TEXT));
+        // Types missing from the registry only conform to themselves.
+        assert!(conforms_to("com.example.custom", "com.example.custom"));
+        assert!(!conforms_to("com.example.custom", DATA));
+    }
+
+    #[test]
+    fn supertypes_are_listed_nearest_first_once() {
+        let cr2 = lookup("com.canon.cr2-raw-image").unwrap();
+        assert_eq!(
+            cr2.supertypes(),
+            [CAMERA_RAW_IMAGE, IMAGE, DATA, CONTENT, ITEM]
+        );
+        assert_eq!(
+            lookup(HEIC).unwrap().supertypes(),
+            ["public.heif-standard", IMAGE, DATA, CONTENT, ITEM]
+        );
+        assert!(lookup(ITEM).unwrap().supertypes().is_empty());
+    }
+
+    #[test]
+    fn thumbnails_are_not_images() {
+        assert_eq!(for_extension("THM").unwrap().identifier, THM);
+        assert!(conforms_to(THM, DATA));
+        assert!(!conforms_to(THM, IMAGE));
+        let images = conforming_types(IMAGE);
+        assert!(images.iter().any(|ty| ty.identifier == JPEG));
+        assert!(images
+            .iter()
+            .any(|ty| ty.identifier == "com.nikon.raw-image"));
+        assert!(!images
+            .iter()
+            .any(|ty| ty.identifier == THM || ty.identifier == XMP));
+
+        let camera = MockCamera::new("Mock");
+        camera.add_file("MVI_0001.MOV", b"movie");
+        camera.add_file("MVI_0001.THM", b"thumbnail");
+        camera.add_file("IMG_0002.JPG", b"photo");
+        let names = |uti| {
+            let files = camera.files_of_type(uti).unwrap();
+            files.into_iter().map(|file| file.name).collect::<Vec<_>>()
+        };
+        assert_eq!(names(IMAGE), ["IMG_0002.JPG"]);
+        assert_eq!(names(THM), ["MVI_0001.THM"]);
+    }
+
+    #[test]
+    fn the_registry_is_consistent() {
+        let mut extensions = Vec::new();
+        for ty in TYPES {
+            for parent in ty.conforms_to {
+                assert!(
+                    lookup(parent).is_some(),
+                    "{} conforms to {}",
+                    ty.identifier,
+                    parent
+                );
+            }
+            for extension in ty.extensions {
+                assert_eq!(extension.to_ascii_lowercase(), *extension);
+                assert!(
+                    !extensions.contains(extension),
+                    "{} is registered twice",
+                    extension
+                );
+                extensions.push(extension);
+            }
+        }
+    }
+}